@@ -0,0 +1,48 @@
+use crate::{channel_overrides::ChannelScope, dispatcher::AIRequest};
+
+/// Request with the facts the AI remembers about the user.
+/// The facts are sent with this request only and are not kept in the history
+pub struct MemoryAIRequest {
+    original: Box<dyn AIRequest>,
+    facts: Vec<String>,
+}
+
+impl MemoryAIRequest {
+    pub fn new(original: Box<dyn AIRequest>, facts: Vec<String>) -> Self {
+        Self { original, facts }
+    }
+}
+
+impl AIRequest for MemoryAIRequest {
+    fn request(&self) -> String {
+        self.original.request()
+    }
+
+    fn channel(&self) -> String {
+        self.original.channel()
+    }
+
+    fn lang(&self) -> String {
+        self.original.lang()
+    }
+
+    fn user(&self) -> String {
+        self.original.user()
+    }
+
+    fn scope(&self) -> ChannelScope {
+        self.original.scope()
+    }
+
+    fn context(&self) -> Option<String> {
+        let facts = format!(
+            "What you remember about {}: {}",
+            self.original.user(),
+            self.facts.join("; ")
+        );
+        Some(match self.original.context() {
+            Some(context) => format!("{context}\n{facts}"),
+            None => facts,
+        })
+    }
+}
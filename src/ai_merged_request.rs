@@ -0,0 +1,58 @@
+use crate::{channel_overrides::ChannelScope, dispatcher::AIRequest};
+
+/// Several requests to the same channel, answered as one
+pub struct MergedAIRequest {
+    requests: Vec<Box<dyn AIRequest>>,
+}
+
+impl MergedAIRequest {
+    pub fn new(requests: Vec<Box<dyn AIRequest>>) -> Self {
+        assert!(!requests.is_empty(), "Nothing to merge!");
+        Self { requests }
+    }
+}
+
+impl AIRequest for MergedAIRequest {
+    fn request(&self) -> String {
+        self.requests
+            .iter()
+            .map(|r| r.request())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn channel(&self) -> String {
+        self.requests[0].channel()
+    }
+
+    fn lang(&self) -> String {
+        self.requests.last().unwrap().lang()
+    }
+
+    fn user(&self) -> String {
+        self.requests[0].user()
+    }
+
+    fn users(&self) -> Vec<String> {
+        let mut users = Vec::new();
+        for user in self.requests.iter().flat_map(|r| r.users()) {
+            if !users.contains(&user) {
+                users.push(user);
+            }
+        }
+        users
+    }
+
+    fn scope(&self) -> ChannelScope {
+        self.requests[0].scope()
+    }
+
+    fn context(&self) -> Option<String> {
+        let contexts = self
+            .requests
+            .iter()
+            .filter_map(|r| r.context())
+            .collect::<Vec<_>>();
+        (!contexts.is_empty()).then(|| contexts.join("\n"))
+    }
+}
@@ -0,0 +1,46 @@
+use crate::{channel_overrides::ChannelScope, dispatcher::AIRequest};
+
+/// Request text inserted into the prompt template
+pub struct TemplatedAIRequest {
+    original: Box<dyn AIRequest>,
+    text: String,
+}
+
+impl TemplatedAIRequest {
+    /// Placeholders: {request}, {user}, {channel}, {lang}
+    pub fn new(original: Box<dyn AIRequest>, template: &str) -> Self {
+        let text = template
+            .replace("{user}", &original.user())
+            .replace("{channel}", &original.channel())
+            .replace("{lang}", &original.lang())
+            // last, so the request text itself is not searched for placeholders
+            .replace("{request}", &original.request());
+        Self { original, text }
+    }
+}
+
+impl AIRequest for TemplatedAIRequest {
+    fn request(&self) -> String {
+        self.text.clone()
+    }
+
+    fn channel(&self) -> String {
+        self.original.channel()
+    }
+
+    fn lang(&self) -> String {
+        self.original.lang()
+    }
+
+    fn user(&self) -> String {
+        self.original.user()
+    }
+
+    fn scope(&self) -> ChannelScope {
+        self.original.scope()
+    }
+
+    fn context(&self) -> Option<String> {
+        self.original.context()
+    }
+}
@@ -1,4 +1,4 @@
-use crate::dispatcher::AIRequest;
+use crate::{channel_overrides::ChannelScope, dispatcher::AIRequest};
 
 pub struct TranslatedAIRequest {
     original: Box<dyn AIRequest>,
@@ -23,4 +23,16 @@ impl AIRequest for TranslatedAIRequest {
     fn lang(&self) -> String {
         "en".to_string()
     }
+
+    fn user(&self) -> String {
+        self.original.user()
+    }
+
+    fn scope(&self) -> ChannelScope {
+        self.original.scope()
+    }
+
+    fn context(&self) -> Option<String> {
+        self.original.context()
+    }
 }
@@ -12,6 +12,7 @@ pub enum DiscordRequest {
     TextRequest {
         guild_id: Option<GuildId>,
         channel_id: ChannelId,
+        channel_name: String,
         msg_id: MessageId,
         user: User,
         text: String,
@@ -20,6 +21,7 @@ pub enum DiscordRequest {
     VoiceRequest {
         guild_id: GuildId,
         channel_id: ChannelId,
+        channel_name: String,
         user: User,
         text: String,
     },
@@ -43,6 +45,14 @@ pub enum DiscordRequest {
         channel_id: ChannelId,
         user: User,
     },
+
+    /// Switch the channel to another persona
+    SetPersona {
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        user: User,
+        persona: String,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -54,6 +64,12 @@ pub enum DiscordResponse {
         text: String,                      // Message
         tts: Option<Cursor<bytes::Bytes>>, // TTS if any
     },
+    /// Part of the answer generated so far, shown before the final response
+    PartialTextResponse {
+        req_msg_id: MessageId, // Oginal message id
+        channel_id: ChannelId, // Channel to answer to
+        text: String,          // Answer text so far
+    },
     VoiceResponse {
         req_msg_id: Option<MessageId>, // Oginal message id
         guild_id: GuildId,             // Guild to answer to
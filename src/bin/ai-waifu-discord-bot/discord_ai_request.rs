@@ -1,10 +1,26 @@
-use ai_waifu::dispatcher::AIRequest;
-use serenity::model::prelude::ChannelId;
-
+use ai_waifu::{channel_overrides::ChannelScope, dispatcher::AIRequest};
+use serenity::model::prelude::{ChannelId, GuildId};
 
 pub struct DiscordAIRequest {
     pub request: String,
     pub channel_id: ChannelId,
+    pub user: String,
+    pub scope: ChannelScope,
+}
+
+impl DiscordAIRequest {
+    /// Overrides are selected by the guild, the channel ID and name
+    pub fn discord_scope(
+        guild_id: Option<GuildId>,
+        channel_id: ChannelId,
+        channel_name: String,
+    ) -> ChannelScope {
+        let scope = ChannelScope::new(channel_id.to_string()).with_name(channel_name);
+        match guild_id {
+            Some(guild_id) => scope.with_guild(guild_id.to_string()),
+            None => scope,
+        }
+    }
 }
 
 impl AIRequest for DiscordAIRequest {
@@ -19,6 +35,14 @@ impl AIRequest for DiscordAIRequest {
     fn lang(&self) -> String {
         "auto".to_string()
     }
+
+    fn user(&self) -> String {
+        self.user.clone()
+    }
+
+    fn scope(&self) -> ChannelScope {
+        self.scope.clone()
+    }
 }
 
 impl std::fmt::Display for DiscordAIRequest {
@@ -1,6 +1,6 @@
-use std::{borrow::Cow, io::Cursor, ops::DerefMut, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, io::Cursor, ops::DerefMut, sync::Arc};
 
-use ai_waifu::whisper_voice_recognize::OpenAIWhisperVoice2Txt;
+use ai_waifu::{config_reload::Live, whisper_voice_recognize::OpenAIWhisperVoice2Txt};
 use regex::RegexSet;
 use reqwest::Url;
 use rodio::Source;
@@ -10,6 +10,7 @@ use serenity::{
         channel::Message,
         id::{ChannelId, GuildId},
         prelude::{
+            command::CommandOptionType,
             interaction::{Interaction, InteractionResponseType},
             Guild, MessageId, MessageReference, Ready, UserId,
         },
@@ -30,33 +31,38 @@ use crate::{
 
 pub struct DiscordEventHandler {
     control_request_channel_tx: Sender<Req>,
-    channel_whitelist: RegexSet,
+    channel_whitelist: Live<RegexSet>,
     text_responce_channel_rx: Mutex<Option<Receiver<Resp>>>,
 
     voice_listener_builder: VoiceEventListenerBuilder,
     voice_processor: Mutex<Option<VoiceProcessor>>,
 
     voice2txt_url: Url,
+
+    persona_names: Vec<String>,
 }
 
 impl DiscordEventHandler {
     pub fn new(
         control_request_channel_tx: Sender<Req>,
         text_responce_channel_rx: Receiver<Resp>,
-        channel_whitelist: Vec<String>,
+        channel_whitelist: Live<RegexSet>,
         voice2txt_url: Url,
+        persona_names: Vec<String>,
     ) -> Self {
         let (voice_listener_builder, voice_processor) = create_voice_control_pair();
 
         Self {
             control_request_channel_tx,
-            channel_whitelist: RegexSet::new(channel_whitelist).unwrap(),
+            channel_whitelist,
             text_responce_channel_rx: Mutex::new(Some(text_responce_channel_rx)),
 
             voice_listener_builder,
             voice_processor: Mutex::new(Some(voice_processor)),
 
             voice2txt_url,
+
+            persona_names,
         }
     }
 
@@ -138,7 +144,7 @@ impl DiscordEventHandler {
 
     async fn is_channel_allowed(&self, ctx: &Context, channel_id: &ChannelId) -> bool {
         let ch_name = Self::get_chanel_name_by_id(ctx, Some(*channel_id)).await;
-        self.channel_whitelist.is_match(ch_name.as_str())
+        self.channel_whitelist.get().is_match(ch_name.as_str())
     }
 
     async fn join_voice_channel<C, G>(
@@ -170,6 +176,29 @@ impl EventHandler for DiscordEventHandler {
                 },
             )
             .await;
+
+        let _persona_command =
+            serenity::model::application::command::Command::create_global_application_command(
+                &ctx.http,
+                |command| {
+                    command
+                        .name("persona")
+                        .description("Switch the persona for this channel")
+                        .create_option(|option| {
+                            option
+                                .name("name")
+                                .description("Persona name")
+                                .kind(CommandOptionType::String)
+                                .required(true);
+                            // discord allows at most 25 choices
+                            for name in self.persona_names.iter().take(25) {
+                                option.add_string_choice(name, name);
+                            }
+                            option
+                        })
+                },
+            )
+            .await;
     }
 
     async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
@@ -184,6 +213,28 @@ impl EventHandler for DiscordEventHandler {
                     .await;
                     "Resetting conversation state...".to_string()
                 }
+                "persona" => {
+                    let persona = command
+                        .data
+                        .options
+                        .first()
+                        .and_then(|option| option.value.as_ref())
+                        .and_then(|value| value.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    if self.persona_names.contains(&persona) {
+                        self.send_req(Req::SetPersona {
+                            guild_id: command.guild_id.clone(),
+                            channel_id: command.channel_id.clone(),
+                            user: command.user.clone(),
+                            persona: persona.clone(),
+                        })
+                        .await;
+                        format!("Switching persona to {persona}...")
+                    } else {
+                        format!("Unknown persona: {persona}")
+                    }
+                }
                 _ => "not implemented :(".to_string(),
             };
 
@@ -219,8 +270,8 @@ impl EventHandler for DiscordEventHandler {
                     msg_id: Option<MessageId>,
                     text: String,
                     tts: Option<Cursor<bytes::Bytes>>,
-                ) {
-                    if let Err(e) = channel_id
+                ) -> Option<MessageId> {
+                    match channel_id
                         .send_message(&ctx.http, |m| {
                             m.content(text);
                             if let Some(msg_id) = msg_id {
@@ -242,18 +293,71 @@ impl EventHandler for DiscordEventHandler {
                         })
                         .await
                     {
-                        error!("Failed to send message: {e:?}");
+                        Ok(message) => Some(message.id),
+                        Err(e) => {
+                            error!("Failed to send message: {e:?}");
+                            None
+                        }
+                    }
+                }
+
+                async fn edit_text_message(
+                    ctx: &Context,
+                    channel_id: ChannelId,
+                    msg_id: MessageId,
+                    text: String,
+                ) {
+                    if let Err(e) = channel_id
+                        .edit_message(&ctx.http, msg_id, |m| m.content(text))
+                        .await
+                    {
+                        error!("Failed to edit message: {e:?}");
                     }
                 }
 
+                // request message id -> message with the partial answer
+                let mut previews = HashMap::<MessageId, MessageId>::new();
+
                 while let Some(resp) = command_rx.recv().await {
                     match resp {
+                        Resp::PartialTextResponse {
+                            req_msg_id,
+                            channel_id,
+                            text,
+                        } => {
+                            if let Some(preview_id) = previews.get(&req_msg_id) {
+                                edit_text_message(&ctx, channel_id, *preview_id, text).await;
+                            } else if let Some(preview_id) =
+                                send_text_message(&ctx, channel_id, Some(req_msg_id), text, None)
+                                    .await
+                            {
+                                previews.insert(req_msg_id, preview_id);
+                            }
+                        }
                         Resp::TextResponse {
                             req_msg_id,
                             channel_id,
                             text,
                             tts,
-                        } => send_text_message(&ctx, channel_id, req_msg_id, text, tts).await,
+                        } => match req_msg_id.and_then(|id| previews.remove(&id)) {
+                            Some(preview_id) => {
+                                // replace partial answer with the final one, TTS goes separately
+                                edit_text_message(&ctx, channel_id, preview_id, text).await;
+                                if tts.is_some() {
+                                    send_text_message(
+                                        &ctx,
+                                        channel_id,
+                                        Some(preview_id),
+                                        String::new(),
+                                        tts,
+                                    )
+                                    .await;
+                                }
+                            }
+                            None => {
+                                send_text_message(&ctx, channel_id, req_msg_id, text, tts).await;
+                            }
+                        },
                         Resp::VoiceResponse {
                             req_msg_id,
                             guild_id,
@@ -262,8 +366,14 @@ impl EventHandler for DiscordEventHandler {
                             tts,
                         } => {
                             // send text to text channel
+                            let preview_id = req_msg_id.and_then(|id| previews.remove(&id));
                             if let Some(text) = text {
-                                send_text_message(&ctx, channel_id, req_msg_id, text, None).await;
+                                if let Some(preview_id) = preview_id {
+                                    edit_text_message(&ctx, channel_id, preview_id, text).await;
+                                } else {
+                                    send_text_message(&ctx, channel_id, req_msg_id, text, None)
+                                        .await;
+                                }
                             }
 
                             // join channel
@@ -305,11 +415,11 @@ impl EventHandler for DiscordEventHandler {
                                     .collect::<Vec<_>>()
                             };
 
-                            // play tts
+                            // play tts after everything already queued
                             {
                                 let mut guard = handler.lock().await;
 
-                                guard.play_only_source(Input::new(
+                                guard.enqueue_source(Input::new(
                                     is_stereo,
                                     songbird::input::Reader::from_memory(audiobytes),
                                     songbird::input::Codec::Pcm,
@@ -369,11 +479,17 @@ impl EventHandler for DiscordEventHandler {
                                                         "User {} said: {} ({})",
                                                         user_id, text, lang
                                                     );
+                                                    let channel_name = Self::get_chanel_name_by_id(
+                                                        &ctx,
+                                                        Some(channel_id),
+                                                    )
+                                                    .await;
                                                     Self::send_req_static(
                                                         &control_request_channel_tx,
                                                         Req::VoiceRequest {
                                                             guild_id,
                                                             channel_id,
+                                                            channel_name,
                                                             user,
                                                             text,
                                                         },
@@ -416,7 +532,8 @@ impl EventHandler for DiscordEventHandler {
         }
 
         // check if channel is whitelisted
-        if !self.is_channel_allowed(&ctx, &message.channel_id).await {
+        let channel_name = Self::get_chanel_name_by_id(&ctx, Some(message.channel_id)).await;
+        if !self.channel_whitelist.get().is_match(&channel_name) {
             return;
         }
 
@@ -425,6 +542,7 @@ impl EventHandler for DiscordEventHandler {
         self.send_req(Req::TextRequest {
             guild_id: message.guild_id,
             channel_id: message.channel_id,
+            channel_name,
             msg_id: message.id,
             user: message.author,
             text: message.content,
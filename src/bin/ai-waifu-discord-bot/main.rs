@@ -5,6 +5,10 @@ mod process_request;
 mod voice_ch_map;
 mod voice_receiver;
 
+use std::sync::Arc;
+
+use clap::Parser;
+use regex::RegexSet;
 use serenity::{
     client::Client, framework::StandardFramework, model::prelude::ChannelId,
     prelude::GatewayIntents,
@@ -15,7 +19,13 @@ use songbird::{driver::DecodeMode, Config, SerenityInit};
 use tokio::sync::mpsc::{Receiver, Sender};
 use tracing::{debug, error, info, warn};
 
-use ai_waifu::{config::Config as BotConfig, dispatcher::Dispatcher, tts_engine::TTSEngine};
+use ai_waifu::{
+    config::Platform,
+    config_loader::ConfigArgs,
+    config_reload::{follow_config, Live},
+    dispatcher::{AIRequest, Dispatcher},
+    persona::Personas,
+};
 use control::{DiscordRequest, DiscordResponse};
 use discord_event_handler::DiscordEventHandler;
 use tracing_subscriber::{
@@ -31,13 +41,19 @@ use process_request::{process_text_request, process_voice_request};
 
 pub const DISCORD_AUDIO_SAMPLE_RATE: u32 = 48_000;
 
-async fn dispatcher_coroutine<F: Fn() -> String>(
-    mut dispatcher: Box<dyn Dispatcher>,
+/// Ai Waifu discord bot
+#[derive(Parser)]
+struct Cli {
+    #[clap(flatten)]
+    config: ConfigArgs,
+}
+
+async fn dispatcher_coroutine(
+    dispatcher: Arc<dyn Dispatcher>,
     mut control_request_channel_rx: Receiver<DiscordRequest>,
     text_responce_channel_tx: Sender<DiscordResponse>,
-    tts: TTSEngine,
-    busy_messages_generator: F,
-    display_raw_resp: bool,
+    personas: Live<Personas>,
+    position_message: String,
 ) {
     // грязный хак
     fn convert_user_to_pseudo_channel_id(user: &serenity::model::prelude::User) -> ChannelId {
@@ -51,8 +67,9 @@ async fn dispatcher_coroutine<F: Fn() -> String>(
             DiscordRequest::TextRequest {
                 guild_id,
                 channel_id,
+                channel_name,
                 msg_id,
-                user: _,
+                user,
                 text,
             } => {
                 let guild_id = if let Some(gi) = guild_id {
@@ -65,43 +82,67 @@ async fn dispatcher_coroutine<F: Fn() -> String>(
                 let request = DiscordAIRequest {
                     request: text,
                     channel_id,
+                    user: user.name,
+                    scope: DiscordAIRequest::discord_scope(
+                        Some(guild_id),
+                        channel_id,
+                        channel_name,
+                    ),
                 };
 
+                let personas = personas.get();
+                let persona = personas.get(&request.scope, &dispatcher.persona(&request.channel()));
+                let tts = personas.tts(&request.scope, &persona.name);
+
                 process_text_request(
                     request,
-                    dispatcher.as_mut(),
+                    dispatcher.as_ref(),
                     &tts,
                     &mut giuld_ch_user_map,
                     &text_responce_channel_tx,
-                    busy_messages_generator(),
+                    persona.busy_message(),
                     guild_id,
                     channel_id,
                     msg_id,
-                    display_raw_resp,
+                    persona.display_raw_resp,
+                    &position_message,
                 )
                 .await;
             }
             DiscordRequest::VoiceRequest {
                 guild_id,
                 channel_id,
+                channel_name,
                 user,
                 text,
             } => {
                 let request = DiscordAIRequest {
                     request: text,
                     channel_id: convert_user_to_pseudo_channel_id(&user),
+                    user: user.name,
+                    // settings of the voice channel, the history is per user
+                    scope: DiscordAIRequest::discord_scope(
+                        Some(guild_id),
+                        channel_id,
+                        channel_name,
+                    ),
                 };
 
+                let personas = personas.get();
+                let persona = personas.get(&request.scope, &dispatcher.persona(&request.channel()));
+                let tts = personas.tts(&request.scope, &persona.name);
+
                 process_voice_request(
                     request,
-                    dispatcher.as_mut(),
+                    dispatcher.as_ref(),
                     &tts,
                     &mut giuld_ch_user_map,
                     &text_responce_channel_tx,
-                    busy_messages_generator(),
+                    persona.busy_message(),
                     guild_id,
                     channel_id,
-                    display_raw_resp,
+                    persona.display_raw_resp,
+                    &position_message,
                 )
                 .await;
             }
@@ -147,6 +188,27 @@ async fn dispatcher_coroutine<F: Fn() -> String>(
                     info!("Reset conversation by {}", user.name);
                 }
             }
+            DiscordRequest::SetPersona {
+                guild_id: _,
+                channel_id,
+                user,
+                persona,
+            } => {
+                let ch = if channel_id.0 != 0 {
+                    channel_id
+                } else {
+                    convert_user_to_pseudo_channel_id(&user)
+                };
+
+                if let Err(e) = dispatcher
+                    .set_persona(format!("#{}", ch.0), persona.clone())
+                    .await
+                {
+                    error!("Failed to switch persona: {:#?}", e);
+                } else {
+                    info!("Persona switched to {} by {}", persona, user.name);
+                }
+            }
         }
     }
 }
@@ -163,7 +225,10 @@ async fn main() {
         .with(fmt_layer)
         .init();
 
-    let config = BotConfig::load();
+    let args = Cli::parse();
+
+    let config = args.config.load_validated().await;
+    let live_config = args.config.watch(config.clone());
 
     let intents = GatewayIntents::non_privileged()
         | GatewayIntents::MESSAGE_CONTENT
@@ -175,25 +240,19 @@ async fn main() {
     let (text_responce_channel_tx, text_responce_channel_rx) =
         tokio::sync::mpsc::channel::<DiscordResponse>(1);
 
-    let dispatcher = ai_waifu::create_ai_dispatcher(&config);
-
-    let tts = ai_waifu::tts_engine::TTSEngine::with_config(&config.tts_config);
+    let dispatcher = ai_waifu::create_ai_dispatcher(&config, Platform::Discord);
+    follow_config(dispatcher.clone(), live_config.clone());
 
-    let busy_messages = config.busy_messages;
+    // busy messages and voices are updated on config reload
+    let personas = Live::new(live_config.clone(), Personas::with_config);
+    let persona_names = personas.get().names().to_vec();
 
     tokio::spawn(dispatcher_coroutine(
         dispatcher,
         control_request_channel_rx,
         text_responce_channel_tx,
-        tts,
-        move || {
-            use rand::Rng;
-
-            let mut rng = rand::thread_rng();
-            let idx = rng.gen_range(0..busy_messages.len());
-            busy_messages[idx].clone()
-        },
-        config.display_raw_resp,
+        personas,
+        config.request_queue.position_message.clone(),
     ));
 
     let framework = StandardFramework::new();
@@ -203,12 +262,15 @@ async fn main() {
     // read the audio data that other people are sending us!
     let songbird_config = Config::default().decode_mode(DecodeMode::Decode);
 
-    let mut bot = Client::builder(&config.discord_config.discord_token, intents)
+    let mut bot = Client::builder(config.discord_config.discord_token.expose(), intents)
         .event_handler(DiscordEventHandler::new(
             control_request_channel_tx,
             text_responce_channel_rx,
-            config.discord_config.channel_whitelist,
+            Live::new(live_config, |config| {
+                RegexSet::new(&config.discord_config.channel_whitelist).unwrap()
+            }),
             config.stt_config.voice2txt_url,
+            persona_names,
         ))
         .framework(framework)
         .register_songbird_from_config(songbird_config)
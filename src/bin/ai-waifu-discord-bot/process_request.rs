@@ -1,15 +1,19 @@
-use std::{collections::HashMap, io::Cursor};
+use std::{collections::HashMap, io::Cursor, sync::Arc};
 
 use ai_waifu::{
-    dispatcher::{AIError, AIResponseType, Dispatcher},
+    dispatcher::{AIError, AIResponseChunk, AIResponseStream, AIResponseType, Dispatcher},
+    errors::ErrorInfo,
+    sanitizer::{display_text, spoken_text},
     tts_engine::TTSEngine,
+    utils::tts_pipeline::spawn_tts_pipeline,
 };
 
 use bytes::Bytes;
+use futures_util::StreamExt;
 use serenity::model::prelude::{ChannelId, GuildId, MessageId};
 use tokio::sync::mpsc::Sender;
 
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
     control::DiscordResponse,
@@ -17,29 +21,136 @@ use crate::{
     voice_ch_map::{State, VoiceChannelMap},
 };
 
+/// Текст для озвучки и текст для чата
 fn get_texts<'a>(
     resp: &'a HashMap<AIResponseType, String>,
     display_raw_resp: bool,
-) -> (&'a String, String) {
-    let text_to_tts = if let Some(translated_text) = resp.get(&AIResponseType::Translated) {
-        translated_text
-    } else {
-        resp.get(&AIResponseType::RawAnswer).unwrap()
-    };
+) -> (&'a str, String) {
+    let text_to_tts = spoken_text(resp);
 
     let text_to_send = if display_raw_resp {
         format!(
             "{} [{}]",
-            text_to_tts,
+            display_text(resp),
             resp.get(&AIResponseType::RawAnswer).unwrap()
         )
     } else {
-        text_to_tts.clone()
+        display_text(resp).to_string()
     };
 
     (text_to_tts, text_to_send)
 }
 
+/// Send the answer text to the channel as it is generated, if `voice` is set,
+/// each sentence is spoken as soon as it is synthesized.
+/// While the request is waiting in the queue, the user is told the position.
+/// Returns the final answer and whether it was spoken
+async fn stream_answer(
+    mut answer_stream: AIResponseStream,
+    tts: &Arc<TTSEngine>,
+    text_responce_channel_tx: &Sender<DiscordResponse>,
+    guild_id: GuildId,
+    channel_id: ChannelId,
+    msg_id: Option<MessageId>,
+    voice: bool,
+    display_raw_resp: bool,
+    position_message: &str,
+) -> Result<(HashMap<AIResponseType, String>, bool), AIError> {
+    let (sentences_tx, mut audio_rx) = if voice {
+        let (sentences_tx, audio_rx) = spawn_tts_pipeline(tts.clone());
+        (Some(sentences_tx), Some(audio_rx))
+    } else {
+        (None, None)
+    };
+
+    let read_answer = async move {
+        let mut text_so_far = String::new();
+        let mut position_announced = false;
+        while let Some(chunk) = answer_stream.next().await {
+            match chunk? {
+                AIResponseChunk::Sentence(sentence) => {
+                    let (text_to_tts, text_to_send) = get_texts(&sentence, display_raw_resp);
+                    if let Some(sentences_tx) = &sentences_tx {
+                        if let Err(err) = sentences_tx.send(text_to_tts.to_string()).await {
+                            error!("Error send sentence to TTS: {:?}", err);
+                        }
+                    }
+
+                    if let Some(msg_id) = msg_id {
+                        if !text_so_far.is_empty() {
+                            text_so_far.push(' ');
+                        }
+                        text_so_far.push_str(&text_to_send);
+
+                        let resp = DiscordResponse::PartialTextResponse {
+                            req_msg_id: msg_id,
+                            channel_id,
+                            text: text_so_far.clone(),
+                        };
+                        if let Err(err) = text_responce_channel_tx.send(resp).await {
+                            error!("Error send discord responce: {:?}", err);
+                        }
+                    }
+                }
+                AIResponseChunk::Done(resp) => return Ok(resp),
+                AIResponseChunk::Delta(_) => { /* only full sentences are displayed */ }
+                AIResponseChunk::Queued(position) => {
+                    let text = position_message.replace("{position}", &position.to_string());
+                    let resp = if let Some(msg_id) = msg_id {
+                        // превью ответа, будет заменено самим ответом
+                        DiscordResponse::PartialTextResponse {
+                            req_msg_id: msg_id,
+                            channel_id,
+                            text,
+                        }
+                    } else if !position_announced {
+                        // на голосовой запрос нечего редактировать, сообщаем только один раз
+                        DiscordResponse::TextResponse {
+                            req_msg_id: None,
+                            channel_id,
+                            text,
+                            tts: None,
+                        }
+                    } else {
+                        continue;
+                    };
+                    position_announced = true;
+
+                    if let Err(err) = text_responce_channel_tx.send(resp).await {
+                        error!("Error send discord responce: {:?}", err);
+                    }
+                }
+            }
+        }
+        Err(AIError::UnknownError)
+    };
+
+    // Голосовые ответы ставятся в очередь воспроизведения по одному предложению
+    let play_sentences = async {
+        let mut spoken = false;
+        if let Some(audio_rx) = &mut audio_rx {
+            while let Some(tts) = audio_rx.recv().await {
+                let resp = DiscordResponse::VoiceResponse {
+                    req_msg_id: None,
+                    guild_id,
+                    channel_id,
+                    text: None,
+                    tts,
+                };
+                if let Err(err) = text_responce_channel_tx.send(resp).await {
+                    error!("Error send discord responce: {:?}", err);
+                } else {
+                    spoken = true;
+                }
+            }
+        }
+        spoken
+    };
+
+    let (answer, spoken) = tokio::join!(read_answer, play_sentences);
+    Ok((answer?, spoken))
+}
+
 async fn generate_tts<T: Into<String>>(resp: T, tts: &TTSEngine) -> Option<Cursor<Bytes>> {
     match tts.say(resp).await {
         Ok(tts) => Some(tts),
@@ -50,10 +161,27 @@ async fn generate_tts<T: Into<String>>(resp: T, tts: &TTSEngine) -> Option<Curso
     }
 }
 
+/// Что ответить на ошибку: временные ошибки - как занятость, на остальные - извиниться
+fn error_message(err: &AIError, busy_message: String) -> String {
+    match err {
+        AIError::Busy => busy_message,
+        err if err.is_retryable() => {
+            warn!("AI Error: {}", err);
+            busy_message
+        }
+        err => {
+            error!("AI Error: {}", err);
+            err.user_message().to_string()
+        }
+    }
+}
+
+/// Отправить запрос диспетчеру, ответ обрабатывается в отдельной задаче,
+/// чтобы запросы других каналов не ждали, пока этот стоит в очереди
 pub async fn process_text_request(
     request: DiscordAIRequest,
-    dispatcher: &mut dyn Dispatcher,
-    tts: &TTSEngine,
+    dispatcher: &dyn Dispatcher,
+    tts: &Arc<TTSEngine>,
     giuld_ch_user_map: &mut VoiceChannelMap,
     text_responce_channel_tx: &Sender<DiscordResponse>,
     busy_message: String,
@@ -61,43 +189,90 @@ pub async fn process_text_request(
     channel_id: ChannelId,
     msg_id: MessageId,
     display_raw_resp: bool,
+    position_message: &str,
 ) {
     info!("{}", request);
-    match dispatcher.try_process_request(Box::new(request)).await {
-        Ok(resp) => {
-            let (text_to_tts, text_to_send) = get_texts(&resp, display_raw_resp);
+    let voice = giuld_ch_user_map.get_voice_state(guild_id, channel_id) == State::Voice;
+    let answer_stream = dispatcher
+        .try_process_request_streamed(Box::new(request))
+        .await;
 
-            let tts_data = generate_tts(text_to_tts, tts).await;
+    let tts = tts.clone();
+    let text_responce_channel_tx = text_responce_channel_tx.clone();
+    let position_message = position_message.to_string();
+    tokio::spawn(async move {
+        let answer = match answer_stream {
+            Ok(answer_stream) => {
+                stream_answer(
+                    answer_stream,
+                    &tts,
+                    &text_responce_channel_tx,
+                    guild_id,
+                    channel_id,
+                    Some(msg_id),
+                    voice,
+                    display_raw_resp,
+                    &position_message,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
 
-            let resp = if giuld_ch_user_map.get_voice_state(guild_id, channel_id) == State::Voice
-                && tts_data.is_some()
-            {
-                // Если бот в голосовом канале, то читать сообщени вслух, а отправлять текст без вложения
-                DiscordResponse::VoiceResponse {
-                    req_msg_id: Some(msg_id),
-                    guild_id: guild_id,
-                    channel_id: channel_id,
-                    text: Some(text_to_send.clone()),
-                    tts: tts_data.unwrap(),
-                }
+        send_text_answer(
+            answer,
+            &tts,
+            &text_responce_channel_tx,
+            busy_message,
+            channel_id,
+            guild_id,
+            msg_id,
+            voice,
+            display_raw_resp,
+        )
+        .await;
+    });
+}
+
+async fn send_text_answer(
+    answer: Result<(HashMap<AIResponseType, String>, bool), AIError>,
+    tts: &Arc<TTSEngine>,
+    text_responce_channel_tx: &Sender<DiscordResponse>,
+    busy_message: String,
+    channel_id: ChannelId,
+    guild_id: GuildId,
+    msg_id: MessageId,
+    voice: bool,
+    display_raw_resp: bool,
+) {
+    match answer {
+        Ok((resp, spoken)) => {
+            let (text_to_tts, text_to_send) = get_texts(&resp, display_raw_resp);
+
+            // Если бот в голосовом канале, то ответ уже прочитан вслух, отправлять текст без вложения
+            // иначе сообщение + вложение; ответ из одних ремарок озвучивать нечего
+            let tts_data = if spoken || text_to_tts.is_empty() {
+                None
             } else {
-                // бот не в голосовом канале, сообщение + вложение
-                DiscordResponse::TextResponse {
-                    req_msg_id: Some(msg_id),
-                    channel_id: channel_id,
-                    text: text_to_send.clone(),
-                    tts: tts_data,
-                }
+                generate_tts(text_to_tts, tts).await
+            };
+
+            let resp = DiscordResponse::TextResponse {
+                req_msg_id: Some(msg_id),
+                channel_id: channel_id,
+                text: text_to_send.clone(),
+                tts: tts_data,
             };
 
             if let Err(err) = text_responce_channel_tx.send(resp).await {
                 error!("Error send discord responce: {:?}", err);
             }
         }
-        Err(AIError::Busy) => {
-            let resp = if giuld_ch_user_map.get_voice_state(guild_id, channel_id) == State::Voice {
+        Err(err) => {
+            let message = error_message(&err, busy_message);
+            let resp = if voice {
                 // Если бот в голосовом канале, то возмутиться вслух, а текст не отправлять
-                match tts.say(&busy_message).await {
+                match tts.say(&message).await {
                     Ok(tts) => DiscordResponse::VoiceResponse {
                         req_msg_id: Some(msg_id),
                         guild_id: guild_id,
@@ -120,7 +295,7 @@ pub async fn process_text_request(
                 DiscordResponse::TextResponse {
                     req_msg_id: Some(msg_id),
                     channel_id: channel_id,
-                    text: busy_message,
+                    text: message,
                     tts: None,
                 }
             };
@@ -129,49 +304,80 @@ pub async fn process_text_request(
                 error!("Error send discord responce: {:?}", err);
             }
         }
-        Err(err) => {
-            error!("AI Error: {:?}", err);
-        }
     }
 }
 
+/// Отправить голосовой запрос диспетчеру, ответ обрабатывается в отдельной задаче
 pub async fn process_voice_request(
     request: DiscordAIRequest,
-    dispatcher: &mut dyn Dispatcher,
-    tts: &TTSEngine,
+    dispatcher: &dyn Dispatcher,
+    tts: &Arc<TTSEngine>,
     giuld_ch_user_map: &mut VoiceChannelMap,
     text_responce_channel_tx: &Sender<DiscordResponse>,
     busy_message: String,
     guild_id: GuildId,
     channel_id: ChannelId,
     display_raw_resp: bool,
+    position_message: &str,
 ) {
     info!("{}", request);
-    match dispatcher.try_process_request(Box::new(request)).await {
-        Ok(resp) => {
-            let (text_to_tts, text_to_send) = get_texts(&resp, display_raw_resp);
+    let voice = giuld_ch_user_map.get_voice_state(guild_id, channel_id) == State::Voice;
+    let answer_stream = dispatcher
+        .try_process_request_streamed(Box::new(request))
+        .await;
 
-            let tts_data = generate_tts(text_to_tts, tts).await;
+    let tts = tts.clone();
+    let text_responce_channel_tx = text_responce_channel_tx.clone();
+    let position_message = position_message.to_string();
+    tokio::spawn(async move {
+        let answer = match answer_stream {
+            Ok(answer_stream) => {
+                stream_answer(
+                    answer_stream,
+                    &tts,
+                    &text_responce_channel_tx,
+                    guild_id,
+                    channel_id,
+                    None,
+                    voice,
+                    display_raw_resp,
+                    &position_message,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        };
 
-            if giuld_ch_user_map.get_voice_state(guild_id, channel_id) == State::Voice
-                && tts_data.is_some()
-            {
-                // Если бот в голосовом канале, то читать сообщени вслух, а отправлять текст без вложения
-                let resp = DiscordResponse::VoiceResponse {
-                    req_msg_id: None,
-                    guild_id: guild_id,
-                    channel_id: channel_id,
-                    text: if display_raw_resp {
-                        Some(text_to_send.clone())
-                    } else {
-                        None
-                    },
-                    tts: tts_data.unwrap(),
-                };
-                if let Err(err) = text_responce_channel_tx.send(resp).await {
-                    error!("Error send discord responce: {:?}", err);
-                }
-            } else {
+        send_voice_answer(
+            answer,
+            &tts,
+            &text_responce_channel_tx,
+            busy_message,
+            channel_id,
+            guild_id,
+            voice,
+            display_raw_resp,
+        )
+        .await;
+    });
+}
+
+async fn send_voice_answer(
+    answer: Result<(HashMap<AIResponseType, String>, bool), AIError>,
+    tts: &Arc<TTSEngine>,
+    text_responce_channel_tx: &Sender<DiscordResponse>,
+    busy_message: String,
+    channel_id: ChannelId,
+    guild_id: GuildId,
+    voice: bool,
+    display_raw_resp: bool,
+) {
+    match answer {
+        Ok((resp, spoken)) => {
+            let (_, text_to_send) = get_texts(&resp, display_raw_resp);
+
+            // Если бот в голосовом канале, то ответ уже прочитан вслух, текст только если нужен сырой ответ
+            if !spoken || display_raw_resp {
                 // send text response
                 let resp = DiscordResponse::TextResponse {
                     req_msg_id: None,
@@ -184,10 +390,11 @@ pub async fn process_voice_request(
                 }
             }
         }
-        Err(AIError::Busy) => {
-            if giuld_ch_user_map.get_voice_state(guild_id, channel_id) == State::Voice {
+        Err(err) => {
+            let message = error_message(&err, busy_message);
+            if voice {
                 // Если бот в голосовом канале, то возмутиться вслух
-                match tts.say(&busy_message).await {
+                match tts.say(&message).await {
                     Ok(tts) => {
                         let resp = DiscordResponse::VoiceResponse {
                             req_msg_id: None,
@@ -209,7 +416,7 @@ pub async fn process_voice_request(
                 let resp = DiscordResponse::TextResponse {
                     req_msg_id: None,
                     channel_id: channel_id,
-                    text: busy_message,
+                    text: message,
                     tts: None,
                 };
                 if let Err(err) = text_responce_channel_tx.send(resp).await {
@@ -217,8 +424,5 @@ pub async fn process_voice_request(
                 }
             }
         }
-        Err(err) => {
-            error!("AI Error: {:?}", err);
-        }
     }
 }
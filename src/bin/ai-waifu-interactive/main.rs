@@ -12,13 +12,20 @@ use cpal::traits::{DeviceTrait, HostTrait};
 use clap::Parser;
 
 use ai_waifu::{
-    config::Config,
-    dispatcher::{AIRequest, AIResponseType},
+    config::Platform,
+    config_loader::ConfigArgs,
+    config_reload::{follow_config, Live},
+    dispatcher::{AIRequest, AIResponseChunk, AIResponseType},
+    errors::ErrorInfo,
+    persona::Personas,
+    sanitizer::{display_text, spoken_text},
     utils::{
         audio_dev::get_audio_device_by_name,
         audio_input::{get_voice_request, spawn_audio_input},
-        say::say,
+        say::say_queue,
+        tts_pipeline::spawn_tts_pipeline,
     },
+    vtube_studio::Avatar,
 };
 
 #[allow(unused_imports)]
@@ -56,6 +63,9 @@ struct Cli {
     /// Response subtitles file
     #[clap(long)]
     subtitles_ans: Option<PathBuf>,
+
+    #[clap(flatten)]
+    config: ConfigArgs,
 }
 
 /// print all available devices
@@ -96,7 +106,7 @@ fn process_rusty_result(
 
 #[tokio::main]
 async fn main() {
-    use futures_util::future::FutureExt;
+    use futures_util::{future::FutureExt, StreamExt};
 
     let fmt_layer = tracing_subscriber::fmt::layer().with_target(false);
     let filter_layer = EnvFilter::try_from_default_env()
@@ -108,10 +118,11 @@ async fn main() {
         .with(fmt_layer)
         .init();
 
-    let config = Config::load();
-
     let args = Cli::parse();
 
+    let config = args.config.load_validated().await;
+    let live_config = args.config.watch(config.clone());
+
     let ht = cpal::default_host();
 
     if args.list_audio_devices {
@@ -157,11 +168,14 @@ async fn main() {
         error!("No audio output device found, only text output will be available!");
     }
 
-    let mut last_tts_data: Option<Cursor<bytes::Bytes>> = None;
+    let mut last_tts_data: Vec<Cursor<bytes::Bytes>> = vec![];
 
-    let mut dispatcher = ai_waifu::create_ai_dispatcher(&config);
+    let dispatcher = ai_waifu::create_ai_dispatcher(&config, Platform::Interactive);
+    follow_config(dispatcher.clone(), live_config.clone());
 
-    let tts = ai_waifu::tts_engine::TTSEngine::with_config(&config.tts_config);
+    let personas = Live::new(live_config.clone(), Personas::with_config);
+
+    let avatar = config.vtube_studio.clone().map(Avatar::spawn);
 
     let mut audio_request_ctrl = if let Some(ain) = audio_in {
         let (audio_req_tx, audio_req_rx) = tokio::sync::mpsc::channel(1);
@@ -170,8 +184,12 @@ async fn main() {
             audio_req_tx,
             args.noise_gate,
             config.stt_config.voice2txt_url,
-            config.stt_config.minimal_audio_fragment_length,
-            config.stt_config.maximal_audio_fragment_length,
+            Live::new(live_config.clone(), |config| {
+                (
+                    config.stt_config.minimal_audio_fragment_length,
+                    config.stt_config.maximal_audio_fragment_length,
+                )
+            }),
             tokio::runtime::Handle::current(),
         ) {
             Ok(stream) => Some((audio_req_rx, stream)),
@@ -209,10 +227,15 @@ async fn main() {
 
         if request.request == "/repeat" {
             info!("Repeat...");
-            if let Some(lsd) = &last_tts_data {
-                say(&audio_out, lsd.clone(), || {});
-            } else {
+            if last_tts_data.is_empty() {
                 warn!("Nothing to repeat!");
+            } else {
+                let fragments = last_tts_data.clone().into_iter().inspect(|fragment| {
+                    if let Some(avatar) = &avatar {
+                        avatar.speak(fragment.get_ref());
+                    }
+                });
+                say_queue(&audio_out, fragments, || {});
             }
             continue;
         }
@@ -225,6 +248,25 @@ async fn main() {
             continue;
         }
 
+        if request.request == "/persona" || request.request.starts_with("/persona ") {
+            let persona = request.request["/persona".len()..].trim();
+            if persona.is_empty() {
+                info!(
+                    "Personas: {}, current: {}",
+                    personas.get().names().join(", "),
+                    dispatcher.persona(&request.channel())
+                );
+            } else if let Err(e) = dispatcher
+                .set_persona(request.channel(), persona.to_string())
+                .await
+            {
+                error!("Failed to switch persona: {:?}", e);
+            } else {
+                warn!("Switched to persona {}", persona);
+            }
+            continue;
+        }
+
         if request.request == "/exit" {
             warn!("Exiting...");
             break;
@@ -237,63 +279,139 @@ async fn main() {
             }
         }
 
-        let res = match dispatcher.try_process_request(Box::new(request)).await {
-            Ok(res) => res,
+        let scope = request.scope();
+        let persona = dispatcher.persona(&request.channel());
+        let display_raw_resp = personas.get().get(&scope, &persona).display_raw_resp;
+        let tts = personas.get().tts(&scope, &persona);
+
+        let mut answer_stream = match dispatcher
+            .try_process_request_streamed(Box::new(request))
+            .await
+        {
+            Ok(answer_stream) => answer_stream,
             Err(e) => {
                 error!("Error: {:?}", e);
                 continue;
             }
         };
 
-        let text_to_tts = if let Some(translated_text) = res.get(&AIResponseType::Translated) {
-            translated_text
-        } else {
-            res.get(&AIResponseType::RawAnswer).unwrap()
+        // speak sentences as soon as they are synthesized
+        let (sentences_tx, mut audio_rx) = spawn_tts_pipeline(tts);
+        let playback = {
+            let audio_out = audio_out.clone();
+            let subtitles_req = args.subtitles_req.clone();
+            let subtitles_ans = args.subtitles_ans.clone();
+            let avatar = avatar.clone();
+            tokio::task::spawn_blocking(move || {
+                say_queue(
+                    &audio_out,
+                    std::iter::from_fn(|| audio_rx.blocking_recv()).inspect(|fragment| {
+                        if let Some(avatar) = &avatar {
+                            avatar.speak(fragment.get_ref());
+                        }
+                    }),
+                    || {
+                        if let Some(subtitles_req) = &subtitles_req {
+                            trace!("Clearing request subtitles...");
+                            if let Err(e) = std::fs::write(subtitles_req, "") {
+                                error!("Failed to clear request subtitles: {:?}", e);
+                            }
+                        }
+
+                        std::thread::sleep(std::time::Duration::from_millis(750));
+                        if let Some(subtitles_ans) = &subtitles_ans {
+                            trace!("Clearing answer subtitles...");
+                            if let Err(e) = std::fs::write(subtitles_ans, "") {
+                                error!("Failed to clear answer subtitles: {:?}", e);
+                            }
+                        }
+                    },
+                )
+            })
         };
 
-        // write the line
-        if !text_to_tts.is_empty() {
-            let sub_text = if config.display_raw_resp {
-                let raw_text = res.get(&AIResponseType::RawAnswer).unwrap();
-                println!("< {} [{}]", text_to_tts, raw_text);
-                raw_text.clone()
-            } else {
-                println!("< {}", text_to_tts);
-                text_to_tts.clone()
-            };
-
-            if let Some(subtitles_ans) = &args.subtitles_ans {
-                debug!("Writing answer subtitles...");
-                if let Err(e) = std::fs::write(subtitles_ans, &sub_text) {
-                    error!("Failed to write answer subtitles: {:?}", e);
-                }
-            }
-        }
+        // write the answer as it arrives
+        let mut sub_text = String::new();
+        while let Some(chunk) = answer_stream.next().await {
+            match chunk {
+                Ok(AIResponseChunk::Sentence(sentence)) => {
+                    let sentence_text = display_text(&sentence);
+                    if let Some(avatar) = &avatar {
+                        avatar.react(&sentence);
+                    }
+                    if let Err(e) = sentences_tx.send(spoken_text(&sentence).to_string()).await {
+                        error!("Failed to send sentence to TTS: {:?}", e);
+                    }
 
-        // TTS
-        match tts.say(text_to_tts).await {
-            Ok(sound_data) => {
-                last_tts_data.replace(sound_data.clone());
-                say(&audio_out, sound_data, || {
-                    if let Some(subtitles_req) = &args.subtitles_req {
-                        trace!("Clearing request subtitles...");
-                        if let Err(e) = std::fs::write(subtitles_req, "") {
-                            error!("Failed to clear request subtitles: {:?}", e);
-                        }
+                    if sub_text.is_empty() {
+                        print!("<");
+                    }
+                    if display_raw_resp {
+                        let raw_text = sentence.get(&AIResponseType::RawAnswer).unwrap();
+                        print!(" {} [{}]", sentence_text, raw_text);
+                    } else {
+                        print!(" {}", sentence_text);
                     }
+                    std::io::stdout().flush().unwrap();
+
+                    if !sub_text.is_empty() {
+                        sub_text.push(' ');
+                    }
+                    sub_text.push_str(if display_raw_resp {
+                        sentence.get(&AIResponseType::RawAnswer).unwrap()
+                    } else {
+                        sentence_text
+                    });
 
-                    std::thread::sleep(std::time::Duration::from_millis(750));
                     if let Some(subtitles_ans) = &args.subtitles_ans {
-                        trace!("Clearing answer subtitles...");
-                        if let Err(e) = std::fs::write(subtitles_ans, "") {
-                            error!("Failed to clear answer subtitles: {:?}", e);
+                        debug!("Writing answer subtitles...");
+                        if let Err(e) = std::fs::write(subtitles_ans, &sub_text) {
+                            error!("Failed to write answer subtitles: {:?}", e);
                         }
                     }
-                });
+                }
+                Ok(AIResponseChunk::Done(_)) => { /* all sentences already received */ }
+                Ok(AIResponseChunk::Delta(_)) => { /* only full sentences are displayed */ }
+                Ok(AIResponseChunk::Queued(position)) => {
+                    info!("Request is queued, position: {}", position);
+                }
+                Err(e) => {
+                    error!("Error: {}", e);
+                    // the apology is spoken, the details are only in the log
+                    if sub_text.is_empty() {
+                        print!("<");
+                    } else {
+                        sub_text.push(' ');
+                    }
+                    print!(" {}", e.user_message());
+                    std::io::stdout().flush().unwrap();
+                    sub_text.push_str(e.user_message());
+                    if let Err(e) = sentences_tx.send(e.user_message().to_string()).await {
+                        error!("Failed to send apology to TTS: {:?}", e);
+                    }
+                    break;
+                }
+            }
+        }
+
+        if !sub_text.is_empty() {
+            println!();
+        }
+
+        // wait until the whole answer is spoken
+        drop(sentences_tx);
+        match playback.await {
+            Ok(played) => {
+                if !played.is_empty() {
+                    last_tts_data = played;
+                }
             }
-            Err(err) => {
-                error!("TTS error: {:?}", err);
+            Err(e) => {
+                error!("Playback error: {:?}", e);
             }
         }
+        if let Some(avatar) = &avatar {
+            avatar.calm_down();
+        }
     }
 }
@@ -1,15 +1,24 @@
 mod twitch_request;
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, io::Write, path::PathBuf, sync::Arc};
 
 use cpal::traits::{DeviceTrait, HostTrait};
 
 use clap::Parser;
 
 use ai_waifu::{
-    config::Config,
-    dispatcher::AIResponseType,
-    utils::{audio_dev::get_audio_device_by_name, say::say},
+    config::Platform,
+    config_loader::ConfigArgs,
+    config_reload::{follow_config, Live},
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType},
+    errors::ErrorInfo,
+    persona::{Personas, DEFAULT_PERSONA},
+    sanitizer::{display_text, spoken_text},
+    tts_engine::TTSEngine,
+    utils::{
+        audio_dev::get_audio_device_by_name, say::say_queue, tts_pipeline::spawn_tts_pipeline,
+    },
+    vtube_studio::Avatar,
 };
 
 #[allow(unused_imports)]
@@ -46,6 +55,9 @@ struct Cli {
     /// Response subtitles file
     #[clap(long)]
     subtitles_ans: Option<PathBuf>,
+
+    #[clap(flatten)]
+    config: ConfigArgs,
 }
 
 /// print all available devices
@@ -83,10 +95,11 @@ async fn main() {
         .with(fmt_layer)
         .init();
 
-    let config = Config::load();
-
     let args = Cli::parse();
 
+    let config = args.config.load_validated().await;
+    let live_config = args.config.watch(config.clone());
+
     let ht = cpal::default_host();
 
     if args.list_audio_devices {
@@ -113,9 +126,15 @@ async fn main() {
         error!("No audio output device found, only text output will be available!");
     }
 
-    let mut dispatcher = ai_waifu::create_ai_dispatcher(&config);
+    let dispatcher = ai_waifu::create_ai_dispatcher(&config, Platform::Twitch);
+    follow_config(dispatcher.clone(), live_config.clone());
+
+    let personas = Arc::new(Live::new(live_config.clone(), Personas::with_config));
 
-    let tts = ai_waifu::tts_engine::TTSEngine::with_config(&config.tts_config);
+    let avatar = config.vtube_studio.clone().map(Avatar::spawn);
+
+    // switched by the channel moderators, applies to all the users
+    let current_persona = Arc::new(std::sync::Mutex::new(DEFAULT_PERSONA.to_string()));
 
     let twitch_config = twitch_irc::ClientConfig::default();
     let (mut incoming_messages, client) = twitch_irc::TwitchIRCClient::<
@@ -126,13 +145,21 @@ async fn main() {
     let (message_channel_tx, mut message_channel_rx) =
         tokio::sync::mpsc::channel::<twitch_request::TwitchRequest>(2);
 
-    let (tts_channel_tx, mut tts_channel_rx) =
-        tokio::sync::mpsc::channel::<HashMap<AIResponseType, String>>(2);
+    // every answer is a stream of sentences, answers are spoken in the order of requests
+    let (tts_channel_tx, mut tts_channel_rx) = tokio::sync::mpsc::unbounded_channel::<(
+        String,
+        Arc<TTSEngine>,
+        tokio::sync::mpsc::Receiver<HashMap<AIResponseType, String>>,
+    )>();
 
     let channel = args.channel.unwrap();
+    let scope = twitch_request::TwitchRequest::twitch_scope(&channel);
 
     //-------------------------------------------------------------------------------
 
+    let irc_personas = personas.clone();
+    let irc_current_persona = current_persona.clone();
+    let irc_channel = channel.clone();
     let join_handle = tokio::spawn(async move {
         while let Some(message) = incoming_messages.recv().await {
             match message {
@@ -142,6 +169,24 @@ async fn main() {
                 | ServerMessage::RoomState(_) => {}
                 ServerMessage::Privmsg(m) => {
                     let text = m.message_text.trim();
+
+                    if let Some(persona) = text.strip_prefix("!persona ") {
+                        let is_moderator = m
+                            .badges
+                            .iter()
+                            .any(|b| b.name == "moderator" || b.name == "broadcaster");
+                        let persona = persona.trim();
+                        if !is_moderator {
+                            warn!("{} is not allowed to switch persona", m.sender.name);
+                        } else if !irc_personas.get().names().iter().any(|p| p == persona) {
+                            warn!("Unknown persona: {}", persona);
+                        } else {
+                            info!("Persona switched to {} by {}", persona, m.sender.name);
+                            *irc_current_persona.lock().unwrap() = persona.to_string();
+                        }
+                        continue;
+                    }
+
                     if text.contains("@")
                         | text.contains("http")
                         | text.contains("!")
@@ -158,6 +203,7 @@ async fn main() {
                     let request = twitch_request::TwitchRequest {
                         request: text.to_owned(),
                         username: m.sender.name,
+                        twitch_channel: irc_channel.clone(),
                     };
 
                     if let Err(_e) = message_channel_tx.try_send(request) {
@@ -169,46 +215,151 @@ async fn main() {
         }
     });
 
-    let subtitles_req = args.subtitles_req.clone();
+    // not reloaded, the same for all the answers
+    let display_raw_resp = config.for_scope(&scope).display_raw_resp;
     let processing_handle = tokio::spawn(async move {
+        use futures_util::StreamExt;
+
+        // answers to different users are generated in parallel, so the loop only starts them
         while let Some(request) = message_channel_rx.recv().await {
-            if let Some(subtitles_req) = &subtitles_req {
-                debug!("Writing request subtitles...");
-                if let Err(e) = std::fs::write(subtitles_req, &request.request) {
-                    error!("Failed to write request subtitles: {:?}", e);
-                }
+            let username = request.username.clone();
+            let request_text = request.request.clone();
+
+            let persona = current_persona.lock().unwrap().clone();
+            match dispatcher.set_persona(request.channel(), persona).await {
+                Ok(()) => {}
+                // the user's previous answer is still generated, the next request switches
+                Err(AIError::Busy) => debug!("Persona of {} is switched later", username),
+                Err(e) => error!("Failed to switch persona of {}: {:?}", username, e),
             }
-
-            match dispatcher.try_process_request(Box::new(request)).await {
-                Ok(res) => tts_channel_tx.send(res).await.unwrap(),
+            // the voice of the persona that actually answers
+            let persona = dispatcher.persona(&request.channel());
+
+            let mut answer_stream = match dispatcher
+                .try_process_request_streamed(Box::new(request))
+                .await
+            {
+                Ok(answer_stream) => answer_stream,
                 Err(e) => {
                     error!("Error: {:?}", e);
                     continue;
                 }
             };
+
+            let (sentences_tx, sentences_rx) = tokio::sync::mpsc::channel(16);
+            tts_channel_tx
+                .send((
+                    request_text,
+                    personas.get().tts(&scope, &persona),
+                    sentences_rx,
+                ))
+                .unwrap();
+
+            // write the answer as it arrives
+            tokio::spawn(async move {
+                while let Some(chunk) = answer_stream.next().await {
+                    match chunk {
+                        Ok(AIResponseChunk::Sentence(sentence)) => {
+                            let text = display_text(&sentence);
+                            if display_raw_resp {
+                                let raw_text = sentence.get(&AIResponseType::RawAnswer).unwrap();
+                                println!("< {}: {} [{}]", username, text, raw_text);
+                            } else {
+                                println!("< {}: {}", username, text);
+                            }
+                            std::io::stdout().flush().unwrap();
+
+                            if let Err(e) = sentences_tx.send(sentence).await {
+                                error!("Failed to send sentence to TTS: {:?}", e);
+                            }
+                        }
+                        Ok(AIResponseChunk::Done(_)) => { /* all sentences already received */ }
+                        Ok(AIResponseChunk::Delta(_)) => { /* only full sentences are displayed */ }
+                        Ok(AIResponseChunk::Queued(position)) => {
+                            info!("Request of {} is queued, position: {}", username, position);
+                        }
+                        Err(e) => {
+                            error!("Error: {}", e);
+                            // apologize to the stream, the details are only in the log
+                            let apology = HashMap::from([(
+                                AIResponseType::RawAnswer,
+                                e.user_message().to_string(),
+                            )]);
+                            println!("< {}: {}", username, e.user_message());
+                            if let Err(e) = sentences_tx.send(apology).await {
+                                error!("Failed to send apology to TTS: {:?}", e);
+                            }
+                            break;
+                        }
+                    }
+                }
+            });
         }
     });
 
     let subtitles_req = args.subtitles_req.clone();
     let subtitles_ans = args.subtitles_ans.clone();
     let tts_handle = tokio::spawn(async move {
-        while let Some(res) = tts_channel_rx.recv().await {
-            let text_to_tts = if let Some(translated_text) = res.get(&AIResponseType::Translated) {
-                translated_text
-            } else {
-                res.get(&AIResponseType::RawAnswer).unwrap()
+        while let Some((request_text, tts, mut sentences_rx)) = tts_channel_rx.recv().await {
+            // the request is shown while its answer is spoken
+            if let Some(subtitles_req) = &subtitles_req {
+                debug!("Writing request subtitles...");
+                if let Err(e) = std::fs::write(subtitles_req, &request_text) {
+                    error!("Failed to write request subtitles: {:?}", e);
+                }
+            }
+
+            // speak sentences as soon as they are synthesized
+            let (tts_sentences_tx, mut audio_rx) = spawn_tts_pipeline(tts);
+            let playback = {
+                let audio_out = audio_out.clone();
+                let subtitles_req = subtitles_req.clone();
+                let subtitles_ans = subtitles_ans.clone();
+                let avatar = avatar.clone();
+                tokio::task::spawn_blocking(move || {
+                    say_queue(
+                        &audio_out,
+                        std::iter::from_fn(|| audio_rx.blocking_recv()).inspect(|fragment| {
+                            if let Some(avatar) = &avatar {
+                                avatar.speak(fragment.get_ref());
+                            }
+                        }),
+                        || {
+                            if let Some(subtitles_req) = &subtitles_req {
+                                trace!("Clearing request subtitles...");
+                                if let Err(e) = std::fs::write(subtitles_req, "") {
+                                    error!("Failed to clear request subtitles: {:?}", e);
+                                }
+                            }
+
+                            std::thread::sleep(std::time::Duration::from_millis(750));
+                            if let Some(subtitles_ans) = &subtitles_ans {
+                                trace!("Clearing answer subtitles...");
+                                if let Err(e) = std::fs::write(subtitles_ans, "") {
+                                    error!("Failed to clear answer subtitles: {:?}", e);
+                                }
+                            }
+                        },
+                    )
+                })
             };
 
-            // write the line
-            if !text_to_tts.is_empty() {
-                let sub_text = if config.display_raw_resp {
-                    let raw_text = res.get(&AIResponseType::RawAnswer).unwrap();
-                    println!("< {} [{}]", text_to_tts, raw_text);
-                    raw_text.clone()
+            // the line is already printed by the processing task
+            let mut sub_text = String::new();
+            while let Some(sentence) = sentences_rx.recv().await {
+                let text_to_tts = spoken_text(&sentence);
+                if let Some(avatar) = &avatar {
+                    avatar.react(&sentence);
+                }
+
+                if !sub_text.is_empty() {
+                    sub_text.push(' ');
+                }
+                sub_text.push_str(if display_raw_resp {
+                    sentence.get(&AIResponseType::RawAnswer).unwrap()
                 } else {
-                    println!("< {}", text_to_tts);
-                    text_to_tts.clone()
-                };
+                    display_text(&sentence)
+                });
 
                 if let Some(subtitles_ans) = &subtitles_ans {
                     debug!("Writing answer subtitles...");
@@ -216,32 +367,20 @@ async fn main() {
                         error!("Failed to write answer subtitles: {:?}", e);
                     }
                 }
-            }
-
-            // TTS
-            match tts.say(text_to_tts).await {
-                Ok(sound_data) => {
-                    say(&audio_out, sound_data, || {
-                        if let Some(subtitles_req) = &subtitles_req {
-                            trace!("Clearing request subtitles...");
-                            if let Err(e) = std::fs::write(subtitles_req, "") {
-                                error!("Failed to clear request subtitles: {:?}", e);
-                            }
-                        }
 
-                        std::thread::sleep(std::time::Duration::from_millis(750));
-                        if let Some(subtitles_ans) = &subtitles_ans {
-                            trace!("Clearing answer subtitles...");
-                            if let Err(e) = std::fs::write(subtitles_ans, "") {
-                                error!("Failed to clear answer subtitles: {:?}", e);
-                            }
-                        }
-                    });
-                }
-                Err(err) => {
-                    error!("TTS error: {:?}", err);
+                if let Err(e) = tts_sentences_tx.send(text_to_tts.to_string()).await {
+                    error!("Failed to send sentence to TTS: {:?}", e);
                 }
             }
+
+            // wait until the whole answer is spoken
+            drop(tts_sentences_tx);
+            if let Err(e) = playback.await {
+                error!("Playback error: {:?}", e);
+            }
+            if let Some(avatar) = &avatar {
+                avatar.calm_down();
+            }
         }
     });
 
@@ -1,8 +1,16 @@
-use ai_waifu::dispatcher::AIRequest;
+use ai_waifu::{channel_overrides::ChannelScope, dispatcher::AIRequest};
 
 pub struct TwitchRequest {
     pub request: String,
     pub username: String,
+    pub twitch_channel: String,
+}
+
+impl TwitchRequest {
+    /// Overrides are selected by the Twitch channel name
+    pub fn twitch_scope(twitch_channel: &str) -> ChannelScope {
+        ChannelScope::new(twitch_channel).with_name(twitch_channel)
+    }
 }
 
 impl AIRequest for TwitchRequest {
@@ -17,6 +25,14 @@ impl AIRequest for TwitchRequest {
     fn lang(&self) -> String {
         "auto".to_string()
     }
+
+    fn user(&self) -> String {
+        self.username.clone()
+    }
+
+    fn scope(&self) -> ChannelScope {
+        Self::twitch_scope(&self.twitch_channel)
+    }
 }
 
 impl std::fmt::Display for TwitchRequest {
@@ -5,7 +5,8 @@ use rodio::DeviceTrait;
 use tracing::info;
 
 use ai_waifu::{
-    config::Config,
+    config_loader::ConfigArgs,
+    config_reload::Live,
     utils::audio_dev::get_audio_device_by_name,
     utils::audio_input::{get_voice_request, spawn_audio_input},
 };
@@ -28,6 +29,9 @@ struct Cli {
     /// Audio noise_gate, 0.0 - 1.0
     #[clap(short, long, default_value_t = 0.1)]
     noise_gate: f32,
+
+    #[clap(flatten)]
+    config: ConfigArgs,
 }
 
 /// print all available devices
@@ -55,10 +59,11 @@ async fn main() {
         .with(fmt_layer)
         .init();
 
-    let config = Config::load();
-
     let args = Cli::parse();
 
+    let config = args.config.load_validated().await;
+    let live_config = args.config.watch(config.clone());
+
     let ht = cpal::default_host();
 
     if args.list_audio_devices {
@@ -84,8 +89,12 @@ async fn main() {
             audio_req_tx,
             args.noise_gate,
             config.stt_config.voice2txt_url,
-            config.stt_config.minimal_audio_fragment_length,
-            config.stt_config.maximal_audio_fragment_length,
+            Live::new(live_config, |config| {
+                (
+                    config.stt_config.minimal_audio_fragment_length,
+                    config.stt_config.maximal_audio_fragment_length,
+                )
+            }),
             tokio::runtime::Handle::current(),
         )
         .expect("Failed to init audio input");
@@ -0,0 +1,337 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rand::Rng;
+use serde_json::{json, Value};
+
+use crate::{dispatcher::AIError, memory_store::MemoryStore, tools::Tool};
+
+/// Limits for a single dice roll, so the answer stays short
+const MAX_DICE_COUNT: u32 = 100;
+const MAX_DICE_SIDES: u32 = 1000;
+
+/// Limit of nested parentheses, unary minuses and functions, so the parser stack stays small
+const MAX_NESTING: usize = 100;
+
+fn string_arg<'a>(args: &'a Value, name: &str) -> Result<&'a str, AIError> {
+    args.get(name)
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| AIError::AnswerError(format!("missing argument \"{name}\"")))
+}
+
+/// Current local date and time
+pub struct TimeTool;
+
+#[async_trait]
+impl Tool for TimeTool {
+    fn name(&self) -> &str {
+        "get_current_time"
+    }
+
+    fn description(&self) -> &str {
+        "Get the current local date, time and day of the week"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({ "type": "object", "properties": {} })
+    }
+
+    async fn call(&self, _user: &str, _args: Value) -> Result<String, AIError> {
+        Ok(chrono::Local::now()
+            .format("%A, %Y-%m-%d %H:%M:%S %:z")
+            .to_string())
+    }
+}
+
+/// Parse dice notation like "d20", "3d6" or "2d10+4"
+pub fn parse_dice(notation: &str) -> Result<(u32, u32, i64), String> {
+    let notation = notation.trim().to_lowercase().replace(' ', "");
+    let (count, rest) = notation
+        .split_once('d')
+        .ok_or_else(|| format!("invalid dice notation: {notation}"))?;
+
+    let (sides, modifier) = match rest.find(['+', '-']) {
+        Some(pos) => (&rest[..pos], &rest[pos..]),
+        None => (rest, ""),
+    };
+
+    let count = if count.is_empty() {
+        1
+    } else {
+        count
+            .parse::<u32>()
+            .map_err(|_| format!("invalid dice count: {count}"))?
+    };
+    let sides = sides
+        .parse::<u32>()
+        .map_err(|_| format!("invalid dice sides: {sides}"))?;
+    let modifier = if modifier.is_empty() {
+        0
+    } else {
+        modifier
+            .trim_start_matches('+')
+            .parse::<i64>()
+            .map_err(|_| format!("invalid modifier: {modifier}"))?
+    };
+
+    if !(1..=MAX_DICE_COUNT).contains(&count) {
+        return Err(format!("dice count must be 1..={MAX_DICE_COUNT}"));
+    }
+    if !(2..=MAX_DICE_SIDES).contains(&sides) {
+        return Err(format!("dice sides must be 2..={MAX_DICE_SIDES}"));
+    }
+
+    Ok((count, sides, modifier))
+}
+
+/// Roll the dice, returns every roll and the total with the modifier
+pub fn roll_dice(notation: &str) -> Result<(Vec<u32>, i64), String> {
+    let (count, sides, modifier) = parse_dice(notation)?;
+    let mut rng = rand::thread_rng();
+    let rolls = (0..count)
+        .map(|_| rng.gen_range(1..=sides))
+        .collect::<Vec<_>>();
+    let total = rolls.iter().map(|r| *r as i64).sum::<i64>() + modifier;
+    Ok((rolls, total))
+}
+
+pub struct DiceTool;
+
+#[async_trait]
+impl Tool for DiceTool {
+    fn name(&self) -> &str {
+        "roll_dice"
+    }
+
+    fn description(&self) -> &str {
+        "Roll dice, use it for games and random choices"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "dice": {
+                    "type": "string",
+                    "description": "Dice in the standard notation, e.g. \"d20\", \"3d6\", \"2d10+4\""
+                }
+            },
+            "required": ["dice"]
+        })
+    }
+
+    async fn call(&self, _user: &str, args: Value) -> Result<String, AIError> {
+        let (rolls, total) = roll_dice(string_arg(&args, "dice")?).map_err(AIError::AnswerError)?;
+        Ok(json!({ "rolls": rolls, "total": total }).to_string())
+    }
+}
+
+/// Recursive descent parser of arithmetic expressions
+struct ExpressionParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    depth: usize,
+}
+
+impl<'a> ExpressionParser<'a> {
+    fn skip_spaces(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_spaces();
+        self.chars.peek().copied()
+    }
+
+    // a rule inside parentheses, a unary minus, an exponent or a function argument
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<f64, String>) -> Result<f64, String> {
+        if self.depth >= MAX_NESTING {
+            return Err("expression is nested too deeply".to_string());
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    // expression = term (("+" | "-") term)*
+    fn expression(&mut self) -> Result<f64, String> {
+        let mut value = self.term()?;
+        while let Some(op) = self.peek().filter(|c| matches!(c, '+' | '-')) {
+            self.chars.next();
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Ok(value)
+    }
+
+    // term = unary (("*" | "/" | "%") unary)*
+    fn term(&mut self) -> Result<f64, String> {
+        let mut value = self.unary()?;
+        while let Some(op) = self.peek().filter(|c| matches!(c, '*' | '/' | '%')) {
+            self.chars.next();
+            let rhs = self.unary()?;
+            value = match op {
+                '*' => value * rhs,
+                _ if rhs == 0.0 => return Err("division by zero".to_string()),
+                '/' => value / rhs,
+                _ => value % rhs,
+            };
+        }
+        Ok(value)
+    }
+
+    // unary = "-" unary | power
+    fn unary(&mut self) -> Result<f64, String> {
+        if self.peek() == Some('-') {
+            self.chars.next();
+            Ok(-self.nested(Self::unary)?)
+        } else {
+            self.power()
+        }
+    }
+
+    // power = primary ("^" unary)?
+    fn power(&mut self) -> Result<f64, String> {
+        let base = self.primary()?;
+        if self.peek() == Some('^') {
+            self.chars.next();
+            Ok(base.powf(self.nested(Self::unary)?))
+        } else {
+            Ok(base)
+        }
+    }
+
+    // primary = number | "(" expression ")" | function "(" expression ")"
+    fn primary(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.nested(Self::expression)?;
+                if self.peek() != Some(')') {
+                    return Err("missing closing parenthesis".to_string());
+                }
+                self.chars.next();
+                Ok(value)
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let mut number = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_ascii_digit() || *c == '.') {
+                    number.push(c);
+                }
+                number
+                    .parse::<f64>()
+                    .map_err(|_| format!("invalid number: {number}"))
+            }
+            Some(c) if c.is_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric()) {
+                    name.push(c);
+                }
+                let value = match name.as_str() {
+                    "pi" => return Ok(std::f64::consts::PI),
+                    "e" => return Ok(std::f64::consts::E),
+                    _ => self.nested(Self::primary)?,
+                };
+                match name.as_str() {
+                    "sqrt" => Ok(value.sqrt()),
+                    "abs" => Ok(value.abs()),
+                    "sin" => Ok(value.sin()),
+                    "cos" => Ok(value.cos()),
+                    "tan" => Ok(value.tan()),
+                    "ln" => Ok(value.ln()),
+                    "log" => Ok(value.log10()),
+                    "round" => Ok(value.round()),
+                    _ => Err(format!("unknown function: {name}")),
+                }
+            }
+            Some(c) => Err(format!("unexpected symbol: {c}")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// Evaluate an arithmetic expression: + - * / % ^, parentheses, pi, e,
+/// and the functions sqrt, abs, sin, cos, tan, ln, log, round
+pub fn evaluate(expression: &str) -> Result<f64, String> {
+    let mut parser = ExpressionParser {
+        chars: expression.chars().peekable(),
+        depth: 0,
+    };
+    let value = parser.expression()?;
+    match parser.peek() {
+        Some(c) => Err(format!("unexpected symbol: {c}")),
+        None if value.is_finite() => Ok(value),
+        None => Err("result is not a number".to_string()),
+    }
+}
+
+pub struct CalculatorTool;
+
+#[async_trait]
+impl Tool for CalculatorTool {
+    fn name(&self) -> &str {
+        "calculate"
+    }
+
+    fn description(&self) -> &str {
+        "Evaluate an arithmetic expression exactly, use it instead of calculating in mind"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "expression": {
+                    "type": "string",
+                    "description": "Expression with + - * / % ^, parentheses, pi, e, sqrt, abs, sin, cos, tan, ln, log, round"
+                }
+            },
+            "required": ["expression"]
+        })
+    }
+
+    async fn call(&self, _user: &str, args: Value) -> Result<String, AIError> {
+        let value = evaluate(string_arg(&args, "expression")?).map_err(AIError::AnswerError)?;
+        Ok(value.to_string())
+    }
+}
+
+/// Store a fact about the user in the long-term memory when asked to remember something
+pub struct RememberTool {
+    memory: Arc<MemoryStore>,
+}
+
+impl RememberTool {
+    pub fn new(memory: Arc<MemoryStore>) -> Self {
+        Self { memory }
+    }
+}
+
+#[async_trait]
+impl Tool for RememberTool {
+    fn name(&self) -> &str {
+        "remember"
+    }
+
+    fn description(&self) -> &str {
+        "Remember a fact about the user for future conversations, use it when the user asks to remember something"
+    }
+
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "fact": {
+                    "type": "string",
+                    "description": "Short sentence to remember, starting with the user name"
+                }
+            },
+            "required": ["fact"]
+        })
+    }
+
+    async fn call(&self, user: &str, args: Value) -> Result<String, AIError> {
+        self.memory.remember(user, string_arg(&args, "fact")?).await;
+        Ok("Remembered".to_string())
+    }
+}
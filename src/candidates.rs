@@ -0,0 +1,230 @@
+/// Selection of the best answer when the backend returns several candidates (`Reply_count` > 1)
+use regex::{Regex, RegexBuilder};
+use tracing::debug;
+
+use crate::{config::CandidateRuleConfig, memory_store::lexical_similarity};
+
+/// Scores a candidate answer from 0 (unusable) to 1 (perfect)
+pub trait CandidateRule: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// `recent` - previous answers of the AI, the latest last
+    fn score(&self, candidate: &str, recent: &[String]) -> f32;
+}
+
+/// Answers out of `min..=max` characters are penalized in proportion to the difference,
+/// e.g. long answers are tiresome to listen to
+pub struct LengthRule {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl CandidateRule for LengthRule {
+    fn name(&self) -> &str {
+        "length"
+    }
+
+    fn score(&self, candidate: &str, _recent: &[String]) -> f32 {
+        let len = candidate.trim().chars().count();
+        if len < self.min {
+            len as f32 / self.min as f32
+        } else if len > self.max {
+            self.max as f32 / len as f32
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Candidates with a banned word are unusable
+pub struct BannedWordsRule {
+    words: Vec<Regex>,
+}
+
+impl BannedWordsRule {
+    /// `words` - case-insensitive regexes
+    pub fn new(words: &[String]) -> Result<Self, regex::Error> {
+        let words = words
+            .iter()
+            .map(|w| RegexBuilder::new(w).case_insensitive(true).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { words })
+    }
+}
+
+impl CandidateRule for BannedWordsRule {
+    fn name(&self) -> &str {
+        "banned words"
+    }
+
+    fn score(&self, candidate: &str, _recent: &[String]) -> f32 {
+        if self.words.iter().any(|w| w.is_match(candidate)) {
+            0.0
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Writing system of the letters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Cyrillic,
+    Greek,
+    /// Kana and kanji
+    Japanese,
+    Han,
+    Hangul,
+    Arabic,
+    Other,
+}
+
+impl Script {
+    pub fn of(c: char) -> Self {
+        match c {
+            'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' => Script::Latin,
+            '\u{0400}'..='\u{04FF}' => Script::Cyrillic,
+            '\u{0370}'..='\u{03FF}' => Script::Greek,
+            '\u{3040}'..='\u{30FF}' => Script::Japanese,
+            '\u{4E00}'..='\u{9FFF}' => Script::Han,
+            '\u{AC00}'..='\u{D7AF}' => Script::Hangul,
+            '\u{0600}'..='\u{06FF}' => Script::Arabic,
+            _ => Script::Other,
+        }
+    }
+
+    /// Script of the language code, e.g. "ru" or "en-US"
+    pub fn of_lang(lang: &str) -> Self {
+        let lang = lang.split(['-', '_']).next().unwrap_or_default();
+        match lang.to_lowercase().as_str() {
+            "ru" | "uk" | "be" | "bg" | "sr" | "mk" | "kk" => Script::Cyrillic,
+            "el" => Script::Greek,
+            "ja" => Script::Japanese,
+            "zh" => Script::Han,
+            "ko" => Script::Hangul,
+            "ar" | "fa" => Script::Arabic,
+            _ => Script::Latin,
+        }
+    }
+
+    fn matches(self, letter: Script) -> bool {
+        // japanese text is full of kanji
+        self == letter || (self == Script::Japanese && letter == Script::Han)
+    }
+}
+
+/// Share of the letters written in the script of the expected language,
+/// models tend to switch the language in the middle of the answer
+pub struct LanguageRule {
+    pub script: Script,
+}
+
+impl LanguageRule {
+    pub fn new(lang: &str) -> Self {
+        Self {
+            script: Script::of_lang(lang),
+        }
+    }
+}
+
+impl CandidateRule for LanguageRule {
+    fn name(&self) -> &str {
+        "language"
+    }
+
+    fn score(&self, candidate: &str, _recent: &[String]) -> f32 {
+        let letters = candidate
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .map(Script::of)
+            .collect::<Vec<_>>();
+        if letters.is_empty() {
+            return 1.0;
+        }
+        let matching = letters.iter().filter(|s| self.script.matches(**s)).count();
+        matching as f32 / letters.len() as f32
+    }
+}
+
+/// Candidates similar to the last `window` answers are penalized
+pub struct RepetitionRule {
+    pub window: usize,
+}
+
+impl CandidateRule for RepetitionRule {
+    fn name(&self) -> &str {
+        "repetition"
+    }
+
+    fn score(&self, candidate: &str, recent: &[String]) -> f32 {
+        let similarity = recent
+            .iter()
+            .rev()
+            .take(self.window)
+            .map(|answer| lexical_similarity(candidate, answer))
+            .fold(0.0, f32::max);
+        1.0 - similarity
+    }
+}
+
+/// Weighted sum of the rule scores, the candidate with the highest one wins
+#[derive(Default)]
+pub struct CandidateSelector {
+    rules: Vec<(Box<dyn CandidateRule>, f32)>,
+}
+
+impl CandidateSelector {
+    pub fn new(config: &[CandidateRuleConfig]) -> Result<Self, regex::Error> {
+        let mut selector = Self::default();
+        for rule in config {
+            selector = match rule {
+                CandidateRuleConfig::Length { min, max, weight } => selector.with_rule(
+                    Box::new(LengthRule {
+                        min: *min,
+                        max: *max,
+                    }),
+                    *weight,
+                ),
+                CandidateRuleConfig::BannedWords { words, weight } => {
+                    selector.with_rule(Box::new(BannedWordsRule::new(words)?), *weight)
+                }
+                CandidateRuleConfig::Language { lang, weight } => {
+                    selector.with_rule(Box::new(LanguageRule::new(lang)), *weight)
+                }
+                CandidateRuleConfig::Repetition { window, weight } => {
+                    selector.with_rule(Box::new(RepetitionRule { window: *window }), *weight)
+                }
+            };
+        }
+        Ok(selector)
+    }
+
+    pub fn with_rule(mut self, rule: Box<dyn CandidateRule>, weight: f32) -> Self {
+        self.rules.push((rule, weight));
+        self
+    }
+
+    pub fn score(&self, candidate: &str, recent: &[String]) -> f32 {
+        self.rules
+            .iter()
+            .map(|(rule, weight)| {
+                let score = rule.score(candidate, recent);
+                debug!("{} score of {:?}: {}", rule.name(), candidate, score);
+                score * weight
+            })
+            .sum()
+    }
+
+    /// Index of the best candidate, the first one of the equal ones, `None` if there are no candidates
+    pub fn select(&self, candidates: &[String], recent: &[String]) -> Option<usize> {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, candidate) in candidates.iter().enumerate() {
+            let score = self.score(candidate, recent);
+            if best.map_or(true, |(_, best_score)| score > best_score) {
+                best = Some((i, score));
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+}
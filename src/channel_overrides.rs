@@ -0,0 +1,164 @@
+/// Per guild and channel settings
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use regex::Regex;
+use serde::{Deserialize, Deserializer};
+
+use crate::config::{Config, OverrideConfig};
+
+/// Where the request came from, selects the overrides
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct ChannelScope {
+    /// Discord guild ID
+    pub guild: Option<String>,
+    /// Discord channel ID, Twitch channel name
+    pub channel: String,
+    /// Human readable channel name
+    pub name: Option<String>,
+}
+
+impl ChannelScope {
+    pub fn new(channel: impl Into<String>) -> Self {
+        Self {
+            channel: channel.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn with_guild(mut self, guild: impl Into<String>) -> Self {
+        self.guild = Some(guild.into());
+        self
+    }
+
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+}
+
+/// Channel name regex, compiled when the config is loaded.
+/// An invalid one is kept to be reported by the validation and matches nothing
+#[derive(Clone, Debug)]
+pub struct NamePattern {
+    pattern: String,
+    regex: Result<Regex, regex::Error>,
+}
+
+impl NamePattern {
+    pub fn new(pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        Self {
+            regex: Regex::new(&pattern),
+            pattern,
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn regex(&self) -> Result<&Regex, &regex::Error> {
+        self.regex.as_ref()
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        self.regex.as_ref().is_ok_and(|re| re.is_match(name))
+    }
+}
+
+impl<'de> Deserialize<'de> for NamePattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Self::new)
+    }
+}
+
+impl OverrideConfig {
+    /// All the set selectors match, an override without selectors matches nothing
+    pub fn matches(&self, scope: &ChannelScope) -> bool {
+        if self.guild.is_none() && self.channel.is_none() && self.name.is_none() {
+            return false;
+        }
+
+        let guild = match &self.guild {
+            Some(guild) => scope.guild.as_ref() == Some(guild),
+            None => true,
+        };
+        let channel = match &self.channel {
+            Some(channel) => scope.channel == *channel,
+            None => true,
+        };
+        let name = match (&self.name, &scope.name) {
+            (Some(pattern), Some(name)) => pattern.is_match(name),
+            (Some(_), None) => false,
+            (None, _) => true,
+        };
+        guild && channel && name
+    }
+}
+
+impl Config {
+    /// Indices of the overrides matching the scope
+    pub fn matching_overrides(&self, scope: &ChannelScope) -> Vec<usize> {
+        self.overrides
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| o.matches(scope))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Config with the matching overrides applied in order
+    pub fn for_scope(&self, scope: &ChannelScope) -> Config {
+        let mut config = self.clone();
+        for i in self.matching_overrides(scope) {
+            let o = &self.overrides[i];
+            if let Some(initial_prompt) = &o.initial_prompt {
+                config.initial_prompt = initial_prompt.clone();
+            }
+            if let Some(answer_lang) = &o.answer_lang {
+                config.deeplx_translate_config.dest_lang = answer_lang.clone();
+            }
+            if let Some(tts_config) = &o.tts_config {
+                config.tts_config = tts_config.clone();
+            }
+            if let Some(display_raw_resp) = o.display_raw_resp {
+                config.display_raw_resp = display_raw_resp;
+            }
+        }
+        config
+    }
+}
+
+/// Configs of the scopes, resolved once per set of the matching overrides
+pub struct ScopedConfigs {
+    config: Config,
+    resolved: Mutex<HashMap<Vec<usize>, Arc<Config>>>,
+}
+
+impl ScopedConfigs {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config,
+            resolved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Config without the overrides
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Same as `Config::for_scope`, the scopes with the same overrides share the config
+    pub fn get(&self, scope: &ChannelScope) -> Arc<Config> {
+        let key = self.config.matching_overrides(scope);
+        self.resolved
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| Arc::new(self.config.for_scope(scope)))
+            .clone()
+    }
+}
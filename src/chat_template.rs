@@ -0,0 +1,126 @@
+/// Raw prompts for the models without a chat endpoint
+use serde::{Deserialize, Serialize};
+
+use crate::config::ChatTemplate;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ChatRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// One message of the chat history
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct ChatTurn {
+    pub role: ChatRole,
+    pub content: String,
+}
+
+impl ChatTurn {
+    pub fn new(role: ChatRole, content: impl Into<String>) -> Self {
+        Self {
+            role,
+            content: content.into(),
+        }
+    }
+}
+
+impl ChatTemplate {
+    /// Prompt ending with the start of the assistant answer
+    pub fn render(&self, history: &[ChatTurn]) -> String {
+        // the system messages are merged, the templates have one place for them
+        let system = history
+            .iter()
+            .filter(|t| t.role == ChatRole::System)
+            .map(|t| t.content.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let turns = history.iter().filter(|t| t.role != ChatRole::System);
+
+        let mut prompt = String::new();
+        match self {
+            ChatTemplate::ChatML => {
+                if !system.is_empty() {
+                    prompt.push_str(&format!("<|im_start|>system\n{system}<|im_end|>\n"));
+                }
+                for turn in turns {
+                    let role = match turn.role {
+                        ChatRole::Assistant => "assistant",
+                        _ => "user",
+                    };
+                    prompt.push_str(&format!("<|im_start|>{role}\n{}<|im_end|>\n", turn.content));
+                }
+                prompt.push_str("<|im_start|>assistant\n");
+            }
+            ChatTemplate::Llama2 => {
+                let mut system = if system.is_empty() {
+                    String::new()
+                } else {
+                    format!("<<SYS>>\n{system}\n<</SYS>>\n\n")
+                };
+                for turn in turns {
+                    match turn.role {
+                        ChatRole::Assistant => prompt.push_str(&format!(" {} </s>", turn.content)),
+                        _ => {
+                            // the system prompt is a part of the first instruction
+                            let system = std::mem::take(&mut system);
+                            prompt.push_str(&format!("<s>[INST] {system}{} [/INST]", turn.content));
+                        }
+                    }
+                }
+            }
+            ChatTemplate::Alpaca => {
+                if !system.is_empty() {
+                    prompt.push_str(&format!("{system}\n\n"));
+                }
+                for turn in turns {
+                    match turn.role {
+                        ChatRole::Assistant => prompt.push_str(&format!("{}\n\n", turn.content)),
+                        _ => prompt.push_str(&format!(
+                            "### Instruction:\n{}\n\n### Response:\n",
+                            turn.content
+                        )),
+                    }
+                }
+            }
+            ChatTemplate::Vicuna => {
+                if !system.is_empty() {
+                    prompt.push_str(&format!("{system}\n\n"));
+                }
+                for turn in turns {
+                    match turn.role {
+                        ChatRole::Assistant => prompt.push_str(&format!(" {}</s>\n", turn.content)),
+                        _ => prompt.push_str(&format!("USER: {}\nASSISTANT:", turn.content)),
+                    }
+                }
+            }
+        }
+        prompt
+    }
+
+    /// Sequences the model starts the next turn with, the answer ends before them
+    pub fn stop_sequences(&self) -> &'static [&'static str] {
+        match self {
+            ChatTemplate::ChatML => &["<|im_end|>", "<|im_start|>"],
+            ChatTemplate::Llama2 => &["</s>", "[INST]"],
+            ChatTemplate::Alpaca => &["### Instruction:", "### Response:"],
+            ChatTemplate::Vicuna => &["</s>", "USER:"],
+        }
+    }
+}
+
+/// Position of the first stop sequence in the answer
+pub fn stop_position(answer: &str, stop: &[String]) -> Option<usize> {
+    stop.iter()
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| answer.find(s.as_str()))
+        .min()
+}
+
+/// Answer up to the first stop sequence, in case the server did not stop on it
+pub fn cut_at_stop(answer: &str, stop: &[String]) -> String {
+    let end = stop_position(answer, stop).unwrap_or(answer.len());
+    answer[..end].trim().to_string()
+}
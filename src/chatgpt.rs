@@ -1,33 +1,338 @@
-use std::{collections::HashMap, io::Write, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
 
 use async_trait::async_trait;
 
 use chatgpt::{
     prelude::{ChatGPT as ChatGPTClient, Conversation, ModelConfiguration},
-    types::ChatMessage,
+    types::{ChatMessage, Role},
 };
 use futures_util::StreamExt;
 use maplit::hashmap;
+use reqwest::StatusCode;
+use tokio::sync::mpsc::Sender;
 
-use tracing::error;
+use tracing::{error, info};
 
-use crate::dispatcher::{AIError, AIRequest, AIResponseType, AIinterface, AIinterfaceStreamed};
+use crate::{
+    candidates::CandidateSelector,
+    chat_template::{ChatRole, ChatTurn},
+    config::ContextBudgetConfig,
+    context_budget::{estimate_tokens, ContextBudget, SUMMARY_PREFIX},
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    memory_store::{parse_facts, FactExtractor, FACT_EXTRACTION_PROMPT},
+    openai_tool_calls::OpenAIToolCalls,
+    resilience::{self, Failure, Service},
+    sentence_splitter::SentenceSplitter,
+    tools::ToolRegistry,
+    usage::TokenUsage,
+};
 
 pub struct ChatGPT {
-    _client: ChatGPTClient,
+    client: ChatGPTClient,
     conversation: Conversation,
+    context_budget: Option<ContextBudget>,
+    api_key: String,
+    config: ModelConfiguration,
+    tool_calls: Option<OpenAIToolCalls>,
+    service: Service,
+    last_usage: Option<TokenUsage>,
+    /// Summary made for the last request, it is paid for with the answer
+    summary_usage: Option<TokenUsage>,
+    candidates: Option<Arc<CandidateSelector>>,
+}
+
+/// Network errors, rate limits and server errors are retried
+fn failure(e: chatgpt::err::Error) -> Failure {
+    if let chatgpt::err::Error::ClientError(e) = e {
+        return Failure::from_reqwest(e);
+    }
+
+    let message = format!("ChatGPT error: {:?}", e);
+    let failure = match &e {
+        chatgpt::err::Error::BackendError { error_type, .. }
+            if error_type.contains("rate_limit") =>
+        {
+            Failure::from_status(StatusCode::TOO_MANY_REQUESTS, message)
+        }
+        chatgpt::err::Error::BackendError { error_type, .. } if error_type == "server_error" => {
+            Failure::transient(message)
+        }
+        _ => Failure::permanent(message),
+    };
+    failure.with_source(e)
 }
 
 impl ChatGPT {
     pub fn new<S: Into<String>>(api_key: S, config: ModelConfiguration, prompt: S) -> Self {
-        let client = match ChatGPTClient::new_with_config(api_key, config) {
+        let api_key = api_key.into();
+        let client = match ChatGPTClient::new_with_config(api_key.clone(), config.clone()) {
             Ok(c) => c,
             Err(e) => panic!("Failed to create ChatGPT client: {e:?}"),
         };
 
         Self {
             conversation: client.new_conversation_directed(prompt),
-            _client: client,
+            client,
+            context_budget: None,
+            api_key,
+            config,
+            tool_calls: None,
+            service: Service::get(resilience::OPENAI),
+            last_usage: None,
+            summary_usage: None,
+            candidates: None,
+        }
+    }
+
+    /// Timeouts, retries and the circuit breaker of the server, OpenAI by default
+    pub fn with_service(mut self, service: Service) -> Self {
+        self.tool_calls = self
+            .tool_calls
+            .map(|tool_calls| tool_calls.with_service(service.clone()));
+        self.service = service;
+        self
+    }
+
+    /// History with the request, the request is added to the conversation after the answer.
+    /// The context of the request is sent only this time
+    fn with_request(&self, request: String, context: Option<String>) -> Vec<ChatMessage> {
+        let mut history = self.conversation.history.clone();
+        if let Some(context) = context {
+            history.push(ChatMessage {
+                role: Role::System,
+                content: context,
+            });
+        }
+        history.push(ChatMessage {
+            role: Role::User,
+            content: request,
+        });
+        history
+    }
+
+    /// Let the model call the tools before answering
+    pub fn with_tools(mut self, tools: Arc<ToolRegistry>) -> Self {
+        if !tools.is_empty() {
+            self.tool_calls = Some(OpenAIToolCalls::new(
+                self.api_key.clone(),
+                &self.config,
+                tools,
+                self.service.clone(),
+            ));
+        }
+        self
+    }
+
+    /// Pick the best of the `Reply_count` replies instead of the first one.
+    /// Such answers are not streamed by tokens, only by sentences
+    pub fn with_candidates(mut self, candidates: Arc<CandidateSelector>) -> Self {
+        self.candidates = Some(candidates);
+        self
+    }
+
+    fn selects_candidates(&self) -> bool {
+        self.candidates.is_some() && self.config.reply_count > 1
+    }
+
+    /// Best of the replies, the first one without the selector
+    fn best_reply(&self, replies: Vec<String>) -> Result<String, AIError> {
+        let best = match &self.candidates {
+            Some(candidates) if replies.len() > 1 => {
+                let recent = self
+                    .conversation
+                    .history
+                    .iter()
+                    .filter(|m| m.role == Role::Assistant)
+                    .map(|m| m.content.clone())
+                    .collect::<Vec<_>>();
+                candidates.select(&replies, &recent).unwrap_or(0)
+            }
+            _ => 0,
+        };
+        replies.into_iter().nth(best).ok_or(AIError::UnknownError)
+    }
+
+    fn push_turn(&mut self, request: String, answer: String) {
+        self.conversation.history.push(ChatMessage {
+            role: Role::User,
+            content: request,
+        });
+        self.conversation.history.push(ChatMessage {
+            role: Role::Assistant,
+            content: answer,
+        });
+    }
+
+    /// Whole answer, after the tool calls if there are tools.
+    /// The request and the chosen answer are added to the history
+    async fn answer(
+        &mut self,
+        request: String,
+        context: Option<String>,
+        user: &str,
+    ) -> Result<String, AIError> {
+        let history = self.with_request(request.clone(), context);
+        let (replies, usage) = match &self.tool_calls {
+            Some(tool_calls) => {
+                let answer = tool_calls
+                    .answer(&history, user, self.config.reply_count)
+                    .await?;
+                (answer.replies, answer.usage)
+            }
+            None => {
+                let client = &self.client;
+                let resp = self
+                    .service
+                    .call(|| async { client.send_history(&history).await.map_err(failure) })
+                    .await
+                    .map_err(|e| self.service.error(e))?;
+
+                let replies = resp
+                    .message_choices
+                    .iter()
+                    .map(|choice| choice.message.content.clone())
+                    .collect::<Vec<_>>();
+                // all the replies are paid for
+                let usage = TokenUsage::new(
+                    resp.usage.prompt_tokens.into(),
+                    resp.usage.completion_tokens.into(),
+                );
+                (replies, Some(usage))
+            }
+        };
+
+        let answer = self.best_reply(replies)?;
+        self.push_turn(request, answer.clone());
+        self.last_usage = usage.or_else(|| self.estimate_last_usage());
+        Ok(answer)
+    }
+
+    /// Send the ready answer to the listener at once and by sentences
+    async fn send_whole(answer: &str, chunks: &Sender<AIResponseChunk>) {
+        let _ = chunks
+            .send(AIResponseChunk::Delta(answer.to_string()))
+            .await;
+
+        let mut splitter = SentenceSplitter::new();
+        for sentence in splitter.push(answer).into_iter().chain(splitter.finish()) {
+            let _ = chunks
+                .send(AIResponseChunk::Sentence(hashmap! {
+                    AIResponseType::RawAnswer => sentence,
+                }))
+                .await;
+        }
+    }
+
+    /// Usage of the last turn of the history, for the answers without the reported usage
+    fn estimate_last_usage(&self) -> Option<TokenUsage> {
+        let (answer, prompt) = self.conversation.history.split_last()?;
+        Some(TokenUsage::estimate(
+            prompt.iter().map(|m| m.content.as_str()),
+            &answer.content,
+        ))
+    }
+
+    /// Limit the history size, older turns are folded into a summary
+    pub fn with_context_budget(mut self, config: ContextBudgetConfig) -> Self {
+        self.context_budget = Some(ContextBudget::new(config));
+        self
+    }
+
+    /// Fact extractor using the same backend
+    pub fn fact_extractor(&self) -> ChatGPTFactExtractor {
+        ChatGPTFactExtractor {
+            client: self.client.clone(),
+            service: self.service.clone(),
+        }
+    }
+
+    fn is_summary(message: &ChatMessage) -> bool {
+        message.role == Role::System && message.content.starts_with(SUMMARY_PREFIX)
+    }
+
+    /// Make room for the request in the context window:
+    /// the system prompt and the recent turns are kept, older turns are summarized by the model
+    async fn fit_context(&mut self, request: &str) {
+        self.summary_usage = None;
+        let budget = if let Some(budget) = &self.context_budget {
+            budget
+        } else {
+            return;
+        };
+
+        let history = &mut self.conversation.history;
+        let has_summary = history.get(1).map_or(false, Self::is_summary);
+        let start = (1 + has_summary as usize).min(history.len());
+
+        let pinned_tokens = history[..start]
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .sum::<usize>()
+            + estimate_tokens(request);
+        let sizes = history[start..]
+            .iter()
+            .map(|m| estimate_tokens(&m.content))
+            .collect::<Vec<_>>();
+
+        let fold = budget.messages_to_fold(pinned_tokens, &sizes);
+        if fold == 0 {
+            return;
+        }
+        info!("Context budget exceeded, summarizing {fold} messages");
+
+        let mut transcript = vec![];
+        if has_summary {
+            transcript.push(history[1].content[SUMMARY_PREFIX.len()..].to_string());
+        }
+        for m in history.drain(start..start + fold) {
+            let speaker = if m.role == Role::User {
+                "User"
+            } else if m.role == Role::Assistant {
+                "Assistant"
+            } else {
+                "System"
+            };
+            transcript.push(format!("{}: {}", speaker, m.content));
+        }
+
+        let summary_request = vec![
+            ChatMessage {
+                role: Role::System,
+                content: budget.summary_prompt().to_string(),
+            },
+            ChatMessage {
+                role: Role::User,
+                content: transcript.join("\n"),
+            },
+        ];
+
+        // if summarization fails, the old turns are just forgotten, so the request still fits
+        let client = &self.client;
+        let summary = self
+            .service
+            .call(|| async { client.send_history(&summary_request).await.map_err(failure) })
+            .await;
+        match summary {
+            Ok(resp) => {
+                self.summary_usage = Some(TokenUsage::new(
+                    resp.usage.prompt_tokens.into(),
+                    resp.usage.completion_tokens.into(),
+                ));
+                let summary = ChatMessage {
+                    role: Role::System,
+                    content: format!("{}{}", SUMMARY_PREFIX, resp.message().content),
+                };
+                let history = &mut self.conversation.history;
+                if has_summary {
+                    history[1] = summary;
+                } else {
+                    history.insert(1.min(history.len()), summary);
+                }
+            }
+            Err(e) => error!(
+                "Failed to summarize the conversation: {}",
+                self.service.error(e)
+            ),
         }
     }
 }
@@ -39,17 +344,103 @@ impl AIinterface for ChatGPT {
         _request: Box<dyn AIRequest>,
     ) -> Result<HashMap<AIResponseType, String>, AIError> {
         let request = _request.request();
+        self.fit_context(&request).await;
 
-        self.conversation
-            .send_message(request)
+        let answer = self
+            .answer(request, _request.context(), &_request.user())
+            .await?;
+        Ok(hashmap! {
+            AIResponseType::RawAnswer => answer,
+        })
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let (user, context) = (request.user(), request.context());
+        let request = request.request();
+        self.fit_context(&request).await;
+
+        // the winner is known only when all the replies are complete
+        if self.selects_candidates() {
+            let answer = self.answer(request, context, &user).await?;
+            Self::send_whole(&answer, &chunks).await;
+            return Ok(hashmap! {
+                AIResponseType::RawAnswer => answer,
+            });
+        }
+
+        let history = self.with_request(request.clone(), context);
+        if let Some(tool_calls) = &self.tool_calls {
+            let answer = tool_calls.answer_streamed(&history, &user, &chunks).await?;
+            self.push_turn(request, answer.clone());
+            // the stream does not report the usage
+            self.last_usage = self.estimate_last_usage();
+            return Ok(hashmap! {
+                AIResponseType::RawAnswer => answer,
+            });
+        }
+
+        // only the request is retried, the answer is streamed once
+        let client = &self.client;
+        let mut r = self
+            .service
+            .call(|| async {
+                client
+                    .send_history_streaming(&history)
+                    .await
+                    .map_err(failure)
+            })
             .await
-            .map_err(|e| AIError::AnswerError(format!("ChatGPT error: {:?}", e)))?;
+            .map_err(|e| self.service.error(e))?;
+
+        let mut splitter = SentenceSplitter::new();
+        let mut response_chunks = vec![];
+        while let Some(chunk) = r.next().await {
+            // only the first reply is streamed to the listener
+            if let chatgpt::types::ResponseChunk::Content {
+                delta,
+                response_index: 0,
+            } = &chunk
+            {
+                let _ = chunks.send(AIResponseChunk::Delta(delta.clone())).await;
+                for sentence in splitter.push(delta) {
+                    let _ = chunks
+                        .send(AIResponseChunk::Sentence(hashmap! {
+                            AIResponseType::RawAnswer => sentence,
+                        }))
+                        .await;
+                }
+            }
+            // We don't really care about other types, other than parsing them into a ChatMessage later
+            response_chunks.push(chunk);
+        }
+
+        if let Some(sentence) = splitter.finish() {
+            let _ = chunks
+                .send(AIResponseChunk::Sentence(hashmap! {
+                    AIResponseType::RawAnswer => sentence,
+                }))
+                .await;
+        }
 
-        match self.conversation.history.last() {
+        match ChatMessage::from_response_chunks(response_chunks)
+            .into_iter()
+            .next()
+        {
             Some(m) => {
                 let res = hashmap! {
                     AIResponseType::RawAnswer => m.content.clone(),
                 };
+                self.conversation.history.push(ChatMessage {
+                    role: Role::User,
+                    content: request,
+                });
+                self.conversation.history.push(m);
+                // the stream does not report the usage
+                self.last_usage = self.estimate_last_usage();
                 Ok(res)
             }
             None => Err(AIError::UnknownError),
@@ -67,6 +458,21 @@ impl AIinterface for ChatGPT {
         }
     }
 
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        let history = &mut self.conversation.history;
+        let n = history.len();
+        if n >= 3 && history[n - 1].role == Role::Assistant && history[n - 2].role == Role::User {
+            history.truncate(n - 2);
+            Ok(())
+        } else {
+            Err(AIError::ContextError)
+        }
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        TokenUsage::combine(self.last_usage, self.summary_usage)
+    }
+
     async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
         self.conversation
             .save_history_json(file)
@@ -86,90 +492,71 @@ impl AIinterface for ChatGPT {
         .map_err(|_| AIError::ContextError)?;
         Ok(())
     }
-}
 
-#[async_trait]
-impl AIinterfaceStreamed for ChatGPT {
-    async fn process_streamed(&mut self, request: Box<dyn AIRequest>) -> Result<(), AIError> {
-        let request = request.request();
+    fn history(&self) -> Option<Vec<ChatTurn>> {
+        let turns = self.conversation.history.iter().map(|m| {
+            let role = if m.role == Role::System {
+                ChatRole::System
+            } else if m.role == Role::Assistant {
+                ChatRole::Assistant
+            } else {
+                ChatRole::User
+            };
+            ChatTurn::new(role, m.content.clone())
+        });
+        Some(turns.collect())
+    }
 
-        let mut r = self
-            .conversation
-            .send_message_streaming(request)
-            .await
-            .map_err(|e| AIError::AnswerError(format!("ChatGPT error: {:?}", e)))?;
-
-        /*
-        let chanks = r
-            .map(|chank| {
-                match &chank {
-                    chatgpt::types::ResponseChunk::Content { delta, .. } => {
-                        println!("Token: {:?}", delta);
-                    }
-                    _ => {}
-                };
-                chank
+    fn set_history(&mut self, history: Vec<ChatTurn>) {
+        self.conversation.history = history
+            .into_iter()
+            .map(|t| ChatMessage {
+                role: match t.role {
+                    ChatRole::System => Role::System,
+                    ChatRole::User => Role::User,
+                    ChatRole::Assistant => Role::Assistant,
+                },
+                content: t.content,
             })
-            .collect::<Vec<_>>().await;
-        */
-        
-        let mut chanks = vec![];
-        while let Some(chunk) = r.next().await{
-            match chunk {
-                chatgpt::types::ResponseChunk::Content {
-                    delta,
-                    response_index,
-                } => {
-                    // Printing part of response without the newline
-                    print!("{delta}");
-                    // Manually flushing the standard output, as `print` macro does not do that
-                    std::io::stdout().lock().flush().unwrap();
-                    chanks.push(chatgpt::types::ResponseChunk::Content {
-                        delta,
-                        response_index,
-                    });
-                }
-                // We don't really care about other types, other than parsing them into a ChatMessage later
-                other => chanks.push(other),
-            }
-        }
-
-        let messages = ChatMessage::from_response_chunks(chanks);
-        for m in messages {
-            println!("{:?}: {}", m.role, m.content);
-            self.conversation.history.push(m);
-        }
-        Ok(())
+            .collect();
     }
+}
 
-    async fn reset(&mut self) -> Result<(), AIError> {
-        if self.conversation.history.len() == 0 {
-            Err(AIError::ResetErrorEmpty)
-        } else {
-            let first_message = self.conversation.history.remove(0);
-            self.conversation.history.clear();
-            self.conversation.history.push(first_message);
-            Ok(())
-        }
-    }
+pub struct ChatGPTFactExtractor {
+    client: ChatGPTClient,
+    service: Service,
+}
 
-    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
-        self.conversation
-            .save_history_json(file)
-            .await
-            .map_err(|_| AIError::ContextError)
-    }
+#[async_trait]
+impl FactExtractor for ChatGPTFactExtractor {
+    async fn extract(
+        &self,
+        user: &str,
+        request: &str,
+        answer: &str,
+    ) -> Result<(Vec<String>, TokenUsage), AIError> {
+        let history = vec![
+            ChatMessage {
+                role: Role::System,
+                content: FACT_EXTRACTION_PROMPT.to_string(),
+            },
+            ChatMessage {
+                role: Role::User,
+                content: format!("{user}: {request}\nAssistant: {answer}"),
+            },
+        ];
 
-    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
-        if !file.exists() {
-            error!("File \"{file:?}\" not exists");
-            return Err(AIError::ContextError);
-        }
+        let client = &self.client;
+        let resp = self
+            .service
+            .call(|| async { client.send_history(&history).await.map_err(failure) })
+            .await
+            .map_err(|e| self.service.error(e))?;
 
-        self.conversation.history = serde_json::from_reader::<_, Vec<ChatMessage>>(
-            std::fs::File::open(file).map_err(|_| AIError::ContextError)?,
-        )
-        .map_err(|_| AIError::ContextError)?;
-        Ok(())
+        let usage = TokenUsage::new(
+            resp.usage.prompt_tokens.into(),
+            resp.usage.completion_tokens.into(),
+        );
+        Ok((parse_facts(&resp.message().content), usage))
     }
 }
@@ -1,8 +1,27 @@
-use std::path::PathBuf;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use reqwest::Url;
 use serde::Deserialize;
 
+use crate::{
+    channel_overrides::NamePattern,
+    config_loader::{self, ConfigError},
+    secret::Secret,
+};
+
+/// Supported `GPT_Version` values
+pub const GPT_VERSIONS: [&str; 6] = [
+    "Gpt35Turbo",
+    "Gpt35Turbo_0301",
+    "Gpt4",
+    "Gpt4_32k",
+    "Gpt4_0314",
+    "Gpt4_32k_0314",
+];
+
 fn default_silerio_bridge_url() -> Url {
     Url::parse("http://localhost:8961/say").unwrap()
 }
@@ -15,17 +34,159 @@ fn default_openai_whisper_url() -> Url {
     Url::parse("http://localhost:3157/transcribe").unwrap()
 }
 
+fn default_deeplx_url() -> Url {
+    Url::parse("https://www2.deepl.com/jsonrpc").unwrap()
+}
+
 fn auto() -> String {
     "auto".to_string()
 }
 
-#[derive(Deserialize)]
+fn default_openai_embeddings_url() -> Url {
+    Url::parse("https://api.openai.com/v1/embeddings").unwrap()
+}
+
+fn default_embeddings_model() -> String {
+    "text-embedding-ada-002".to_string()
+}
+
+fn default_memory_max_facts() -> usize {
+    100
+}
+
+fn default_memory_recall_count() -> usize {
+    3
+}
+
+fn default_context_max_tokens() -> usize {
+    3000
+}
+
+fn default_context_keep_recent_turns() -> usize {
+    4
+}
+
+fn default_context_summary_prompt() -> String {
+    "Summarize the conversation below in a few sentences. \
+    Keep names, facts and promises, the summary will be used as your memory."
+        .to_string()
+}
+
+fn default_openai_moderation_url() -> Url {
+    Url::parse("https://api.openai.com/v1/moderations").unwrap()
+}
+
+fn default_moderation_action() -> ModerationAction {
+    ModerationAction::Mask
+}
+
+fn default_moderation_max_regenerations() -> usize {
+    2
+}
+
+fn default_translate_drop_nonconfident() -> f64 {
+    0.55
+}
+
+fn default_max_concurrent_requests() -> usize {
+    4
+}
+
+fn default_queue_depth() -> usize {
+    3
+}
+
+fn default_queue_overflow_policy() -> QueueOverflowPolicy {
+    QueueOverflowPolicy::DropNewest
+}
+
+fn default_queue_position_message() -> String {
+    "You're #{position} in line, please wait".to_string()
+}
+
+fn default_service_timeout() -> f32 {
+    30.0
+}
+
+fn default_max_retries() -> u32 {
+    2
+}
+
+fn default_initial_backoff() -> f32 {
+    0.5
+}
+
+fn default_max_backoff() -> f32 {
+    8.0
+}
+
+fn default_breaker_threshold() -> u32 {
+    5
+}
+
+fn default_breaker_cooldown() -> f32 {
+    30.0
+}
+
+fn default_candidate_max_length() -> usize {
+    300
+}
+
+fn default_candidate_lang() -> String {
+    "en".to_string()
+}
+
+fn default_candidate_window() -> usize {
+    5
+}
+
+fn default_rule_weight() -> f32 {
+    1.0
+}
+
+fn default_user_rate_period() -> f32 {
+    60.0
+}
+
+fn default_rate_limit_message() -> String {
+    "You're asking too fast, give me a moment to breathe".to_string()
+}
+
+fn default_budget_message() -> String {
+    "I've talked too much today, let's continue tomorrow".to_string()
+}
+
+fn default_vts_url() -> Url {
+    Url::parse("ws://localhost:8001").unwrap()
+}
+
+fn default_vts_plugin_name() -> String {
+    "AI Waifu".to_string()
+}
+
+fn default_vts_plugin_developer() -> String {
+    "ai-waifu-bot".to_string()
+}
+
+fn default_mouth_parameter() -> String {
+    "MouthOpen".to_string()
+}
+
+fn default_mouth_gain() -> f32 {
+    4.0
+}
+
+fn default_mouth_fps() -> u32 {
+    30
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum AIEngineType {
     ChatGPT {
-        // OpenAI API token
+        // OpenAI API token, a string or {"env"}, {"file"}, {"command"}
         #[serde(rename = "OpenAI_Token")]
-        openai_token: String,
+        openai_token: Secret,
 
         /// The GPT version used Gpt35Turbo, Gpt35Turbo_0301, Gpt4, Gpt4_32k, Gpt4_0314, Gpt4_32k_0314,
         #[serde(rename = "GPT_Version")]
@@ -40,9 +201,69 @@ pub enum AIEngineType {
         #[serde(rename = "Model")]
         model: Option<String>,
     },
+    /// llama.cpp server /completion endpoint, the prompt is made with `Prompt_template`
+    LlamaCpp(LocalModelConfig),
+    /// Ollama /api/chat endpoint, the prompt is made by Ollama
+    Ollama(LocalModelConfig),
 }
 
-#[derive(Deserialize)]
+/// How the chat history is written into a raw prompt
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ChatTemplate {
+    #[default]
+    ChatML,
+    Llama2,
+    Alpaca,
+    Vicuna,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct LocalModelConfig {
+    #[serde(rename = "Url")]
+    pub url: Url, // llama.cpp /completion or Ollama /api/chat endpoint
+    #[serde(rename = "Model")]
+    pub model: Option<String>, // Model name, required by Ollama
+    #[serde(rename = "Prompt_template", default)]
+    pub template: ChatTemplate, // ChatML, Llama2, Alpaca or Vicuna, llama.cpp only
+    #[serde(rename = "Stop", default)]
+    pub stop: Vec<String>, // Stop sequences added to the template ones
+    #[serde(rename = "Max_tokens")]
+    pub max_tokens: Option<u32>, // Answer length limit
+    #[serde(rename = "Grammar")]
+    pub grammar: Option<String>, // GBNF grammar of the answer, llama.cpp only
+    #[serde(rename = "Json_mode", default)]
+    pub json_mode: bool, // Answer with a JSON object
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ContextBudgetConfig {
+    /// Maximum estimated size of the prompt and the history sent to the model, in tokens
+    #[serde(rename = "Max_tokens", default = "default_context_max_tokens")]
+    pub max_tokens: usize,
+
+    /// Number of the last question-answer turns that are never summarized
+    #[serde(
+        rename = "Keep_recent_turns",
+        default = "default_context_keep_recent_turns"
+    )]
+    pub keep_recent_turns: usize,
+
+    /// Instruction for the model to fold older turns into the summary
+    #[serde(rename = "Summary_prompt", default = "default_context_summary_prompt")]
+    pub summary_prompt: String,
+}
+
+impl Default for ContextBudgetConfig {
+    fn default() -> Self {
+        Self {
+            max_tokens: default_context_max_tokens(),
+            keep_recent_turns: default_context_keep_recent_turns(),
+            summary_prompt: default_context_summary_prompt(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
 pub struct AIEngine {
     #[serde(rename = "Engine_Type")]
     pub engine_type: AIEngineType,
@@ -67,28 +288,80 @@ pub struct AIEngine {
     #[serde(rename = "Reply_count")]
     pub reply_count: Option<u32>,
 
+    /// Rules choosing the best of `Reply_count` replies, repetition of the recent answers if not set
+    #[serde(rename = "Candidate_rules")]
+    pub candidate_rules: Option<Vec<CandidateRuleConfig>>,
+
     /// File to store the AI conversation history
     #[serde(rename = "Context_path")]
     pub context_path: Option<PathBuf>,
+
+    /// Limits of the conversation history, older turns are summarized
+    #[serde(rename = "Context_budget", default)]
+    pub context_budget: ContextBudgetConfig,
+
+    /// Built-in tools the AI may call: "time", "dice", "calculator", "remember"
+    #[serde(rename = "Tools", default)]
+    pub tools: Vec<String>,
+
+    /// Backends tried in order when the previous one fails, `Engine_Type` is the first one
+    #[serde(rename = "Fallback", default)]
+    pub fallback: Vec<FallbackBackendConfig>,
+
+    /// Seconds to wait for the backend answer before trying the next one
+    #[serde(rename = "Backend_timeout")]
+    pub backend_timeout: Option<f32>,
+
+    /// Rules selecting the backend tried first, the first matching rule is used
+    #[serde(rename = "Routing", default)]
+    pub routing: Vec<RouteConfig>,
+}
+
+/// Name of the `Engine_Type` backend in the routing rules
+pub const MAIN_BACKEND: &str = "main";
+
+#[derive(Deserialize, Clone)]
+pub struct FallbackBackendConfig {
+    #[serde(rename = "Name")]
+    pub name: String, // Used in the routing rules
+    #[serde(rename = "Engine_Type")]
+    pub engine_type: AIEngineType, // Same as AIEngine Engine_Type
 }
 
-#[derive(Deserialize)]
+/// All the set conditions must match
+#[derive(Deserialize, Clone)]
+pub struct RouteConfig {
+    #[serde(rename = "Platform")]
+    pub platform: Option<Platform>, // Discord, Twitch or Interactive
+    #[serde(rename = "Persona")]
+    pub persona: Option<String>, // Persona name
+    #[serde(rename = "Min_length")]
+    pub min_length: Option<usize>, // Request length in characters
+    #[serde(rename = "Max_length")]
+    pub max_length: Option<usize>,
+    #[serde(rename = "Backend")]
+    pub backend: String, // "main" or a Fallback name
+}
+
+#[derive(Deserialize, Clone)]
 pub struct DiscordConfig {
     #[serde(rename = "Discord_Token")]
-    pub discord_token: String, // Discord bot token
+    pub discord_token: Secret, // Discord bot token
     #[serde(rename = "Discord_channel_whitelist")]
     pub channel_whitelist: Vec<String>, // Discord channel whitelist (empty = all channels), supports wildcards
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct DeepLxTranslateConfig {
     #[serde(rename = "Speaker_lang", default = "auto")]
     pub src_lang: String, // Optional request language
     #[serde(rename = "Answer_lang")]
     pub dest_lang: String, // Answer langualge
+    #[serde(rename = "Url", default = "default_deeplx_url")]
+    pub url: Url, // Optional DeepL JSON-RPC endpoint
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum TTSConfig {
     Disabled,
@@ -108,7 +381,7 @@ pub enum TTSConfig {
     },
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct STTConfig {
     #[serde(rename = "STT_Url", default = "default_openai_whisper_url")]
     pub voice2txt_url: Url, // Optional voice to text service URL
@@ -120,7 +393,369 @@ pub struct STTConfig {
     pub maximal_audio_fragment_length: f32, // Maximal audio fragment length in seconds
 }
 
-#[derive(Deserialize)]
+/// What to do with a new request if the channel queue is full
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum QueueOverflowPolicy {
+    /// Drop the oldest waiting request, the new one goes to the end of the queue
+    DropOldest,
+    /// Reject the new request, the user gets a busy message
+    DropNewest,
+    /// Append the new request text to the last waiting request
+    Merge,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RequestQueueConfig {
+    #[serde(rename = "Depth", default = "default_queue_depth")]
+    pub depth: usize, // Maximum number of requests waiting in a channel queue
+    #[serde(rename = "Overflow_policy", default = "default_queue_overflow_policy")]
+    pub overflow_policy: QueueOverflowPolicy, // What to do if the queue is full
+    #[serde(
+        rename = "Position_message",
+        default = "default_queue_position_message"
+    )]
+    pub position_message: String, // Message for queued requests, "{position}" is replaced with the queue position
+}
+
+impl Default for RequestQueueConfig {
+    fn default() -> Self {
+        Self {
+            depth: default_queue_depth(),
+            overflow_policy: default_queue_overflow_policy(),
+            position_message: default_queue_position_message(),
+        }
+    }
+}
+
+/// Token accounting, the budgets are in tokens, a missing limit is not checked
+#[derive(Deserialize, Clone)]
+pub struct UsageConfig {
+    #[serde(rename = "Usage_path")]
+    pub usage_path: Option<PathBuf>, // Directory to store the usage per day, kept only in memory if not set
+    #[serde(rename = "Daily_budget")]
+    pub daily_budget: Option<u64>, // Tokens per day for all the channels
+    #[serde(rename = "Monthly_budget")]
+    pub monthly_budget: Option<u64>, // Tokens per calendar month for all the channels
+    #[serde(rename = "User_daily_budget")]
+    pub user_daily_budget: Option<u64>, // Tokens per day for one user
+    #[serde(rename = "User_requests")]
+    pub user_requests: Option<u32>, // Requests of one user per User_rate_period
+    #[serde(rename = "User_rate_period", default = "default_user_rate_period")]
+    pub user_rate_period: f32, // Seconds
+    #[serde(rename = "Rate_limit_message", default = "default_rate_limit_message")]
+    pub rate_limit_message: String, // Answer to the user who asks too often
+    #[serde(rename = "Budget_message", default = "default_budget_message")]
+    pub budget_message: String, // Answer when a budget is spent
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self {
+            usage_path: None,
+            daily_budget: None,
+            monthly_budget: None,
+            user_daily_budget: None,
+            user_requests: None,
+            user_rate_period: default_user_rate_period(),
+            rate_limit_message: default_rate_limit_message(),
+            budget_message: default_budget_message(),
+        }
+    }
+}
+
+/// Avatar control through the VTube Studio plugin API
+#[derive(Deserialize, Clone, Debug)]
+pub struct VTubeStudioConfig {
+    #[serde(rename = "Url", default = "default_vts_url")]
+    pub url: Url, // Plugin API WebSocket
+    #[serde(rename = "Plugin_name", default = "default_vts_plugin_name")]
+    pub plugin_name: String, // Shown in the VTube Studio permission dialog
+    #[serde(rename = "Plugin_developer", default = "default_vts_plugin_developer")]
+    pub plugin_developer: String,
+    #[serde(rename = "Token_path")]
+    pub token_path: Option<PathBuf>, // Keeps the token, otherwise the plugin is allowed on every start
+    #[serde(rename = "Emotions", default)]
+    pub emotions: Vec<EmotionConfig>, // The first matching emotion of the answer is shown
+    #[serde(rename = "Mouth_parameter", default = "default_mouth_parameter")]
+    pub mouth_parameter: String, // Input parameter driven by the speech loudness
+    #[serde(rename = "Mouth_gain", default = "default_mouth_gain")]
+    pub mouth_gain: f32, // Loudness multiplier, the mouth is fully open at 1/Mouth_gain
+    #[serde(rename = "Mouth_fps", default = "default_mouth_fps")]
+    pub mouth_fps: u32, // Mouth updates per second
+}
+
+/// Emotion of the answer: `[Name]` tag or one of the keywords, and how it is shown
+#[derive(Deserialize, Clone, Debug)]
+pub struct EmotionConfig {
+    #[serde(rename = "Name")]
+    pub name: String, // Emotion tag, e.g. "happy" for [happy]
+    #[serde(rename = "Keywords", default)]
+    pub keywords: Vec<String>, // Words of the answer or its *actions*, case-insensitive
+    #[serde(rename = "Expression")]
+    pub expression: Option<String>, // Expression file, active until the answer is spoken
+    #[serde(rename = "Hotkey")]
+    pub hotkey: Option<String>, // Hotkey name or ID, triggered once
+}
+
+/// How an external service is called
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+pub struct ServicePolicy {
+    #[serde(rename = "Timeout", default = "default_service_timeout")]
+    pub timeout: f32, // Seconds for one attempt
+    #[serde(rename = "Max_retries", default = "default_max_retries")]
+    pub max_retries: u32, // Retries after transient errors: timeouts, connection errors, 429 and 5xx
+    #[serde(rename = "Initial_backoff", default = "default_initial_backoff")]
+    pub initial_backoff: f32, // Seconds before the first retry, doubled for each next one, with jitter
+    #[serde(rename = "Max_backoff", default = "default_max_backoff")]
+    pub max_backoff: f32, // Seconds, the longest pause between retries
+    #[serde(rename = "Breaker_threshold", default = "default_breaker_threshold")]
+    pub breaker_threshold: u32, // Failed calls in a row that open the circuit breaker, 0 - never open
+    #[serde(rename = "Breaker_cooldown", default = "default_breaker_cooldown")]
+    pub breaker_cooldown: f32, // Seconds the open breaker fails the calls without trying
+}
+
+impl Default for ServicePolicy {
+    fn default() -> Self {
+        Self {
+            timeout: default_service_timeout(),
+            max_retries: default_max_retries(),
+            initial_backoff: default_initial_backoff(),
+            max_backoff: default_max_backoff(),
+            breaker_threshold: default_breaker_threshold(),
+            breaker_cooldown: default_breaker_cooldown(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Default)]
+pub struct ResilienceConfig {
+    #[serde(flatten)]
+    pub default: ServicePolicy, // Used by the services not listed in Services
+    #[serde(rename = "Services", default)]
+    pub services: HashMap<String, ServicePolicy>, // By service name, see resilience::SERVICES
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(tag = "type")]
+pub enum EmbeddingsConfig {
+    /// Only lexical search
+    Disabled,
+    /// OpenAI compatible /v1/embeddings endpoint
+    OpenAI {
+        #[serde(rename = "Url", default = "default_openai_embeddings_url")]
+        url: Url,
+        #[serde(rename = "Model", default = "default_embeddings_model")]
+        model: String,
+        /// API token, the AI engine token is used if not set
+        #[serde(rename = "Token")]
+        token: Option<Secret>,
+    },
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MemoryConfig {
+    #[serde(rename = "Memory_path")]
+    pub memory_path: PathBuf, // Directory to store facts about users
+    #[serde(rename = "Embeddings")]
+    pub embeddings: Option<EmbeddingsConfig>, // Embedding backend for the fact search
+    #[serde(rename = "Max_facts", default = "default_memory_max_facts")]
+    pub max_facts: usize, // Facts per user, the oldest ones are forgotten
+    #[serde(rename = "Recall_count", default = "default_memory_recall_count")]
+    pub recall_count: usize, // Facts added to each request
+}
+
+/// Where the answers are published
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Platform {
+    Discord,
+    Twitch,
+    Interactive,
+}
+
+/// What to do with an answer that failed moderation
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ModerationAction {
+    /// Replace the banned words with asterisks
+    Mask,
+    /// Ask the AI for another answer
+    Regenerate,
+    /// Replace the answer with one of the canned lines
+    Canned,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ModerationEndpointConfig {
+    #[serde(rename = "Url", default = "default_openai_moderation_url")]
+    pub url: Url, // OpenAI compatible /v1/moderations endpoint
+    #[serde(rename = "Token")]
+    pub token: Option<Secret>, // API token, AIEngine OpenAI token if not set
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ModerationConfig {
+    #[serde(rename = "Banned_words", default)]
+    pub banned_words: Vec<String>, // Case-insensitive regexes
+    #[serde(rename = "Endpoint")]
+    pub endpoint: Option<ModerationEndpointConfig>, // Moderation API, only regexes if not set
+    #[serde(rename = "Default_action", default = "default_moderation_action")]
+    pub default_action: ModerationAction, // Action for the platforms not listed in Actions
+    #[serde(rename = "Actions", default)]
+    pub actions: HashMap<Platform, ModerationAction>, // Action per platform
+    #[serde(rename = "Canned_lines", default)]
+    pub canned_lines: Vec<String>, // Answers used instead of the rejected ones
+    #[serde(
+        rename = "Max_regenerations",
+        default = "default_moderation_max_regenerations"
+    )]
+    pub max_regenerations: usize, // Canned line is used if all the attempts fail
+}
+
+impl ModerationConfig {
+    pub fn action(&self, platform: Platform) -> ModerationAction {
+        self.actions
+            .get(&platform)
+            .copied()
+            .unwrap_or(self.default_action)
+    }
+}
+
+/// Layer around the AI, listed from the outermost: the request passes the layers
+/// in the listed order, the answer in the reverse one
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum MiddlewareConfig {
+    /// Requests are translated to english, answers to the persona language
+    Translate {
+        #[serde(rename = "Src_lang")]
+        src_lang: Option<String>, // DeepLx_Translate_Config Speaker_lang if not set
+        #[serde(rename = "Dest_lang")]
+        dest_lang: Option<String>, // persona answer language if not set
+        #[serde(
+            rename = "Drop_nonconfident_lvl",
+            default = "default_translate_drop_nonconfident"
+        )]
+        drop_nonconfident_result: f64, // request translation is dropped if confidence < value
+    },
+    /// Numbers in the answer are written in words for TTS
+    NumbersToWords,
+    /// Answers are checked as set in the Moderation config
+    Moderation,
+    /// Request text is inserted into the template: {request}, {user}, {channel}, {lang}
+    PromptTemplate {
+        #[serde(rename = "Template")]
+        template: String,
+    },
+    /// Requests and answers are logged
+    Logging,
+    /// Stage directions, emotion tags, markdown, emoji and links are separated from the speech,
+    /// should be before Translate and NumbersToWords to clean their answers too
+    Sanitize,
+}
+
+/// Rule scoring the reply candidates, the scores are multiplied by `Weight` and summed up
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "type")]
+pub enum CandidateRuleConfig {
+    /// Replies shorter than `Min` or longer than `Max` characters are penalized
+    Length {
+        #[serde(rename = "Min", default)]
+        min: usize,
+        #[serde(rename = "Max", default = "default_candidate_max_length")]
+        max: usize,
+        #[serde(rename = "Weight", default = "default_rule_weight")]
+        weight: f32,
+    },
+    /// Replies with the words are rejected
+    BannedWords {
+        #[serde(rename = "Words")]
+        words: Vec<String>, // Case-insensitive regexes
+        #[serde(rename = "Weight", default = "default_rule_weight")]
+        weight: f32,
+    },
+    /// Replies in another writing system are penalized
+    Language {
+        #[serde(rename = "Lang", default = "default_candidate_lang")]
+        lang: String, // Language of the model answers, before the translation
+        #[serde(rename = "Weight", default = "default_rule_weight")]
+        weight: f32,
+    },
+    /// Replies similar to the recent answers are penalized
+    Repetition {
+        #[serde(rename = "Window", default = "default_candidate_window")]
+        window: usize, // How many recent answers are compared
+        #[serde(rename = "Weight", default = "default_rule_weight")]
+        weight: f32,
+    },
+}
+
+impl CandidateRuleConfig {
+    /// Used if the rules are not set
+    pub fn default_rules() -> Vec<Self> {
+        vec![Self::Repetition {
+            window: default_candidate_window(),
+            weight: default_rule_weight(),
+        }]
+    }
+}
+
+impl MiddlewareConfig {
+    /// Used if the chain is not set: Sanitize, Moderation (if configured), Translate, NumbersToWords
+    pub fn default_chain(moderation: bool) -> Vec<Self> {
+        let mut chain = vec![Self::Sanitize];
+        if moderation {
+            chain.push(Self::Moderation);
+        }
+        chain.push(Self::Translate {
+            src_lang: None,
+            dest_lang: None,
+            drop_nonconfident_result: default_translate_drop_nonconfident(),
+        });
+        chain.push(Self::NumbersToWords);
+        chain
+    }
+}
+
+/// Persona settings, missing ones are taken from the main config
+#[derive(Deserialize, Clone)]
+pub struct PersonaConfig {
+    #[serde(rename = "AI_initial_prompt")]
+    pub initial_prompt: String, // Initial prompt for the AI
+    #[serde(rename = "Temperature")]
+    pub temperature: Option<f32>,
+    #[serde(rename = "Top_p")]
+    pub top_p: Option<f32>,
+    #[serde(rename = "Presence_penalty")]
+    pub presence_penalty: Option<f32>,
+    #[serde(rename = "Frequency_penalty")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(rename = "TTS_Config")]
+    pub tts_config: Option<TTSConfig>, // TTS engine and voice
+    #[serde(rename = "Answer_lang")]
+    pub answer_lang: Option<String>, // Answer language
+    #[serde(rename = "Busy_messages")]
+    pub busy_messages: Option<Vec<String>>, // Messages to send when the AI is busy
+}
+
+/// Settings of the matching guilds and channels, all the set selectors must match
+#[derive(Deserialize, Clone)]
+pub struct OverrideConfig {
+    #[serde(rename = "Guild")]
+    pub guild: Option<String>, // Discord guild ID
+    #[serde(rename = "Channel")]
+    pub channel: Option<String>, // Discord channel ID or Twitch channel name
+    #[serde(rename = "Name")]
+    pub name: Option<NamePattern>, // Channel name regex
+    #[serde(rename = "AI_initial_prompt")]
+    pub initial_prompt: Option<String>, // Initial prompt for the AI
+    #[serde(rename = "Answer_lang")]
+    pub answer_lang: Option<String>, // Answer language
+    #[serde(rename = "TTS_Config")]
+    pub tts_config: Option<TTSConfig>, // TTS engine and voice
+    #[serde(rename = "DisplayRawResp")]
+    pub display_raw_resp: Option<bool>, // Display raw AI response
+}
+
+#[derive(Deserialize, Clone)]
 pub struct Config {
     #[serde(rename = "AIEngine")]
     pub ai_engine: AIEngine, // AI engine
@@ -138,13 +773,48 @@ pub struct Config {
     pub busy_messages: Vec<String>, // Messages to send when the AI is busy
     #[serde(rename = "STT_Config")]
     pub stt_config: STTConfig, // STT config
+    #[serde(rename = "Request_queue", default)]
+    pub request_queue: RequestQueueConfig, // Per-channel request queue config
+    #[serde(
+        rename = "Max_concurrent_requests",
+        default = "default_max_concurrent_requests"
+    )]
+    pub max_concurrent_requests: usize, // How many channels are answered in parallel
+    #[serde(rename = "Memory")]
+    pub memory: Option<MemoryConfig>, // Long-term memory about users
+    #[serde(rename = "Personas", default)]
+    pub personas: HashMap<String, PersonaConfig>, // Additional personas, selectable per channel
+    #[serde(rename = "Moderation")]
+    pub moderation: Option<ModerationConfig>, // Answer moderation before publishing
+    #[serde(rename = "Middleware")]
+    pub middleware: Option<Vec<MiddlewareConfig>>, // Layers around the AI, default: Sanitize, Moderation (if configured), Translate, NumbersToWords
+    #[serde(rename = "Overrides", default)]
+    pub overrides: Vec<OverrideConfig>, // Per guild and channel settings, the later matching ones win
+    #[serde(rename = "Resilience", default)]
+    pub resilience: ResilienceConfig, // Timeouts, retries and circuit breakers of the external services
+    #[serde(rename = "Usage", default)]
+    pub usage: UsageConfig, // Token accounting, budgets and per-user rate limits
+    #[serde(rename = "VTube_Studio")]
+    pub vtube_studio: Option<VTubeStudioConfig>, // Avatar expressions and lip sync
 }
 
 impl Config {
+    /// Load `config.json` with the local overrides and the environment variables
     pub fn load() -> Self {
-        let contents =
-            std::fs::read_to_string("config.json").expect("Failed to read config.json file!");
-        serde_json::from_str::<Config>(&contents).unwrap()
+        Self::load_from(Path::new(config_loader::DEFAULT_CONFIG_PATH))
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    pub fn load_from(path: &Path) -> Result<Self, ConfigError> {
+        config_loader::Layers::load(path, std::env::vars())?.to_config()
+    }
+
+    pub fn from_value(value: serde_json::Value) -> Result<Self, ConfigError> {
+        serde_path_to_error::deserialize(value).map_err(|e| ConfigError::Invalid {
+            origin: config_loader::ConfigOrigin::Unknown,
+            key_path: e.path().to_string(),
+            message: e.into_inner().to_string(),
+        })
     }
 }
 
@@ -161,16 +831,23 @@ impl Default for Config {
                 presence_penalty: None,
                 frequency_penalty: None,
                 reply_count: None,
+                candidate_rules: None,
                 context_path: None,
+                context_budget: ContextBudgetConfig::default(),
+                tools: vec![],
+                fallback: vec![],
+                backend_timeout: None,
+                routing: vec![],
             },
             initial_prompt: "Act as japan pop-idol".to_string(),
             discord_config: DiscordConfig {
-                discord_token: "".to_string(),
+                discord_token: Secret::default(),
                 channel_whitelist: vec![],
             },
             deeplx_translate_config: DeepLxTranslateConfig {
                 src_lang: auto(),
                 dest_lang: "".to_string(),
+                url: default_deeplx_url(),
             },
             tts_config: TTSConfig::Disabled,
             display_raw_resp: false,
@@ -181,6 +858,16 @@ impl Default for Config {
                 minimal_audio_fragment_length: 0.0,
                 maximal_audio_fragment_length: 0.0,
             },
+            request_queue: RequestQueueConfig::default(),
+            max_concurrent_requests: default_max_concurrent_requests(),
+            memory: None,
+            personas: HashMap::new(),
+            moderation: None,
+            middleware: None,
+            overrides: vec![],
+            resilience: ResilienceConfig::default(),
+            usage: UsageConfig::default(),
+            vtube_studio: None,
         }
     }
 }
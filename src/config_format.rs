@@ -0,0 +1,261 @@
+/// Config file formats and the error locations in them
+use std::path::Path;
+
+use regex::Regex;
+use serde_json::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    /// JSON with comments and trailing commas, plain JSON is a subset of it
+    Json5,
+    Toml,
+    Yaml,
+}
+
+/// Syntax error, line and column are one-based
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub column: usize,
+    /// Field being parsed when the error occurred, empty at the top level
+    pub key_path: String,
+    pub message: String,
+}
+
+impl ConfigFormat {
+    /// By the file extension: `.toml`, `.yaml`/`.yml`, JSON5 otherwise
+    pub fn from_path(path: &Path) -> Self {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        match ext.as_deref() {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json5,
+        }
+    }
+
+    pub fn parse(&self, text: &str) -> Result<Value, SyntaxError> {
+        let (line, column, message) = match self {
+            ConfigFormat::Json5 => match json5::from_str::<Value>(text) {
+                Ok(value) => return Ok(value),
+                Err(json5::Error::Message { msg, location }) => match location {
+                    Some(location) => (location.line, location.column, msg),
+                    None => (1, 1, msg),
+                },
+            },
+            ConfigFormat::Toml => match toml::from_str::<Value>(text) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    let offset = e.span().map(|span| span.start).unwrap_or(0);
+                    let (line, column) = line_col(text, offset);
+                    (line, column, e.message().to_string())
+                }
+            },
+            ConfigFormat::Yaml => match serde_yaml::from_str::<Value>(text) {
+                Ok(value) => return Ok(value),
+                Err(e) => {
+                    let (line, column) = e
+                        .location()
+                        .map(|l| (l.line(), l.column()))
+                        .unwrap_or((1, 1));
+                    (line, column, e.to_string())
+                }
+            },
+        };
+
+        Err(SyntaxError {
+            line,
+            column,
+            key_path: self.key_path_at(text, line, column),
+            message,
+        })
+    }
+
+    /// Path of the field at the given position, e.g. `AIEngine.Engine_Type`
+    pub fn key_path_at(&self, text: &str, line: usize, column: usize) -> String {
+        match self {
+            ConfigFormat::Json5 => json5_key_path(&text[..offset(text, line, column)]),
+            ConfigFormat::Toml => toml_key_path(text, line),
+            ConfigFormat::Yaml => yaml_key_path(text, line),
+        }
+    }
+}
+
+/// One-based line and column of the byte offset
+pub fn line_col(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// Byte offset of the one-based line and column
+fn offset(text: &str, line: usize, column: usize) -> usize {
+    let mut offset = 0;
+    for (i, l) in text.split_inclusive('\n').enumerate() {
+        if i + 1 == line {
+            return offset
+                + l.char_indices()
+                    .nth(column.saturating_sub(1))
+                    .map(|(i, _)| i)
+                    .unwrap_or(l.len());
+        }
+        offset += l.len();
+    }
+    text.len()
+}
+
+enum Frame {
+    Object(Option<String>),
+    Array(usize),
+}
+
+fn frames_to_path(frames: &[Frame]) -> String {
+    let mut path = String::new();
+    for frame in frames {
+        match frame {
+            Frame::Object(Some(key)) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(key);
+            }
+            Frame::Object(None) => break,
+            Frame::Array(index) => path.push_str(&format!("[{index}]")),
+        }
+    }
+    path
+}
+
+/// Path of the field open at the end of the JSON5 text
+fn json5_key_path(text: &str) -> String {
+    let mut frames = vec![];
+    let mut token = String::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' | '\'' => {
+                token.clear();
+                while let Some(s) = chars.next() {
+                    match s {
+                        '\\' => {
+                            chars.next();
+                        }
+                        s if s == c => break,
+                        s => token.push(s),
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for s in chars.by_ref() {
+                    if s == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut prev = ' ';
+                for s in chars.by_ref() {
+                    if prev == '*' && s == '/' {
+                        break;
+                    }
+                    prev = s;
+                }
+            }
+            '{' => frames.push(Frame::Object(None)),
+            '[' => frames.push(Frame::Array(0)),
+            '}' | ']' => {
+                frames.pop();
+            }
+            ':' => {
+                if let Some(Frame::Object(key)) = frames.last_mut() {
+                    *key = Some(std::mem::take(&mut token));
+                }
+            }
+            ',' => match frames.last_mut() {
+                Some(Frame::Object(key)) => *key = None,
+                Some(Frame::Array(index)) => *index += 1,
+                None => {}
+            },
+            c if c.is_alphanumeric() || c == '_' || c == '$' => {
+                // unquoted key
+                token.clear();
+                token.push(c);
+                while let Some(&s) = chars.peek() {
+                    if !(s.is_alphanumeric() || s == '_' || s == '$') {
+                        break;
+                    }
+                    token.push(s);
+                    chars.next();
+                }
+            }
+            _ => {}
+        }
+    }
+    frames_to_path(&frames)
+}
+
+/// Last table header and the key of the line
+fn toml_key_path(text: &str, line: usize) -> String {
+    let mut table = String::new();
+    let mut key = String::new();
+    for l in text.lines().take(line) {
+        let l = l.trim();
+        if l.starts_with('[') {
+            table = l.trim_matches(|c| c == '[' || c == ']').trim().to_string();
+            key.clear();
+        } else if let Some((k, _)) = l.split_once('=') {
+            key = k.trim().trim_matches('"').to_string();
+        } else {
+            key.clear();
+        }
+    }
+
+    match (table.is_empty(), key.is_empty()) {
+        (_, true) => table,
+        (true, false) => key,
+        (false, false) => format!("{table}.{key}"),
+    }
+}
+
+/// Keys of the lines with a smaller indentation above the line
+fn yaml_key_path(text: &str, line: usize) -> String {
+    let mut keys: Vec<(usize, String)> = vec![];
+    for l in text.lines().take(line) {
+        let content = l.trim_start();
+        if content.is_empty() || content.starts_with('#') {
+            continue;
+        }
+        let indent = l.len() - content.len();
+        let Some((key, _)) = content.trim_start_matches("- ").split_once(':') else {
+            continue;
+        };
+        while keys.last().map(|(i, _)| *i >= indent).unwrap_or(false) {
+            keys.pop();
+        }
+        keys.push((indent, key.trim().trim_matches('"').to_string()));
+    }
+    keys.into_iter()
+        .map(|(_, key)| key)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Position of the field in the text, each key is searched after the previous one.
+/// Approximate, but the fields are usually unique enough
+pub fn find_key_path(text: &str, key_path: &[String]) -> Option<(usize, usize)> {
+    let mut start = 0;
+    let mut found = None;
+    for key in key_path {
+        let re = Regex::new(&format!(r#"(^|[^\w$]){}([^\w$]|$)"#, regex::escape(key))).ok()?;
+        let m = re.find_at(text, start)?;
+        // skip the separator matched before the key
+        let key_start = m.start() + m.as_str().find(key.as_str()).unwrap_or(0);
+        found = Some(key_start);
+        start = key_start + key.len();
+    }
+    found.map(|offset| line_col(text, offset))
+}
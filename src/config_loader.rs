@@ -0,0 +1,595 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::{
+    de::{self, value::StrDeserializer, DeserializeSeed, MapAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::{Map, Value};
+use tracing::{error, warn};
+
+use crate::{
+    config::Config,
+    config_format::{find_key_path, ConfigFormat},
+    config_validation::{ConfigProblem, Severity},
+};
+
+/// Used if `--config` is not set
+pub const DEFAULT_CONFIG_PATH: &str = "config.json";
+
+/// Prefix of the environment variables overriding the config fields
+pub const ENV_PREFIX: &str = "WAIFU_";
+
+/// Separator of the nested field names in the environment variables
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Replacement of the secret values in the printed config
+pub const REDACTED: &str = "<redacted>";
+
+/// Fields with these words in the name are not printed
+const SECRET_WORDS: [&str; 4] = ["token", "secret", "password", "api_key"];
+
+/// Where the invalid value came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigOrigin {
+    /// One-based line and column, if the field is found in the file
+    File(PathBuf, Option<(usize, usize)>),
+    /// Environment variable
+    Env(String),
+    Unknown,
+}
+
+impl fmt::Display for ConfigOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigOrigin::File(path, Some((line, column))) => {
+                write!(f, "{}:{line}:{column}", path.display())
+            }
+            ConfigOrigin::File(path, None) => write!(f, "{}", path.display()),
+            ConfigOrigin::Env(name) => write!(f, "environment variable {name}"),
+            ConfigOrigin::Unknown => write!(f, "config"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Config file can't be read
+    Read(PathBuf, std::io::Error),
+    /// Config file syntax error
+    Parse {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        key_path: String,
+        message: String,
+    },
+    /// Merged config does not match the expected structure
+    Invalid {
+        origin: ConfigOrigin,
+        key_path: String,
+        message: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "Failed to read {}: {e}", path.display()),
+            ConfigError::Parse {
+                path,
+                line,
+                column,
+                key_path,
+                message,
+            } => {
+                write!(f, "Failed to parse {}:{line}:{column}", path.display())?;
+                if !key_path.is_empty() {
+                    write!(f, " at {key_path}")?;
+                }
+                write!(f, ": {message}")
+            }
+            ConfigError::Invalid {
+                origin,
+                key_path,
+                message,
+            } => write!(f, "Invalid config ({origin}) at {key_path}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Config options shared by all the binaries
+#[derive(clap::Args)]
+pub struct ConfigArgs {
+    /// Config file, `<name>.local.<ext>` next to it overrides its fields
+    #[clap(long = "config", default_value = DEFAULT_CONFIG_PATH)]
+    pub config_path: PathBuf,
+
+    /// Print the merged config with the secrets redacted and exit
+    #[clap(long, default_value_t = false)]
+    pub print_config: bool,
+}
+
+impl ConfigArgs {
+    /// Load the config, panics if it is invalid
+    pub fn load(&self) -> Config {
+        self.load_layers()
+            .to_config()
+            .unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    fn load_layers(&self) -> Layers {
+        let layers =
+            Layers::load(&self.config_path, std::env::vars()).unwrap_or_else(|e| panic!("{e}"));
+
+        if self.print_config {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&redact_secrets(layers.value.clone())).unwrap()
+            );
+            std::process::exit(0);
+        }
+        layers
+    }
+
+    /// Load and validate the config, exits with the report if there are errors
+    pub async fn load_validated(&self) -> Config {
+        let layers = self.load_layers();
+        let config = layers.to_config().unwrap_or_else(|e| panic!("{e}"));
+        let mut report = config.validate().await;
+        report.problems.extend(layers.env_problems());
+
+        if report.has_errors() {
+            error!("{report}");
+            std::process::exit(1);
+        }
+        for problem in &report.problems {
+            if problem.severity == Severity::Warning {
+                warn!("Config {}: {}", problem.key_path, problem.message);
+            }
+        }
+
+        // the services are created later and take their policies from there
+        crate::resilience::configure(&config.resilience);
+        config
+    }
+}
+
+struct ConfigFile {
+    path: PathBuf,
+    text: String,
+    value: Value,
+}
+
+/// Merged config and where its fields came from
+pub struct Layers {
+    pub value: Value,
+    /// Base file first
+    files: Vec<ConfigFile>,
+    env_fields: EnvFields,
+}
+
+impl Layers {
+    /// Base file, the local override file if it exists, then the environment variables
+    pub fn load(
+        path: &Path,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut files = vec![read_config_file(path)?];
+        let local = local_path(path);
+        if local.exists() {
+            files.push(read_config_file(&local)?);
+        }
+
+        let mut value = Value::Object(Map::new());
+        for file in &files {
+            merge(&mut value, file.value.clone());
+        }
+        let env_fields = apply_env(&mut value, vars);
+
+        Ok(Self {
+            value,
+            files,
+            env_fields,
+        })
+    }
+
+    /// Invalid fields are reported with the file position or the variable name
+    pub fn to_config(&self) -> Result<Config, ConfigError> {
+        serde_path_to_error::deserialize(self.value.clone()).map_err(|e| {
+            let key_path = e
+                .path()
+                .iter()
+                .filter_map(|segment| match segment {
+                    serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>();
+            ConfigError::Invalid {
+                origin: self.origin(&key_path),
+                key_path: e.path().to_string(),
+                message: e.into_inner().to_string(),
+            }
+        })
+    }
+
+    /// Variables that set nothing, the bot works without them
+    pub fn env_problems(&self) -> Vec<ConfigProblem> {
+        self.env_fields
+            .unknown
+            .iter()
+            .map(|(name, reason)| ConfigProblem {
+                severity: Severity::Warning,
+                key_path: name.clone(),
+                message: format!("environment variable is not applied: {reason}"),
+            })
+            .collect()
+    }
+
+    /// The last layer that set the field
+    fn origin(&self, key_path: &[String]) -> ConfigOrigin {
+        for (name, path) in self.env_fields.set.iter().rev() {
+            if path.starts_with(key_path) || key_path.starts_with(path) {
+                return ConfigOrigin::Env(name.clone());
+            }
+        }
+
+        for file in self.files.iter().rev() {
+            if get_field(&file.value, key_path).is_some() {
+                return ConfigOrigin::File(file.path.clone(), find_key_path(&file.text, key_path));
+            }
+        }
+        ConfigOrigin::Unknown
+    }
+}
+
+fn get_field<'a>(value: &'a Value, key_path: &[String]) -> Option<&'a Value> {
+    key_path
+        .iter()
+        .try_fold(value, |value, key| value.as_object()?.get(key))
+}
+
+/// `config.json` -> `config.local.json`
+pub fn local_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{stem}.local.{}", ext.to_string_lossy()),
+        None => format!("{stem}.local"),
+    };
+    path.with_file_name(name)
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile, ConfigError> {
+    let text = std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_owned(), e))?;
+    let value = ConfigFormat::from_path(path)
+        .parse(&text)
+        .map_err(|e| ConfigError::Parse {
+            path: path.to_owned(),
+            line: e.line,
+            column: e.column,
+            key_path: e.key_path,
+            message: e.message,
+        })?;
+    Ok(ConfigFile {
+        path: path.to_owned(),
+        text,
+        value,
+    })
+}
+
+/// Format is selected by the file extension
+pub fn read_file(path: &Path) -> Result<Value, ConfigError> {
+    Ok(read_config_file(path)?.value)
+}
+
+/// Base file, the local override file if it exists, then the environment variables
+pub fn load_layered(
+    path: &Path,
+    vars: impl IntoIterator<Item = (String, String)>,
+) -> Result<Value, ConfigError> {
+    Ok(Layers::load(path, vars)?.value)
+}
+
+/// Objects are merged field by field, everything else is replaced
+pub fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                match base.get_mut(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Environment variables applied to the config
+#[derive(Debug, Default, PartialEq)]
+pub struct EnvFields {
+    /// Variable names and the paths of the fields set by them
+    pub set: Vec<(String, Vec<String>)>,
+    /// Variables that don't name a config field and the reason
+    pub unknown: Vec<(String, String)>,
+}
+
+/// `WAIFU_AIENGINE__ENGINE_TYPE__OPENAI_TOKEN=...` sets `AIEngine.Engine_Type.OpenAI_Token`.
+/// Names are matched case-insensitively with the `Config` fields, or with the existing keys
+/// where any names are taken (`Personas`, tagged sections like `Engine_Type`)
+pub fn apply_env(value: &mut Value, vars: impl IntoIterator<Item = (String, String)>) -> EnvFields {
+    let mut vars = vars
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+        .collect::<Vec<_>>();
+    // stable result if a field is set by several variables
+    vars.sort();
+
+    let mut fields = EnvFields::default();
+    for (name, v) in vars {
+        let path = name[ENV_PREFIX.len()..]
+            .split(ENV_SEPARATOR)
+            .collect::<Vec<_>>();
+        match resolve_path(value, &path) {
+            Ok(key_path) => {
+                set_field(value, &key_path, v);
+                fields.set.push((name, key_path));
+            }
+            Err(reason) => fields.unknown.push((name, reason)),
+        }
+    }
+    fields
+}
+
+/// Config field names of the variable path
+fn resolve_path(value: &Value, path: &[&str]) -> Result<Vec<String>, String> {
+    let mut key_path: Vec<String> = vec![];
+    let mut value = Some(value);
+    for name in path {
+        if name.is_empty() {
+            return Err("empty field name".to_string());
+        }
+        let existing = value
+            .and_then(|v| v.as_object())
+            .and_then(|object| object.keys().find(|k| k.eq_ignore_ascii_case(name)));
+        let key = match config_fields(&key_path) {
+            Fields::Struct(fields) => fields
+                .iter()
+                .find(|field| field.eq_ignore_ascii_case(name))
+                .map(|field| field.to_string())
+                .ok_or_else(|| format!("{} has no field {name}", parent_name(&key_path)))?,
+            Fields::Value => return Err(format!("{} has no fields", parent_name(&key_path))),
+            Fields::Any => existing.cloned().unwrap_or(name.to_string()),
+        };
+        value = value.and_then(|v| v.get(&key));
+        key_path.push(key);
+    }
+    Ok(key_path)
+}
+
+fn parent_name(key_path: &[String]) -> String {
+    if key_path.is_empty() {
+        "config".to_string()
+    } else {
+        key_path.join(".")
+    }
+}
+
+fn set_field(value: &mut Value, key_path: &[String], v: String) {
+    if !value.is_object() {
+        *value = Value::Object(Map::new());
+    }
+    let object = value.as_object_mut().unwrap();
+    let key = key_path[0].clone();
+
+    if key_path.len() > 1 {
+        let field = object.entry(key).or_insert(Value::Null);
+        set_field(field, &key_path[1..], v);
+    } else {
+        let parsed = match object.get(&key) {
+            // the string fields are kept strings, e.g. a numeric token
+            Some(Value::String(_)) => Value::String(v),
+            _ => serde_json::from_str(&v).unwrap_or(Value::String(v)),
+        };
+        object.insert(key, parsed);
+    }
+}
+
+/// What the config type at a path is made of
+#[derive(Debug)]
+enum Fields {
+    /// Struct with these field names
+    Struct(&'static [&'static str]),
+    /// Number, string, list or a plain enum
+    Value,
+    /// Map, tagged enum or a custom type, the field names are not known
+    Any,
+}
+
+fn config_fields(key_path: &[String]) -> Fields {
+    match Config::deserialize(FieldsProbe { key_path }) {
+        Err(Found(fields)) => fields,
+        Ok(_) => Fields::Any,
+    }
+}
+
+/// Deserializer feeding the fields of the path to `Config`, it stops where the path ends
+struct FieldsProbe<'a> {
+    key_path: &'a [String],
+}
+
+/// Error of `FieldsProbe`, the only way to return the result
+#[derive(Debug)]
+struct Found(Fields);
+
+impl fmt::Display for Found {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0)
+    }
+}
+
+impl std::error::Error for Found {}
+
+impl de::Error for Found {
+    fn custom<T: fmt::Display>(_msg: T) -> Self {
+        Found(Fields::Any)
+    }
+}
+
+macro_rules! probe_values {
+    ($($method:ident)*) => {$(
+        fn $method<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Found> {
+            Err(Found(Fields::Value))
+        }
+    )*};
+}
+
+impl<'de, 'a> Deserializer<'de> for FieldsProbe<'a> {
+    type Error = Found;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Found> {
+        Err(Found(Fields::Any))
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Found> {
+        Err(Found(Fields::Any))
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Found> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Found> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Found> {
+        match self.key_path.split_first() {
+            Some((key, key_path)) => visitor.visit_map(FieldProbe {
+                key: Some(key),
+                key_path,
+            }),
+            None => Err(Found(Fields::Struct(fields))),
+        }
+    }
+
+    // the map values are probed further, e.g. the persona fields
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Found> {
+        match self.key_path.split_first() {
+            Some((key, key_path)) => visitor.visit_map(FieldProbe {
+                key: Some(key),
+                key_path,
+            }),
+            None => Err(Found(Fields::Any)),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Found> {
+        Err(Found(Fields::Value))
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _visitor: V,
+    ) -> Result<V::Value, Found> {
+        Err(Found(Fields::Value))
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Found> {
+        Err(Found(Fields::Value))
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        _visitor: V,
+    ) -> Result<V::Value, Found> {
+        Err(Found(Fields::Value))
+    }
+
+    probe_values!(
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64 deserialize_f32
+        deserialize_f64 deserialize_char deserialize_str deserialize_string deserialize_bytes
+        deserialize_byte_buf deserialize_unit deserialize_seq deserialize_identifier
+    );
+}
+
+/// Single field of the path
+struct FieldProbe<'a> {
+    key: Option<&'a String>,
+    key_path: &'a [String],
+}
+
+impl<'de, 'a> MapAccess<'de> for FieldProbe<'a> {
+    type Error = Found;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Found> {
+        self.key
+            .take()
+            .map(|key| seed.deserialize(StrDeserializer::<Found>::new(key)))
+            .transpose()
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, Found> {
+        seed.deserialize(FieldsProbe {
+            key_path: self.key_path,
+        })
+    }
+}
+
+fn is_secret(key: &str) -> bool {
+    let key = key.to_lowercase();
+    SECRET_WORDS.iter().any(|w| key.contains(w))
+}
+
+/// Replace the values of the secret fields, e.g. tokens
+pub fn redact_secrets(value: Value) -> Value {
+    match value {
+        Value::Object(object) => Value::Object(
+            object
+                .into_iter()
+                .map(|(key, value)| {
+                    let value = match value {
+                        Value::String(s) if is_secret(&key) && !s.is_empty() => {
+                            Value::String(REDACTED.to_string())
+                        }
+                        value => redact_secrets(value),
+                    };
+                    (key, value)
+                })
+                .collect(),
+        ),
+        Value::Array(array) => Value::Array(array.into_iter().map(redact_secrets).collect()),
+        value => value,
+    }
+}
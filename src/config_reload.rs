@@ -0,0 +1,283 @@
+/// Config reload without restarting the bot
+use std::{
+    collections::BTreeSet,
+    fmt,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde_json::Value;
+use tokio::sync::{mpsc, watch};
+use tracing::{error, info, warn};
+
+use crate::{
+    config::Config,
+    config_loader::{local_path, ConfigArgs, ConfigError, Layers},
+    config_validation::ValidationReport,
+    dispatcher::Dispatcher,
+};
+
+/// Fields applied without restarting the bot, see `Config::apply_live`
+pub const LIVE_FIELDS: [&str; 6] = [
+    "AI_initial_prompt",
+    "Busy_messages",
+    "Discord_Config.Discord_channel_whitelist",
+    "TTS_Config",
+    "STT_Config.Minimal_audio_fragment_length",
+    "STT_Config.Maximal_audio_fragment_length",
+];
+
+/// How often the config files are checked for changes
+pub const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Current config, updated on reload
+pub type LiveConfig = watch::Receiver<Arc<Config>>;
+
+/// Config that is never reloaded
+pub fn fixed_config(config: Config) -> LiveConfig {
+    watch::channel(Arc::new(config)).1
+}
+
+/// Changed fields of the reloaded config
+#[derive(Debug, Default, PartialEq)]
+pub struct ConfigChanges {
+    /// Applied to the running bot
+    pub live: Vec<String>,
+    /// Ignored until restart
+    pub restart: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn between(old: &Value, new: &Value) -> Self {
+        let mut changed = vec![];
+        changed_fields(old, new, &mut vec![], &mut changed);
+
+        let (live, restart) = changed.into_iter().partition(|path| is_live(path));
+        Self { live, restart }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty() && self.restart.is_empty()
+    }
+}
+
+fn is_live(key_path: &str) -> bool {
+    LIVE_FIELDS.iter().any(|field| {
+        key_path == *field
+            || key_path
+                .strip_prefix(field)
+                .is_some_and(|rest| rest.starts_with('.'))
+    })
+}
+
+/// Paths of the changed fields, objects are compared field by field
+fn changed_fields(old: &Value, new: &Value, path: &mut Vec<String>, changed: &mut Vec<String>) {
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            let keys = old.keys().chain(new.keys()).collect::<BTreeSet<_>>();
+            for key in keys {
+                path.push(key.clone());
+                changed_fields(
+                    old.get(key).unwrap_or(&Value::Null),
+                    new.get(key).unwrap_or(&Value::Null),
+                    path,
+                    changed,
+                );
+                path.pop();
+            }
+        }
+        (old, new) if old != new => changed.push(path.join(".")),
+        _ => {}
+    }
+}
+
+impl Config {
+    /// Take the fields listed in `LIVE_FIELDS` from the reloaded config
+    pub fn apply_live(&mut self, new: &Config) {
+        self.initial_prompt = new.initial_prompt.clone();
+        self.busy_messages = new.busy_messages.clone();
+        self.discord_config.channel_whitelist = new.discord_config.channel_whitelist.clone();
+        self.tts_config = new.tts_config.clone();
+        self.stt_config.minimal_audio_fragment_length =
+            new.stt_config.minimal_audio_fragment_length;
+        self.stt_config.maximal_audio_fragment_length =
+            new.stt_config.maximal_audio_fragment_length;
+    }
+}
+
+#[derive(Debug)]
+pub enum ReloadError {
+    Load(ConfigError),
+    Invalid(ValidationReport),
+}
+
+impl fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReloadError::Load(e) => write!(f, "{e}"),
+            ReloadError::Invalid(report) => write!(f, "{report}"),
+        }
+    }
+}
+
+/// Loads the config files again and publishes the config with the live fields updated
+pub struct ConfigReloader {
+    path: PathBuf,
+    /// Last loaded files, the changes are reported once
+    value: Value,
+    config: Config,
+    tx: watch::Sender<Arc<Config>>,
+}
+
+impl ConfigReloader {
+    /// `value` - merged config files the `config` was made of
+    pub fn new(path: PathBuf, value: Value, config: Config) -> Self {
+        let (tx, _) = watch::channel(Arc::new(config.clone()));
+        Self {
+            path,
+            value,
+            config,
+            tx,
+        }
+    }
+
+    pub fn subscribe(&self) -> LiveConfig {
+        self.tx.subscribe()
+    }
+
+    /// The running config is kept if the new one is invalid
+    pub fn reload(&mut self) -> Result<ConfigChanges, ReloadError> {
+        let layers = Layers::load(&self.path, std::env::vars()).map_err(ReloadError::Load)?;
+        let new = layers.to_config().map_err(ReloadError::Load)?;
+        let report = new.validate_values();
+        if report.has_errors() {
+            return Err(ReloadError::Invalid(report));
+        }
+
+        let changes = ConfigChanges::between(&self.value, &layers.value);
+        self.value = layers.value;
+        if !changes.live.is_empty() {
+            self.config.apply_live(&new);
+            self.tx.send_replace(Arc::new(self.config.clone()));
+        }
+        Ok(changes)
+    }
+
+    /// Modification times of the config file and its local override file
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        [self.path.clone(), local_path(&self.path)]
+            .iter()
+            .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+
+    fn reload_logged(&mut self) {
+        match self.reload() {
+            Ok(changes) if changes.is_empty() => info!("Config is not changed"),
+            Ok(changes) => {
+                if !changes.live.is_empty() {
+                    info!("Config reloaded: {}", changes.live.join(", "));
+                }
+                if !changes.restart.is_empty() {
+                    warn!(
+                        "Config changes applied after restart: {}",
+                        changes.restart.join(", ")
+                    );
+                }
+            }
+            Err(e) => error!("Config is not reloaded: {e}"),
+        }
+    }
+
+    /// Reload on the config files change and on SIGHUP, until all the subscribers are dropped
+    pub fn spawn(mut self) -> LiveConfig {
+        let config = self.subscribe();
+
+        let (hangup_tx, mut hangup_rx) = mpsc::channel(1);
+        #[cfg(unix)]
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    error!("Failed to listen SIGHUP: {e}");
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                if hangup_tx.send(()).await.is_err() {
+                    break;
+                }
+            }
+        });
+        #[cfg(not(unix))]
+        drop(hangup_tx);
+
+        tokio::spawn(async move {
+            let mut modified = self.modified();
+            let mut interval = tokio::time::interval(WATCH_INTERVAL);
+            while !self.tx.is_closed() {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let now = self.modified();
+                        if now == modified {
+                            continue;
+                        }
+                        modified = now;
+                        info!("Config file changed, reloading...");
+                    }
+                    Some(()) = hangup_rx.recv() => info!("SIGHUP received, reloading config..."),
+                }
+                self.reload_logged();
+            }
+        });
+
+        config
+    }
+}
+
+impl ConfigArgs {
+    /// Start watching the config files, `config` is the loaded one
+    pub fn watch(&self, config: Config) -> LiveConfig {
+        let layers =
+            Layers::load(&self.config_path, std::env::vars()).unwrap_or_else(|e| panic!("{e}"));
+        ConfigReloader::new(self.config_path.clone(), layers.value, config).spawn()
+    }
+}
+
+/// New channels of the dispatcher use the reloaded config
+pub fn follow_config(dispatcher: Arc<dyn Dispatcher>, mut config: LiveConfig) {
+    tokio::spawn(async move {
+        while config.changed().await.is_ok() {
+            let current = config.borrow_and_update().clone();
+            dispatcher.reload_config(&current);
+        }
+    });
+}
+
+/// Value made of the config, made again after the config is reloaded
+pub struct Live<T> {
+    state: Mutex<(LiveConfig, Arc<T>)>,
+    make: Box<dyn Fn(&Config) -> T + Send + Sync>,
+}
+
+impl<T> Live<T> {
+    pub fn new(config: LiveConfig, make: impl Fn(&Config) -> T + Send + Sync + 'static) -> Self {
+        let value = Arc::new(make(&config.borrow()));
+        Self {
+            state: Mutex::new((config, value)),
+            make: Box::new(make),
+        }
+    }
+
+    pub fn get(&self) -> Arc<T> {
+        let mut state = self.state.lock().unwrap();
+        let (config, value) = &mut *state;
+        if config.has_changed().unwrap_or(false) {
+            *value = Arc::new((self.make)(&config.borrow_and_update()));
+        }
+        value.clone()
+    }
+}
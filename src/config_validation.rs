@@ -0,0 +1,728 @@
+/// Startup checks of the config, all the problems are reported at once
+use std::{collections::HashSet, fmt, time::Duration};
+
+use futures_util::future::join_all;
+use regex::{Regex, RegexBuilder};
+use reqwest::Url;
+use tokio::net::TcpStream;
+
+use crate::{
+    config::{
+        AIEngineType, CandidateRuleConfig, Config, EmbeddingsConfig, LocalModelConfig,
+        MiddlewareConfig, ModerationAction, ServicePolicy, TTSConfig, GPT_VERSIONS, MAIN_BACKEND,
+    },
+    persona::DEFAULT_PERSONA,
+    resilience::SERVICES,
+    tools::BUILTIN_TOOLS,
+};
+
+/// How long to wait for a service to accept the connection
+const URL_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    /// The bot can't work with this value
+    Error,
+    /// The bot works, but probably not as intended
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigProblem {
+    pub severity: Severity,
+    /// Field path, e.g. `AIEngine.Temperature`
+    pub key_path: String,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{severity}: {}: {}", self.key_path, self.message)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ValidationReport {
+    pub problems: Vec<ConfigProblem>,
+}
+
+impl ValidationReport {
+    fn add(&mut self, severity: Severity, key_path: impl Into<String>, message: impl Into<String>) {
+        self.problems.push(ConfigProblem {
+            severity,
+            key_path: key_path.into(),
+            message: message.into(),
+        });
+    }
+
+    fn error(&mut self, key_path: impl Into<String>, message: impl Into<String>) {
+        self.add(Severity::Error, key_path, message);
+    }
+
+    fn warning(&mut self, key_path: impl Into<String>, message: impl Into<String>) {
+        self.add(Severity::Warning, key_path, message);
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.problems.iter().any(|p| p.severity == Severity::Error)
+    }
+
+    /// Key paths of the problems with the given severity
+    pub fn key_paths(&self, severity: Severity) -> Vec<&str> {
+        self.problems
+            .iter()
+            .filter(|p| p.severity == severity)
+            .map(|p| p.key_path.as_str())
+            .collect()
+    }
+
+    fn check_range(&mut self, key_path: String, value: Option<f32>, min: f32, max: f32) {
+        match value {
+            Some(value) if !(min..=max).contains(&value) => {
+                self.error(key_path, format!("{value} is out of range {min}..{max}"))
+            }
+            _ => {}
+        }
+    }
+
+    fn check_engine(&mut self, prefix: &str, engine_type: &AIEngineType) {
+        match engine_type {
+            AIEngineType::ChatGPT {
+                openai_token,
+                engine,
+            } => {
+                if openai_token.is_empty() {
+                    self.error(format!("{prefix}.OpenAI_Token"), "empty");
+                }
+                if let Some(engine) = engine {
+                    if !GPT_VERSIONS.contains(&engine.as_str()) {
+                        self.error(
+                            format!("{prefix}.GPT_Version"),
+                            format!(
+                                "unknown version {engine}, supported values {}",
+                                GPT_VERSIONS.join(", ")
+                            ),
+                        );
+                    }
+                }
+            }
+            AIEngineType::LLaMa { .. } => {}
+            AIEngineType::LlamaCpp(local) => {
+                if local.model.is_some() {
+                    self.warning(
+                        format!("{prefix}.Model"),
+                        "ignored, llama.cpp serves the model it was started with",
+                    );
+                }
+                self.check_local_model(prefix, local);
+            }
+            AIEngineType::Ollama(local) => {
+                if local.model.as_deref().map_or(true, |m| m.trim().is_empty()) {
+                    self.error(format!("{prefix}.Model"), "required by Ollama");
+                }
+                if local.grammar.is_some() {
+                    self.warning(
+                        format!("{prefix}.Grammar"),
+                        "not supported by Ollama, use Json_mode",
+                    );
+                }
+                self.check_local_model(prefix, local);
+            }
+        }
+    }
+
+    /// Settings shared by llama.cpp and Ollama
+    fn check_local_model(&mut self, prefix: &str, local: &LocalModelConfig) {
+        if local.max_tokens == Some(0) {
+            self.error(format!("{prefix}.Max_tokens"), "must be at least 1");
+        }
+        for (i, stop) in local.stop.iter().enumerate() {
+            if stop.is_empty() {
+                self.error(format!("{prefix}.Stop[{i}]"), "empty");
+            }
+        }
+        if local.grammar.is_some() && local.json_mode {
+            self.warning(
+                format!("{prefix}.Json_mode"),
+                "ignored, Grammar is used instead",
+            );
+        }
+    }
+
+    fn check_sampling(
+        &mut self,
+        prefix: &str,
+        temperature: Option<f32>,
+        top_p: Option<f32>,
+        presence_penalty: Option<f32>,
+        frequency_penalty: Option<f32>,
+    ) {
+        self.check_range(format!("{prefix}.Temperature"), temperature, 0.0, 2.0);
+        self.check_range(format!("{prefix}.Top_p"), top_p, 0.0, 1.0);
+        self.check_range(
+            format!("{prefix}.Presence_penalty"),
+            presence_penalty,
+            -2.0,
+            2.0,
+        );
+        self.check_range(
+            format!("{prefix}.Frequency_penalty"),
+            frequency_penalty,
+            -2.0,
+            2.0,
+        );
+    }
+
+    fn check_service_policy(&mut self, prefix: &str, policy: &ServicePolicy) {
+        let key_path = |field: &str| format!("{prefix}.{field}");
+        if policy.timeout <= 0.0 {
+            self.error(key_path("Timeout"), "must be positive");
+        }
+        if policy.initial_backoff < 0.0 {
+            self.error(key_path("Initial_backoff"), "must not be negative");
+        }
+        if policy.max_backoff < policy.initial_backoff {
+            self.error(
+                key_path("Max_backoff"),
+                format!("less than Initial_backoff {}", policy.initial_backoff),
+            );
+        }
+        if policy.breaker_cooldown < 0.0 {
+            self.error(key_path("Breaker_cooldown"), "must not be negative");
+        }
+    }
+
+    fn check_busy_messages(&mut self, key_path: String, messages: &[String]) {
+        if messages.is_empty() {
+            self.warning(key_path, "empty, the default message is used");
+        }
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let errors = self.key_paths(Severity::Error).len();
+        let warnings = self.problems.len() - errors;
+        write!(f, "Config has {errors} error(s) and {warnings} warning(s)")?;
+        for problem in &self.problems {
+            write!(f, "\n  {problem}")?;
+        }
+        Ok(())
+    }
+}
+
+fn tts_url(tts: &TTSConfig) -> Option<&Url> {
+    match tts {
+        TTSConfig::Disabled => None,
+        TTSConfig::SilerioTTSConfig {
+            tts_service_url, ..
+        }
+        | TTSConfig::JPVoicesTTSConfig {
+            tts_service_url, ..
+        } => Some(tts_service_url),
+    }
+}
+
+/// Problem if nothing accepts connections at the URL host and port
+async fn check_url(key_path: String, url: Url) -> Option<ConfigProblem> {
+    let message = match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => {
+            match tokio::time::timeout(URL_CHECK_TIMEOUT, TcpStream::connect((host, port))).await {
+                Ok(Ok(_)) => return None,
+                Ok(Err(e)) => format!("{url} is unreachable: {e}"),
+                Err(_) => format!("{url} is unreachable: no answer in {URL_CHECK_TIMEOUT:?}"),
+            }
+        }
+        _ => format!("{url} has no host or port"),
+    };
+    // the service may be started later, so the bot is not stopped
+    Some(ConfigProblem {
+        severity: Severity::Warning,
+        key_path,
+        message,
+    })
+}
+
+/// Checked in parallel, each URL once
+pub async fn check_urls(urls: Vec<(String, Url)>) -> Vec<ConfigProblem> {
+    let mut seen = HashSet::new();
+    let checks = urls
+        .into_iter()
+        .filter(|(_, url)| seen.insert(url.clone()))
+        .map(|(key_path, url)| check_url(key_path, url));
+    join_all(checks).await.into_iter().flatten().collect()
+}
+
+impl Config {
+    /// All the problems of the config, including the unreachable services
+    pub async fn validate(&self) -> ValidationReport {
+        let mut report = self.validate_values();
+        report
+            .problems
+            .extend(check_urls(self.service_urls()).await);
+        report
+    }
+
+    /// Checks that don't need the network
+    pub fn validate_values(&self) -> ValidationReport {
+        let mut report = ValidationReport::default();
+
+        let ai = &self.ai_engine;
+        report.check_engine("AIEngine.Engine_Type", &ai.engine_type);
+        self.check_fallback(&mut report);
+        report.check_sampling(
+            "AIEngine",
+            ai.temperature,
+            ai.top_p,
+            ai.presence_penalty,
+            ai.frequency_penalty,
+        );
+        if ai.reply_count == Some(0) {
+            report.error("AIEngine.Reply_count", "must be at least 1");
+        }
+        self.check_candidates(&mut report);
+        if ai.context_budget.max_tokens == 0 {
+            report.error("AIEngine.Context_budget.Max_tokens", "must be at least 1");
+        }
+        for (i, tool) in ai.tools.iter().enumerate() {
+            if !BUILTIN_TOOLS.contains(&tool.as_str()) {
+                report.error(
+                    format!("AIEngine.Tools[{i}]"),
+                    format!(
+                        "unknown tool {tool}, supported values {}",
+                        BUILTIN_TOOLS.join(", ")
+                    ),
+                );
+            } else if tool == "remember" && self.memory.is_none() {
+                report.warning(
+                    format!("AIEngine.Tools[{i}]"),
+                    "\"remember\" needs the Memory config, the tool is skipped",
+                );
+            }
+        }
+
+        if self.initial_prompt.trim().is_empty() {
+            report.warning("AI_initial_prompt", "empty");
+        }
+
+        for (i, pattern) in self.discord_config.channel_whitelist.iter().enumerate() {
+            if let Err(e) = Regex::new(pattern) {
+                report.error(
+                    format!("Discord_Config.Discord_channel_whitelist[{i}]"),
+                    format!("invalid regex: {e}"),
+                );
+            }
+        }
+
+        report.check_busy_messages("Busy_messages".to_string(), &self.busy_messages);
+
+        let stt = &self.stt_config;
+        if stt.minimal_audio_fragment_length < 0.0 {
+            report.error("STT_Config.Minimal_audio_fragment_length", "is negative");
+        }
+        if stt.maximal_audio_fragment_length <= 0.0 {
+            report.error(
+                "STT_Config.Maximal_audio_fragment_length",
+                "must be positive",
+            );
+        } else if stt.minimal_audio_fragment_length > stt.maximal_audio_fragment_length {
+            report.error(
+                "STT_Config.Minimal_audio_fragment_length",
+                format!(
+                    "{} is greater than Maximal_audio_fragment_length {}",
+                    stt.minimal_audio_fragment_length, stt.maximal_audio_fragment_length
+                ),
+            );
+        }
+
+        if self.max_concurrent_requests == 0 {
+            report.error("Max_concurrent_requests", "must be at least 1");
+        }
+
+        report.check_service_policy("Resilience", &self.resilience.default);
+        let mut services = self.resilience.services.iter().collect::<Vec<_>>();
+        services.sort_by_key(|(name, _)| name.as_str());
+        for (name, policy) in services {
+            let prefix = format!("Resilience.Services.{name}");
+            if !SERVICES.contains(&name.as_str()) {
+                report.warning(
+                    &prefix,
+                    format!("unknown service, known services {}", SERVICES.join(", ")),
+                );
+            }
+            report.check_service_policy(&prefix, policy);
+        }
+
+        self.check_usage(&mut report);
+        self.check_vtube_studio(&mut report);
+
+        if let Some(memory) = &self.memory {
+            if memory.max_facts == 0 {
+                report.error("Memory.Max_facts", "must be at least 1");
+            }
+        }
+
+        let mut personas = self.personas.iter().collect::<Vec<_>>();
+        personas.sort_by_key(|(name, _)| name.as_str());
+        for (name, persona) in personas {
+            let prefix = format!("Personas.{name}");
+            report.check_sampling(
+                &prefix,
+                persona.temperature,
+                persona.top_p,
+                persona.presence_penalty,
+                persona.frequency_penalty,
+            );
+            if persona.initial_prompt.trim().is_empty() {
+                report.warning(format!("{prefix}.AI_initial_prompt"), "empty");
+            }
+            if let Some(busy_messages) = &persona.busy_messages {
+                report.check_busy_messages(format!("{prefix}.Busy_messages"), busy_messages);
+            }
+        }
+
+        for (i, o) in self.overrides.iter().enumerate() {
+            if o.guild.is_none() && o.channel.is_none() && o.name.is_none() {
+                report.error(
+                    format!("Overrides[{i}]"),
+                    "needs Guild, Channel or Name, otherwise it never applies",
+                );
+            }
+            if let Some(pattern) = &o.name {
+                if let Err(e) = pattern.regex() {
+                    report.error(
+                        format!("Overrides[{i}].Name"),
+                        format!("invalid regex: {e}"),
+                    );
+                }
+            }
+            if o.answer_lang.as_deref().map(str::trim) == Some("") {
+                report.error(format!("Overrides[{i}].Answer_lang"), "empty");
+            }
+        }
+
+        if let Some(moderation) = &self.moderation {
+            for (i, pattern) in moderation.banned_words.iter().enumerate() {
+                if let Err(e) = RegexBuilder::new(pattern).case_insensitive(true).build() {
+                    report.error(
+                        format!("Moderation.Banned_words[{i}]"),
+                        format!("invalid regex: {e}"),
+                    );
+                }
+            }
+            let replaces = std::iter::once(moderation.default_action)
+                .chain(moderation.actions.values().copied())
+                .any(|a| a != ModerationAction::Mask);
+            if replaces && moderation.canned_lines.is_empty() {
+                report.warning("Moderation.Canned_lines", "empty, the default line is used");
+            }
+        }
+
+        let chain = self
+            .middleware
+            .clone()
+            .unwrap_or_else(|| MiddlewareConfig::default_chain(self.moderation.is_some()));
+        for (i, layer) in chain.iter().enumerate() {
+            match layer {
+                MiddlewareConfig::Moderation if self.moderation.is_none() => report.error(
+                    format!("Middleware[{i}]"),
+                    "Moderation layer needs the Moderation config",
+                ),
+                MiddlewareConfig::Translate {
+                    dest_lang,
+                    drop_nonconfident_result,
+                    ..
+                } => {
+                    if !(0.0..=1.0).contains(drop_nonconfident_result) {
+                        report.error(
+                            format!("Middleware[{i}].Drop_nonconfident_lvl"),
+                            format!("{drop_nonconfident_result} is out of range 0..1"),
+                        );
+                    }
+                    if dest_lang.is_none() {
+                        self.check_answer_langs(&mut report);
+                    }
+                }
+                MiddlewareConfig::Sanitize => {
+                    let cleaned_before = chain[..i].iter().any(|layer| {
+                        matches!(
+                            layer,
+                            MiddlewareConfig::Translate { .. } | MiddlewareConfig::NumbersToWords
+                        )
+                    });
+                    if cleaned_before {
+                        report.warning(
+                            format!("Middleware[{i}]"),
+                            "should be before Translate and NumbersToWords to clean their answers",
+                        );
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        report
+    }
+
+    /// Rules choosing the best of `Reply_count` replies
+    fn check_candidates(&self, report: &mut ValidationReport) {
+        let ai = &self.ai_engine;
+        let several = ai.reply_count.unwrap_or(1) > 1;
+        if several && ai.engine_type.local_model().is_some() {
+            report.warning(
+                "AIEngine.Reply_count",
+                "ignored, llama.cpp and Ollama return one reply",
+            );
+        }
+        let Some(rules) = &ai.candidate_rules else {
+            return;
+        };
+        if !several {
+            report.warning(
+                "AIEngine.Candidate_rules",
+                "ignored, Reply_count is less than 2",
+            );
+        }
+        for (i, rule) in rules.iter().enumerate() {
+            let prefix = format!("AIEngine.Candidate_rules[{i}]");
+            match rule {
+                CandidateRuleConfig::Length { min, max, .. } if min > max => {
+                    report.error(format!("{prefix}.Min"), format!("greater than Max {max}"));
+                }
+                CandidateRuleConfig::BannedWords { words, .. } => {
+                    for (j, pattern) in words.iter().enumerate() {
+                        if let Err(e) = RegexBuilder::new(pattern).case_insensitive(true).build() {
+                            report.error(
+                                format!("{prefix}.Words[{j}]"),
+                                format!("invalid regex: {e}"),
+                            );
+                        }
+                    }
+                }
+                CandidateRuleConfig::Language { lang, .. } if lang.trim().is_empty() => {
+                    report.error(format!("{prefix}.Lang"), "empty");
+                }
+                CandidateRuleConfig::Repetition { window: 0, .. } => {
+                    report.error(format!("{prefix}.Window"), "must be at least 1");
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Budgets and the rate limit, zero would reject every request
+    fn check_usage(&self, report: &mut ValidationReport) {
+        let usage = &self.usage;
+        let budgets = [
+            ("Usage.Daily_budget", usage.daily_budget),
+            ("Usage.Monthly_budget", usage.monthly_budget),
+            ("Usage.User_daily_budget", usage.user_daily_budget),
+        ];
+        for (key_path, budget) in budgets {
+            if budget == Some(0) {
+                report.error(key_path, "must be positive, remove it to disable the limit");
+            }
+        }
+        if let (Some(daily), Some(monthly)) = (usage.daily_budget, usage.monthly_budget) {
+            if daily > monthly {
+                report.warning(
+                    "Usage.Daily_budget",
+                    format!("greater than Monthly_budget {monthly}"),
+                );
+            }
+        }
+        if usage.user_requests == Some(0) {
+            report.error(
+                "Usage.User_requests",
+                "must be positive, remove it to disable the limit",
+            );
+        }
+        if usage.user_requests.is_some() && usage.user_rate_period <= 0.0 {
+            report.error("Usage.User_rate_period", "must be positive");
+        }
+    }
+
+    /// Avatar control, the emotions need something to show
+    fn check_vtube_studio(&self, report: &mut ValidationReport) {
+        let Some(vts) = &self.vtube_studio else {
+            return;
+        };
+        if !matches!(vts.url.scheme(), "ws" | "wss") {
+            report.error("VTube_Studio.Url", "must be a ws:// or wss:// URL");
+        }
+        if vts.mouth_fps == 0 {
+            report.error("VTube_Studio.Mouth_fps", "must be at least 1");
+        }
+        if vts.mouth_gain <= 0.0 {
+            report.error("VTube_Studio.Mouth_gain", "must be positive");
+        }
+        for (i, emotion) in vts.emotions.iter().enumerate() {
+            let prefix = format!("VTube_Studio.Emotions[{i}]");
+            if emotion.name.trim().is_empty() {
+                report.error(format!("{prefix}.Name"), "empty");
+            }
+            if emotion.expression.is_none() && emotion.hotkey.is_none() {
+                report.warning(
+                    &prefix,
+                    "has neither Expression nor Hotkey, nothing is shown",
+                );
+            }
+        }
+    }
+
+    /// Backend names, the routing rules and the timeout
+    fn check_fallback(&self, report: &mut ValidationReport) {
+        let ai = &self.ai_engine;
+        let mut names = vec![MAIN_BACKEND];
+        for (i, fallback) in ai.fallback.iter().enumerate() {
+            let prefix = format!("AIEngine.Fallback[{i}]");
+            if fallback.name.trim().is_empty() {
+                report.error(format!("{prefix}.Name"), "empty");
+            } else if names.contains(&fallback.name.as_str()) {
+                report.error(
+                    format!("{prefix}.Name"),
+                    format!("{} is already used", fallback.name),
+                );
+            }
+            names.push(&fallback.name);
+            report.check_engine(&format!("{prefix}.Engine_Type"), &fallback.engine_type);
+        }
+
+        let mut engines =
+            std::iter::once(&ai.engine_type).chain(ai.fallback.iter().map(|f| &f.engine_type));
+        if !ai.tools.is_empty() && engines.any(|e| e.local_model().is_some()) {
+            report.warning(
+                "AIEngine.Tools",
+                "not supported by the local models, they answer without the tools",
+            );
+        }
+
+        if let Some(timeout) = ai.backend_timeout {
+            if timeout <= 0.0 {
+                report.error("AIEngine.Backend_timeout", "must be positive");
+            }
+        }
+
+        for (i, route) in ai.routing.iter().enumerate() {
+            let prefix = format!("AIEngine.Routing[{i}]");
+            if !names.contains(&route.backend.as_str()) {
+                report.error(
+                    format!("{prefix}.Backend"),
+                    format!(
+                        "unknown backend {}, known backends {}",
+                        route.backend,
+                        names.join(", ")
+                    ),
+                );
+            }
+            if let (Some(min), Some(max)) = (route.min_length, route.max_length) {
+                if min > max {
+                    report.error(
+                        format!("{prefix}.Min_length"),
+                        format!("{min} is greater than Max_length {max}"),
+                    );
+                }
+            }
+            if let Some(persona) = &route.persona {
+                if persona != DEFAULT_PERSONA && !self.personas.contains_key(persona) {
+                    report.warning(
+                        format!("{prefix}.Persona"),
+                        format!("unknown persona {persona}, the rule never matches"),
+                    );
+                }
+            }
+        }
+    }
+
+    /// Translation needs the answer language of every persona
+    fn check_answer_langs(&self, report: &mut ValidationReport) {
+        if self.deeplx_translate_config.dest_lang.trim().is_empty() {
+            report.error(
+                "DeepLx_Translate_Config.Answer_lang",
+                "empty, but the answers are translated",
+            );
+        }
+        for (name, persona) in &self.personas {
+            if persona.answer_lang.as_deref().map(str::trim) == Some("") {
+                report.error(
+                    format!("Personas.{name}.Answer_lang"),
+                    "empty, but the answers are translated",
+                );
+            }
+        }
+    }
+
+    /// URLs of the services the bot connects to, by field path
+    pub fn service_urls(&self) -> Vec<(String, Url)> {
+        let mut urls = vec![];
+
+        let fallback = self.ai_engine.fallback.iter().enumerate();
+        let engines = std::iter::once((
+            "AIEngine.Engine_Type".to_string(),
+            &self.ai_engine.engine_type,
+        ))
+        .chain(fallback.map(|(i, f)| {
+            (
+                format!("AIEngine.Fallback[{i}].Engine_Type"),
+                &f.engine_type,
+            )
+        }));
+        for (prefix, engine_type) in engines {
+            match engine_type {
+                AIEngineType::LLaMa { api_url, .. } => {
+                    urls.push((format!("{prefix}.Url"), api_url.clone()));
+                }
+                AIEngineType::LlamaCpp(local) | AIEngineType::Ollama(local) => {
+                    urls.push((format!("{prefix}.Url"), local.url.clone()));
+                }
+                AIEngineType::ChatGPT { .. } => {}
+            }
+        }
+        if let Some(url) = tts_url(&self.tts_config) {
+            urls.push(("TTS_Config.TTS_Service_Url".to_string(), url.clone()));
+        }
+        let mut personas = self.personas.iter().collect::<Vec<_>>();
+        personas.sort_by_key(|(name, _)| name.as_str());
+        for (name, persona) in personas {
+            if let Some(url) = persona.tts_config.as_ref().and_then(tts_url) {
+                urls.push((
+                    format!("Personas.{name}.TTS_Config.TTS_Service_Url"),
+                    url.clone(),
+                ));
+            }
+        }
+        for (i, o) in self.overrides.iter().enumerate() {
+            if let Some(url) = o.tts_config.as_ref().and_then(tts_url) {
+                urls.push((
+                    format!("Overrides[{i}].TTS_Config.TTS_Service_Url"),
+                    url.clone(),
+                ));
+            }
+        }
+        urls.push((
+            "DeepLx_Translate_Config.Url".to_string(),
+            self.deeplx_translate_config.url.clone(),
+        ));
+        urls.push((
+            "STT_Config.STT_Url".to_string(),
+            self.stt_config.voice2txt_url.clone(),
+        ));
+        if let Some(EmbeddingsConfig::OpenAI { url, .. }) =
+            self.memory.as_ref().and_then(|m| m.embeddings.as_ref())
+        {
+            urls.push(("Memory.Embeddings.Url".to_string(), url.clone()));
+        }
+        if let Some(endpoint) = self.moderation.as_ref().and_then(|m| m.endpoint.as_ref()) {
+            urls.push(("Moderation.Endpoint.Url".to_string(), endpoint.url.clone()));
+        }
+        if let Some(vts) = &self.vtube_studio {
+            urls.push(("VTube_Studio.Url".to_string(), vts.url.clone()));
+        }
+
+        urls
+    }
+}
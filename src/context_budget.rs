@@ -0,0 +1,53 @@
+use crate::config::ContextBudgetConfig;
+
+/// Start of the system message that holds the summary of the older turns
+pub const SUMMARY_PREFIX: &str = "Summary of the earlier conversation: ";
+
+/// Service tokens the API adds to every message
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// Rough estimate of the message size in tokens, about 4 characters per token
+pub fn estimate_tokens(text: &str) -> usize {
+    MESSAGE_OVERHEAD_TOKENS + text.chars().count().div_ceil(4)
+}
+
+/// Decides which part of the conversation history no longer fits into the context window
+pub struct ContextBudget {
+    config: ContextBudgetConfig,
+}
+
+impl ContextBudget {
+    pub fn new(config: ContextBudgetConfig) -> Self {
+        Self { config }
+    }
+
+    /// Instruction for the model to summarize older turns
+    pub fn summary_prompt(&self) -> &str {
+        &self.config.summary_prompt
+    }
+
+    /// Returns how many of the oldest messages have to be folded into the summary.
+    /// `pinned_tokens` - size of everything that is always sent (system prompt, summary, new request),
+    /// `messages` - sizes of the history messages from the oldest to the newest
+    pub fn messages_to_fold(&self, pinned_tokens: usize, messages: &[usize]) -> usize {
+        let max_tokens = self.config.max_tokens;
+        let total = pinned_tokens + messages.iter().sum::<usize>();
+        if total <= max_tokens {
+            return 0;
+        }
+
+        // whole turns only, a question without the answer confuses the model
+        let keep = (self.config.keep_recent_turns * 2).min(messages.len());
+        let mut fold = messages.len() - keep;
+        fold = (fold + fold % 2).min(messages.len());
+
+        // recent turns alone do not fit, fold them too, but the last turn stays
+        let mut rest = pinned_tokens + messages[fold..].iter().sum::<usize>();
+        while rest > max_tokens && fold + 2 < messages.len() {
+            rest -= messages[fold] + messages[fold + 1];
+            fold += 2;
+        }
+
+        fold
+    }
+}
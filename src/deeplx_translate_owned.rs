@@ -5,17 +5,27 @@ use std::{
 
 use async_trait::async_trait;
 
-use maplit::hashmap;
-use reqwest::header;
+use reqwest::{header, Url};
 use serde_json::Value;
+use tokio::sync::mpsc::{self, Sender};
 use tracing::{debug, trace};
 
 use crate::{
     ai_translated_request::TranslatedAIRequest,
-    dispatcher::{AIError, AIRequest, AIResponseType, AIinterface},
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    errors::TranslateError,
+    resilience::{self, check_status, Failure, Service},
+    usage::TokenUsage,
 };
 
-static DEEPLX_URL: &str = "https://www2.deepl.com/jsonrpc";
+pub static DEEPLX_URL: &str = "https://www2.deepl.com/jsonrpc";
+
+/// Numbers written in words are translated better, so they are preferred if available
+fn text_to_translate(answer: &HashMap<AIResponseType, String>) -> &String {
+    answer
+        .get(&AIResponseType::NoDigits)
+        .unwrap_or(&answer[&AIResponseType::RawAnswer])
+}
 
 fn get_i_count(translate_text: &str) -> u64 {
     translate_text.chars().filter(|c| *c == 'i').count() as u64
@@ -36,21 +46,24 @@ fn get_time_stamp(mut i_count: u64) -> u64 {
 }
 
 /// Rust port of https://github.com/OwO-Network/DeepLX/blob/main/main.go
-pub struct DeepLxTranslatorOwned {
-    src_lang: Option<String>,
-    dest_lang: String,
-    ai: Box<dyn AIinterface>,
+pub struct DeepLxTranslator {
     id: i64,
     drop_nonconfident_result: Option<f64>,
+    service: Service,
+    url: Url,
 
     pub headers: header::HeaderMap,
 }
 
-impl DeepLxTranslatorOwned {
-    fn to_header(h: &mut header::HeaderMap, key: &'static str, value: &'static str) {
-        h.insert(key, header::HeaderValue::from_static(value));
-    }
+/// AI wrapper, translates requests to english and answers back to the user language
+pub struct DeepLxTranslatorOwned {
+    src_lang: Option<String>,
+    dest_lang: String,
+    ai: Box<dyn AIinterface>,
+    translator: DeepLxTranslator,
+}
 
+impl DeepLxTranslatorOwned {
     /**
      * ai - An AI object implements AIinterface
      * src_lang - Source text language or None (Auto)
@@ -76,6 +89,44 @@ impl DeepLxTranslatorOwned {
             "en".to_string()
         };
 
+        Self {
+            src_lang,
+            dest_lang: dl,
+            ai,
+            translator: DeepLxTranslator::new(drop_nonconfident_result),
+        }
+    }
+
+    /// DeepL JSON-RPC endpoint, `DEEPLX_URL` by default
+    pub fn with_url(mut self, url: Url) -> Self {
+        self.translator = self.translator.with_url(url);
+        self
+    }
+
+    pub async fn translate<S, SRC, DEST>(
+        &mut self,
+        text: S,
+        src_lang: Option<SRC>,
+        dest_lang: DEST,
+        override_drop_nonconfident_result: Option<f64>,
+    ) -> Result<String, TranslateError>
+    where
+        S: Into<String>,
+        SRC: Into<String>,
+        DEST: Into<String>,
+    {
+        self.translator
+            .translate(text, src_lang, dest_lang, override_drop_nonconfident_result)
+            .await
+    }
+}
+
+impl DeepLxTranslator {
+    fn to_header(h: &mut header::HeaderMap, key: &'static str, value: &'static str) {
+        h.insert(key, header::HeaderValue::from_static(value));
+    }
+
+    pub fn new(drop_nonconfident_result: Option<f64>) -> Self {
         let random_start_id = (rand::random::<i64>() % 99999 + 8300000) * 1000;
 
         let mut headers = header::HeaderMap::new();
@@ -96,24 +147,29 @@ impl DeepLxTranslatorOwned {
         Self::to_header(&mut headers, "Connection", "keep-alive");
 
         Self {
-            src_lang,
-            dest_lang: dl,
-            ai,
             // generate random id
             id: random_start_id,
             drop_nonconfident_result,
+            service: Service::get(resilience::DEEPLX),
+            url: Url::parse(DEEPLX_URL).unwrap(),
 
             headers,
         }
     }
 
+    /// DeepL JSON-RPC endpoint, `DEEPLX_URL` by default
+    pub fn with_url(mut self, url: Url) -> Self {
+        self.url = url;
+        self
+    }
+
     pub async fn translate<S, SRC, DEST>(
         &mut self,
         text: S,
         src_lang: Option<SRC>,
         dest_lang: DEST,
         override_drop_nonconfident_result: Option<f64>,
-    ) -> Result<String, String>
+    ) -> Result<String, TranslateError>
     where
         S: Into<String>,
         SRC: Into<String>,
@@ -157,7 +213,7 @@ impl DeepLxTranslatorOwned {
         self.id = current_id;
 
         // serialise json
-        let post_str = serde_json::to_string(&req).map_err(|e| e.to_string())?;
+        let post_str = req.to_string();
 
         // add space if necessary
         let post_str = if (self.id + 5) % 29 == 0 || (self.id + 3) % 13 == 0 {
@@ -167,47 +223,62 @@ impl DeepLxTranslatorOwned {
         };
 
         let client = reqwest::Client::new();
-        let resp = client
-            .post(DEEPLX_URL)
-            .headers(self.headers.clone())
-            .body(post_str)
-            .send()
+        let (encoding, body) = self
+            .service
+            .call(|| async {
+                let resp = client
+                    .post(self.url.clone())
+                    .headers(self.headers.clone())
+                    .body(post_str.clone())
+                    .send()
+                    .await
+                    .map_err(Failure::from_reqwest)?;
+                let resp = check_status(resp).await?;
+
+                let encoding = resp.headers().get("Content-Encoding").cloned();
+                let body = resp.bytes().await.map_err(Failure::from_reqwest)?;
+                Ok((encoding, body))
+            })
             .await
-            .map_err(|e| e.to_string())?;
-
-        let compressed = match resp.headers().get("Content-Encoding") {
-            Some(v) => {
-                let value = v.to_str().unwrap();
-                if value.contains("br") {
-                    true
-                } else {
-                    return Err(format!("Unsupported Content-Encoding: {}", value));
+            .map_err(|e| TranslateError::Service(self.service.error(e)))?;
+
+        let compressed = match encoding {
+            Some(v) => match v.to_str() {
+                Ok(value) if value.contains("br") => true,
+                _ => {
+                    return Err(TranslateError::BadResponse(format!(
+                        "Unsupported Content-Encoding: {:?}",
+                        v
+                    )))
                 }
-            }
+            },
             None => false,
         };
 
-        let body = resp.bytes().await.map_err(|e| e.to_string())?;
-
         let resp = if compressed {
             // uncompress brotli
             let mut decoder = brotli::Decompressor::new(&body[..], 8192);
             let mut decompressed = Vec::new();
-            decoder.read_to_end(&mut decompressed).unwrap();
+            decoder.read_to_end(&mut decompressed).map_err(|e| {
+                TranslateError::BadResponse(format!("Failed to decompress response: {e}"))
+            })?;
 
-            let resp = String::from_utf8(decompressed)
-                .map_err(|_| "Failed to decode response as UTF-8 string")?;
+            let resp = String::from_utf8(decompressed).map_err(|_| {
+                TranslateError::BadResponse("Failed to decode response as UTF-8 string".to_string())
+            })?;
             trace!("br compressed response: {}", resp);
             resp
         } else {
             // raw response
-            let resp = String::from_utf8(body.to_vec())
-                .map_err(|_| "Failed to decode response as UTF-8 string")?;
+            let resp = String::from_utf8(body.to_vec()).map_err(|_| {
+                TranslateError::BadResponse("Failed to decode response as UTF-8 string".to_string())
+            })?;
             trace!("responce response: {}", resp);
             resp
         };
 
-        let resp = serde_json::from_str(resp.as_str()).map_err(|e| e.to_string())?;
+        let resp = serde_json::from_str(resp.as_str())
+            .map_err(|e| TranslateError::BadResponse(e.to_string()))?;
 
         // Ok resp:
         //  Object {"id": Number(8373055001), "jsonrpc": String("2.0"), "result": Object {"detectedLanguages": Object {}, "lang": String("EN"), "lang_is_confident": Bool(false), "texts": Array [Object {"alternatives": Array [], "text": String("Hi")}]}}
@@ -221,12 +292,15 @@ impl DeepLxTranslatorOwned {
             if let Some(Value::Object(error)) = result.get("error") {
                 if let Value::Number(e) = &error["code"] {
                     if e.eq(&Into::<serde_json::Number>::into(-32600)) {
-                        return Err("Invalid target Lang".to_string());
+                        return Err(TranslateError::InvalidLanguage);
                     } else {
-                        return Err(format!(
-                            "Failed to translate, error {}: {}",
-                            e, &error["message"]
-                        ));
+                        return Err(TranslateError::Rpc {
+                            code: e.as_i64().unwrap_or_default(),
+                            message: error["message"]
+                                .as_str()
+                                .map(str::to_string)
+                                .unwrap_or_else(|| error["message"].to_string()),
+                        });
                     }
                 }
             } else if let Some(Value::Number(id)) = result.get("id") {
@@ -244,7 +318,10 @@ impl DeepLxTranslatorOwned {
                                     {
                                         let unsupported_p = unsupported.as_f64().unwrap();
                                         if unsupported_p > drop_nonconfident_result {
-                                            return Err(format!("Translate input is not confident ({unsupported_p}), posibly gabrage input '{text}'"));
+                                            return Err(TranslateError::NotConfident {
+                                                unsupported: unsupported_p,
+                                                text,
+                                            });
                                         }
                                     }
                                 }
@@ -257,16 +334,26 @@ impl DeepLxTranslatorOwned {
                                 }
                             }
                         }
-                        return Err("Missing text results".to_string());
+                        return Err(TranslateError::BadResponse(
+                            "Missing text results".to_string(),
+                        ));
                     } else {
-                        return Err("Missing translate result".to_string());
+                        return Err(TranslateError::BadResponse(
+                            "Missing translate result".to_string(),
+                        ));
                     }
                 } else {
-                    return Err(format!("Invaid response id {}, actual: {}", id, current_id));
+                    return Err(TranslateError::BadResponse(format!(
+                        "Invaid response id {}, actual: {}",
+                        id, current_id
+                    )));
                 }
             }
         }
-        Err(format!("Failed to parse result ({})", resp))
+        Err(TranslateError::BadResponse(format!(
+            "Failed to parse result ({})",
+            resp
+        )))
     }
 }
 
@@ -286,39 +373,96 @@ impl AIinterface for DeepLxTranslatorOwned {
         // translate input to english
         let translated = self
             .translate(r.clone(), Some(req_lang), "en", None)
-            .await
-            .map_err(|e| AIError::TranslateError(e))?;
+            .await?;
         debug!("{r} ({lang:?}) => {translated}", lang = &self.src_lang);
 
         // preocess AI request
-        let raw_answer = &self
+        let mut res = self
             .ai
             .process(Box::new(TranslatedAIRequest::new(request, translated)))
-            .await?[&AIResponseType::RawAnswer];
-
-        let no_digits_answer = crate::num2words::convert_numbers2words(raw_answer.clone());
+            .await?;
+        let answer = text_to_translate(&res).clone();
 
         // translate answer to user language
         let translated_answer = self
             .translate(
-                no_digits_answer.clone(),
+                answer.clone(),
                 Some("en"),
                 self.dest_lang.clone(),
                 Some(1.0), // do not drop non-confident results
             )
-            .await
-            .map_err(|e| AIError::TranslateError(e))?;
+            .await?;
         debug!(
-            "{raw_answer} => {translated_answer} ({lang})",
+            "{answer} => {translated_answer} ({lang})",
             lang = &self.dest_lang
         );
 
-        let res = hashmap! {
-            AIResponseType::RawAnswer => raw_answer.clone(),
-            AIResponseType::NoDigits => no_digits_answer,
-            AIResponseType::Translated => translated_answer,
+        res.insert(AIResponseType::Translated, translated_answer);
+        Ok(res)
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let r = request.request();
+        let req_lang = if let Some(l) = &self.src_lang {
+            l.clone()
+        } else {
+            request.lang()
         };
 
+        // translate input to english
+        let translated = self
+            .translate(r.clone(), Some(req_lang), "en", None)
+            .await?;
+        debug!("{r} ({lang:?}) => {translated}", lang = &self.src_lang);
+
+        let (en_chunks_tx, mut en_chunks_rx) = mpsc::channel(16);
+        let dest_lang = self.dest_lang.clone();
+        let translator = &mut self.translator;
+
+        // translate the answer sentence by sentence while the AI is still generating it
+        let translate_sentences = async {
+            let mut translated_sentences = vec![];
+            while let Some(chunk) = en_chunks_rx.recv().await {
+                let chunk = match chunk {
+                    AIResponseChunk::Sentence(mut sentence) => {
+                        let en_sentence = text_to_translate(&sentence).clone();
+                        let translated_sentence = translator
+                            .translate(
+                                en_sentence.clone(),
+                                Some("en"),
+                                dest_lang.clone(),
+                                Some(1.0), // do not drop non-confident results
+                            )
+                            .await?;
+                        debug!("{en_sentence} => {translated_sentence} ({dest_lang})");
+
+                        translated_sentences.push(translated_sentence.clone());
+                        sentence.insert(AIResponseType::Translated, translated_sentence);
+                        AIResponseChunk::Sentence(sentence)
+                    }
+                    other => other,
+                };
+                let _ = chunks.send(chunk).await;
+            }
+            Ok::<_, AIError>(translated_sentences)
+        };
+
+        // preocess AI request
+        let (answer, translated_sentences) = tokio::join!(
+            self.ai.process_streamed(
+                Box::new(TranslatedAIRequest::new(request, translated)),
+                en_chunks_tx
+            ),
+            translate_sentences
+        );
+        let mut res = answer?;
+        let translated_sentences = translated_sentences?;
+
+        res.insert(AIResponseType::Translated, translated_sentences.join(" "));
         Ok(res)
     }
 
@@ -326,6 +470,14 @@ impl AIinterface for DeepLxTranslatorOwned {
         self.ai.reset().await
     }
 
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        self.ai.last_usage()
+    }
+
     async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
         self.ai.save_context(file).await
     }
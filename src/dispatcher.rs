@@ -1,12 +1,32 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{collections::HashMap, path::PathBuf, pin::Pin, sync::Arc};
 
 use std::collections::hash_map::Entry;
 
 use async_trait::async_trait;
+use futures_util::{Stream, StreamExt};
 use maplit::hashmap;
-use serenity::futures::lock::Mutex;
+use tokio::sync::{
+    mpsc::{self, Receiver, Sender},
+    Mutex, Semaphore,
+};
 use tracing::{error, info};
 
+use crate::{
+    channel_overrides::ChannelScope,
+    chat_template::ChatTurn,
+    config::{Config, RequestQueueConfig},
+    errors::{STTError, ServiceError, TTSError, TranslateError},
+    persona::DEFAULT_PERSONA,
+    request_queue::{AnswerListener, PendingRequest, RequestQueue},
+    usage::{TokenUsage, UsageTracker},
+};
+
+/// Размер очереди частей потокового ответа
+pub const CHUNKS_QUEUE_SIZE: usize = 32;
+
+/// Сколько каналов по умолчанию обрабатываются одновременно
+pub const DEFAULT_CONCURRENCY_LIMIT: usize = 4;
+
 #[derive(Debug, Clone)]
 pub enum AIError {
     /// ИИ занят
@@ -15,12 +35,21 @@ pub enum AIError {
     /// Ошибка сети
     NetworkError,
 
-    /// Translate error
-    TranslateError(String),
+    /// Ошибка перевода
+    TranslateError(TranslateError),
 
     /// Answer error
     AnswerError(String),
 
+    /// Ошибка сервиса, который отвечает за ИИ
+    ServiceError(ServiceError),
+
+    /// Ошибка синтеза речи
+    TTSError(TTSError),
+
+    /// Ошибка распознавания речи
+    STTError(STTError),
+
     /// Неизвестная ошибка
     UnknownError,
 
@@ -29,6 +58,9 @@ pub enum AIError {
 
     /// Ошибка контекста
     ContextError,
+
+    /// Персона не найдена в конфигурации
+    UnknownPersona(String),
 }
 
 pub trait AIRequest: Send {
@@ -38,6 +70,76 @@ pub trait AIRequest: Send {
     fn channel(&self) -> String;
     /// Возвращает язык запроса
     fn lang(&self) -> String;
+    /// Возвращает пользователя, о котором ИИ запоминает факты.
+    /// По умолчанию каждый канал - отдельный пользователь
+    fn user(&self) -> String {
+        self.channel()
+    }
+    /// Возвращает всех пользователей, которым отвечает запрос.
+    /// У объединенного запроса их несколько, расход делится между ними
+    fn users(&self) -> Vec<String> {
+        vec![self.user()]
+    }
+    /// Возвращает откуда пришел запрос, по нему выбираются настройки канала.
+    /// По умолчанию определяется только каналом
+    fn scope(&self) -> ChannelScope {
+        ChannelScope::new(self.channel())
+    }
+    /// Возвращает сведения только для этого запроса, например факты о пользователе.
+    /// ИИ получает их системным сообщением перед запросом, в историю они не попадают
+    fn context(&self) -> Option<String> {
+        None
+    }
+}
+
+/// Копия запроса, чтобы задать его ИИ еще раз
+#[derive(Clone)]
+pub struct RetryRequest {
+    request: String,
+    channel: String,
+    lang: String,
+    user: String,
+    scope: ChannelScope,
+    context: Option<String>,
+}
+
+impl RetryRequest {
+    pub fn new(request: &dyn AIRequest) -> Self {
+        Self {
+            request: request.request(),
+            channel: request.channel(),
+            lang: request.lang(),
+            user: request.user(),
+            scope: request.scope(),
+            context: request.context(),
+        }
+    }
+}
+
+impl AIRequest for RetryRequest {
+    fn request(&self) -> String {
+        self.request.clone()
+    }
+
+    fn channel(&self) -> String {
+        self.channel.clone()
+    }
+
+    fn lang(&self) -> String {
+        self.lang.clone()
+    }
+
+    fn user(&self) -> String {
+        self.user.clone()
+    }
+
+    fn scope(&self) -> ChannelScope {
+        self.scope.clone()
+    }
+
+    fn context(&self) -> Option<String> {
+        self.context.clone()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -45,11 +147,40 @@ pub enum AIResponseType {
     RawAnswer,
     NoDigits,
     Translated,
+    /// Текст для TTS: без разметки, эмодзи, ссылок, кода и ремарок
+    Spoken,
+    /// Текст для чата и субтитров: разметка сохранена, теги эмоций убраны
+    Display,
+    /// Ремарки `*...*` и теги эмоций `[...]`, по одной на строку
+    Actions,
+}
+
+/// Часть потокового ответа ИИ
+#[derive(Debug, Clone)]
+pub enum AIResponseChunk {
+    /// Фрагмент сырого ответа в том виде, как он пришел от модели
+    Delta(String),
+    /// Законченное предложение во всех доступных вариантах
+    Sentence(HashMap<AIResponseType, String>),
+    /// Окончательный ответ целиком
+    Done(HashMap<AIResponseType, String>),
+    /// Запрос ожидает в очереди канала, позиция начиная с 1
+    Queued(usize),
+}
+
+/// Поток частей ответа ИИ, заканчивается на `AIResponseChunk::Done` или ошибке
+pub type AIResponseStream = Pin<Box<dyn Stream<Item = Result<AIResponseChunk, AIError>> + Send>>;
+
+fn receiver_stream<T: Send + 'static>(rx: Receiver<T>) -> Pin<Box<dyn Stream<Item = T> + Send>> {
+    Box::pin(futures_util::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|item| (item, rx))
+    }))
 }
 
 /// Интерфейс ИИ:
 ///  - ChatGPT
 ///  - LLaMA
+///  - llama.cpp и Ollama без OpenAI API
 #[async_trait]
 pub trait AIinterface: Sync + Send {
     /// Обработать запрос
@@ -58,93 +189,319 @@ pub trait AIinterface: Sync + Send {
         request: Box<dyn AIRequest>,
     ) -> Result<HashMap<AIResponseType, String>, AIError>;
 
+    /// Обработать запрос, отправляя части ответа в `chunks` по мере готовности.
+    /// По умолчанию весь ответ отправляется одним предложением
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let res = self.process(request).await?;
+        let _ = chunks.send(AIResponseChunk::Sentence(res.clone())).await;
+        Ok(res)
+    }
+
     /// Сбросить состояние ИИ
     async fn reset(&mut self) -> Result<(), AIError>;
 
-    /// Сохранить контекст
-    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError>;
-
-    /// Загрузить контекст
-    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError>;
-}
-
-#[async_trait]
-pub trait AIinterfaceStreamed {
-    /// Обработать запрос
-    async fn process_streamed(&mut self, request: Box<dyn AIRequest>) -> Result<(), AIError>;
+    /// Забыть последний запрос и ответ на него, если ИИ это поддерживает
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        Err(AIError::UnknownError)
+    }
 
-    /// Сбросить состояние ИИ
-    async fn reset(&mut self) -> Result<(), AIError>;
+    /// Сколько токенов потратил последний запрос, `None` если ИИ этого не знает
+    fn last_usage(&self) -> Option<TokenUsage> {
+        None
+    }
 
     /// Сохранить контекст
     async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError>;
 
     /// Загрузить контекст
     fn load_context(&mut self, file: PathBuf) -> Result<(), AIError>;
+
+    /// История беседы, `None` если ИИ ее не отдает
+    fn history(&self) -> Option<Vec<ChatTurn>> {
+        None
+    }
+
+    /// Заменить историю беседы, например при переключении на другую модель.
+    /// По умолчанию игнорируется
+    fn set_history(&mut self, _history: Vec<ChatTurn>) {}
 }
 
 pub trait AIBuilder: Send + Sync {
     fn build(&mut self) -> Box<dyn AIinterface>;
-}
 
-pub trait AIBuilderStreamed: Send + Sync {
-    fn build_streamed(&mut self) -> Box<dyn AIinterfaceStreamed>;
+    /// Построить ИИ для персоны, `None` если персона неизвестна.
+    /// По умолчанию доступна только персона по умолчанию
+    fn build_persona(&mut self, persona: &str) -> Option<Box<dyn AIinterface>> {
+        if persona == DEFAULT_PERSONA {
+            Some(self.build())
+        } else {
+            None
+        }
+    }
+
+    /// Построить ИИ персоны для канала, `scope` выбирает настройки канала.
+    /// По умолчанию настройки канала не учитываются
+    fn build_for(&mut self, _scope: &ChannelScope, persona: &str) -> Option<Box<dyn AIinterface>> {
+        self.build_persona(persona)
+    }
+
+    /// Применить перезагруженную конфигурацию, по умолчанию игнорируется
+    fn reload_config(&mut self, _config: &Config) {}
 }
 
+/// Диспетчер запросов, запросы разных каналов обрабатываются параллельно,
+/// запросы одного канала - по очереди
 #[async_trait]
 pub trait Dispatcher: Send + Sync {
     /// Обработать запрос
     async fn try_process_request(
-        &mut self,
+        &self,
         request: Box<dyn AIRequest>,
     ) -> Result<HashMap<AIResponseType, String>, AIError>;
 
+    /// Обработать запрос, ответ возвращается потоком по мере генерации
+    async fn try_process_request_streamed(
+        &self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<AIResponseStream, AIError>;
+
     /// Сбросить состояние ИИ
-    async fn reset(&mut self, channel: String) -> Result<(), AIError>;
+    async fn reset(&self, channel: String) -> Result<(), AIError>;
+
+    /// Привязать канал к персоне, ИИ канала создается заново
+    async fn set_persona(&self, channel: String, persona: String) -> Result<(), AIError>;
+
+    /// Персона, к которой привязан канал
+    fn persona(&self, channel: &str) -> String;
+
+    /// Применить перезагруженную конфигурацию, ИИ новых каналов создаются с новыми настройками
+    fn reload_config(&self, config: &Config);
+}
+
+/// ИИ канала, построенный для персоны
+struct ChannelAI {
+    ai: Box<dyn AIinterface>,
+    context_path: Option<PathBuf>,
+}
+
+/// ИИ канала и очередь запросов к нему
+struct ChannelState {
+    ai: Mutex<ChannelAI>,
+    queue: std::sync::Mutex<RequestQueue>,
 }
 
 pub struct AIDispatcher<AIB: AIBuilder> {
-    ai_constructor: AIB,
-    user_map: HashMap<String, Mutex<Box<dyn AIinterface>>>,
+    ai_constructor: std::sync::Mutex<AIB>,
+    user_map: std::sync::Mutex<HashMap<String, Arc<ChannelState>>>,
+    personas: std::sync::Mutex<HashMap<String, String>>,
+    scopes: std::sync::Mutex<HashMap<String, ChannelScope>>,
     context_path: Option<PathBuf>,
+    queue_config: RequestQueueConfig,
+    concurrency_limit: Arc<Semaphore>,
+    usage: Option<Arc<UsageTracker>>,
 }
 
 impl<AIB: AIBuilder> AIDispatcher<AIB> {
     pub fn new(ai_constructor: AIB, context_path: Option<PathBuf>) -> Self {
         Self {
-            ai_constructor,
-            user_map: HashMap::new(),
+            ai_constructor: std::sync::Mutex::new(ai_constructor),
+            user_map: std::sync::Mutex::new(HashMap::new()),
+            personas: std::sync::Mutex::new(HashMap::new()),
+            scopes: std::sync::Mutex::new(HashMap::new()),
             context_path,
+            queue_config: RequestQueueConfig::default(),
+            concurrency_limit: Arc::new(Semaphore::new(DEFAULT_CONCURRENCY_LIMIT)),
+            usage: None,
         }
     }
 
-    fn context_path(&self, channel: String) -> Option<PathBuf> {
-        if let Some(context_path) = &self.context_path {
-            Some(context_path.clone().join(channel))
-        } else {
-            None
+    /// Максимальное число запросов, которые ИИ обрабатывает одновременно (во всех каналах)
+    pub fn with_concurrency_limit(mut self, limit: usize) -> Self {
+        self.concurrency_limit = Arc::new(Semaphore::new(limit.max(1)));
+        self
+    }
+
+    /// Настройки очереди запросов каждого канала
+    pub fn with_queue_config(mut self, queue_config: RequestQueueConfig) -> Self {
+        self.queue_config = queue_config;
+        self
+    }
+
+    /// Учитывать потраченные токены, запросы сверх лимитов получают готовый ответ без обращения к ИИ
+    pub fn with_usage(mut self, usage: Arc<UsageTracker>) -> Self {
+        self.usage = Some(usage);
+        self
+    }
+
+    /// У каждой персоны в канале своя история беседы
+    fn context_path(&self, channel: &str, persona: &str) -> Option<PathBuf> {
+        self.context_path.as_ref().map(|context_path| {
+            if persona == DEFAULT_PERSONA {
+                context_path.join(channel)
+            } else {
+                context_path.join(format!("{channel}.{persona}"))
+            }
+        })
+    }
+
+    /// Откуда пришел последний запрос канала
+    fn scope(&self, channel: &str) -> ChannelScope {
+        self.scopes
+            .lock()
+            .unwrap()
+            .get(channel)
+            .cloned()
+            .unwrap_or_else(|| ChannelScope::new(channel))
+    }
+
+    fn build_ai(&self, channel: &str, persona: &str) -> Result<ChannelAI, AIError> {
+        let scope = self.scope(channel);
+        let mut ai = self
+            .ai_constructor
+            .lock()
+            .unwrap()
+            .build_for(&scope, persona)
+            .ok_or_else(|| AIError::UnknownPersona(persona.to_string()))?;
+
+        let context_path = self.context_path(channel, persona);
+        if let Some(filename) = &context_path {
+            if filename.exists() {
+                info!("Loading context from {:?}", filename);
+                if ai.load_context(filename.clone()).is_err() {
+                    error!("Failed to load context, skipping...");
+                }
+            }
         }
+
+        Ok(ChannelAI { ai, context_path })
     }
 
-    fn get_channel(&mut self, channel: String) -> &Mutex<Box<dyn AIinterface>> {
-        let context_filename = self.context_path(channel.clone());
-        let entry = self.user_map.entry(channel.clone());
-        match entry {
-            Entry::Occupied(entry) => entry.into_mut(),
+    /// Ставит запрос в очередь канала, свободный канал начинает обработку сразу
+    fn enqueue(&self, request: Box<dyn AIRequest>, tx: AnswerListener) -> Result<(), AIError> {
+        self.scopes
+            .lock()
+            .unwrap()
+            .insert(request.channel(), request.scope());
+        let channel = self.get_channel(request.channel())?;
+
+        let first = channel
+            .queue
+            .lock()
+            .unwrap()
+            .push(PendingRequest::new(request, tx))?;
+        if let Some(first) = first {
+            tokio::spawn(channel_worker(
+                channel,
+                first,
+                self.concurrency_limit.clone(),
+                self.usage.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn get_channel(&self, channel: String) -> Result<Arc<ChannelState>, AIError> {
+        let persona = self.persona(&channel);
+        let mut user_map = self.user_map.lock().unwrap();
+        match user_map.entry(channel) {
+            Entry::Occupied(entry) => Ok(entry.get().clone()),
             Entry::Vacant(entry) => {
-                let mut ai_context = self.ai_constructor.build();
-
-                if let Some(filename) = context_filename {
-                    if filename.exists() {
-                        info!("Loading context from {:?}", filename);
-                        if let Err(_) = ai_context.load_context(filename) {
-                            error!("Failed to load context, skipping...");
-                        }
-                    }
-                }
+                let ai = self.build_ai(entry.key(), &persona)?;
+                Ok(entry
+                    .insert(Arc::new(ChannelState {
+                        ai: Mutex::new(ai),
+                        queue: std::sync::Mutex::new(RequestQueue::new(self.queue_config.clone())),
+                    }))
+                    .clone())
+            }
+        }
+    }
+}
 
-                entry.insert(Mutex::new(ai_context))
+async fn send_to_all(listeners: &[AnswerListener], chunk: Result<AIResponseChunk, AIError>) {
+    for listener in listeners {
+        // listener may be gone, but the answer is still needed for the others and the context
+        let _ = listener.send(chunk.clone()).await;
+    }
+}
+
+/// Обрабатывает один запрос канала, ждет свободного места в общем лимите одновременных запросов
+async fn process_pending(
+    channel: Arc<ChannelState>,
+    request: Box<dyn AIRequest>,
+    listeners: Vec<AnswerListener>,
+    concurrency_limit: Arc<Semaphore>,
+    usage: Option<Arc<UsageTracker>>,
+) {
+    let (text, users, channel_name) = (request.request(), request.users(), request.channel());
+    let _permit = concurrency_limit.acquire().await.unwrap();
+    let mut channel_ai = channel.ai.lock().await;
+    let channel_ai = &mut *channel_ai;
+    let (chunks_tx, mut chunks_rx) = mpsc::channel(CHUNKS_QUEUE_SIZE);
+
+    let forward = async {
+        while let Some(chunk) = chunks_rx.recv().await {
+            send_to_all(&listeners, Ok(chunk)).await;
+        }
+    };
+
+    let (result, _) = tokio::join!(channel_ai.ai.process_streamed(request, chunks_tx), forward);
+
+    let result = match result {
+        Ok(result) => {
+            if let Some(usage) = &usage {
+                // бэкенды без статистики оцениваются по запросу и ответу
+                let tokens = channel_ai.ai.last_usage().unwrap_or_else(|| {
+                    let answer = result.get(&AIResponseType::RawAnswer);
+                    let answer = answer.map_or("", String::as_str);
+                    TokenUsage::estimate([text.as_str()], answer)
+                });
+                usage.record_shared(&users, &channel_name, tokens);
             }
+            if let Some(filename) = channel_ai.context_path.clone() {
+                if channel_ai.ai.save_context(filename).await.is_err() {
+                    error!("Failed to save context, skipping...");
+                }
+            }
+            Ok(AIResponseChunk::Done(result))
+        }
+        Err(e) => Err(e),
+    };
+    send_to_all(&listeners, result).await;
+}
+
+/// Обрабатывает запросы канала по очереди, пока она не опустеет.
+/// Каждый запрос выполняется в своей задаче: паника ИИ или слоя не оставляет канал занятым навсегда
+async fn channel_worker(
+    channel: Arc<ChannelState>,
+    first: PendingRequest,
+    concurrency_limit: Arc<Semaphore>,
+    usage: Option<Arc<UsageTracker>>,
+) {
+    let mut pending = first;
+    loop {
+        let (request, listeners) = pending.into_parts();
+        let task = tokio::spawn(process_pending(
+            channel.clone(),
+            request,
+            listeners.clone(),
+            concurrency_limit.clone(),
+            usage.clone(),
+        ));
+        if let Err(e) = task.await {
+            error!("Request processing failed: {}", e);
+            send_to_all(&listeners, Err(AIError::UnknownError)).await;
+        }
+        drop(listeners);
+
+        let next = channel.queue.lock().unwrap().pop_next();
+        match next {
+            Some(next) => pending = next,
+            None => break,
         }
     }
 }
@@ -153,46 +510,66 @@ impl<AIB: AIBuilder> AIDispatcher<AIB> {
 impl<AIB: AIBuilder> Dispatcher for AIDispatcher<AIB> {
     /// Обработать запрос
     async fn try_process_request(
-        &mut self,
+        &self,
         request: Box<dyn AIRequest>,
     ) -> Result<HashMap<AIResponseType, String>, AIError> {
-        let channel_name = request.channel();
-        let context_path = self.context_path(channel_name.clone());
-        let channel = self.get_channel(channel_name.clone());
+        let mut answer = self.try_process_request_streamed(request).await?;
+        while let Some(chunk) = answer.next().await {
+            if let AIResponseChunk::Done(result) = chunk? {
+                return Ok(result);
+            }
+        }
+        Err(AIError::UnknownError)
+    }
 
-        let mut channel_ai = channel.try_lock().ok_or(AIError::Busy)?;
+    /// Обработать запрос, ответ возвращается потоком по мере генерации.
+    /// Если канал занят, запрос встает в очередь
+    async fn try_process_request_streamed(
+        &self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<AIResponseStream, AIError> {
+        let (tx, rx) = mpsc::channel(CHUNKS_QUEUE_SIZE);
 
         if request.request().is_empty() {
             let res = hashmap! {
                 AIResponseType::RawAnswer => "".to_string(),
             };
-            Ok(res)
-        } else {
-            let result = channel_ai.process(request).await;
-
-            match result {
-                Ok(result) => {
-                    if let Some(filename) = context_path {
-                        if let Err(_) = channel_ai.save_context(filename).await {
-                            error!("Failed to save context, skipping...");
-                        }
-                    }
-                    Ok(result)
-                }
-                Err(e) => Err(e),
+            let _ = tx.send(Ok(AIResponseChunk::Done(res))).await;
+            return Ok(receiver_stream(rx));
+        }
+
+        if let Some(usage) = &self.usage {
+            if let Err(limit) = usage.check(&request.user()) {
+                info!("{} is limited: {:?}", request.user(), limit);
+                let res = hashmap! {
+                    AIResponseType::RawAnswer => usage.limit_message(limit).to_string(),
+                };
+                let _ = tx.send(Ok(AIResponseChunk::Sentence(res.clone()))).await;
+                let _ = tx.send(Ok(AIResponseChunk::Done(res))).await;
+                return Ok(receiver_stream(rx));
             }
         }
+
+        let user = request.user();
+        if let Err(e) = self.enqueue(request, tx) {
+            // отклоненный запрос не считается в лимите запросов пользователя
+            if let Some(usage) = &self.usage {
+                usage.cancel(&user);
+            }
+            return Err(e);
+        }
+
+        Ok(receiver_stream(rx))
     }
 
     /// Сбросить состояние ИИ
-    async fn reset(&mut self, channel: String) -> Result<(), AIError> {
-        let context_path = self.context_path(channel.clone());
-        let ch = self.user_map.entry(channel);
-        let reset_result = if let Entry::Occupied(mut entry) = ch {
-            let mut channel_ai = entry.get_mut().try_lock().ok_or(AIError::Busy)?;
-            channel_ai.reset().await
+    async fn reset(&self, channel: String) -> Result<(), AIError> {
+        let ch = self.user_map.lock().unwrap().get(&channel).cloned();
+        let (context_path, reset_result) = if let Some(ch) = ch {
+            let mut channel_ai = ch.ai.try_lock().map_err(|_| AIError::Busy)?;
+            (channel_ai.context_path.clone(), channel_ai.ai.reset().await)
         } else {
-            Ok(())
+            (self.context_path(&channel, &self.persona(&channel)), Ok(()))
         };
 
         if let Some(filename) = context_path {
@@ -206,4 +583,41 @@ impl<AIB: AIBuilder> Dispatcher for AIDispatcher<AIB> {
 
         reset_result
     }
+
+    /// Привязать канал к персоне, ИИ канала создается заново
+    async fn set_persona(&self, channel: String, persona: String) -> Result<(), AIError> {
+        if self.persona(&channel) == persona {
+            return Ok(());
+        }
+
+        let ai = self.build_ai(&channel, &persona)?;
+        let ch = self.user_map.lock().unwrap().get(&channel).cloned();
+        if let Some(ch) = ch {
+            *ch.ai.try_lock().map_err(|_| AIError::Busy)? = ai;
+        }
+
+        info!("Channel {} now uses persona {}", channel, persona);
+        let mut personas = self.personas.lock().unwrap();
+        if persona == DEFAULT_PERSONA {
+            personas.remove(&channel);
+        } else {
+            personas.insert(channel, persona);
+        }
+        Ok(())
+    }
+
+    /// Персона, к которой привязан канал
+    fn persona(&self, channel: &str) -> String {
+        self.personas
+            .lock()
+            .unwrap()
+            .get(channel)
+            .cloned()
+            .unwrap_or(DEFAULT_PERSONA.to_string())
+    }
+
+    /// Применить перезагруженную конфигурацию, ИИ новых каналов создаются с новыми настройками
+    fn reload_config(&self, config: &Config) {
+        self.ai_constructor.lock().unwrap().reload_config(config);
+    }
 }
@@ -0,0 +1,382 @@
+/// Errors of the TTS, STT, translation, AI and VTube Studio subsystems.
+/// `AIError` wraps the others, so the bots choose the reaction by `ErrorInfo::kind`
+use std::{error::Error, fmt};
+
+use reqwest::StatusCode;
+
+use crate::{dispatcher::AIError, resilience::Failure};
+
+/// What went wrong from the point of view of the bot
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The AI is answering another request of the channel or the queue is full
+    Busy,
+    /// The provider limits the requests
+    RateLimited,
+    /// A service is down, does not answer or its circuit breaker is open
+    Unavailable,
+    /// The request can't be processed: unsupported language, garbage input, bad arguments
+    InvalidInput,
+    /// The credentials are missing or rejected, only the operator can fix it
+    Unauthorized,
+    /// Unexpected answer of a service or a bug
+    Internal,
+}
+
+impl ErrorKind {
+    /// Asking again later may succeed
+    pub fn is_retryable(self) -> bool {
+        matches!(
+            self,
+            ErrorKind::Busy | ErrorKind::RateLimited | ErrorKind::Unavailable
+        )
+    }
+
+    /// Short message for the users, the details go to the log
+    pub fn user_message(self) -> &'static str {
+        match self {
+            ErrorKind::Busy => "I'm answering someone else, wait a moment.",
+            ErrorKind::RateLimited => "Too many questions at once, ask me again in a minute.",
+            ErrorKind::Unavailable => "I can't think right now, try again later.",
+            ErrorKind::InvalidInput => "Sorry, I didn't get that.",
+            ErrorKind::Unauthorized | ErrorKind::Internal => "Sorry, something went wrong.",
+        }
+    }
+}
+
+/// Common questions about the subsystem errors
+pub trait ErrorInfo: Error {
+    fn kind(&self) -> ErrorKind;
+
+    /// HTTP status of the failed call, if the service answered
+    fn status(&self) -> Option<StatusCode> {
+        None
+    }
+
+    fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
+
+    fn user_message(&self) -> &'static str {
+        self.kind().user_message()
+    }
+}
+
+/// Failed call of an external service
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceError {
+    /// Name of the service, see `resilience::SERVICES`
+    pub service: String,
+    pub failure: Failure,
+}
+
+impl ServiceError {
+    pub fn new(service: impl Into<String>, failure: Failure) -> Self {
+        Self {
+            service: service.into(),
+            failure,
+        }
+    }
+
+    pub fn from_reqwest(service: impl Into<String>, e: reqwest::Error) -> Self {
+        Self::new(service, Failure::from_reqwest(e))
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.service, self.failure)
+    }
+}
+
+impl Error for ServiceError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.failure.source()
+    }
+}
+
+impl ErrorInfo for ServiceError {
+    fn kind(&self) -> ErrorKind {
+        match self.failure.status {
+            Some(StatusCode::TOO_MANY_REQUESTS) => ErrorKind::RateLimited,
+            Some(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => ErrorKind::Unauthorized,
+            Some(status) if status == StatusCode::REQUEST_TIMEOUT || status.is_server_error() => {
+                ErrorKind::Unavailable
+            }
+            Some(status) if status.is_client_error() => ErrorKind::InvalidInput,
+            _ if self.failure.transient => ErrorKind::Unavailable,
+            _ => ErrorKind::Internal,
+        }
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        self.failure.status
+    }
+}
+
+/// Speech synthesis errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum TTSError {
+    /// The TTS server failed
+    Service(ServiceError),
+}
+
+impl fmt::Display for TTSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TTSError::Service(e) => write!(f, "TTS failed: {e}"),
+        }
+    }
+}
+
+impl Error for TTSError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TTSError::Service(e) => Some(e),
+        }
+    }
+}
+
+impl ErrorInfo for TTSError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            TTSError::Service(e) => e.kind(),
+        }
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            TTSError::Service(e) => e.status(),
+        }
+    }
+}
+
+/// Speech recognition errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum STTError {
+    /// The recognition server failed
+    Service(ServiceError),
+    /// The server answered without the expected field
+    BadResponse(String),
+}
+
+impl fmt::Display for STTError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            STTError::Service(e) => write!(f, "Speech recognition failed: {e}"),
+            STTError::BadResponse(e) => write!(f, "Incorrect recognition server response: {e}"),
+        }
+    }
+}
+
+impl Error for STTError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            STTError::Service(e) => Some(e),
+            STTError::BadResponse(_) => None,
+        }
+    }
+}
+
+impl ErrorInfo for STTError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            STTError::Service(e) => e.kind(),
+            STTError::BadResponse(_) => ErrorKind::Internal,
+        }
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            STTError::Service(e) => e.status(),
+            STTError::BadResponse(_) => None,
+        }
+    }
+}
+
+/// DeepL JSON-RPC error code of too many requests
+pub const DEEPL_TOO_MANY_REQUESTS: i64 = 1042911;
+
+/// Translation errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum TranslateError {
+    /// The translation server failed
+    Service(ServiceError),
+    /// The target language is not supported
+    InvalidLanguage,
+    /// The text is probably garbage, `unsupported` - probability of an unsupported language
+    NotConfident { unsupported: f64, text: String },
+    /// Error returned by the JSON-RPC
+    Rpc { code: i64, message: String },
+    /// Unexpected answer of the server
+    BadResponse(String),
+}
+
+impl fmt::Display for TranslateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranslateError::Service(e) => write!(f, "Translation failed: {e}"),
+            TranslateError::InvalidLanguage => f.write_str("Invalid target Lang"),
+            TranslateError::NotConfident { unsupported, text } => write!(
+                f,
+                "Translate input is not confident ({unsupported}), posibly gabrage input '{text}'"
+            ),
+            TranslateError::Rpc { code, message } => {
+                write!(f, "Failed to translate, error {code}: {message}")
+            }
+            TranslateError::BadResponse(e) => write!(f, "Failed to parse translation: {e}"),
+        }
+    }
+}
+
+impl Error for TranslateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TranslateError::Service(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorInfo for TranslateError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            TranslateError::Service(e) => e.kind(),
+            TranslateError::InvalidLanguage | TranslateError::NotConfident { .. } => {
+                ErrorKind::InvalidInput
+            }
+            TranslateError::Rpc { code, .. } if *code == DEEPL_TOO_MANY_REQUESTS => {
+                ErrorKind::RateLimited
+            }
+            TranslateError::Rpc { .. } | TranslateError::BadResponse(_) => ErrorKind::Internal,
+        }
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            TranslateError::Service(e) => e.status(),
+            _ => None,
+        }
+    }
+}
+
+/// VTube Studio API error of a denied authentication request
+pub const VTS_USER_DENIED: i64 = 50;
+
+/// VTube Studio plugin API errors
+#[derive(Debug, Clone, PartialEq)]
+pub enum VTSError {
+    /// The WebSocket can't be opened, is closed or does not answer
+    Connection(String),
+    /// The user denied the plugin or the token is rejected
+    Unauthorized(String),
+    /// `APIError` answer
+    Api { id: i64, message: String },
+    /// Unexpected answer of VTube Studio
+    BadResponse(String),
+}
+
+impl fmt::Display for VTSError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VTSError::Connection(e) => write!(f, "VTube Studio connection failed: {e}"),
+            VTSError::Unauthorized(e) => write!(f, "VTube Studio authentication failed: {e}"),
+            VTSError::Api { id, message } => write!(f, "VTube Studio error {id}: {message}"),
+            VTSError::BadResponse(e) => write!(f, "Incorrect VTube Studio response: {e}"),
+        }
+    }
+}
+
+impl Error for VTSError {}
+
+impl ErrorInfo for VTSError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            VTSError::Connection(_) => ErrorKind::Unavailable,
+            VTSError::Unauthorized(_) => ErrorKind::Unauthorized,
+            VTSError::Api { id, .. } if *id == VTS_USER_DENIED => ErrorKind::Unauthorized,
+            VTSError::Api { .. } | VTSError::BadResponse(_) => ErrorKind::Internal,
+        }
+    }
+}
+
+impl fmt::Display for AIError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AIError::Busy => f.write_str("AI is busy"),
+            AIError::NetworkError => f.write_str("Network error"),
+            AIError::TranslateError(e) => e.fmt(f),
+            AIError::AnswerError(e) => write!(f, "Answer error: {e}"),
+            AIError::ServiceError(e) => write!(f, "AI service failed: {e}"),
+            AIError::TTSError(e) => e.fmt(f),
+            AIError::STTError(e) => e.fmt(f),
+            AIError::UnknownError => f.write_str("Unknown error"),
+            AIError::ResetErrorEmpty => f.write_str("Nothing to reset"),
+            AIError::ContextError => f.write_str("Context error"),
+            AIError::UnknownPersona(persona) => write!(f, "Unknown persona {persona}"),
+        }
+    }
+}
+
+impl Error for AIError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AIError::TranslateError(e) => Some(e),
+            AIError::ServiceError(e) => Some(e),
+            AIError::TTSError(e) => Some(e),
+            AIError::STTError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl ErrorInfo for AIError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            AIError::Busy => ErrorKind::Busy,
+            AIError::NetworkError => ErrorKind::Unavailable,
+            AIError::TranslateError(e) => e.kind(),
+            AIError::ServiceError(e) => e.kind(),
+            AIError::TTSError(e) => e.kind(),
+            AIError::STTError(e) => e.kind(),
+            AIError::ResetErrorEmpty | AIError::UnknownPersona(_) => ErrorKind::InvalidInput,
+            AIError::AnswerError(_) | AIError::UnknownError | AIError::ContextError => {
+                ErrorKind::Internal
+            }
+        }
+    }
+
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            AIError::TranslateError(e) => e.status(),
+            AIError::ServiceError(e) => e.status(),
+            AIError::TTSError(e) => e.status(),
+            AIError::STTError(e) => e.status(),
+            _ => None,
+        }
+    }
+}
+
+impl From<ServiceError> for AIError {
+    fn from(e: ServiceError) -> Self {
+        AIError::ServiceError(e)
+    }
+}
+
+impl From<TranslateError> for AIError {
+    fn from(e: TranslateError) -> Self {
+        AIError::TranslateError(e)
+    }
+}
+
+impl From<TTSError> for AIError {
+    fn from(e: TTSError) -> Self {
+        AIError::TTSError(e)
+    }
+}
+
+impl From<STTError> for AIError {
+    fn from(e: STTError) -> Self {
+        AIError::STTError(e)
+    }
+}
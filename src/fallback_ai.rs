@@ -0,0 +1,258 @@
+/// Chain of backends tried in order, the conversation carries across switches
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Sender};
+use tracing::warn;
+
+use crate::{
+    chat_template::ChatTurn,
+    config::{Platform, RouteConfig},
+    dispatcher::{
+        AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface, RetryRequest,
+        CHUNKS_QUEUE_SIZE,
+    },
+    memory_store::FactExtractor,
+    persona::Persona,
+    usage::TokenUsage,
+};
+
+/// Builds one model of the chain, without the memory and the middleware
+pub trait BackendBuilder: Send + Sync {
+    fn build_backend(&self, persona: &Persona) -> Box<dyn AIinterface>;
+
+    /// Facts for the long-term memory are extracted by the first backend of the chain
+    fn fact_extractor(&self) -> Arc<dyn FactExtractor>;
+}
+
+impl RouteConfig {
+    /// Platform and persona are known when the chain is built,
+    /// rules for other platforms or personas are dropped then
+    pub fn applies_to(&self, platform: Option<Platform>, persona: &str) -> bool {
+        self.platform.map_or(true, |p| Some(p) == platform)
+            && self.persona.as_deref().map_or(true, |p| p == persona)
+    }
+}
+
+/// Request length range served first by the backend
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    pub min_length: Option<usize>,
+    pub max_length: Option<usize>,
+    /// Index in the chain
+    pub backend: usize,
+}
+
+impl Route {
+    fn matches(&self, request: &str) -> bool {
+        let length = request.chars().count();
+        self.min_length.map_or(true, |min| length >= min)
+            && self.max_length.map_or(true, |max| length <= max)
+    }
+}
+
+pub struct Backend {
+    pub name: String,
+    pub ai: Box<dyn AIinterface>,
+}
+
+impl Backend {
+    pub fn new(name: impl Into<String>, ai: Box<dyn AIinterface>) -> Self {
+        Self {
+            name: name.into(),
+            ai,
+        }
+    }
+}
+
+/// Asks the next backend when the previous one fails or times out.
+/// The backend that answered last owns the history, the next one gets a copy of it
+pub struct FallbackAI {
+    backends: Vec<Backend>,
+    routes: Vec<Route>,
+    timeout: Option<Duration>,
+    current: usize,
+}
+
+impl FallbackAI {
+    /// Panics without backends
+    pub fn new(backends: Vec<Backend>) -> Self {
+        assert!(!backends.is_empty(), "The backend chain is empty");
+        Self {
+            backends,
+            routes: vec![],
+            timeout: None,
+            current: 0,
+        }
+    }
+
+    /// The first matching route selects the backend tried first
+    pub fn with_routes(mut self, routes: Vec<Route>) -> Self {
+        self.routes = routes;
+        self
+    }
+
+    /// How long to wait for the answer, for the streamed answers - for its first part
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Indices of the backends in the order they are tried
+    pub fn order(&self, request: &str) -> Vec<usize> {
+        let mut order = (0..self.backends.len()).collect::<Vec<_>>();
+        if let Some(route) = self.routes.iter().find(|r| r.matches(request)) {
+            if route.backend < order.len() {
+                order.remove(route.backend);
+                order.insert(0, route.backend);
+            }
+        }
+        order
+    }
+
+    /// Name of the backend that answered last
+    pub fn current(&self) -> &str {
+        &self.backends[self.current].name
+    }
+
+    /// Copy the history of the current backend to the next one
+    fn switch_to(&mut self, backend: usize) {
+        if backend == self.current {
+            return;
+        }
+        if let Some(history) = self.backends[self.current].ai.history() {
+            self.backends[backend].ai.set_history(history);
+        }
+    }
+
+    fn timeout_error(&self, backend: usize) -> AIError {
+        AIError::AnswerError(format!("{} timed out", self.backends[backend].name))
+    }
+}
+
+#[async_trait]
+impl AIinterface for FallbackAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let request = RetryRequest::new(request.as_ref());
+        let mut last_error = AIError::UnknownError;
+
+        for backend in self.order(&request.request()) {
+            self.switch_to(backend);
+            let answer = self.backends[backend].ai.process(Box::new(request.clone()));
+            let res = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, answer)
+                    .await
+                    .unwrap_or_else(|_| Err(self.timeout_error(backend))),
+                None => answer.await,
+            };
+
+            match res {
+                Ok(answer) => {
+                    self.current = backend;
+                    return Ok(answer);
+                }
+                Err(e) => {
+                    warn!("Backend {} failed: {:?}", self.backends[backend].name, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    /// The next backend is tried only until a part of the answer is sent
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let request = RetryRequest::new(request.as_ref());
+        let mut last_error = AIError::UnknownError;
+
+        for backend in self.order(&request.request()) {
+            self.switch_to(backend);
+            let timeout = self.timeout;
+            let mut started = false;
+
+            let (tx, mut rx) = mpsc::channel(CHUNKS_QUEUE_SIZE);
+            let res = {
+                let answer = self.backends[backend]
+                    .ai
+                    .process_streamed(Box::new(request.clone()), tx);
+                let deadline = async {
+                    match timeout {
+                        Some(timeout) => tokio::time::sleep(timeout).await,
+                        None => std::future::pending().await,
+                    }
+                };
+                tokio::pin!(answer, deadline);
+
+                let res = loop {
+                    tokio::select! {
+                        res = &mut answer => break Some(res),
+                        Some(chunk) = rx.recv() => {
+                            started = true;
+                            let _ = chunks.send(chunk).await;
+                        }
+                        _ = &mut deadline, if !started => break None,
+                    }
+                };
+                // parts sent right before the end of the answer
+                while let Ok(chunk) = rx.try_recv() {
+                    started = true;
+                    let _ = chunks.send(chunk).await;
+                }
+                res
+            };
+            let res = res.unwrap_or_else(|| Err(self.timeout_error(backend)));
+
+            match res {
+                Ok(answer) => {
+                    self.current = backend;
+                    return Ok(answer);
+                }
+                Err(e) if started => {
+                    // the listeners already got a part of this answer
+                    self.current = backend;
+                    return Err(e);
+                }
+                Err(e) => {
+                    warn!("Backend {} failed: {:?}", self.backends[backend].name, e);
+                    last_error = e;
+                }
+            }
+        }
+        Err(last_error)
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        self.backends[self.current].ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.backends[self.current].ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        self.backends[self.current].ai.last_usage()
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.backends[self.current].ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.backends[self.current].ai.load_context(file)
+    }
+
+    fn history(&self) -> Option<Vec<ChatTurn>> {
+        self.backends[self.current].ai.history()
+    }
+
+    fn set_history(&mut self, history: Vec<ChatTurn>) {
+        self.backends[self.current].ai.set_history(history);
+    }
+}
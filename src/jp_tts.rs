@@ -6,9 +6,15 @@ use reqwest::IntoUrl;
 
 use bytes::Bytes;
 
+use crate::{
+    errors::TTSError,
+    resilience::{self, check_status, Failure, Service},
+};
+
 pub struct JpTTS {
     _client: reqwest::Client,
     builder: reqwest::RequestBuilder,
+    service: Service,
 }
 
 impl JpTTS {
@@ -35,26 +41,35 @@ impl JpTTS {
         Self {
             _client: client,
             builder,
+            service: Service::get(resilience::JP_TTS),
         }
     }
 
-    pub async fn say<S>(&self, text: S) -> Result<Cursor<Bytes>, String>
+    pub async fn say<S>(&self, text: S) -> Result<Cursor<Bytes>, TTSError>
     where
         S: Into<String>,
     {
-        let res = self.builder.try_clone().unwrap();
+        let text = text.into();
+        let res = self
+            .service
+            .call(|| async {
+                let res = self.builder.try_clone().unwrap();
 
-        let res = res
-            .query(&[("text", text.into())])
-            .send()
-            .await
-            .map_err(|e| format!("Error: {}", e))?;
+                let res = res
+                    .query(&[("text", text.as_str())])
+                    .send()
+                    .await
+                    .map_err(Failure::from_reqwest)?;
 
-        // read response as wav file
-        let res = res
-            .bytes()
+                // read response as wav file
+                check_status(res)
+                    .await?
+                    .bytes()
+                    .await
+                    .map_err(Failure::from_reqwest)
+            })
             .await
-            .map_err(|e| format!("Http error: {}", e))?;
+            .map_err(|e| TTSError::Service(self.service.error(e)))?;
 
         Ok(Cursor::new(res))
     }
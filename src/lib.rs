@@ -1,10 +1,46 @@
+pub mod ai_memory_request;
+pub mod ai_merged_request;
+pub mod ai_templated_request;
 pub mod ai_translated_request;
+pub mod builtin_tools;
+pub mod candidates;
+pub mod channel_overrides;
+pub mod chat_template;
 pub mod chatgpt;
 pub mod config;
+pub mod config_format;
+pub mod config_loader;
+pub mod config_reload;
+pub mod config_validation;
+pub mod context_budget;
 pub mod deeplx_translate_owned;
 pub mod dispatcher;
 pub mod dummy_ai;
+pub mod errors;
+pub mod fallback_ai;
+pub mod local_model;
+pub mod logging_ai;
+pub mod memory_ai;
+pub mod memory_store;
+pub mod middleware;
+pub mod moderated_ai;
+pub mod moderation;
 pub mod num2words;
+pub mod numbers_to_words_ai;
+pub mod openai_embeddings;
+pub mod openai_moderation;
+pub mod openai_tool_calls;
+pub mod persona;
+pub mod prompt_template_ai;
+pub mod request_queue;
+pub mod resilience;
+pub mod sanitized_ai;
+pub mod sanitizer;
+pub mod secret;
+pub mod sentence_splitter;
+pub mod tools;
+pub mod usage;
+pub mod vtube_studio;
 pub mod whisper_voice_recognize;
 
 pub mod jp_tts;
@@ -13,43 +49,47 @@ pub mod tts_engine;
 
 pub mod utils;
 
+use std::sync::Arc;
+
 use config::AIEngine;
-use dispatcher::AIinterfaceStreamed;
+use dispatcher::AIinterface;
+use secret::Secret;
 
 #[allow(unused)]
 static CARGO_MANIFEST_DIR: &str = env!("CARGO_MANIFEST_DIR");
 
-static mut CUSTOM_LLAMA_MODEL_NAME: Option<String> = None;
-
-fn select_gpt_model(config: &config::Config) -> ::chatgpt::prelude::ChatGPTEngine {
-    match &config.ai_engine.engine_type {
+fn select_gpt_model(engine_type: &config::AIEngineType) -> ::chatgpt::prelude::ChatGPTEngine {
+    match engine_type {
         config::AIEngineType::ChatGPT { engine, .. } => {
             if let Some(engine) = engine {
                 match engine.as_str() {
-                "Gpt35Turbo" => ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo,
-                "Gpt35Turbo_0301" => ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo_0301,
-                "Gpt4" => ::chatgpt::prelude::ChatGPTEngine::Gpt4,
-                "Gpt4_32k" => ::chatgpt::prelude::ChatGPTEngine::Gpt4_32k,
-                "Gpt4_0314" => ::chatgpt::prelude::ChatGPTEngine::Gpt4_0314,
-                "Gpt4_32k_0314" => ::chatgpt::prelude::ChatGPTEngine::Gpt4_32k_0314,
-                _ => panic!("Invalid engine: {engine}, supported values Gpt35Turbo, Gpt35Turbo_0301, Gpt4, Gpt4_32k, Gpt4_0314, Gpt4_32k_0314"),
-            }
+                    "Gpt35Turbo" => ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo,
+                    "Gpt35Turbo_0301" => ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo_0301,
+                    "Gpt4" => ::chatgpt::prelude::ChatGPTEngine::Gpt4,
+                    "Gpt4_32k" => ::chatgpt::prelude::ChatGPTEngine::Gpt4_32k,
+                    "Gpt4_0314" => ::chatgpt::prelude::ChatGPTEngine::Gpt4_0314,
+                    "Gpt4_32k_0314" => ::chatgpt::prelude::ChatGPTEngine::Gpt4_32k_0314,
+                    _ => panic!(
+                        "Invalid engine: {engine}, supported values {}",
+                        config::GPT_VERSIONS.join(", ")
+                    ),
+                }
             } else {
                 ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo
             }
         }
         config::AIEngineType::LLaMa { model, .. } => {
             if let Some(model) = model {
-                unsafe {
-                    CUSTOM_LLAMA_MODEL_NAME = Some(model.clone());
-                    ::chatgpt::prelude::ChatGPTEngine::Custom(
-                        &CUSTOM_LLAMA_MODEL_NAME.as_ref().unwrap(),
-                    )
-                }
+                // the engine needs a static name, the model is selected once per backend
+                ::chatgpt::prelude::ChatGPTEngine::Custom(Box::leak(model.clone().into_boxed_str()))
             } else {
                 ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo
             }
         }
+        // local models don't use chatgpt_rs
+        config::AIEngineType::LlamaCpp(_) | config::AIEngineType::Ollama(_) => {
+            ::chatgpt::prelude::ChatGPTEngine::Gpt35Turbo
+        }
     }
 }
 
@@ -79,59 +119,213 @@ fn build_ai_config(
     (ai_conf, ai_config)
 }
 
-pub fn create_streamed_ai(config: &config::Config) -> Box<dyn AIinterfaceStreamed> {
-    use dispatcher::AIBuilderStreamed;
-    use utils::chatgpt_builder::ChatGPTAIBuilder;
+/// Long-term memory about users, if enabled in the config
+pub fn create_memory_store(
+    config: &config::Config,
+    openai_token: &str,
+) -> Option<Arc<memory_store::MemoryStore>> {
+    let memory_config = config.memory.as_ref()?;
+    let memory = memory_store::MemoryStore::new(memory_config.clone());
 
-    // common config
-    let (ai_conf, mut ai_config) = build_ai_config(config);
+    let memory = match &memory_config.embeddings {
+        Some(config::EmbeddingsConfig::OpenAI { url, model, token }) => {
+            memory.with_embeddings(Box::new(openai_embeddings::OpenAIEmbeddings::new(
+                url.clone(),
+                model.clone(),
+                token
+                    .as_ref()
+                    .map_or(openai_token, Secret::expose)
+                    .to_string(),
+            )))
+        }
+        Some(config::EmbeddingsConfig::Disabled) | None => memory,
+    };
 
-    match &ai_conf.engine_type {
-        config::AIEngineType::ChatGPT { openai_token, .. } => {
-            ai_config.engine(select_gpt_model(config));
+    Some(Arc::new(memory))
+}
 
-            ChatGPTAIBuilder::new(openai_token.clone(), ai_config.build().unwrap(), config)
-                .build_streamed()
-        }
-        config::AIEngineType::LLaMa { api_url, .. } => {
-            ai_config.api_url(api_url.clone()); // set local url (llama server)
+/// Answer moderation, if enabled in the config
+pub fn create_moderator(
+    config: &config::Config,
+    openai_token: &str,
+) -> Option<Arc<moderation::Moderator>> {
+    let moderation_config = config.moderation.as_ref()?;
+    let moderator = moderation::Moderator::new(moderation_config)
+        .unwrap_or_else(|e| panic!("Invalid banned word regex: {e}"));
 
-            ChatGPTAIBuilder::new("no-token".to_owned(), ai_config.build().unwrap(), config)
-                .build_streamed()
+    let moderator = match &moderation_config.endpoint {
+        Some(endpoint) => {
+            moderator.with_backend(Box::new(openai_moderation::OpenAIModeration::new(
+                endpoint.url.clone(),
+                endpoint
+                    .token
+                    .as_ref()
+                    .map_or(openai_token, Secret::expose)
+                    .to_string(),
+            )))
         }
+        None => moderator,
+    };
+
+    Some(Arc::new(moderator))
+}
+
+/// Selection of the best reply, if the backend is asked for several ones
+pub fn create_candidate_selector(
+    config: &config::Config,
+) -> Option<Arc<candidates::CandidateSelector>> {
+    if config.ai_engine.reply_count.unwrap_or(1) <= 1 {
+        return None;
     }
+    let rules = config
+        .ai_engine
+        .candidate_rules
+        .clone()
+        .unwrap_or_else(config::CandidateRuleConfig::default_rules);
+    let selector = candidates::CandidateSelector::new(&rules)
+        .unwrap_or_else(|e| panic!("Invalid candidate banned word regex: {e}"));
+    Some(Arc::new(selector))
 }
 
-pub fn create_ai_dispatcher(config: &config::Config) -> Box<dyn dispatcher::Dispatcher> {
-    use dispatcher::AIDispatcher;
+/// Layers around the AI, listed from the outermost
+pub fn create_middleware(
+    config: &config::Config,
+    openai_token: &str,
+    platform: config::Platform,
+) -> Vec<Arc<dyn middleware::Middleware>> {
+    use config::MiddlewareConfig;
+
+    let chain = config
+        .middleware
+        .clone()
+        .unwrap_or(MiddlewareConfig::default_chain(config.moderation.is_some()));
+
+    let moderator = create_moderator(config, openai_token);
+
+    chain
+        .into_iter()
+        .map(|layer| -> Arc<dyn middleware::Middleware> {
+            match layer {
+                MiddlewareConfig::Translate {
+                    src_lang,
+                    dest_lang,
+                    drop_nonconfident_result,
+                } => Arc::new(middleware::Translate {
+                    src_lang: src_lang.unwrap_or(config.deeplx_translate_config.src_lang.clone()),
+                    dest_lang,
+                    drop_nonconfident_result,
+                    url: config.deeplx_translate_config.url.clone(),
+                }),
+                MiddlewareConfig::NumbersToWords => Arc::new(middleware::NumbersToWords),
+                MiddlewareConfig::Moderation => Arc::new(middleware::Moderation {
+                    moderator: moderator
+                        .clone()
+                        .expect("Moderation middleware requires the Moderation config"),
+                    action: config.moderation.as_ref().unwrap().action(platform),
+                }),
+                MiddlewareConfig::PromptTemplate { template } => {
+                    Arc::new(middleware::PromptTemplate { template })
+                }
+                MiddlewareConfig::Logging => Arc::new(middleware::Logging),
+                MiddlewareConfig::Sanitize => Arc::new(middleware::Sanitize),
+            }
+        })
+        .collect()
+}
+
+/// OpenAI token for the memory embeddings and the moderation endpoint without own tokens,
+/// taken from the first ChatGPT backend
+fn openai_token(ai_engine: &AIEngine) -> &str {
+    std::iter::once(&ai_engine.engine_type)
+        .chain(ai_engine.fallback.iter().map(|f| &f.engine_type))
+        .find_map(|engine_type| match engine_type {
+            config::AIEngineType::ChatGPT { openai_token, .. } => Some(openai_token.expose()),
+            _ => None,
+        })
+        .unwrap_or("")
+}
+
+fn create_backend_builder(
+    config: &config::Config,
+    engine_type: &config::AIEngineType,
+    memory: Option<Arc<memory_store::MemoryStore>>,
+) -> Box<dyn fallback_ai::BackendBuilder> {
+    use utils::{chatgpt_builder::ChatGPTAIBuilder, local_model_builder::LocalModelBuilder};
+
+    if let Some((api, local_config)) = engine_type.local_model() {
+        return Box::new(LocalModelBuilder::new(api, local_config.clone(), config));
+    }
 
     // common config
-    let (ai_conf, mut ai_config) = build_ai_config(config);
+    let (_, mut ai_config) = build_ai_config(config);
+    ai_config.engine(select_gpt_model(engine_type));
 
-    match &ai_conf.engine_type {
+    let (openai_token, service) = match engine_type {
         config::AIEngineType::ChatGPT { openai_token, .. } => {
-            ai_config.engine(select_gpt_model(config));
-
-            Box::new(AIDispatcher::new(
-                utils::chatgpt_en_deeplx_builder::ChatGPTEnAIBuilder::new(
-                    openai_token.clone(),
-                    ai_config.build().unwrap(),
-                    config,
-                ),
-                config.ai_engine.context_path.clone(),
-            ))
+            (openai_token.expose().to_string(), resilience::OPENAI)
         }
         config::AIEngineType::LLaMa { api_url, .. } => {
             ai_config.api_url(api_url.clone()); // set local url (llama server)
-
-            Box::new(AIDispatcher::new(
-                utils::chatgpt_en_deeplx_builder::ChatGPTEnAIBuilder::new(
-                    "no-token".to_string(),
-                    ai_config.build().unwrap(),
-                    config,
-                ),
-                config.ai_engine.context_path.clone(),
-            ))
+            ("no-token".to_string(), resilience::LLAMA)
         }
+        config::AIEngineType::LlamaCpp(_) | config::AIEngineType::Ollama(_) => unreachable!(),
+    };
+
+    let builder = ChatGPTAIBuilder::new(openai_token, ai_config.build().unwrap(), config, memory)
+        .with_service(service);
+    match create_candidate_selector(config) {
+        Some(selector) => Box::new(builder.with_candidates(selector)),
+        None => Box::new(builder),
     }
 }
+
+/// `Engine_Type` followed by the `Fallback` backends, all of them share the memory
+pub fn create_backend_chain(
+    config: &config::Config,
+) -> utils::backend_chain_builder::BackendChainBuilder {
+    use utils::backend_chain_builder::BackendChainBuilder;
+
+    let ai_engine = &config.ai_engine;
+    let memory = create_memory_store(config, openai_token(ai_engine));
+
+    let main = create_backend_builder(config, &ai_engine.engine_type, memory.clone());
+    let chain = BackendChainBuilder::new(config, main, memory.clone());
+
+    ai_engine.fallback.iter().fold(chain, |chain, fallback| {
+        let backend = create_backend_builder(config, &fallback.engine_type, memory.clone());
+        chain.with_backend(&fallback.name, backend)
+    })
+}
+
+pub fn create_streamed_ai(config: &config::Config) -> Box<dyn AIinterface> {
+    use dispatcher::AIBuilder;
+
+    create_backend_chain(config).build()
+}
+
+/// `platform` - where the answers are published, selects the moderation action
+/// and the routing rules
+pub fn create_ai_dispatcher(
+    config: &config::Config,
+    platform: config::Platform,
+) -> Arc<dyn dispatcher::Dispatcher> {
+    let middleware = create_middleware(config, openai_token(&config.ai_engine), platform);
+    let builder = create_backend_chain(config)
+        .with_platform(platform)
+        .with_middleware(middleware);
+    create_dispatcher_with(builder, config)
+}
+
+fn create_dispatcher_with<B: dispatcher::AIBuilder + 'static>(
+    builder: B,
+    config: &config::Config,
+) -> Arc<dyn dispatcher::Dispatcher> {
+    use dispatcher::AIDispatcher;
+
+    Arc::new(
+        AIDispatcher::new(builder, config.ai_engine.context_path.clone())
+            .with_queue_config(config.request_queue.clone())
+            .with_concurrency_limit(config.max_concurrent_requests)
+            .with_usage(Arc::new(usage::UsageTracker::new(config.usage.clone()))),
+    )
+}
@@ -0,0 +1,639 @@
+/// llama.cpp and Ollama servers without the OpenAI compatibility layer
+use std::{collections::HashMap, path::PathBuf};
+
+use async_trait::async_trait;
+use maplit::hashmap;
+use serde_json::{json, Map, Value};
+use tokio::sync::mpsc::Sender;
+use tracing::{error, info};
+
+use crate::{
+    chat_template::{cut_at_stop, stop_position, ChatRole, ChatTurn},
+    config::{AIEngine, AIEngineType, ContextBudgetConfig, LocalModelConfig},
+    context_budget::{estimate_tokens, ContextBudget, SUMMARY_PREFIX},
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    memory_store::{parse_facts, FactExtractor, FACT_EXTRACTION_PROMPT},
+    persona::Persona,
+    resilience::{self, check_status, Failure, Service},
+    sentence_splitter::SentenceSplitter,
+    usage::TokenUsage,
+};
+
+/// Grammar of a JSON object for llama.cpp, Ollama has its own JSON mode
+pub const JSON_GRAMMAR: &str = r##"root ::= object
+value ::= object | array | string | number | ("true" | "false" | "null") ws
+object ::= "{" ws ( string ":" ws value ("," ws string ":" ws value)* )? "}" ws
+array ::= "[" ws ( value ("," ws value)* )? "]" ws
+string ::= "\"" ( [^"\\\x7F\x00-\x1F] | "\\" (["\\/bfnrt] | "u" [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F] [0-9a-fA-F]) )* "\"" ws
+number ::= ("-"? ([0-9] | [1-9] [0-9]*)) ("." [0-9]+)? ([eE] [-+]? [0-9]+)? ws
+ws ::= ([ \t\n] ws)?
+"##;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LocalApi {
+    /// `/completion`, raw prompt
+    LlamaCpp,
+    /// `/api/chat`, messages
+    Ollama,
+}
+
+impl LocalApi {
+    /// Name of the server in `Resilience.Services`
+    pub fn service(self) -> &'static str {
+        match self {
+            LocalApi::LlamaCpp => resilience::LLAMA_CPP,
+            LocalApi::Ollama => resilience::OLLAMA,
+        }
+    }
+}
+
+impl AIEngineType {
+    /// Local model server, if the engine is one
+    pub fn local_model(&self) -> Option<(LocalApi, &LocalModelConfig)> {
+        match self {
+            AIEngineType::LlamaCpp(config) => Some((LocalApi::LlamaCpp, config)),
+            AIEngineType::Ollama(config) => Some((LocalApi::Ollama, config)),
+            _ => None,
+        }
+    }
+}
+
+/// Sampling parameters, the server defaults are used for the missing ones
+#[derive(Clone, Copy, Default, PartialEq, Debug)]
+pub struct Sampling {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+}
+
+impl Sampling {
+    pub fn with_engine(engine: &AIEngine) -> Self {
+        Self {
+            temperature: engine.temperature,
+            top_p: engine.top_p,
+            presence_penalty: engine.presence_penalty,
+            frequency_penalty: engine.frequency_penalty,
+        }
+    }
+
+    /// Persona settings take precedence
+    pub fn with_persona(self, persona: &Persona) -> Self {
+        Self {
+            temperature: persona.temperature.or(self.temperature),
+            top_p: persona.top_p.or(self.top_p),
+            presence_penalty: persona.presence_penalty.or(self.presence_penalty),
+            frequency_penalty: persona.frequency_penalty.or(self.frequency_penalty),
+        }
+    }
+
+    fn insert_into(&self, map: &mut Map<String, Value>) {
+        let parameters = [
+            ("temperature", self.temperature),
+            ("top_p", self.top_p),
+            ("presence_penalty", self.presence_penalty),
+            ("frequency_penalty", self.frequency_penalty),
+        ];
+        for (name, value) in parameters {
+            if let Some(value) = value {
+                map.insert(name.to_string(), json!(value));
+            }
+        }
+    }
+}
+
+/// Text of one streamed line of the answer
+#[derive(Debug, PartialEq)]
+pub struct StreamPart {
+    pub content: String,
+    /// The server finished the answer
+    pub done: bool,
+    /// Tokens of the request and the answer, sent with the last part
+    pub usage: Option<TokenUsage>,
+}
+
+/// Splits the streamed body into lines, a line may come in several chunks
+#[derive(Default)]
+pub(crate) struct LineBuffer(Vec<u8>);
+
+impl LineBuffer {
+    pub(crate) fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.0.extend_from_slice(bytes);
+        let mut lines = vec![];
+        while let Some(end) = self.0.iter().position(|b| *b == b'\n') {
+            let line = self.0.drain(..=end).collect::<Vec<_>>();
+            lines.push(String::from_utf8_lossy(&line).trim().to_string());
+        }
+        lines
+    }
+
+    pub(crate) fn finish(&mut self) -> Option<String> {
+        let line = String::from_utf8_lossy(&std::mem::take(&mut self.0))
+            .trim()
+            .to_string();
+        (!line.is_empty()).then_some(line)
+    }
+}
+
+/// Completion requests, the history is kept by the caller
+#[derive(Clone)]
+pub struct LocalModelClient {
+    client: reqwest::Client,
+    api: LocalApi,
+    config: LocalModelConfig,
+    sampling: Sampling,
+    service: Service,
+}
+
+impl LocalModelClient {
+    pub fn new(api: LocalApi, config: LocalModelConfig) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api,
+            config,
+            sampling: Sampling::default(),
+            service: Service::get(api.service()),
+        }
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Configured stop sequences, for llama.cpp the template ones too
+    pub fn stop(&self) -> Vec<String> {
+        let template = match self.api {
+            LocalApi::LlamaCpp => self.config.template.stop_sequences(),
+            LocalApi::Ollama => &[],
+        };
+        template
+            .iter()
+            .map(|s| s.to_string())
+            .chain(self.config.stop.iter().cloned())
+            .collect()
+    }
+
+    pub fn request_body(&self, history: &[ChatTurn], stream: bool) -> Value {
+        let mut body = Map::new();
+        body.insert("stream".to_string(), json!(stream));
+        match self.api {
+            LocalApi::LlamaCpp => {
+                body.insert(
+                    "prompt".to_string(),
+                    json!(self.config.template.render(history)),
+                );
+                body.insert("stop".to_string(), json!(self.stop()));
+                body.insert("cache_prompt".to_string(), json!(true));
+                if let Some(max_tokens) = self.config.max_tokens {
+                    body.insert("n_predict".to_string(), json!(max_tokens));
+                }
+                self.sampling.insert_into(&mut body);
+
+                let grammar = match &self.config.grammar {
+                    Some(grammar) => Some(grammar.as_str()),
+                    None if self.config.json_mode => Some(JSON_GRAMMAR),
+                    None => None,
+                };
+                if let Some(grammar) = grammar {
+                    body.insert("grammar".to_string(), json!(grammar));
+                }
+            }
+            LocalApi::Ollama => {
+                body.insert(
+                    "model".to_string(),
+                    json!(self.config.model.clone().unwrap_or_default()),
+                );
+                body.insert("messages".to_string(), json!(history));
+
+                let mut options = Map::new();
+                self.sampling.insert_into(&mut options);
+                if let Some(max_tokens) = self.config.max_tokens {
+                    options.insert("num_predict".to_string(), json!(max_tokens));
+                }
+                if !self.config.stop.is_empty() {
+                    options.insert("stop".to_string(), json!(self.config.stop));
+                }
+                body.insert("options".to_string(), Value::Object(options));
+
+                if self.config.json_mode {
+                    body.insert("format".to_string(), json!("json"));
+                }
+            }
+        }
+        Value::Object(body)
+    }
+
+    /// Parse a line of the streamed answer or the whole answer, `None` for the service lines
+    pub fn parse_part(&self, line: &str) -> Result<Option<StreamPart>, AIError> {
+        // llama.cpp streams server-sent events
+        let line = line.strip_prefix("data:").unwrap_or(line).trim();
+        if !line.starts_with('{') {
+            return Ok(None);
+        }
+
+        let value = serde_json::from_str::<Value>(line).map_err(|e| {
+            AIError::AnswerError(format!("{:?} error: {e}, response: {line}", self.api))
+        })?;
+        if let Some(e) = value.get("error") {
+            let message = e
+                .get("message")
+                .and_then(Value::as_str)
+                .or(e.as_str())
+                .map(str::to_string)
+                .unwrap_or(e.to_string());
+            return Err(AIError::AnswerError(format!(
+                "{:?} error: {message}",
+                self.api
+            )));
+        }
+
+        let (content, done) = match self.api {
+            LocalApi::LlamaCpp => (&value["content"], &value["stop"]),
+            LocalApi::Ollama => (&value["message"]["content"], &value["done"]),
+        };
+        let (prompt_tokens, completion_tokens) = match self.api {
+            LocalApi::LlamaCpp => (&value["tokens_evaluated"], &value["tokens_predicted"]),
+            LocalApi::Ollama => (&value["prompt_eval_count"], &value["eval_count"]),
+        };
+        Ok(Some(StreamPart {
+            content: content.as_str().unwrap_or_default().to_string(),
+            done: done.as_bool().unwrap_or(false),
+            usage: prompt_tokens
+                .as_u64()
+                .zip(completion_tokens.as_u64())
+                .map(|(prompt, completion)| TokenUsage::new(prompt, completion)),
+        }))
+    }
+
+    async fn send(&self, history: &[ChatTurn], stream: bool) -> Result<reqwest::Response, Failure> {
+        let res = self
+            .client
+            .post(self.config.url.clone())
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(self.request_body(history, stream).to_string())
+            .send()
+            .await
+            .map_err(Failure::from_reqwest)?;
+        check_status(res).await
+    }
+
+    /// Only the request is retried, a streamed answer is read once
+    async fn post(&self, history: &[ChatTurn], stream: bool) -> Result<reqwest::Response, AIError> {
+        self.service
+            .call(|| self.send(history, stream))
+            .await
+            .map_err(|e| self.service.error(e).into())
+    }
+
+    /// Whole answer to the history
+    pub async fn complete(&self, history: &[ChatTurn]) -> Result<String, AIError> {
+        Ok(self.complete_with_usage(history).await?.0)
+    }
+
+    /// Whole answer to the history and the tokens it took, if the server reports them
+    pub async fn complete_with_usage(
+        &self,
+        history: &[ChatTurn],
+    ) -> Result<(String, Option<TokenUsage>), AIError> {
+        let res = self
+            .service
+            .call(|| async {
+                self.send(history, false)
+                    .await?
+                    .text()
+                    .await
+                    .map_err(Failure::from_reqwest)
+            })
+            .await
+            .map_err(|e| self.service.error(e))?;
+        match self.parse_part(&res)? {
+            Some(part) => Ok((cut_at_stop(&part.content, &self.stop()), part.usage)),
+            None => Err(AIError::AnswerError(format!(
+                "{:?} error: unexpected response: {res}",
+                self.api
+            ))),
+        }
+    }
+}
+
+pub struct LocalModel {
+    client: LocalModelClient,
+    history: Vec<ChatTurn>,
+    context_budget: Option<ContextBudget>,
+    last_usage: Option<TokenUsage>,
+    /// Summary made for the last request, it is paid for with the answer
+    summary_usage: Option<TokenUsage>,
+}
+
+impl LocalModel {
+    pub fn new(client: LocalModelClient, prompt: impl Into<String>) -> Self {
+        Self {
+            client,
+            history: vec![ChatTurn::new(ChatRole::System, prompt)],
+            context_budget: None,
+            last_usage: None,
+            summary_usage: None,
+        }
+    }
+
+    /// Limit the history size, older turns are folded into a summary
+    pub fn with_context_budget(mut self, config: ContextBudgetConfig) -> Self {
+        self.context_budget = Some(ContextBudget::new(config));
+        self
+    }
+
+    /// Fact extractor using the same server
+    pub fn fact_extractor(&self) -> LocalFactExtractor {
+        LocalFactExtractor {
+            client: self.client.clone(),
+        }
+    }
+
+    pub fn history(&self) -> &[ChatTurn] {
+        &self.history
+    }
+
+    fn is_summary(turn: &ChatTurn) -> bool {
+        turn.role == ChatRole::System && turn.content.starts_with(SUMMARY_PREFIX)
+    }
+
+    /// Make room for the request in the context window, the same way as for ChatGPT
+    async fn fit_context(&mut self, request: &str) {
+        self.summary_usage = None;
+        let budget = if let Some(budget) = &self.context_budget {
+            budget
+        } else {
+            return;
+        };
+
+        let history = &mut self.history;
+        let has_summary = history.get(1).is_some_and(Self::is_summary);
+        let start = (1 + has_summary as usize).min(history.len());
+
+        let pinned_tokens = history[..start]
+            .iter()
+            .map(|t| estimate_tokens(&t.content))
+            .sum::<usize>()
+            + estimate_tokens(request);
+        let sizes = history[start..]
+            .iter()
+            .map(|t| estimate_tokens(&t.content))
+            .collect::<Vec<_>>();
+
+        let fold = budget.messages_to_fold(pinned_tokens, &sizes);
+        if fold == 0 {
+            return;
+        }
+        info!("Context budget exceeded, summarizing {fold} messages");
+
+        let mut transcript = vec![];
+        if has_summary {
+            transcript.push(history[1].content[SUMMARY_PREFIX.len()..].to_string());
+        }
+        for t in history.drain(start..start + fold) {
+            let speaker = match t.role {
+                ChatRole::User => "User",
+                ChatRole::Assistant => "Assistant",
+                ChatRole::System => "System",
+            };
+            transcript.push(format!("{}: {}", speaker, t.content));
+        }
+
+        let summary_request = vec![
+            ChatTurn::new(ChatRole::System, budget.summary_prompt()),
+            ChatTurn::new(ChatRole::User, transcript.join("\n")),
+        ];
+
+        // if summarization fails, the old turns are just forgotten, so the request still fits
+        match self.client.complete_with_usage(&summary_request).await {
+            Ok((summary, usage)) => {
+                self.summary_usage =
+                    Some(usage.unwrap_or_else(|| estimate_usage(&summary_request, &summary)));
+                let summary = ChatTurn::new(ChatRole::System, format!("{SUMMARY_PREFIX}{summary}"));
+                let history = &mut self.history;
+                if has_summary {
+                    history[1] = summary;
+                } else {
+                    history.insert(1.min(history.len()), summary);
+                }
+            }
+            Err(e) => error!("Failed to summarize the conversation: {:?}", e),
+        }
+    }
+
+    /// History with the request, added to the model history after the answer.
+    /// The context of the request is sent only this time
+    async fn with_request(&mut self, request: &str, context: Option<String>) -> Vec<ChatTurn> {
+        self.fit_context(request).await;
+        let mut history = self.history.clone();
+        if let Some(context) = context {
+            history.push(ChatTurn::new(ChatRole::System, context));
+        }
+        history.push(ChatTurn::new(ChatRole::User, request));
+        history
+    }
+
+    fn push_turn(&mut self, request: String, answer: String) {
+        self.history.push(ChatTurn::new(ChatRole::User, request));
+        self.history
+            .push(ChatTurn::new(ChatRole::Assistant, answer));
+    }
+}
+
+/// Estimate for the servers that don't report the usage
+fn estimate_usage(history: &[ChatTurn], answer: &str) -> TokenUsage {
+    TokenUsage::estimate(history.iter().map(|t| t.content.as_str()), answer)
+}
+
+async fn send_sentence(chunks: &Sender<AIResponseChunk>, sentence: String) {
+    let _ = chunks
+        .send(AIResponseChunk::Sentence(hashmap! {
+            AIResponseType::RawAnswer => sentence,
+        }))
+        .await;
+}
+
+async fn send_delta(
+    chunks: &Sender<AIResponseChunk>,
+    splitter: &mut SentenceSplitter,
+    delta: &str,
+) {
+    let _ = chunks.send(AIResponseChunk::Delta(delta.to_string())).await;
+    for sentence in splitter.push(delta) {
+        send_sentence(chunks, sentence).await;
+    }
+}
+
+/// End of the text without the last `chars` characters
+fn held_back(text: &str, chars: usize) -> usize {
+    if chars == 0 {
+        return text.len();
+    }
+    text.char_indices()
+        .rev()
+        .nth(chars - 1)
+        .map_or(0, |(i, _)| i)
+}
+
+#[async_trait]
+impl AIinterface for LocalModel {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let text = request.request();
+        let history = self.with_request(&text, request.context()).await;
+        let (answer, usage) = self.client.complete_with_usage(&history).await?;
+
+        self.last_usage = usage.or_else(|| Some(estimate_usage(&history, &answer)));
+        self.push_turn(text, answer.clone());
+        Ok(hashmap! {
+            AIResponseType::RawAnswer => answer,
+        })
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let text = request.request();
+        let history = self.with_request(&text, request.context()).await;
+        let mut res = self.client.post(&history, true).await?;
+
+        // a stop sequence may come in several parts, so the text that may be
+        // its beginning is sent only when the next parts show it is not
+        let stop = self.client.stop();
+        let hold = stop.iter().map(|s| s.chars().count()).max().unwrap_or(0);
+
+        let mut buffer = LineBuffer::default();
+        let mut splitter = SentenceSplitter::new();
+        let mut answer = String::new();
+        let mut sent = 0;
+        let mut usage = None;
+        let mut finished = false;
+        while !finished {
+            let lines = match res
+                .chunk()
+                .await
+                .map_err(|e| self.client.service.error(Failure::from_reqwest(e)))?
+            {
+                Some(bytes) => buffer.push(&bytes),
+                None => {
+                    finished = true;
+                    buffer.finish().into_iter().collect()
+                }
+            };
+            for line in lines {
+                let Some(part) = self.client.parse_part(&line)? else {
+                    continue;
+                };
+                answer.push_str(&part.content);
+                usage = part.usage.or(usage);
+                if part.done || stop_position(&answer, &stop).is_some() {
+                    finished = true;
+                    break;
+                }
+
+                let end = held_back(&answer, hold);
+                if end > sent {
+                    send_delta(&chunks, &mut splitter, &answer[sent..end]).await;
+                    sent = end;
+                }
+            }
+        }
+        let end = stop_position(&answer, &stop).unwrap_or(answer.len());
+        if end > sent {
+            send_delta(&chunks, &mut splitter, &answer[sent..end]).await;
+        }
+        if let Some(sentence) = splitter.finish() {
+            send_sentence(&chunks, sentence).await;
+        }
+
+        let answer = cut_at_stop(&answer, &stop);
+        self.last_usage = usage.or_else(|| Some(estimate_usage(&history, &answer)));
+        self.push_turn(text, answer.clone());
+        Ok(hashmap! {
+            AIResponseType::RawAnswer => answer,
+        })
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        if self.history.is_empty() {
+            Err(AIError::ResetErrorEmpty)
+        } else {
+            self.history.truncate(1);
+            Ok(())
+        }
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        let history = &mut self.history;
+        let n = history.len();
+        if n >= 3
+            && history[n - 1].role == ChatRole::Assistant
+            && history[n - 2].role == ChatRole::User
+        {
+            history.truncate(n - 2);
+            Ok(())
+        } else {
+            Err(AIError::ContextError)
+        }
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        TokenUsage::combine(self.last_usage, self.summary_usage)
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        let json = serde_json::to_string(&self.history).map_err(|_| AIError::ContextError)?;
+        std::fs::write(file, json).map_err(|_| AIError::ContextError)
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        if !file.exists() {
+            error!("File \"{file:?}\" not exists");
+            return Err(AIError::ContextError);
+        }
+
+        self.history = serde_json::from_reader::<_, Vec<ChatTurn>>(
+            std::fs::File::open(file).map_err(|_| AIError::ContextError)?,
+        )
+        .map_err(|_| AIError::ContextError)?;
+        Ok(())
+    }
+
+    fn history(&self) -> Option<Vec<ChatTurn>> {
+        Some(self.history.clone())
+    }
+
+    fn set_history(&mut self, history: Vec<ChatTurn>) {
+        self.history = history;
+    }
+}
+
+pub struct LocalFactExtractor {
+    client: LocalModelClient,
+}
+
+#[async_trait]
+impl FactExtractor for LocalFactExtractor {
+    async fn extract(
+        &self,
+        user: &str,
+        request: &str,
+        answer: &str,
+    ) -> Result<(Vec<String>, TokenUsage), AIError> {
+        let history = vec![
+            ChatTurn::new(ChatRole::System, FACT_EXTRACTION_PROMPT),
+            ChatTurn::new(
+                ChatRole::User,
+                format!("{user}: {request}\nAssistant: {answer}"),
+            ),
+        ];
+        let (facts, usage) = self.client.complete_with_usage(&history).await?;
+        let usage = usage.unwrap_or_else(|| estimate_usage(&history, &facts));
+        Ok((parse_facts(&facts), usage))
+    }
+}
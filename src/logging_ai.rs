@@ -0,0 +1,103 @@
+use std::{collections::HashMap, path::PathBuf, time::Instant};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+use tracing::{error, info};
+
+use crate::{
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    usage::TokenUsage,
+};
+
+/// Logs the requests and the answers passing through
+pub struct LoggingAI {
+    ai: Box<dyn AIinterface>,
+}
+
+impl LoggingAI {
+    pub fn new(ai: Box<dyn AIinterface>) -> Self {
+        Self { ai }
+    }
+}
+
+fn log_request(request: &dyn AIRequest) {
+    info!(
+        "Request from {} ({}, {}): {}",
+        request.user(),
+        request.channel(),
+        request.lang(),
+        request.request()
+    );
+}
+
+fn log_answer(
+    channel: &str,
+    started: Instant,
+    res: &Result<HashMap<AIResponseType, String>, AIError>,
+) {
+    match res {
+        Ok(answer) => info!(
+            "Answer to {} in {:.1}s: {:?}",
+            channel,
+            started.elapsed().as_secs_f32(),
+            answer
+        ),
+        Err(e) => error!(
+            "Failed to answer {} in {:.1}s: {:?}",
+            channel,
+            started.elapsed().as_secs_f32(),
+            e
+        ),
+    }
+}
+
+#[async_trait]
+impl AIinterface for LoggingAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        log_request(request.as_ref());
+        let (channel, started) = (request.channel(), Instant::now());
+
+        let res = self.ai.process(request).await;
+        log_answer(&channel, started, &res);
+        res
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        log_request(request.as_ref());
+        let (channel, started) = (request.channel(), Instant::now());
+
+        let res = self.ai.process_streamed(request, chunks).await;
+        log_answer(&channel, started, &res);
+        res
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        info!("Reset context");
+        self.ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        self.ai.last_usage()
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        info!("Save context to {:?}", file);
+        self.ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        info!("Load context from {:?}", file);
+        self.ai.load_context(file)
+    }
+}
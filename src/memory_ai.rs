@@ -0,0 +1,136 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+use tracing::{debug, error};
+
+use crate::{
+    ai_memory_request::MemoryAIRequest,
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    memory_store::{FactExtractor, MemoryStore},
+    usage::TokenUsage,
+};
+
+/// Adds relevant facts about the user to each request and learns new ones from the answers.
+/// The extraction finishes after the answer, its tokens are counted with the next request
+pub struct MemoryAI {
+    ai: Box<dyn AIinterface>,
+    memory: Arc<MemoryStore>,
+    extractor: Arc<dyn FactExtractor>,
+    /// Finished extractions not counted yet
+    learned_usage: Arc<Mutex<TokenUsage>>,
+    /// Extractions counted with the last request
+    counted_usage: Option<TokenUsage>,
+}
+
+impl MemoryAI {
+    pub fn new(
+        ai: Box<dyn AIinterface>,
+        memory: Arc<MemoryStore>,
+        extractor: Arc<dyn FactExtractor>,
+    ) -> Self {
+        Self {
+            ai,
+            memory,
+            extractor,
+            learned_usage: Arc::default(),
+            counted_usage: None,
+        }
+    }
+
+    fn count_learned(&mut self) {
+        let learned = std::mem::take(&mut *self.learned_usage.lock().unwrap());
+        self.counted_usage = (learned.total() > 0).then_some(learned);
+    }
+
+    async fn with_memories(&self, request: Box<dyn AIRequest>) -> Box<dyn AIRequest> {
+        let facts = self
+            .memory
+            .recall(&request.user(), &request.request())
+            .await;
+        if facts.is_empty() {
+            request
+        } else {
+            debug!("Recalled: {:?}", facts);
+            Box::new(MemoryAIRequest::new(request, facts))
+        }
+    }
+
+    /// Extract facts in background, the answer is not delayed
+    fn learn(&self, user: String, request: String, answer: &HashMap<AIResponseType, String>) {
+        let answer = match answer.get(&AIResponseType::RawAnswer) {
+            Some(answer) => answer.clone(),
+            None => return,
+        };
+        let memory = self.memory.clone();
+        let extractor = self.extractor.clone();
+        let learned_usage = self.learned_usage.clone();
+        tokio::spawn(async move {
+            match extractor.extract(&user, &request, &answer).await {
+                Ok((facts, usage)) => {
+                    *learned_usage.lock().unwrap() += usage;
+                    for fact in facts {
+                        debug!("Remember about {}: {}", user, fact);
+                        memory.remember(&user, &fact).await;
+                    }
+                }
+                Err(e) => error!("Failed to extract facts: {:?}", e),
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl AIinterface for MemoryAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let (user, text) = (request.user(), request.request());
+        let request = self.with_memories(request).await;
+
+        let res = self.ai.process(request).await?;
+        self.count_learned();
+        self.learn(user, text, &res);
+        Ok(res)
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let (user, text) = (request.user(), request.request());
+        let request = self.with_memories(request).await;
+
+        let res = self.ai.process_streamed(request, chunks).await?;
+        self.count_learned();
+        self.learn(user, text, &res);
+        Ok(res)
+    }
+
+    /// Only the conversation is reset, facts about users are kept
+    async fn reset(&mut self) -> Result<(), AIError> {
+        self.ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        TokenUsage::combine(self.counted_usage, self.ai.last_usage())
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.load_context(file)
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    sync::Mutex,
+};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::{config::MemoryConfig, dispatcher::AIError, usage::TokenUsage};
+
+/// Minimal cosine similarity of embeddings for a fact to be recalled
+const MIN_EMBEDDING_SIMILARITY: f32 = 0.75;
+
+/// Facts that are this similar are considered the same
+const DUPLICATE_SIMILARITY: f32 = 0.8;
+
+/// Backend that turns text into a vector for semantic search
+#[async_trait]
+pub trait EmbeddingBackend: Send + Sync {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, AIError>;
+}
+
+/// Instruction to pull facts about the user out of one exchange
+pub const FACT_EXTRACTION_PROMPT: &str = "You extract long-term facts about the user from a conversation: \
+    preferences, hobbies, plans, personal details. Write each fact as a short sentence \
+    starting with the user name, one fact per line. If there is nothing worth remembering, answer NONE.";
+
+/// Facts from the answer to `FACT_EXTRACTION_PROMPT`, one per line
+pub fn parse_facts(answer: &str) -> Vec<String> {
+    answer
+        .lines()
+        .map(|l| l.trim().trim_start_matches('-').trim())
+        .filter(|l| !l.is_empty() && !l.eq_ignore_ascii_case("NONE"))
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Extracts durable facts about the user from a conversation turn
+#[async_trait]
+pub trait FactExtractor: Send + Sync {
+    /// The facts and the tokens it took
+    async fn extract(
+        &self,
+        user: &str,
+        request: &str,
+        answer: &str,
+    ) -> Result<(Vec<String>, TokenUsage), AIError>;
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MemoryFact {
+    pub text: String,
+    /// Missing if the embedding backend was unavailable when the fact was stored
+    pub embedding: Option<Vec<f32>>,
+}
+
+fn words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() > 2)
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// Lexical similarity of two texts (Jaccard index of their words)
+pub fn lexical_similarity(a: &str, b: &str) -> f32 {
+    let (a, b) = (words(a), words(b));
+    let union = a.union(&b).count();
+    if union == 0 {
+        0.0
+    } else {
+        a.intersection(&b).count() as f32 / union as f32
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    let dot = a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+    let norm =
+        a.iter().map(|x| x * x).sum::<f32>().sqrt() * b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        0.0
+    } else {
+        dot / norm
+    }
+}
+
+/// File name of the user facts: letters, digits, `-` and `_` are kept, the other bytes are
+/// `%XX`-encoded, so a user name can't point outside `Memory_path`
+fn file_name(user: &str) -> String {
+    let mut name = String::with_capacity(user.len());
+    for b in user.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'-' || b == b'_' {
+            name.push(b as char);
+        } else {
+            name.push_str(&format!("%{b:02X}"));
+        }
+    }
+    name
+}
+
+/// Long-term facts about each user, stored as `<Memory_path>/<user>.json`.
+/// Not affected by the conversation reset
+pub struct MemoryStore {
+    config: MemoryConfig,
+    embeddings: Option<Box<dyn EmbeddingBackend>>,
+    facts: Mutex<HashMap<String, Vec<MemoryFact>>>,
+}
+
+impl MemoryStore {
+    pub fn new(config: MemoryConfig) -> Self {
+        Self {
+            config,
+            embeddings: None,
+            facts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Use semantic search, lexical search is still used if the backend fails
+    pub fn with_embeddings(mut self, embeddings: Box<dyn EmbeddingBackend>) -> Self {
+        self.embeddings = Some(embeddings);
+        self
+    }
+
+    fn user_file(&self, user: &str) -> PathBuf {
+        self.config
+            .memory_path
+            .join(format!("{}.json", file_name(user)))
+    }
+
+    fn load_user(&self, user: &str) -> Vec<MemoryFact> {
+        let file = self.user_file(user);
+        if !file.exists() {
+            return vec![];
+        }
+
+        match std::fs::File::open(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+        {
+            Ok(facts) => facts,
+            Err(e) => {
+                error!("Failed to load memory from {:?}: {}", file, e);
+                vec![]
+            }
+        }
+    }
+
+    fn save_user(&self, user: &str, facts: &[MemoryFact]) {
+        let file = self.user_file(user);
+        let res = std::fs::create_dir_all(&self.config.memory_path)
+            .map_err(|e| e.to_string())
+            .and_then(|_| std::fs::File::create(&file).map_err(|e| e.to_string()))
+            .and_then(|f| serde_json::to_writer(f, facts).map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            error!("Failed to save memory to {:?}: {}", file, e);
+        }
+    }
+
+    /// All the facts about the user
+    pub fn facts(&self, user: &str) -> Vec<MemoryFact> {
+        let mut facts = self.facts.lock().unwrap();
+        facts
+            .entry(user.to_string())
+            .or_insert_with(|| self.load_user(user))
+            .clone()
+    }
+
+    async fn embed(&self, text: &str) -> Option<Vec<f32>> {
+        match &self.embeddings {
+            Some(embeddings) => match embeddings.embed(text).await {
+                Ok(embedding) => Some(embedding),
+                Err(e) => {
+                    warn!("Embedding failed, using lexical search: {:?}", e);
+                    None
+                }
+            },
+            None => None,
+        }
+    }
+
+    /// Store a fact about the user, duplicates are skipped
+    pub async fn remember(&self, user: &str, fact: &str) {
+        let fact = fact.trim();
+        if fact.is_empty() {
+            return;
+        }
+        let embedding = self.embed(fact).await;
+
+        let mut facts = self.facts.lock().unwrap();
+        let user_facts = facts
+            .entry(user.to_string())
+            .or_insert_with(|| self.load_user(user));
+
+        let duplicate = user_facts.iter().any(|known| {
+            let similarity = match (&known.embedding, &embedding) {
+                (Some(a), Some(b)) => cosine_similarity(a, b),
+                _ => lexical_similarity(&known.text, fact),
+            };
+            similarity >= DUPLICATE_SIMILARITY
+        });
+        if duplicate {
+            return;
+        }
+
+        user_facts.push(MemoryFact {
+            text: fact.to_string(),
+            embedding,
+        });
+        if user_facts.len() > self.config.max_facts {
+            let extra = user_facts.len() - self.config.max_facts;
+            user_facts.drain(..extra);
+        }
+        self.save_user(user, user_facts);
+    }
+
+    /// Facts about the user relevant to the request, the most relevant first
+    pub async fn recall(&self, user: &str, request: &str) -> Vec<String> {
+        let facts = self.facts(user);
+        if facts.is_empty() || self.config.recall_count == 0 {
+            return vec![];
+        }
+
+        let query = if facts.iter().any(|f| f.embedding.is_some()) {
+            self.embed(request).await
+        } else {
+            None
+        };
+
+        let mut scored = facts
+            .into_iter()
+            .filter_map(|fact| {
+                let score = match (&fact.embedding, &query) {
+                    (Some(a), Some(b)) => {
+                        Some(cosine_similarity(a, b)).filter(|s| *s >= MIN_EMBEDDING_SIMILARITY)
+                    }
+                    _ => Some(lexical_similarity(&fact.text, request)).filter(|s| *s > 0.0),
+                };
+                score.map(|score| (score, fact.text))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        scored
+            .into_iter()
+            .take(self.config.recall_count)
+            .map(|(_, text)| text)
+            .collect()
+    }
+}
@@ -0,0 +1,99 @@
+use std::sync::Arc;
+
+use reqwest::Url;
+
+use crate::{
+    config::ModerationAction, deeplx_translate_owned::DeepLxTranslatorOwned,
+    dispatcher::AIinterface, logging_ai::LoggingAI, moderated_ai::ModeratedAI,
+    moderation::Moderator, numbers_to_words_ai::NumbersToWordsAI, persona::Persona,
+    prompt_template_ai::PromptTemplateAI, sanitized_ai::SanitizedAI,
+};
+
+/// Layer around the AI, each persona AI gets its own copy of the chain
+pub trait Middleware: Send + Sync {
+    fn wrap(&self, ai: Box<dyn AIinterface>, persona: &Persona) -> Box<dyn AIinterface>;
+}
+
+/// Wrap the AI into the layers, listed from the outermost
+pub fn wrap_all(
+    middleware: &[Arc<dyn Middleware>],
+    ai: Box<dyn AIinterface>,
+    persona: &Persona,
+) -> Box<dyn AIinterface> {
+    middleware
+        .iter()
+        .rev()
+        .fold(ai, |ai, layer| layer.wrap(ai, persona))
+}
+
+/// Requests are translated to english, answers to `dest_lang` or the persona language
+pub struct Translate {
+    pub src_lang: String,
+    pub dest_lang: Option<String>,
+    pub drop_nonconfident_result: f64,
+    /// DeepL JSON-RPC endpoint
+    pub url: Url,
+}
+
+impl Middleware for Translate {
+    fn wrap(&self, ai: Box<dyn AIinterface>, persona: &Persona) -> Box<dyn AIinterface> {
+        let dest_lang = self
+            .dest_lang
+            .clone()
+            .unwrap_or(persona.answer_lang.clone());
+        Box::new(
+            DeepLxTranslatorOwned::new(
+                ai,
+                Some(self.src_lang.clone()),
+                Some(dest_lang),
+                Some(self.drop_nonconfident_result),
+            )
+            .with_url(self.url.clone()),
+        )
+    }
+}
+
+pub struct NumbersToWords;
+
+impl Middleware for NumbersToWords {
+    fn wrap(&self, ai: Box<dyn AIinterface>, _persona: &Persona) -> Box<dyn AIinterface> {
+        Box::new(NumbersToWordsAI::new(ai))
+    }
+}
+
+pub struct Moderation {
+    pub moderator: Arc<Moderator>,
+    pub action: ModerationAction,
+}
+
+impl Middleware for Moderation {
+    fn wrap(&self, ai: Box<dyn AIinterface>, _persona: &Persona) -> Box<dyn AIinterface> {
+        Box::new(ModeratedAI::new(ai, self.moderator.clone(), self.action))
+    }
+}
+
+pub struct PromptTemplate {
+    pub template: String,
+}
+
+impl Middleware for PromptTemplate {
+    fn wrap(&self, ai: Box<dyn AIinterface>, _persona: &Persona) -> Box<dyn AIinterface> {
+        Box::new(PromptTemplateAI::new(ai, self.template.clone()))
+    }
+}
+
+pub struct Sanitize;
+
+impl Middleware for Sanitize {
+    fn wrap(&self, ai: Box<dyn AIinterface>, _persona: &Persona) -> Box<dyn AIinterface> {
+        Box::new(SanitizedAI::new(ai))
+    }
+}
+
+pub struct Logging;
+
+impl Middleware for Logging {
+    fn wrap(&self, ai: Box<dyn AIinterface>, _persona: &Persona) -> Box<dyn AIinterface> {
+        Box::new(LoggingAI::new(ai))
+    }
+}
@@ -0,0 +1,248 @@
+use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Sender};
+use tracing::warn;
+
+use crate::{
+    config::ModerationAction,
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface, RetryRequest},
+    moderation::{Moderator, Verdict},
+    usage::TokenUsage,
+};
+
+/// The variants of the answer are made from the raw one, so only it is sent to the moderator.
+/// Masking is local and applies to all of them
+async fn check_answer(moderator: &Moderator, answer: &HashMap<AIResponseType, String>) -> Verdict {
+    let text = answer
+        .get(&AIResponseType::RawAnswer)
+        .or_else(|| answer.values().next());
+    match text {
+        Some(text) => moderator.check(text).await,
+        None => Verdict::default(),
+    }
+}
+
+fn mask_answer(
+    moderator: &Moderator,
+    answer: &HashMap<AIResponseType, String>,
+) -> HashMap<AIResponseType, String> {
+    answer
+        .iter()
+        .map(|(k, v)| (k.clone(), moderator.mask(v)))
+        .collect()
+}
+
+fn canned_answer(
+    line: String,
+    answer: &HashMap<AIResponseType, String>,
+) -> HashMap<AIResponseType, String> {
+    answer.keys().map(|k| (k.clone(), line.clone())).collect()
+}
+
+/// Checks the answers before they are published, the flagged ones are
+/// masked, regenerated or replaced with a canned line
+pub struct ModeratedAI {
+    ai: Box<dyn AIinterface>,
+    moderator: Arc<Moderator>,
+    action: ModerationAction,
+    /// The regenerated answers are paid for too
+    rejected_usage: Option<TokenUsage>,
+}
+
+impl ModeratedAI {
+    pub fn new(
+        ai: Box<dyn AIinterface>,
+        moderator: Arc<Moderator>,
+        action: ModerationAction,
+    ) -> Self {
+        Self {
+            ai,
+            moderator,
+            action,
+            rejected_usage: None,
+        }
+    }
+
+    /// The rejected answer is removed from the history, so the AI does not continue it
+    async fn forget_rejected(&mut self) {
+        if let Err(e) = self.ai.forget_last_turn().await {
+            warn!(
+                "Failed to remove the rejected answer from the history: {:?}",
+                e
+            );
+        }
+    }
+
+    /// Moderate the whole answer, regenerating it if needed
+    async fn moderate(
+        &mut self,
+        request: RetryRequest,
+        mut answer: HashMap<AIResponseType, String>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let mut regenerations = 0;
+        loop {
+            let verdict = check_answer(&self.moderator, &answer).await;
+            if !verdict.is_flagged() {
+                return Ok(answer);
+            }
+
+            match self.action {
+                ModerationAction::Mask if verdict.is_maskable() => {
+                    warn!(
+                        "Moderation: masked answer to {} ({})",
+                        request.channel(),
+                        verdict.reasons()
+                    );
+                    return Ok(mask_answer(&self.moderator, &answer));
+                }
+                ModerationAction::Regenerate
+                    if regenerations < self.moderator.max_regenerations() =>
+                {
+                    regenerations += 1;
+                    warn!(
+                        "Moderation: regenerating answer to {}, attempt {} ({})",
+                        request.channel(),
+                        regenerations,
+                        verdict.reasons()
+                    );
+                    self.forget_rejected().await;
+                    self.rejected_usage =
+                        TokenUsage::combine(self.rejected_usage, self.ai.last_usage());
+                    answer = self.ai.process(Box::new(request.clone())).await?;
+                }
+                _ => {
+                    warn!(
+                        "Moderation: replaced answer to {} with a canned line ({})",
+                        request.channel(),
+                        verdict.reasons()
+                    );
+                    self.forget_rejected().await;
+                    return Ok(canned_answer(self.moderator.canned_line(), &answer));
+                }
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl AIinterface for ModeratedAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let retry = RetryRequest::new(request.as_ref());
+        self.rejected_usage = None;
+        let answer = self.ai.process(request).await?;
+        self.moderate(retry, answer).await
+    }
+
+    /// Sentences are checked before they are sent on. Raw deltas are not published at all.
+    /// With `Mask` and `Canned` the sentences are the only checked units, what the moderator
+    /// would flag only across sentences gets through. With `Regenerate` the answer is held back
+    /// until it is complete and checked as a whole
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let retry = RetryRequest::new(request.as_ref());
+        self.rejected_usage = None;
+        let (inner_tx, mut inner_rx) = mpsc::channel(16);
+        let moderator = self.moderator.clone();
+        let action = self.action;
+        let channel = retry.channel();
+
+        let moderate_sentences = async {
+            let mut held_back = vec![];
+            let mut blocked = false;
+            while let Some(chunk) = inner_rx.recv().await {
+                let sentence = match chunk {
+                    AIResponseChunk::Sentence(sentence) => sentence,
+                    AIResponseChunk::Delta(_) => continue,
+                    other => {
+                        let _ = chunks.send(other).await;
+                        continue;
+                    }
+                };
+                if blocked {
+                    continue;
+                }
+                if action == ModerationAction::Regenerate {
+                    held_back.push(sentence);
+                    continue;
+                }
+
+                let verdict = check_answer(&moderator, &sentence).await;
+                let sentence = if !verdict.is_flagged() {
+                    sentence
+                } else if action == ModerationAction::Mask && verdict.is_maskable() {
+                    warn!(
+                        "Moderation: masked sentence to {} ({})",
+                        channel,
+                        verdict.reasons()
+                    );
+                    mask_answer(&moderator, &sentence)
+                } else {
+                    // the rest of the answer is dropped
+                    warn!(
+                        "Moderation: replaced answer to {} with a canned line ({})",
+                        channel,
+                        verdict.reasons()
+                    );
+                    blocked = true;
+                    canned_answer(moderator.canned_line(), &sentence)
+                };
+                let _ = chunks.send(AIResponseChunk::Sentence(sentence)).await;
+            }
+            (held_back, blocked)
+        };
+
+        let (answer, (held_back, blocked)) = tokio::join!(
+            self.ai.process_streamed(request, inner_tx),
+            moderate_sentences
+        );
+        let answer = answer?;
+
+        if blocked {
+            self.forget_rejected().await;
+            return Ok(canned_answer(self.moderator.canned_line(), &answer));
+        }
+        if action != ModerationAction::Regenerate {
+            // the sentences are already checked, the answer gets the same masking
+            return Ok(mask_answer(&self.moderator, &answer));
+        }
+
+        let moderated = self.moderate(retry, answer.clone()).await?;
+        if moderated == answer {
+            for sentence in held_back {
+                let _ = chunks.send(AIResponseChunk::Sentence(sentence)).await;
+            }
+        } else {
+            let _ = chunks
+                .send(AIResponseChunk::Sentence(moderated.clone()))
+                .await;
+        }
+        Ok(moderated)
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        self.ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        TokenUsage::combine(self.rejected_usage, self.ai.last_usage())
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.load_context(file)
+    }
+}
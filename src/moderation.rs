@@ -0,0 +1,125 @@
+use async_trait::async_trait;
+use rand::Rng;
+use regex::{Regex, RegexBuilder};
+use tracing::warn;
+
+use crate::{config::ModerationConfig, dispatcher::AIError};
+
+/// Used if no canned lines are configured
+const DEFAULT_CANNED_LINE: &str = "Let's talk about something else.";
+
+/// External moderation service, e.g. OpenAI /v1/moderations
+#[async_trait]
+pub trait ModerationBackend: Send + Sync {
+    /// Names of the violated categories, empty if the text is fine
+    async fn check(&self, text: &str) -> Result<Vec<String>, AIError>;
+}
+
+/// Result of the text check
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Verdict {
+    /// Banned words found in the text
+    pub banned_words: Vec<String>,
+    /// Categories flagged by the moderation service
+    pub categories: Vec<String>,
+}
+
+impl Verdict {
+    pub fn is_flagged(&self) -> bool {
+        !self.banned_words.is_empty() || !self.categories.is_empty()
+    }
+
+    /// Masking hides the banned words, but not what the service flagged
+    pub fn is_maskable(&self) -> bool {
+        self.categories.is_empty()
+    }
+
+    pub fn reasons(&self) -> String {
+        self.banned_words
+            .iter()
+            .map(|w| format!("\"{w}\""))
+            .chain(self.categories.iter().cloned())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Checks the answers for banned words and, optionally, with the moderation service
+pub struct Moderator {
+    banned_words: Vec<Regex>,
+    backend: Option<Box<dyn ModerationBackend>>,
+    canned_lines: Vec<String>,
+    max_regenerations: usize,
+}
+
+impl Moderator {
+    pub fn new(config: &ModerationConfig) -> Result<Self, regex::Error> {
+        let banned_words = config
+            .banned_words
+            .iter()
+            .map(|w| RegexBuilder::new(w).case_insensitive(true).build())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            banned_words,
+            backend: None,
+            canned_lines: config.canned_lines.clone(),
+            max_regenerations: config.max_regenerations,
+        })
+    }
+
+    pub fn with_backend(mut self, backend: Box<dyn ModerationBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// If the service is unavailable, only the banned words are checked
+    pub async fn check(&self, text: &str) -> Verdict {
+        let banned_words = self
+            .banned_words
+            .iter()
+            .flat_map(|re| re.find_iter(text).map(|m| m.as_str().to_string()))
+            .collect();
+
+        let categories = match &self.backend {
+            Some(backend) => backend.check(text).await.unwrap_or_else(|e| {
+                warn!(
+                    "Moderation service failed, using banned words only: {:?}",
+                    e
+                );
+                vec![]
+            }),
+            None => vec![],
+        };
+
+        Verdict {
+            banned_words,
+            categories,
+        }
+    }
+
+    /// Replace every banned word with asterisks of the same length
+    pub fn mask(&self, text: &str) -> String {
+        self.banned_words.iter().fold(text.to_string(), |text, re| {
+            re.replace_all(&text, |caps: &regex::Captures| {
+                "*".repeat(caps[0].chars().count())
+            })
+            .into_owned()
+        })
+    }
+
+    /// How many times to ask for another answer before using a canned line
+    pub fn max_regenerations(&self) -> usize {
+        self.max_regenerations
+    }
+
+    /// Random canned line
+    pub fn canned_line(&self) -> String {
+        if self.canned_lines.is_empty() {
+            DEFAULT_CANNED_LINE.to_string()
+        } else {
+            let idx = rand::thread_rng().gen_range(0..self.canned_lines.len());
+            self.canned_lines[idx].clone()
+        }
+    }
+}
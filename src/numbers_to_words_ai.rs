@@ -0,0 +1,90 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::{
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    num2words::convert_numbers2words,
+    usage::TokenUsage,
+};
+
+/// Adds `NoDigits` variant of the raw answer
+fn add_no_digits(answer: &mut HashMap<AIResponseType, String>) {
+    if let Some(raw_answer) = answer.get(&AIResponseType::RawAnswer) {
+        let no_digits = convert_numbers2words(raw_answer.clone());
+        answer.insert(AIResponseType::NoDigits, no_digits);
+    }
+}
+
+/// Writes numbers in the answers in words, TTS can't read digits
+pub struct NumbersToWordsAI {
+    ai: Box<dyn AIinterface>,
+}
+
+impl NumbersToWordsAI {
+    pub fn new(ai: Box<dyn AIinterface>) -> Self {
+        Self { ai }
+    }
+}
+
+#[async_trait]
+impl AIinterface for NumbersToWordsAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let mut res = self.ai.process(request).await?;
+        add_no_digits(&mut res);
+        Ok(res)
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let (inner_tx, mut inner_rx) = mpsc::channel(16);
+
+        let convert_sentences = async {
+            while let Some(chunk) = inner_rx.recv().await {
+                let chunk = match chunk {
+                    AIResponseChunk::Sentence(mut sentence) => {
+                        add_no_digits(&mut sentence);
+                        AIResponseChunk::Sentence(sentence)
+                    }
+                    other => other,
+                };
+                let _ = chunks.send(chunk).await;
+            }
+        };
+
+        let (res, _) = tokio::join!(
+            self.ai.process_streamed(request, inner_tx),
+            convert_sentences
+        );
+        let mut res = res?;
+        add_no_digits(&mut res);
+        Ok(res)
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        self.ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        self.ai.last_usage()
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.load_context(file)
+    }
+}
@@ -0,0 +1,74 @@
+/// Embeddings from OpenAI compatible /v1/embeddings endpoint
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::{
+    dispatcher::AIError,
+    errors::ServiceError,
+    memory_store::EmbeddingBackend,
+    resilience::{self, check_status},
+};
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+pub struct OpenAIEmbeddings {
+    client: reqwest::Client,
+    url: Url,
+    model: String,
+    token: String,
+}
+
+impl OpenAIEmbeddings {
+    pub fn new(url: Url, model: String, token: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            model,
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl EmbeddingBackend for OpenAIEmbeddings {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>, AIError> {
+        let body = serde_json::json!({
+            "model": self.model,
+            "input": text,
+        });
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| ServiceError::from_reqwest(resilience::OPENAI, e))?;
+        let res = check_status(res)
+            .await
+            .map_err(|e| ServiceError::new(resilience::OPENAI, e))?
+            .text()
+            .await
+            .map_err(|e| ServiceError::from_reqwest(resilience::OPENAI, e))?;
+
+        let res = serde_json::from_str::<EmbeddingsResponse>(&res)
+            .map_err(|e| AIError::AnswerError(format!("Embeddings error: {e}, response: {res}")))?;
+
+        res.data
+            .into_iter()
+            .next()
+            .map(|d| d.embedding)
+            .ok_or(AIError::UnknownError)
+    }
+}
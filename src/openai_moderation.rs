@@ -0,0 +1,84 @@
+/// Moderation with OpenAI compatible /v1/moderations endpoint
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use reqwest::Url;
+use serde::Deserialize;
+
+use crate::{
+    dispatcher::AIError,
+    errors::ServiceError,
+    moderation::ModerationBackend,
+    resilience::{self, check_status},
+};
+
+#[derive(Deserialize)]
+struct ModerationResult {
+    flagged: bool,
+    #[serde(default)]
+    categories: HashMap<String, bool>,
+}
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationResult>,
+}
+
+pub struct OpenAIModeration {
+    client: reqwest::Client,
+    url: Url,
+    token: String,
+}
+
+impl OpenAIModeration {
+    pub fn new(url: Url, token: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url,
+            token,
+        }
+    }
+}
+
+#[async_trait]
+impl ModerationBackend for OpenAIModeration {
+    async fn check(&self, text: &str) -> Result<Vec<String>, AIError> {
+        let body = serde_json::json!({ "input": text });
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| ServiceError::from_reqwest(resilience::OPENAI, e))?;
+        let res = check_status(res)
+            .await
+            .map_err(|e| ServiceError::new(resilience::OPENAI, e))?
+            .text()
+            .await
+            .map_err(|e| ServiceError::from_reqwest(resilience::OPENAI, e))?;
+
+        let res = serde_json::from_str::<ModerationResponse>(&res)
+            .map_err(|e| AIError::AnswerError(format!("Moderation error: {e}, response: {res}")))?;
+
+        let mut categories = vec![];
+        for result in res.results.into_iter().filter(|r| r.flagged) {
+            let mut flagged = result
+                .categories
+                .into_iter()
+                .filter(|(_, flagged)| *flagged)
+                .map(|(category, _)| category)
+                .collect::<Vec<_>>();
+            if flagged.is_empty() {
+                flagged.push("flagged".to_string());
+            }
+            categories.append(&mut flagged);
+        }
+        categories.sort();
+        categories.dedup();
+        Ok(categories)
+    }
+}
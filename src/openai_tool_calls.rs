@@ -0,0 +1,385 @@
+/// Function calling through OpenAI compatible /v1/chat/completions endpoint
+use std::sync::Arc;
+
+use chatgpt::{
+    prelude::ModelConfiguration,
+    types::{ChatMessage, Role},
+};
+use maplit::hashmap;
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+use crate::{
+    dispatcher::{AIError, AIResponseChunk, AIResponseType},
+    local_model::LineBuffer,
+    resilience::{check_status, Failure, Service},
+    sentence_splitter::SentenceSplitter,
+    tools::ToolRegistry,
+    usage::TokenUsage,
+};
+
+/// Model may chain tool calls, but not forever
+const MAX_TOOL_ROUNDS: usize = 5;
+
+#[derive(Deserialize, Default)]
+struct FunctionCall {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    arguments: String,
+}
+
+#[derive(Deserialize, Default)]
+struct ToolCall {
+    #[serde(default)]
+    id: String,
+    #[serde(default)]
+    function: FunctionCall,
+}
+
+#[derive(Deserialize, Default)]
+struct ResponseMessage {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCall>,
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    message: ResponseMessage,
+}
+
+#[derive(Deserialize)]
+struct Usage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct CompletionResponse {
+    choices: Vec<Choice>,
+    usage: Option<Usage>,
+}
+
+/// Part of a streamed tool call, the parts of a call have the same index
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    index: usize,
+    id: Option<String>,
+    #[serde(default)]
+    function: FunctionCallDelta,
+}
+
+#[derive(Deserialize, Default)]
+struct FunctionCallDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Delta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    #[serde(default)]
+    delta: Delta,
+}
+
+#[derive(Deserialize)]
+struct CompletionChunk {
+    choices: Vec<ChunkChoice>,
+}
+
+/// Final answer of the model after the tool calls
+pub struct ToolAnswer {
+    /// Replies of the last completion, several if `Reply_count` is above 1
+    pub replies: Vec<String>,
+    /// Tokens of all the completions, if the server reports them
+    pub usage: Option<TokenUsage>,
+}
+
+fn role_name(role: &Role) -> &'static str {
+    if *role == Role::System {
+        "system"
+    } else if *role == Role::Assistant {
+        "assistant"
+    } else {
+        "user"
+    }
+}
+
+async fn send_sentence(chunks: &Sender<AIResponseChunk>, sentence: String) {
+    let _ = chunks
+        .send(AIResponseChunk::Sentence(hashmap! {
+            AIResponseType::RawAnswer => sentence,
+        }))
+        .await;
+}
+
+pub struct OpenAIToolCalls {
+    client: reqwest::Client,
+    url: Url,
+    token: String,
+    /// model and sampling parameters sent with every request
+    parameters: Value,
+    tools: Arc<ToolRegistry>,
+    service: Service,
+}
+
+impl OpenAIToolCalls {
+    /// `service` - retries and the circuit breaker of the backend
+    pub fn new(
+        token: String,
+        config: &ModelConfiguration,
+        tools: Arc<ToolRegistry>,
+        service: Service,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: config.api_url.clone(),
+            token,
+            parameters: json!({
+                "model": config.engine.to_string(),
+                "temperature": config.temperature,
+                "top_p": config.top_p,
+                "presence_penalty": config.presence_penalty,
+                "frequency_penalty": config.frequency_penalty,
+            }),
+            tools,
+            service,
+        }
+    }
+
+    pub fn with_service(mut self, service: Service) -> Self {
+        self.service = service;
+        self
+    }
+
+    fn messages(history: &[ChatMessage]) -> Vec<Value> {
+        history
+            .iter()
+            .map(|m| json!({ "role": role_name(&m.role), "content": m.content }))
+            .collect()
+    }
+
+    async fn post(
+        &self,
+        messages: &[Value],
+        reply_count: u32,
+        stream: bool,
+    ) -> Result<reqwest::Response, Failure> {
+        let mut body = self.parameters.clone();
+        body["messages"] = json!(messages);
+        body["tools"] = json!(self.tools.definitions());
+        body["stream"] = json!(stream);
+        if reply_count > 1 {
+            body["n"] = json!(reply_count);
+        }
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .bearer_auth(&self.token)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(Failure::from_reqwest)?;
+        check_status(res).await
+    }
+
+    async fn complete(
+        &self,
+        messages: &[Value],
+        reply_count: u32,
+    ) -> Result<CompletionResponse, AIError> {
+        let res = self
+            .service
+            .call(|| async {
+                self.post(messages, reply_count, false)
+                    .await?
+                    .text()
+                    .await
+                    .map_err(Failure::from_reqwest)
+            })
+            .await
+            .map_err(|e| self.service.error(e))?;
+
+        serde_json::from_str::<CompletionResponse>(&res)
+            .map_err(|e| AIError::AnswerError(format!("ChatGPT error: {e}, response: {res}")))
+    }
+
+    /// Completion streamed to the listener by tokens and sentences.
+    /// The tool calls come in parts and are collected
+    async fn complete_streamed(
+        &self,
+        messages: &[Value],
+        chunks: &Sender<AIResponseChunk>,
+        splitter: &mut SentenceSplitter,
+    ) -> Result<ResponseMessage, AIError> {
+        // only the request is retried, the answer is streamed once
+        let mut res = self
+            .service
+            .call(|| self.post(messages, 1, true))
+            .await
+            .map_err(|e| self.service.error(e))?;
+
+        let mut buffer = LineBuffer::default();
+        let mut message = ResponseMessage::default();
+        let mut finished = false;
+        while !finished {
+            let lines = match res
+                .chunk()
+                .await
+                .map_err(|e| self.service.error(Failure::from_reqwest(e)))?
+            {
+                Some(bytes) => buffer.push(&bytes),
+                None => {
+                    finished = true;
+                    buffer.finish().into_iter().collect()
+                }
+            };
+            for line in lines {
+                let line = line.strip_prefix("data:").unwrap_or(&line).trim();
+                if !line.starts_with('{') {
+                    continue;
+                }
+                let chunk = serde_json::from_str::<CompletionChunk>(line).map_err(|e| {
+                    AIError::AnswerError(format!("ChatGPT error: {e}, response: {line}"))
+                })?;
+                let Some(delta) = chunk.choices.into_iter().next().map(|c| c.delta) else {
+                    continue;
+                };
+
+                if let Some(content) = delta.content.filter(|c| !c.is_empty()) {
+                    let _ = chunks.send(AIResponseChunk::Delta(content.clone())).await;
+                    for sentence in splitter.push(&content) {
+                        send_sentence(chunks, sentence).await;
+                    }
+                    message
+                        .content
+                        .get_or_insert_with(String::new)
+                        .push_str(&content);
+                }
+                for part in delta.tool_calls {
+                    if message.tool_calls.len() <= part.index {
+                        message
+                            .tool_calls
+                            .resize_with(part.index + 1, ToolCall::default);
+                    }
+                    let call = &mut message.tool_calls[part.index];
+                    if let Some(id) = part.id {
+                        call.id = id;
+                    }
+                    call.function
+                        .name
+                        .push_str(&part.function.name.unwrap_or_default());
+                    call.function
+                        .arguments
+                        .push_str(&part.function.arguments.unwrap_or_default());
+                }
+            }
+        }
+        Ok(message)
+    }
+
+    /// Run the tools the model asks for and add the calls with their results to the messages
+    async fn call_tools(&self, messages: &mut Vec<Value>, message: ResponseMessage, user: &str) {
+        messages.push(json!({
+            "role": "assistant",
+            "content": message.content,
+            "tool_calls": message.tool_calls.iter().map(|c| json!({
+                "id": c.id,
+                "type": "function",
+                "function": { "name": c.function.name, "arguments": c.function.arguments },
+            })).collect::<Vec<_>>(),
+        }));
+        for call in &message.tool_calls {
+            let result = self
+                .tools
+                .call(user, &call.function.name, &call.function.arguments)
+                .await;
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": call.id,
+                "content": result,
+            }));
+        }
+    }
+
+    /// Get the answer to the request at the end of the history, running the tools the model asks for.
+    /// `reply_count` replies are asked for, the tool calls of the first one are followed.
+    /// Tool calls and their results are not added to the history, only the final answer is
+    pub async fn answer(
+        &self,
+        history: &[ChatMessage],
+        user: &str,
+        reply_count: u32,
+    ) -> Result<ToolAnswer, AIError> {
+        let mut messages = Self::messages(history);
+        let mut usage: Option<TokenUsage> = None;
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let res = self.complete(&messages, reply_count).await?;
+            // all the replies are paid for
+            if let Some(u) = res.usage {
+                *usage.get_or_insert_with(TokenUsage::default) +=
+                    TokenUsage::new(u.prompt_tokens, u.completion_tokens);
+            }
+
+            let mut replies = res.choices.into_iter().map(|c| c.message);
+            let first = replies.next().ok_or(AIError::UnknownError)?;
+            if first.tool_calls.is_empty() {
+                // the other replies asking for the tools are not finished
+                let replies = std::iter::once(first)
+                    .chain(replies)
+                    .filter(|m| m.tool_calls.is_empty())
+                    .map(|m| m.content.unwrap_or_default())
+                    .collect();
+                return Ok(ToolAnswer { replies, usage });
+            }
+            self.call_tools(&mut messages, first, user).await;
+        }
+
+        warn!("Too many tool calls, giving up");
+        Err(AIError::AnswerError("Too many tool calls".to_string()))
+    }
+
+    /// Same as `answer`, but every completion is streamed, so the final one reaches
+    /// the listener by tokens. Text the model writes before calling the tools is streamed
+    /// and kept in the answer too
+    pub async fn answer_streamed(
+        &self,
+        history: &[ChatMessage],
+        user: &str,
+        chunks: &Sender<AIResponseChunk>,
+    ) -> Result<String, AIError> {
+        let mut messages = Self::messages(history);
+        let mut splitter = SentenceSplitter::new();
+        let mut answer = String::new();
+
+        for _ in 0..MAX_TOOL_ROUNDS {
+            let message = self
+                .complete_streamed(&messages, chunks, &mut splitter)
+                .await?;
+            answer.push_str(message.content.as_deref().unwrap_or_default());
+            if message.tool_calls.is_empty() {
+                if let Some(sentence) = splitter.finish() {
+                    send_sentence(chunks, sentence).await;
+                }
+                return Ok(answer);
+            }
+            self.call_tools(&mut messages, message, user).await;
+        }
+
+        warn!("Too many tool calls, giving up");
+        Err(AIError::AnswerError("Too many tool calls".to_string()))
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rand::Rng;
+
+use crate::{
+    channel_overrides::ChannelScope,
+    config::{Config, TTSConfig},
+    tts_engine::TTSEngine,
+};
+
+/// Persona made of the main config settings
+pub const DEFAULT_PERSONA: &str = "default";
+
+const DEFAULT_BUSY_MESSAGE: &str = "I'm busy, please wait";
+
+/// Persona settings with the missing ones taken from the main config
+#[derive(Clone)]
+pub struct Persona {
+    pub name: String,
+    pub initial_prompt: String,
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub presence_penalty: Option<f32>,
+    pub frequency_penalty: Option<f32>,
+    pub tts_config: TTSConfig,
+    pub answer_lang: String,
+    pub busy_messages: Vec<String>,
+    /// Display raw AI response, not a persona setting, but may differ per channel
+    pub display_raw_resp: bool,
+}
+
+impl Persona {
+    /// Resolve persona by name, `DEFAULT_PERSONA` is always available
+    pub fn resolve(config: &Config, name: &str) -> Option<Self> {
+        let default = Self {
+            name: DEFAULT_PERSONA.to_string(),
+            initial_prompt: config.initial_prompt.clone(),
+            temperature: None,
+            top_p: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            tts_config: config.tts_config.clone(),
+            answer_lang: config.deeplx_translate_config.dest_lang.clone(),
+            busy_messages: config.busy_messages.clone(),
+            display_raw_resp: config.display_raw_resp,
+        };
+
+        // "default" section in the personas overrides the main config settings
+        let persona = match config.personas.get(name) {
+            Some(persona) => persona,
+            None if name == DEFAULT_PERSONA => return Some(default),
+            None => return None,
+        };
+        Some(Self {
+            name: name.to_string(),
+            initial_prompt: persona.initial_prompt.clone(),
+            temperature: persona.temperature,
+            top_p: persona.top_p,
+            presence_penalty: persona.presence_penalty,
+            frequency_penalty: persona.frequency_penalty,
+            tts_config: persona.tts_config.clone().unwrap_or(default.tts_config),
+            answer_lang: persona.answer_lang.clone().unwrap_or(default.answer_lang),
+            busy_messages: persona
+                .busy_messages
+                .clone()
+                .unwrap_or(default.busy_messages),
+            display_raw_resp: default.display_raw_resp,
+        })
+    }
+
+    /// All persona names, the default one first
+    pub fn names(config: &Config) -> Vec<String> {
+        let mut names = config.personas.keys().cloned().collect::<Vec<_>>();
+        names.sort();
+        names.retain(|n| n != DEFAULT_PERSONA);
+        names.insert(0, DEFAULT_PERSONA.to_string());
+        names
+    }
+
+    /// All personas by name
+    pub fn all(config: &Config) -> HashMap<String, Self> {
+        Self::names(config)
+            .into_iter()
+            .filter_map(|name| Self::resolve(config, &name).map(|p| (name, p)))
+            .collect()
+    }
+
+    /// Random busy message
+    pub fn busy_message(&self) -> String {
+        if self.busy_messages.is_empty() {
+            DEFAULT_BUSY_MESSAGE.to_string()
+        } else {
+            let idx = rand::thread_rng().gen_range(0..self.busy_messages.len());
+            self.busy_messages[idx].clone()
+        }
+    }
+}
+
+/// Persona with the channel settings and its TTS engine
+type ResolvedPersona = (Persona, Arc<TTSEngine>);
+
+/// Personas with their TTS engines, used by the bots to speak with the channel persona voice
+pub struct Personas {
+    config: Config,
+    names: Vec<String>,
+    /// By the matching overrides and the persona name
+    resolved: Mutex<HashMap<(Vec<usize>, String), ResolvedPersona>>,
+}
+
+impl Personas {
+    pub fn with_config(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            names: Persona::names(config),
+            resolved: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// All persona names, the default one first
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Persona with the channel settings and its TTS engine, the default one if not found
+    fn resolve(&self, scope: &ChannelScope, name: &str) -> ResolvedPersona {
+        let key = (self.config.matching_overrides(scope), name.to_string());
+        self.resolved
+            .lock()
+            .unwrap()
+            .entry(key)
+            .or_insert_with(|| {
+                let config = self.config.for_scope(scope);
+                let persona = Persona::resolve(&config, name)
+                    .or_else(|| Persona::resolve(&config, DEFAULT_PERSONA))
+                    .unwrap();
+                let tts = Arc::new(TTSEngine::with_config(&persona.tts_config));
+                (persona, tts)
+            })
+            .clone()
+    }
+
+    /// Persona by name with the channel settings, the default one if not found
+    pub fn get(&self, scope: &ChannelScope, name: &str) -> Persona {
+        self.resolve(scope, name).0
+    }
+
+    /// TTS engine of the persona in the channel, the default one if not found
+    pub fn tts(&self, scope: &ChannelScope, name: &str) -> Arc<TTSEngine> {
+        self.resolve(scope, name).1
+    }
+}
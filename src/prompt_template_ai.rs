@@ -0,0 +1,62 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::Sender;
+
+use crate::{
+    ai_templated_request::TemplatedAIRequest,
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    usage::TokenUsage,
+};
+
+/// Inserts each request into the prompt template
+pub struct PromptTemplateAI {
+    ai: Box<dyn AIinterface>,
+    template: String,
+}
+
+impl PromptTemplateAI {
+    pub fn new(ai: Box<dyn AIinterface>, template: String) -> Self {
+        Self { ai, template }
+    }
+}
+
+#[async_trait]
+impl AIinterface for PromptTemplateAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let request = TemplatedAIRequest::new(request, &self.template);
+        self.ai.process(Box::new(request)).await
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let request = TemplatedAIRequest::new(request, &self.template);
+        self.ai.process_streamed(Box::new(request), chunks).await
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        self.ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        self.ai.last_usage()
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.load_context(file)
+    }
+}
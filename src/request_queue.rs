@@ -0,0 +1,130 @@
+use std::collections::VecDeque;
+
+use tokio::sync::mpsc::Sender;
+use tracing::warn;
+
+use crate::{
+    ai_merged_request::MergedAIRequest,
+    config::{QueueOverflowPolicy, RequestQueueConfig},
+    dispatcher::{AIError, AIRequest, AIResponseChunk},
+};
+
+/// Receiver of the answer chunks
+pub type AnswerListener = Sender<Result<AIResponseChunk, AIError>>;
+
+/// Request waiting for its turn, merged requests have several listeners
+pub struct PendingRequest {
+    requests: Vec<Box<dyn AIRequest>>,
+    listeners: Vec<AnswerListener>,
+}
+
+impl PendingRequest {
+    pub fn new(request: Box<dyn AIRequest>, listener: AnswerListener) -> Self {
+        Self {
+            requests: vec![request],
+            listeners: vec![listener],
+        }
+    }
+
+    fn merge(&mut self, other: PendingRequest) {
+        self.requests.extend(other.requests);
+        self.listeners.extend(other.listeners);
+    }
+
+    fn notify(&self, chunk: Result<AIResponseChunk, AIError>) {
+        for listener in &self.listeners {
+            // position updates are not important enough to wait for the listener
+            let _ = listener.try_send(chunk.clone());
+        }
+    }
+
+    /// Request to send to the AI and everyone waiting for the answer
+    pub fn into_parts(mut self) -> (Box<dyn AIRequest>, Vec<AnswerListener>) {
+        let request = if self.requests.len() == 1 {
+            self.requests.pop().unwrap()
+        } else {
+            Box::new(MergedAIRequest::new(self.requests))
+        };
+        (request, self.listeners)
+    }
+}
+
+/// Bounded FIFO of requests to one channel
+pub struct RequestQueue {
+    config: RequestQueueConfig,
+    busy: bool,
+    pending: VecDeque<PendingRequest>,
+}
+
+impl RequestQueue {
+    pub fn new(config: RequestQueueConfig) -> Self {
+        Self {
+            config,
+            busy: false,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Number of requests waiting in the queue
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Add a request to the queue.
+    /// If the channel is idle, the request is returned back to be processed right away,
+    /// otherwise it waits and its listeners get `AIResponseChunk::Queued` with the position
+    pub fn push(&mut self, request: PendingRequest) -> Result<Option<PendingRequest>, AIError> {
+        if !self.busy {
+            self.busy = true;
+            return Ok(Some(request));
+        }
+
+        if self.pending.len() < self.config.depth {
+            request.notify(Ok(AIResponseChunk::Queued(self.pending.len() + 1)));
+            self.pending.push_back(request);
+            return Ok(None);
+        }
+
+        match self.config.overflow_policy {
+            QueueOverflowPolicy::DropNewest => Err(AIError::Busy),
+            QueueOverflowPolicy::DropOldest => {
+                let dropped = self.pending.pop_front().ok_or(AIError::Busy)?;
+                warn!("Request queue is full, dropping the oldest request");
+                dropped.notify(Err(AIError::Busy));
+
+                self.pending.push_back(request);
+                self.notify_positions();
+                Ok(None)
+            }
+            QueueOverflowPolicy::Merge => {
+                let position = self.pending.len();
+                let last = self.pending.back_mut().ok_or(AIError::Busy)?;
+                request.notify(Ok(AIResponseChunk::Queued(position)));
+                last.merge(request);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Take the next request after the current one is answered,
+    /// if there is nothing to do the channel becomes idle
+    pub fn pop_next(&mut self) -> Option<PendingRequest> {
+        let next = self.pending.pop_front();
+        if next.is_some() {
+            self.notify_positions();
+        } else {
+            self.busy = false;
+        }
+        next
+    }
+
+    fn notify_positions(&self) {
+        for (i, request) in self.pending.iter().enumerate() {
+            request.notify(Ok(AIResponseChunk::Queued(i + 1)));
+        }
+    }
+}
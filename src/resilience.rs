@@ -0,0 +1,361 @@
+/// Timeouts, retries and circuit breakers of the external services
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+use reqwest::StatusCode;
+use tracing::{info, warn};
+
+use crate::{
+    config::{ResilienceConfig, ServicePolicy},
+    errors::ServiceError,
+};
+
+/// OpenAI API
+pub const OPENAI: &str = "openai";
+/// OpenAI compatible llama server
+pub const LLAMA: &str = "llama";
+/// llama.cpp server `/completion`
+pub const LLAMA_CPP: &str = "llama_cpp";
+pub const OLLAMA: &str = "ollama";
+pub const DEEPLX: &str = "deeplx";
+pub const SILERO_TTS: &str = "silero_tts";
+pub const JP_TTS: &str = "jp_tts";
+pub const WHISPER: &str = "whisper";
+
+/// Service names of `Resilience.Services`
+pub const SERVICES: [&str; 8] = [
+    OPENAI, LLAMA, LLAMA_CPP, OLLAMA, DEEPLX, SILERO_TTS, JP_TTS, WHISPER,
+];
+
+/// Failed attempt to call a service
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub message: String,
+    /// Worth another attempt: timeouts, connection errors, rate limits and server errors
+    pub transient: bool,
+    /// Status of the answer, `None` if there was no answer
+    pub status: Option<StatusCode>,
+    source: Option<Arc<dyn Error + Send + Sync>>,
+}
+
+impl Failure {
+    pub fn transient(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            transient: true,
+            status: None,
+            source: None,
+        }
+    }
+
+    pub fn permanent(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            transient: false,
+            status: None,
+            source: None,
+        }
+    }
+
+    pub fn from_reqwest(e: reqwest::Error) -> Self {
+        let failure = match e.status() {
+            Some(status) => Self::from_status(status, e.to_string()),
+            None if e.is_timeout() || e.is_connect() || e.is_request() || e.is_body() => {
+                Self::transient(e.to_string())
+            }
+            None => Self::permanent(e.to_string()),
+        };
+        failure.with_source(e)
+    }
+
+    /// Rate limits and server errors are transient
+    pub fn from_status(status: StatusCode, message: impl fmt::Display) -> Self {
+        let message = format!("HTTP {status}: {message}");
+        let failure = if status == StatusCode::TOO_MANY_REQUESTS
+            || status == StatusCode::REQUEST_TIMEOUT
+            || status.is_server_error()
+        {
+            Self::transient(message)
+        } else {
+            Self::permanent(message)
+        };
+        Self {
+            status: Some(status),
+            ..failure
+        }
+    }
+
+    /// The error that caused the failure
+    pub fn with_source(mut self, source: impl Error + Send + Sync + 'static) -> Self {
+        self.source = Some(Arc::new(source));
+        self
+    }
+}
+
+/// The sources are not compared
+impl PartialEq for Failure {
+    fn eq(&self, other: &Self) -> bool {
+        self.message == other.message
+            && self.transient == other.transient
+            && self.status == other.status
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Failure {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref().map(|e| e as &(dyn Error + 'static))
+    }
+}
+
+/// Error of the response with a failed status, the body is the message
+pub async fn check_status(res: reqwest::Response) -> Result<reqwest::Response, Failure> {
+    let status = res.status();
+    if status.is_success() {
+        Ok(res)
+    } else {
+        let body = res.text().await.unwrap_or_default();
+        Err(Failure::from_status(status, body.trim()))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BreakerState {
+    /// Calls go through, counting the failures in a row
+    Closed { failures: u32 },
+    /// Calls fail without trying until the cooldown ends
+    Open { until: Instant },
+    /// The cooldown ended, one trial call decides
+    HalfOpen,
+}
+
+/// Stops calling a service after several failures in a row
+pub struct CircuitBreaker {
+    service: String,
+    /// 0 - never opens
+    threshold: u32,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(service: impl Into<String>, threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            service: service.into(),
+            threshold,
+            cooldown,
+            state: Mutex::new(BreakerState::Closed { failures: 0 }),
+        }
+    }
+
+    pub fn state(&self) -> BreakerState {
+        *self.state.lock().unwrap()
+    }
+
+    /// Whether a call may be made now, the first call after the cooldown is the trial one
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        match *state {
+            BreakerState::Closed { .. } => true,
+            BreakerState::Open { until } if Instant::now() >= until => {
+                info!("Circuit breaker of {} is half-open, trying", self.service);
+                *state = BreakerState::HalfOpen;
+                true
+            }
+            BreakerState::Open { .. } | BreakerState::HalfOpen => false,
+        }
+    }
+
+    /// The service answered, even with a permanent error
+    pub fn record_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        if !matches!(*state, BreakerState::Closed { .. }) {
+            info!("Circuit breaker of {} closed", self.service);
+        }
+        *state = BreakerState::Closed { failures: 0 };
+    }
+
+    pub fn record_failure(&self) {
+        let mut state = self.state.lock().unwrap();
+        let failures = match *state {
+            BreakerState::Closed { failures } => failures + 1,
+            // the trial call failed
+            BreakerState::HalfOpen => self.threshold,
+            BreakerState::Open { .. } => return,
+        };
+        *state = if self.threshold > 0 && failures >= self.threshold {
+            warn!(
+                "Circuit breaker of {} opened for {:?} after {failures} failures",
+                self.service, self.cooldown
+            );
+            BreakerState::Open {
+                until: Instant::now() + self.cooldown,
+            }
+        } else {
+            BreakerState::Closed { failures }
+        };
+    }
+}
+
+/// Call in progress. If it is dropped before its result is recorded, e.g. cancelled by
+/// `Backend_timeout` of the caller, a trial call counts as failed, so the breaker opens again
+/// instead of staying half-open forever
+struct PendingCall<'a> {
+    breaker: &'a CircuitBreaker,
+    recorded: bool,
+}
+
+impl Drop for PendingCall<'_> {
+    fn drop(&mut self) {
+        if !self.recorded && self.breaker.state() == BreakerState::HalfOpen {
+            warn!("Trial call of {} was cancelled", self.breaker.service);
+            self.breaker.record_failure();
+        }
+    }
+}
+
+/// Calls of one service with its policy and circuit breaker
+#[derive(Clone)]
+pub struct Service {
+    name: String,
+    policy: ServicePolicy,
+    breaker: Arc<CircuitBreaker>,
+}
+
+impl Service {
+    /// Service with its own breaker, not shared with the other clients
+    pub fn new(name: impl Into<String>, policy: ServicePolicy) -> Self {
+        let name = name.into();
+        Self {
+            breaker: Arc::new(CircuitBreaker::new(
+                name.clone(),
+                policy.breaker_threshold,
+                Duration::from_secs_f32(policy.breaker_cooldown),
+            )),
+            name,
+            policy,
+        }
+    }
+
+    /// Service configured with `configure`, all the clients of a service share its breaker
+    pub fn get(name: &str) -> Self {
+        let mut registry = REGISTRY.lock().unwrap();
+        let policy = registry
+            .config
+            .services
+            .get(name)
+            .unwrap_or(&registry.config.default)
+            .clone();
+        let service = registry
+            .services
+            .entry(name.to_string())
+            .or_insert_with(|| Service::new(name, policy));
+        service.clone()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn breaker(&self) -> &CircuitBreaker {
+        &self.breaker
+    }
+
+    /// Error of the service for the callers
+    pub fn error(&self, failure: Failure) -> ServiceError {
+        ServiceError::new(&self.name, failure)
+    }
+
+    /// Pause before the retry (starting from 0), between a half and the whole of the exponential delay
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let delay = (self.policy.initial_backoff * 2f32.powi(retry.min(30) as i32))
+            .min(self.policy.max_backoff)
+            .max(0.0);
+        Duration::from_secs_f32(rand::thread_rng().gen_range(delay / 2.0..=delay))
+    }
+
+    /// Call `attempt` until it succeeds, fails with a permanent error or the retries end
+    pub async fn call<T, F, Fut>(&self, mut attempt: F) -> Result<T, Failure>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Failure>>,
+    {
+        let timeout = Duration::from_secs_f32(self.policy.timeout);
+        let mut retry = 0;
+        loop {
+            if !self.breaker.allow() {
+                // not retried, the breaker is open for a while
+                return Err(Failure {
+                    status: Some(StatusCode::SERVICE_UNAVAILABLE),
+                    ..Failure::permanent(format!(
+                        "{} is unavailable, the circuit breaker is open",
+                        self.name
+                    ))
+                });
+            }
+
+            let mut pending = PendingCall {
+                breaker: &self.breaker,
+                recorded: false,
+            };
+            let res = tokio::time::timeout(timeout, attempt())
+                .await
+                .unwrap_or_else(|_| Err(Failure::transient(format!("{} timed out", self.name))));
+            pending.recorded = true;
+
+            let failure = match res {
+                Ok(res) => {
+                    self.breaker.record_success();
+                    return Ok(res);
+                }
+                Err(failure) if !failure.transient => {
+                    self.breaker.record_success();
+                    return Err(failure);
+                }
+                Err(failure) => failure,
+            };
+
+            self.breaker.record_failure();
+            let open = matches!(self.breaker.state(), BreakerState::Open { .. });
+            if open || retry >= self.policy.max_retries {
+                warn!("{} failed: {}", self.name, failure);
+                return Err(failure);
+            }
+
+            let backoff = self.backoff(retry);
+            warn!("{} failed: {}, retry in {:?}", self.name, failure, backoff);
+            tokio::time::sleep(backoff).await;
+            retry += 1;
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    config: ResilienceConfig,
+    services: HashMap<String, Service>,
+}
+
+lazy_static::lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+/// Apply the policies, the breakers are created again
+pub fn configure(config: &ResilienceConfig) {
+    *REGISTRY.lock().unwrap() = Registry {
+        config: config.clone(),
+        services: HashMap::new(),
+    };
+}
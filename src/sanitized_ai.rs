@@ -0,0 +1,119 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use async_trait::async_trait;
+use tokio::sync::mpsc::{self, Sender};
+
+use crate::{
+    dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+    sanitizer::Sanitizer,
+    usage::TokenUsage,
+};
+
+/// Sanitizers of the variants an answer is built from
+#[derive(Default)]
+struct AnswerSanitizer {
+    spoken: Sanitizer,
+    display: Sanitizer,
+    actions: Sanitizer,
+}
+
+impl AnswerSanitizer {
+    /// Adds `Spoken` variant of the text for TTS, `Display` of the translated or raw answer
+    /// and `Actions` of the raw answer, so they don't depend on the translation
+    fn add_variants(&mut self, answer: &mut HashMap<AIResponseType, String>) {
+        use AIResponseType::*;
+
+        let Some(raw) = answer.get(&RawAnswer) else {
+            return;
+        };
+        let actions = self.actions.push(raw).actions;
+        let spoken = [Translated, NoDigits]
+            .iter()
+            .find_map(|t| answer.get(t))
+            .unwrap_or(raw);
+        let spoken = self.spoken.push(spoken).spoken;
+        let display = answer.get(&Translated).unwrap_or(raw);
+        let display = self.display.push(display).display;
+
+        answer.insert(Spoken, spoken);
+        answer.insert(Display, display);
+        if !actions.is_empty() {
+            answer.insert(Actions, actions.join("\n"));
+        }
+    }
+}
+
+/// Separates the speech from the stage directions, emotion tags, markdown, emoji and links,
+/// TTS should read `Spoken` and the chat should show `Display`
+pub struct SanitizedAI {
+    ai: Box<dyn AIinterface>,
+}
+
+impl SanitizedAI {
+    pub fn new(ai: Box<dyn AIinterface>) -> Self {
+        Self { ai }
+    }
+}
+
+#[async_trait]
+impl AIinterface for SanitizedAI {
+    async fn process(
+        &mut self,
+        request: Box<dyn AIRequest>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let mut res = self.ai.process(request).await?;
+        AnswerSanitizer::default().add_variants(&mut res);
+        Ok(res)
+    }
+
+    async fn process_streamed(
+        &mut self,
+        request: Box<dyn AIRequest>,
+        chunks: Sender<AIResponseChunk>,
+    ) -> Result<HashMap<AIResponseType, String>, AIError> {
+        let (inner_tx, mut inner_rx) = mpsc::channel(16);
+
+        let sanitize_sentences = async {
+            // code blocks and actions may span several sentences
+            let mut sanitizer = AnswerSanitizer::default();
+            while let Some(chunk) = inner_rx.recv().await {
+                let chunk = match chunk {
+                    AIResponseChunk::Sentence(mut sentence) => {
+                        sanitizer.add_variants(&mut sentence);
+                        AIResponseChunk::Sentence(sentence)
+                    }
+                    other => other,
+                };
+                let _ = chunks.send(chunk).await;
+            }
+        };
+
+        let (res, _) = tokio::join!(
+            self.ai.process_streamed(request, inner_tx),
+            sanitize_sentences
+        );
+        let mut res = res?;
+        AnswerSanitizer::default().add_variants(&mut res);
+        Ok(res)
+    }
+
+    async fn reset(&mut self) -> Result<(), AIError> {
+        self.ai.reset().await
+    }
+
+    async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+        self.ai.forget_last_turn().await
+    }
+
+    fn last_usage(&self) -> Option<TokenUsage> {
+        self.ai.last_usage()
+    }
+
+    async fn save_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.save_context(file).await
+    }
+
+    fn load_context(&mut self, file: PathBuf) -> Result<(), AIError> {
+        self.ai.load_context(file)
+    }
+}
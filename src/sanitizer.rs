@@ -0,0 +1,213 @@
+/// Separation of the speech from the stage directions, emotion tags, markdown, emoji, code and links
+use std::collections::HashMap;
+
+use regex::{Captures, Regex};
+
+use crate::dispatcher::AIResponseType;
+
+lazy_static::lazy_static! {
+    /// `[happy]` emotion tag or `[text](url)` markdown link, with the spaces after it
+    static ref BRACKETS: Regex =
+        Regex::new(r"\[([^\[\]\n]*)\](\([^()\s]*\))?[ \t]*").unwrap();
+    static ref URL: Regex = Regex::new(r"https?://\S+").unwrap();
+    /// Headers, quotes and list bullets
+    static ref LINE_MARKUP: Regex = Regex::new(r"(?m)^[ \t]*(#+|>+|[-+]|\d+\.)[ \t]+").unwrap();
+    static ref INLINE_MARKUP: Regex = Regex::new(r"`|~~|__").unwrap();
+    static ref SPACE_BEFORE_PUNCTUATION: Regex = Regex::new(r"\s+([,.!?;:])").unwrap();
+    static ref SPACES: Regex = Regex::new(r"[ \t]{2,}").unwrap();
+}
+
+const CODE_FENCE: &str = "```";
+const CODE_FENCE_CHAR: char = '`';
+
+/// Parts of an answer
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sanitized {
+    /// Plain text for TTS
+    pub spoken: String,
+    /// Text for the chat and the subtitles, markdown and `*actions*` are kept, emotion tags are not
+    pub display: String,
+    /// `*actions*` and `[emotion tags]` in the order they were closed, without the markup
+    pub actions: Vec<String>,
+}
+
+/// Code blocks and actions may span several sentences of a streamed answer,
+/// so the sanitizer remembers the open ones between the calls of `push`
+#[derive(Default)]
+pub struct Sanitizer {
+    in_code: bool,
+    /// Text of the action that is not closed yet
+    action: Option<String>,
+}
+
+fn is_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{1F000}'..='\u{1FAFF}' // pictographs, emoticons, flags
+        | '\u{2600}'..='\u{27BF}' // symbols and dingbats
+        | '\u{2B00}'..='\u{2BFF}' // arrows and stars
+        | '\u{FE00}'..='\u{FE0F}' // variation selectors
+        | '\u{200D}' // zero width joiner
+        | '\u{20E3}' // keycap
+        | '\u{E0020}'..='\u{E007F}' // tag characters
+    )
+}
+
+/// Emotion tags are removed, links are kept
+fn strip_tags(text: &str) -> String {
+    BRACKETS
+        .replace_all(text, |caps: &Captures| match caps.get(2) {
+            Some(_) => caps[0].to_string(),
+            None => String::new(),
+        })
+        .into_owned()
+}
+
+fn tidy_spaces(text: &str) -> String {
+    let text = SPACES.replace_all(text, " ");
+    SPACE_BEFORE_PUNCTUATION
+        .replace_all(&text, "$1")
+        .trim()
+        .to_string()
+}
+
+/// Markup, links and emoji are removed, emotion tags are moved to `actions`
+fn to_speech(text: &str, actions: &mut Vec<String>) -> String {
+    let text = BRACKETS.replace_all(text, |caps: &Captures| match caps.get(2) {
+        Some(_) => format!("{} ", &caps[1]),
+        None => {
+            let tag = caps[1].trim();
+            if !tag.is_empty() {
+                actions.push(tag.to_string());
+            }
+            String::new()
+        }
+    });
+    let text = URL.replace_all(&text, "");
+    let text = LINE_MARKUP.replace_all(&text, "");
+    let text = INLINE_MARKUP.replace_all(&text, "");
+    let text = text
+        .chars()
+        .filter(|c| !is_emoji(*c))
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .collect::<String>();
+    tidy_spaces(&text)
+}
+
+impl Sanitizer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sanitize the next part of the answer
+    pub fn push(&mut self, text: &str) -> Sanitized {
+        let chars = text.chars().collect::<Vec<_>>();
+        let mut speech = String::new();
+        // text out of the code blocks, the emotion tags are removed from it
+        let mut display_text = String::new();
+        let mut display = String::new();
+        let mut actions = vec![];
+        if let Some(action) = &mut self.action {
+            if !action.is_empty() {
+                // the next sentence of the action
+                action.push(' ');
+            }
+        }
+
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            let prev = i.checked_sub(1).map(|p| chars[p]);
+            let next = chars.get(i + 1).copied();
+
+            if chars[i..].starts_with(&[CODE_FENCE_CHAR; 3]) {
+                if self.in_code {
+                    display.push_str(CODE_FENCE);
+                } else {
+                    display.push_str(&strip_tags(&std::mem::take(&mut display_text)));
+                    display.push_str(CODE_FENCE);
+                    // the code is not read aloud, the sentence around it is
+                    speech.push(' ');
+                }
+                self.in_code = !self.in_code;
+                i += CODE_FENCE.len();
+                continue;
+            }
+            if self.in_code {
+                display.push(c);
+                i += 1;
+                continue;
+            }
+
+            display_text.push(c);
+            i += 1;
+            if let Some(action) = &mut self.action {
+                if c == '*' {
+                    let action = action.trim().to_string();
+                    if !action.is_empty() {
+                        actions.push(action);
+                    }
+                    self.action = None;
+                    speech.push(' ');
+                } else {
+                    action.push(c);
+                }
+                continue;
+            }
+            if c != '*' {
+                speech.push(c);
+                continue;
+            }
+
+            if next == Some('*') {
+                // bold
+                display_text.push('*');
+                i += 1;
+            } else if !prev.is_some_and(char::is_alphanumeric)
+                && next.is_some_and(|n| !n.is_whitespace())
+            {
+                self.action = Some(String::new());
+            } else {
+                // list bullet or a stray asterisk
+                speech.push(' ');
+            }
+        }
+        display.push_str(&strip_tags(&display_text));
+
+        Sanitized {
+            spoken: to_speech(&speech, &mut actions),
+            display: display.trim().to_string(),
+            actions,
+        }
+    }
+}
+
+/// Sanitize a whole answer
+pub fn sanitize(text: &str) -> Sanitized {
+    Sanitizer::new().push(text)
+}
+
+fn text_of<'a>(answer: &'a HashMap<AIResponseType, String>, types: &[AIResponseType]) -> &'a str {
+    types
+        .iter()
+        .find_map(|t| answer.get(t))
+        .map_or("", String::as_str)
+}
+
+/// Text for TTS: `Spoken`, else the translated, digit-free or raw answer
+pub fn spoken_text(answer: &HashMap<AIResponseType, String>) -> &str {
+    use AIResponseType::*;
+    text_of(answer, &[Spoken, Translated, NoDigits, RawAnswer])
+}
+
+/// Text for the chat and the subtitles: `Display`, else the same text as spoken
+pub fn display_text(answer: &HashMap<AIResponseType, String>) -> &str {
+    use AIResponseType::*;
+    text_of(answer, &[Display, Translated, NoDigits, RawAnswer])
+}
+
+/// Actions and emotion tags of the answer, one per line in `Actions`
+pub fn actions(answer: &HashMap<AIResponseType, String>) -> Vec<&str> {
+    answer
+        .get(&AIResponseType::Actions)
+        .map_or(vec![], |actions| actions.lines().collect())
+}
@@ -0,0 +1,124 @@
+/// Secret config values, e.g. tokens, read from the environment, files or commands
+use std::{fmt, path::PathBuf, process::Command};
+
+use serde::{de, Deserialize, Deserializer};
+use serde_json::Value;
+
+use crate::config_loader::REDACTED;
+
+/// Value hidden from `Debug` and `Display`, use `expose` to get it
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Self::new(value)
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Self::new(value)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+/// Where the secret is taken from
+#[derive(Debug, Clone, PartialEq)]
+pub enum SecretSource {
+    /// Written in the config
+    Value(String),
+    /// `{"env": "NAME"}`
+    Env(String),
+    /// `{"file": "/run/secrets/x"}`, the trailing newline is dropped
+    File(PathBuf),
+    /// `{"command": "pass show ..."}`, run with the system shell, stdout is the secret
+    Command(String),
+}
+
+impl SecretSource {
+    /// A string or an object with one of `env`, `file` or `command` fields
+    pub fn from_value(value: Value) -> Result<Self, String> {
+        const EXPECTED: &str = r#"expected a string, {"env": ..}, {"file": ..} or {"command": ..}"#;
+
+        let object = match value {
+            Value::String(value) => return Ok(SecretSource::Value(value)),
+            Value::Object(object) if object.len() == 1 => object,
+            _ => return Err(EXPECTED.to_string()),
+        };
+        let (key, value) = object.into_iter().next().unwrap();
+        let Value::String(value) = value else {
+            return Err(format!("\"{key}\" must be a string"));
+        };
+        match key.as_str() {
+            "env" => Ok(SecretSource::Env(value)),
+            "file" => Ok(SecretSource::File(value.into())),
+            "command" => Ok(SecretSource::Command(value)),
+            _ => Err(format!("unknown secret source \"{key}\", {EXPECTED}")),
+        }
+    }
+
+    pub fn resolve(&self) -> Result<Secret, String> {
+        match self {
+            SecretSource::Value(value) => Ok(Secret::new(value.clone())),
+            SecretSource::Env(name) => std::env::var(name)
+                .map(Secret::new)
+                .map_err(|e| format!("environment variable {name}: {e}")),
+            SecretSource::File(path) => std::fs::read_to_string(path)
+                .map(|s| Secret::new(s.trim_end_matches(['\r', '\n'])))
+                .map_err(|e| format!("secret file {}: {e}", path.display())),
+            SecretSource::Command(command) => run_command(command),
+        }
+    }
+}
+
+fn run_command(command: &str) -> Result<Secret, String> {
+    let output = if cfg!(windows) {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
+    }
+    .map_err(|e| format!("secret command failed to start: {e}"))?;
+
+    // the output is not shown, it may contain the secret
+    if !output.status.success() {
+        return Err(format!("secret command failed with {}", output.status));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| "secret command output is not UTF-8".to_string())?;
+    Ok(Secret::new(stdout.trim_end_matches(['\r', '\n'])))
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = Value::deserialize(deserializer)?;
+        SecretSource::from_value(value)
+            .and_then(|source| source.resolve())
+            .map_err(de::Error::custom)
+    }
+}
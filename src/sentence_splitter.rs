@@ -0,0 +1,87 @@
+/// Splits a stream of text fragments (LLM token deltas) into complete sentences
+#[derive(Default)]
+pub struct SentenceSplitter {
+    buffer: String,
+}
+
+fn is_terminator(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | '…')
+}
+
+fn is_cjk_terminator(c: char) -> bool {
+    matches!(c, '。' | '！' | '？')
+}
+
+fn is_closer(c: char) -> bool {
+    matches!(
+        c,
+        '"' | '\'' | ')' | ']' | '»' | '”' | '’' | '」' | '』' | '）'
+    )
+}
+
+/// Returns byte offset of the end of the first complete sentence in the text
+fn find_boundary(text: &str) -> Option<usize> {
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c == '\n' {
+            return Some(i + c.len_utf8());
+        }
+
+        let cjk = is_cjk_terminator(c);
+        if !cjk && !is_terminator(c) {
+            continue;
+        }
+
+        // "?!", "...", closing quotes and brackets belong to the sentence
+        let mut end = i + c.len_utf8();
+        while let Some(&(j, n)) = chars.peek() {
+            if is_terminator(n) || is_cjk_terminator(n) || is_closer(n) {
+                end = j + n.len_utf8();
+                chars.next();
+            } else {
+                break;
+            }
+        }
+
+        match chars.peek() {
+            _ if cjk => return Some(end),
+            Some(&(_, n)) if n.is_whitespace() => return Some(end),
+            Some(_) => { /* "3.14", "example.com" - not a sentence end */ }
+            None => return None, // wait for the next fragment
+        }
+    }
+    None
+}
+
+impl SentenceSplitter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append a text fragment, returns all sentences completed by it
+    pub fn push(&mut self, fragment: &str) -> Vec<String> {
+        self.buffer.push_str(fragment);
+
+        let mut sentences = vec![];
+        while let Some(end) = find_boundary(&self.buffer) {
+            let rest = self.buffer.split_off(end);
+            let sentence = std::mem::replace(&mut self.buffer, rest);
+            let sentence = sentence.trim();
+            if !sentence.is_empty() {
+                sentences.push(sentence.to_string());
+            }
+        }
+        sentences
+    }
+
+    /// Returns the rest of the text as the last sentence
+    pub fn finish(&mut self) -> Option<String> {
+        let rest = std::mem::take(&mut self.buffer);
+        let rest = rest.trim();
+        if rest.is_empty() {
+            None
+        } else {
+            Some(rest.to_string())
+        }
+    }
+}
@@ -0,0 +1,123 @@
+/// Tools the AI can call to get information it does not have or to act on the user's behalf
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tracing::{info, warn};
+
+use crate::{
+    builtin_tools::{CalculatorTool, DiceTool, RememberTool, TimeTool},
+    dispatcher::AIError,
+    memory_store::MemoryStore,
+};
+
+/// Names of the built-in tools in the config
+pub const BUILTIN_TOOLS: [&str; 4] = ["time", "dice", "calculator", "remember"];
+
+#[async_trait]
+pub trait Tool: Send + Sync {
+    /// Name the model uses to call the tool
+    fn name(&self) -> &str;
+
+    /// What the tool does, helps the model to decide when to call it
+    fn description(&self) -> &str;
+
+    /// JSON schema of the arguments object
+    fn parameters(&self) -> Value;
+
+    /// Run the tool for the user, the result is sent back to the model as is
+    async fn call(&self, user: &str, args: Value) -> Result<String, AIError>;
+}
+
+/// Set of tools advertised to the model
+#[derive(Default)]
+pub struct ToolRegistry {
+    tools: Vec<Arc<dyn Tool>>,
+}
+
+impl ToolRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Built-in tools by name, "remember" is only available with the memory store
+    pub fn with_config(names: &[String], memory: Option<Arc<MemoryStore>>) -> Self {
+        let mut registry = Self::new();
+        for name in names {
+            registry = match name.as_str() {
+                "time" => registry.with_tool(Arc::new(TimeTool)),
+                "dice" => registry.with_tool(Arc::new(DiceTool)),
+                "calculator" => registry.with_tool(Arc::new(CalculatorTool)),
+                "remember" => match &memory {
+                    Some(memory) => registry.with_tool(Arc::new(RememberTool::new(memory.clone()))),
+                    None => {
+                        warn!("Tool \"remember\" needs the Memory config, skipping...");
+                        registry
+                    }
+                },
+                _ => panic!(
+                    "Invalid tool: {name}, supported values {}",
+                    BUILTIN_TOOLS.join(", ")
+                ),
+            };
+        }
+        registry
+    }
+
+    /// Add a tool, a tool with the same name is replaced
+    pub fn with_tool(mut self, tool: Arc<dyn Tool>) -> Self {
+        self.tools.retain(|t| t.name() != tool.name());
+        self.tools.push(tool);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tools.is_empty()
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.tools.iter().map(|t| t.name()).collect()
+    }
+
+    /// Tool descriptions in the OpenAI function calling format
+    pub fn definitions(&self) -> Vec<Value> {
+        self.tools
+            .iter()
+            .map(|t| {
+                json!({
+                    "type": "function",
+                    "function": {
+                        "name": t.name(),
+                        "description": t.description(),
+                        "parameters": t.parameters(),
+                    }
+                })
+            })
+            .collect()
+    }
+
+    /// Run the tool call from the model. `arguments` - JSON object as a string.
+    /// Errors are returned as text, so the model can tell the user or try again
+    pub async fn call(&self, user: &str, name: &str, arguments: &str) -> String {
+        let tool = match self.tools.iter().find(|t| t.name() == name) {
+            Some(tool) => tool,
+            None => return format!("Error: unknown tool {name}"),
+        };
+
+        let args = if arguments.trim().is_empty() {
+            json!({})
+        } else {
+            match serde_json::from_str::<Value>(arguments) {
+                Ok(args) => args,
+                Err(e) => return format!("Error: invalid arguments: {e}"),
+            }
+        };
+
+        info!("Calling tool {} for {}: {}", name, user, args);
+        match tool.call(user, args).await {
+            Ok(result) => result,
+            Err(AIError::AnswerError(e)) => format!("Error: {e}"),
+            Err(e) => format!("Error: {e:?}"),
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::io::Cursor;
 
 use bytes::Bytes;
 
-use crate::{config, jp_tts::JpTTS, silerio_tts::SilerioTTS};
+use crate::{config, errors::TTSError, jp_tts::JpTTS, silerio_tts::SilerioTTS};
 
 pub enum TTSEngine {
     NullTTS,
@@ -33,7 +33,7 @@ impl TTSEngine {
         }
     }
 
-    pub async fn say<S>(&self, text: S) -> Result<Cursor<Bytes>, String>
+    pub async fn say<S>(&self, text: S) -> Result<Cursor<Bytes>, TTSError>
     where
         S: Into<String>,
     {
@@ -0,0 +1,389 @@
+/// Token usage accounting per user, channel and day, the budgets and the per-user rate limits
+use std::{
+    collections::{HashMap, VecDeque},
+    ops::AddAssign,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use chrono::{Datelike, Local, NaiveDate};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::error;
+
+use crate::{config::UsageConfig, context_budget::estimate_tokens};
+
+/// Tokens spent by one or more requests
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct TokenUsage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl TokenUsage {
+    pub fn new(prompt_tokens: u64, completion_tokens: u64) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+        }
+    }
+
+    /// Local estimate for the backends that don't report the usage
+    pub fn estimate<'a>(prompt: impl IntoIterator<Item = &'a str>, answer: &str) -> Self {
+        Self::new(
+            prompt.into_iter().map(estimate_tokens).sum::<usize>() as u64,
+            estimate_tokens(answer) as u64,
+        )
+    }
+
+    pub fn total(&self) -> u64 {
+        self.prompt_tokens + self.completion_tokens
+    }
+
+    /// Usage of two completions, a missing one is not counted
+    pub fn combine(a: Option<Self>, b: Option<Self>) -> Option<Self> {
+        match (a, b) {
+            (Some(mut a), Some(b)) => {
+                a += b;
+                Some(a)
+            }
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Equal shares of the tokens, the first one gets the remainder
+    fn split(&self, parts: usize) -> Vec<TokenUsage> {
+        let parts = parts.max(1) as u64;
+        let share = Self::new(self.prompt_tokens / parts, self.completion_tokens / parts);
+        let mut shares = vec![share; parts as usize];
+        shares[0] = Self::new(
+            self.prompt_tokens - share.prompt_tokens * (parts - 1),
+            self.completion_tokens - share.completion_tokens * (parts - 1),
+        );
+        shares
+    }
+}
+
+impl AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+    }
+}
+
+/// Usage of a user or a channel
+#[derive(Serialize, Deserialize, Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct UsageRecord {
+    pub requests: u64,
+    #[serde(flatten)]
+    pub tokens: TokenUsage,
+}
+
+impl UsageRecord {
+    fn add(&mut self, tokens: TokenUsage) {
+        self.requests += 1;
+        self.tokens += tokens;
+    }
+}
+
+/// Usage of one day, stored in `<Usage_path>/<date>.json`
+#[derive(Serialize, Deserialize, Clone, Default, Debug, PartialEq)]
+pub struct DayUsage {
+    #[serde(default)]
+    pub users: HashMap<String, UsageRecord>,
+    #[serde(default)]
+    pub channels: HashMap<String, UsageRecord>,
+}
+
+impl DayUsage {
+    /// Tokens spent by all the channels
+    pub fn total(&self) -> u64 {
+        self.channels.values().map(|r| r.tokens.total()).sum()
+    }
+
+    pub fn user_total(&self, user: &str) -> u64 {
+        self.users.get(user).map_or(0, |r| r.tokens.total())
+    }
+}
+
+/// Limit that rejected a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The user sent `User_requests` requests during the last `User_rate_period`
+    UserRate,
+    /// The user spent `User_daily_budget`
+    UserBudget,
+    /// All the channels spent `Daily_budget`
+    DailyBudget,
+    /// All the channels spent `Monthly_budget`
+    MonthlyBudget,
+}
+
+enum WriterMessage {
+    Save(PathBuf, DayUsage),
+    Flush(std::sync::mpsc::Sender<()>),
+}
+
+fn write_day(file: &Path, usage: &DayUsage) {
+    let res = file
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .map_err(|e| e.to_string())
+        .and_then(|_| std::fs::File::create(file).map_err(|e| e.to_string()))
+        .and_then(|f| serde_json::to_writer(f, usage).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        error!("Failed to save usage to {:?}: {}", file, e);
+    }
+}
+
+/// Writes the days saved while the previous write was running, only the latest state of a day
+fn run_writer(mut rx: UnboundedReceiver<WriterMessage>) {
+    while let Some(message) = rx.blocking_recv() {
+        let mut days = HashMap::new();
+        let mut flushes = Vec::new();
+        for message in std::iter::once(message).chain(std::iter::from_fn(|| rx.try_recv().ok())) {
+            match message {
+                WriterMessage::Save(file, usage) => {
+                    days.insert(file, usage);
+                }
+                WriterMessage::Flush(done) => flushes.push(done),
+            }
+        }
+        for (file, usage) in &days {
+            write_day(file, usage);
+        }
+        for done in flushes {
+            let _ = done.send(());
+        }
+    }
+}
+
+pub struct UsageTracker {
+    config: UsageConfig,
+    /// Days of the current month, older ones are loaded again when asked for
+    days: Mutex<HashMap<NaiveDate, DayUsage>>,
+    /// Total of the month to the date, the monthly budget is checked on every request
+    month: Mutex<Option<(NaiveDate, u64)>>,
+    /// Times of the recent requests of each user
+    recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+    /// Files are written by a separate thread, not by the requests
+    writer: Option<UnboundedSender<WriterMessage>>,
+    writer_thread: Option<JoinHandle<()>>,
+}
+
+impl UsageTracker {
+    pub fn new(config: UsageConfig) -> Self {
+        let (writer, writer_thread) = match config.usage_path {
+            Some(_) => {
+                let (tx, rx) = mpsc::unbounded_channel();
+                let thread = std::thread::Builder::new()
+                    .name("usage-writer".to_string())
+                    .spawn(move || run_writer(rx))
+                    .expect("Failed to start the usage writer");
+                (Some(tx), Some(thread))
+            }
+            None => (None, None),
+        };
+        Self {
+            config,
+            days: Mutex::new(HashMap::new()),
+            month: Mutex::new(None),
+            recent: Mutex::new(HashMap::new()),
+            writer,
+            writer_thread,
+        }
+    }
+
+    fn today() -> NaiveDate {
+        Local::now().date_naive()
+    }
+
+    fn day_file(&self, date: NaiveDate) -> Option<PathBuf> {
+        self.config
+            .usage_path
+            .as_ref()
+            .map(|path| path.join(format!("{}.json", date.format("%Y-%m-%d"))))
+    }
+
+    fn load_day(&self, date: NaiveDate) -> DayUsage {
+        let Some(file) = self.day_file(date).filter(|file| file.exists()) else {
+            return DayUsage::default();
+        };
+
+        match std::fs::File::open(&file)
+            .map_err(|e| e.to_string())
+            .and_then(|f| serde_json::from_reader(f).map_err(|e| e.to_string()))
+        {
+            Ok(usage) => usage,
+            Err(e) => {
+                error!("Failed to load usage from {:?}: {}", file, e);
+                DayUsage::default()
+            }
+        }
+    }
+
+    fn save_day(&self, date: NaiveDate, usage: &DayUsage) {
+        let (Some(writer), Some(file)) = (&self.writer, self.day_file(date)) else {
+            return;
+        };
+        let _ = writer.send(WriterMessage::Save(file, usage.clone()));
+    }
+
+    /// Wait until the recorded usage is written to `Usage_path`
+    pub fn flush(&self) {
+        let Some(writer) = &self.writer else {
+            return;
+        };
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        if writer.send(WriterMessage::Flush(done_tx)).is_ok() {
+            let _ = done_rx.recv();
+        }
+    }
+
+    fn with_day<R>(&self, date: NaiveDate, f: impl FnOnce(&mut DayUsage) -> R) -> R {
+        let mut days = self.days.lock().unwrap();
+        f(days.entry(date).or_insert_with(|| self.load_day(date)))
+    }
+
+    /// Usage of the day, loaded from `Usage_path` if it is not in memory
+    pub fn day(&self, date: NaiveDate) -> DayUsage {
+        self.with_day(date, |usage| usage.clone())
+    }
+
+    /// Tokens spent by all the channels from the first day of the month to `date`
+    pub fn month_total(&self, date: NaiveDate) -> u64 {
+        let mut month = self.month.lock().unwrap();
+        if let Some((cached, total)) = *month {
+            if cached == date {
+                return total;
+            }
+        }
+        let total = (1..=date.day())
+            .filter_map(|day| date.with_day(day))
+            .map(|date| self.with_day(date, |usage| usage.total()))
+            .sum();
+        *month = Some((date, total));
+        total
+    }
+
+    /// Count the answered request
+    pub fn record(&self, user: &str, channel: &str, tokens: TokenUsage) {
+        self.record_on(Self::today(), user, channel, tokens)
+    }
+
+    pub fn record_on(&self, date: NaiveDate, user: &str, channel: &str, tokens: TokenUsage) {
+        self.record_shared_on(date, &[user.to_string()], channel, tokens)
+    }
+
+    /// Count the request answered for several users, e.g. the merged one.
+    /// Each user asked once and pays an equal share of the tokens
+    pub fn record_shared(&self, users: &[String], channel: &str, tokens: TokenUsage) {
+        self.record_shared_on(Self::today(), users, channel, tokens)
+    }
+
+    pub fn record_shared_on(
+        &self,
+        date: NaiveDate,
+        users: &[String],
+        channel: &str,
+        tokens: TokenUsage,
+    ) {
+        self.with_day(date, |usage| {
+            for (user, share) in users.iter().zip(tokens.split(users.len())) {
+                usage.users.entry(user.clone()).or_default().add(share);
+            }
+            usage
+                .channels
+                .entry(channel.to_string())
+                .or_default()
+                .add(tokens);
+            self.save_day(date, usage);
+        });
+
+        let first_day = date.with_day(1).unwrap_or(date);
+        self.days.lock().unwrap().retain(|day, _| *day >= first_day);
+        if let Some((cached, total)) = self.month.lock().unwrap().as_mut() {
+            if cached.with_day(1) == Some(first_day) && date <= *cached {
+                *total += tokens.total();
+            }
+        }
+    }
+
+    /// Check the limits before the request is sent to the AI.
+    /// An allowed request is counted by the rate limit
+    pub fn check(&self, user: &str) -> Result<(), Limit> {
+        self.check_on(Self::today(), user)
+    }
+
+    pub fn check_on(&self, date: NaiveDate, user: &str) -> Result<(), Limit> {
+        if let Some(budget) = self.config.monthly_budget {
+            if self.month_total(date) >= budget {
+                return Err(Limit::MonthlyBudget);
+            }
+        }
+        let (total, user_total) =
+            self.with_day(date, |usage| (usage.total(), usage.user_total(user)));
+        if self
+            .config
+            .daily_budget
+            .is_some_and(|budget| total >= budget)
+        {
+            return Err(Limit::DailyBudget);
+        }
+        if self
+            .config
+            .user_daily_budget
+            .is_some_and(|budget| user_total >= budget)
+        {
+            return Err(Limit::UserBudget);
+        }
+
+        let Some(limit) = self.config.user_requests else {
+            return Ok(());
+        };
+        let now = Instant::now();
+        let period = Duration::from_secs_f32(self.config.user_rate_period.max(0.0));
+        let mut recent = self.recent.lock().unwrap();
+        let requests = recent.entry(user.to_string()).or_default();
+        while requests
+            .front()
+            .is_some_and(|t| now.duration_since(*t) >= period)
+        {
+            requests.pop_front();
+        }
+        if requests.len() >= limit as usize {
+            return Err(Limit::UserRate);
+        }
+        requests.push_back(now);
+        Ok(())
+    }
+
+    /// Don't count the request rejected after the check, e.g. by the full queue
+    pub fn cancel(&self, user: &str) {
+        if let Some(requests) = self.recent.lock().unwrap().get_mut(user) {
+            requests.pop_back();
+        }
+    }
+
+    /// Canned answer to the rejected request
+    pub fn limit_message(&self, limit: Limit) -> &str {
+        match limit {
+            Limit::UserRate => &self.config.rate_limit_message,
+            Limit::UserBudget | Limit::DailyBudget | Limit::MonthlyBudget => {
+                &self.config.budget_message
+            }
+        }
+    }
+}
+
+impl Drop for UsageTracker {
+    fn drop(&mut self) {
+        // the writer finishes the pending days and stops
+        self.writer.take();
+        if let Some(thread) = self.writer_thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
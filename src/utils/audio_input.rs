@@ -15,13 +15,12 @@ use noise_gate::NoiseGate;
 
 use tracing::{debug, error, warn};
 
-use crate::whisper_voice_recognize::OpenAIWhisperVoice2Txt;
+use crate::{config_reload::Live, whisper_voice_recognize::OpenAIWhisperVoice2Txt};
 
 /// A sink which sends audiodata to spech recognition.
 pub struct Sink {
     voice2txt_url: Url,
-    minimal_fragment_length: f32,
-    maximal_fragment_length: f32,
+    fragment_lengths: Live<(f32, f32)>, // minimal and maximal fragment length in seconds
     spec: WavSpec,
     audio_req_tx: Sender<(String, String)>,
 
@@ -32,16 +31,14 @@ pub struct Sink {
 impl Sink {
     pub fn new(
         voice2txt_url: Url,
-        minimal_fragment_length: f32,
-        maximal_fragment_length: f32,
+        fragment_lengths: Live<(f32, f32)>,
         audio_req_tx: Sender<(String, String)>,
         spec: WavSpec,
         tokio_handle: Handle,
     ) -> Self {
         Sink {
             voice2txt_url: voice2txt_url,
-            minimal_fragment_length,
-            maximal_fragment_length,
+            fragment_lengths,
             audio_req_tx,
             spec,
             current_buffer: None,
@@ -74,18 +71,19 @@ where
             let sample_rate = self.spec.sample_rate;
 
             let length = buf.len() as f32 / channels as f32 / sample_rate as f32;
+            let (minimal_fragment_length, maximal_fragment_length) = *self.fragment_lengths.get();
 
-            if length < self.minimal_fragment_length {
+            if length < minimal_fragment_length {
                 debug!(
                     "Voice fragment too short ({length}s < {min}s), skipping...",
-                    min = self.minimal_fragment_length,
+                    min = minimal_fragment_length,
                     length = length
                 );
                 return;
-            } else if length > self.maximal_fragment_length {
+            } else if length > maximal_fragment_length {
                 warn!(
                     "Voice fragment too long ({length}s > {max}s), skipping...",
-                    max = self.maximal_fragment_length,
+                    max = maximal_fragment_length,
                     length = length
                 );
                 return;
@@ -127,8 +125,7 @@ pub fn spawn_audio_input(
     audio_req_tx: Sender<(String, String)>,
     noise_gate: f32,
     voice2txt_url: Url,
-    minimal_fragment_length: f32,
-    maximal_fragment_length: f32,
+    fragment_lengths: Live<(f32, f32)>,
     tokio_handle: Handle,
 ) -> Result<Stream, String> {
     let config = ain.default_input_config().map_err(|e| format!("{e}"))?;
@@ -136,12 +133,12 @@ pub fn spawn_audio_input(
     let sample_rate = config.sample_rate().0;
     let channels = config.channels();
 
-    let release_time = (sample_rate as f32 * minimal_fragment_length).round();
+    // noise gate is not rebuilt on config reload
+    let release_time = (sample_rate as f32 * fragment_lengths.get().0).round();
 
     let mut sink = Sink::new(
         voice2txt_url,
-        minimal_fragment_length,
-        maximal_fragment_length,
+        fragment_lengths,
         audio_req_tx,
         WavSpec {
             channels: 1,
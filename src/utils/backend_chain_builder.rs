@@ -0,0 +1,131 @@
+use std::{sync::Arc, time::Duration};
+
+use crate::{
+    channel_overrides::{ChannelScope, ScopedConfigs},
+    config::{Config, Platform, RouteConfig, MAIN_BACKEND},
+    dispatcher::{AIBuilder, AIinterface},
+    fallback_ai::{Backend, BackendBuilder, FallbackAI, Route},
+    memory_ai::MemoryAI,
+    memory_store::MemoryStore,
+    middleware::{self, Middleware},
+    persona::{Persona, DEFAULT_PERSONA},
+};
+
+/// Builds the AI from the backends of `AIEngine`, the first one is tried first
+pub struct BackendChainBuilder {
+    backends: Vec<(String, Box<dyn BackendBuilder>)>,
+    routing: Vec<RouteConfig>,
+    timeout: Option<Duration>,
+    /// Routing rules of other platforms are ignored
+    platform: Option<Platform>,
+    /// Personas are resolved with the channel overrides
+    bot_config: ScopedConfigs,
+    memory: Option<Arc<MemoryStore>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl BackendChainBuilder {
+    /// `main` - the `Engine_Type` backend, the chain is never empty
+    pub fn new(
+        config: &Config,
+        main: Box<dyn BackendBuilder>,
+        memory: Option<Arc<MemoryStore>>,
+    ) -> Self {
+        Self {
+            backends: vec![(MAIN_BACKEND.to_string(), main)],
+            routing: config.ai_engine.routing.clone(),
+            timeout: config
+                .ai_engine
+                .backend_timeout
+                .map(Duration::from_secs_f32),
+            platform: None,
+            bot_config: ScopedConfigs::new(config.clone()),
+            memory,
+            middleware: vec![],
+        }
+    }
+
+    /// Fallback backend, `name` is used in the routing rules
+    pub fn with_backend(
+        mut self,
+        name: impl Into<String>,
+        builder: Box<dyn BackendBuilder>,
+    ) -> Self {
+        self.backends.push((name.into(), builder));
+        self
+    }
+
+    pub fn with_platform(mut self, platform: Platform) -> Self {
+        self.platform = Some(platform);
+        self
+    }
+
+    /// Layers around the AI, listed from the outermost
+    pub fn with_middleware(mut self, middleware: Vec<Arc<dyn Middleware>>) -> Self {
+        self.middleware = middleware;
+        self
+    }
+
+    /// Rules for the persona, unknown backends are skipped
+    fn routes(&self, persona: &str) -> Vec<Route> {
+        self.routing
+            .iter()
+            .filter(|r| r.applies_to(self.platform, persona))
+            .filter_map(|r| {
+                let backend = self
+                    .backends
+                    .iter()
+                    .position(|(name, _)| *name == r.backend)?;
+                Some(Route {
+                    min_length: r.min_length,
+                    max_length: r.max_length,
+                    backend,
+                })
+            })
+            .collect()
+    }
+}
+
+impl AIBuilder for BackendChainBuilder {
+    fn build(&mut self) -> Box<dyn AIinterface> {
+        self.build_persona(DEFAULT_PERSONA).unwrap()
+    }
+
+    fn build_persona(&mut self, persona: &str) -> Option<Box<dyn AIinterface>> {
+        self.build_for(&ChannelScope::default(), persona)
+    }
+
+    fn build_for(&mut self, scope: &ChannelScope, persona: &str) -> Option<Box<dyn AIinterface>> {
+        let persona = Persona::resolve(&self.bot_config.get(scope), persona)?;
+
+        let ai: Box<dyn AIinterface> = match self.backends.as_slice() {
+            [(_, builder)] => builder.build_backend(&persona),
+            backends => {
+                let backends = backends
+                    .iter()
+                    .map(|(name, builder)| Backend::new(name, builder.build_backend(&persona)))
+                    .collect();
+                let ai = FallbackAI::new(backends).with_routes(self.routes(&persona.name));
+                match self.timeout {
+                    Some(timeout) => Box::new(ai.with_timeout(timeout)),
+                    None => Box::new(ai),
+                }
+            }
+        };
+
+        let ai: Box<dyn AIinterface> = if let Some(memory) = &self.memory {
+            let extractor = self.backends[0].1.fact_extractor();
+            Box::new(MemoryAI::new(ai, memory.clone(), extractor))
+        } else {
+            ai
+        };
+
+        Some(middleware::wrap_all(&self.middleware, ai, &persona))
+    }
+
+    /// Prompts of the personas are updated, the existing channels keep the old ones.
+    /// Backends and routing rules are not reloaded
+    fn reload_config(&mut self, config: &Config) {
+        self.bot_config = ScopedConfigs::new(config.clone());
+    }
+}
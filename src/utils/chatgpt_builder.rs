@@ -1,47 +1,97 @@
+use std::sync::Arc;
+
 use chatgpt::prelude::ModelConfiguration;
 
 use crate::{
+    candidates::CandidateSelector,
     chatgpt::ChatGPT,
-    config::Config,
-    dispatcher::{AIBuilder, AIBuilderStreamed, AIinterface},
+    config::{Config, ContextBudgetConfig},
+    dispatcher::AIinterface,
+    fallback_ai::BackendBuilder,
+    memory_store::{FactExtractor, MemoryStore},
+    persona::Persona,
+    resilience::{self, Service},
+    tools::ToolRegistry,
 };
 
+/// Builds `ChatGPT` for the OpenAI API and the servers compatible with it
 pub struct ChatGPTAIBuilder {
     openai_token: String,
     config: ModelConfiguration,
-    initial_prompt: String,
+    context_budget: ContextBudgetConfig,
+    tools: Arc<ToolRegistry>,
+    /// Name of the service in `Resilience.Services`
+    service: String,
+    candidates: Option<Arc<CandidateSelector>>,
 }
 
 impl ChatGPTAIBuilder {
-    pub fn new(openai_token: String, model_config: ModelConfiguration, config: &Config) -> Self {
+    /// `memory` - the store the "remember" tool writes to
+    pub fn new(
+        openai_token: String,
+        model_config: ModelConfiguration,
+        config: &Config,
+        memory: Option<Arc<MemoryStore>>,
+    ) -> Self {
         Self {
+            tools: Arc::new(ToolRegistry::with_config(&config.ai_engine.tools, memory)),
             openai_token,
             config: model_config,
-            initial_prompt: config.initial_prompt.clone(),
+            context_budget: config.ai_engine.context_budget.clone(),
+            service: resilience::OPENAI.to_string(),
+            candidates: None,
         }
     }
-}
 
-impl AIBuilder for ChatGPTAIBuilder {
-    fn build(&mut self) -> Box<dyn AIinterface> {
-        let ai = ChatGPT::new(
-            self.openai_token.clone(),
-            self.config.clone(),
-            self.initial_prompt.clone(),
-        );
+    /// Servers other than OpenAI get their own retries and circuit breaker
+    pub fn with_service(mut self, service: &str) -> Self {
+        self.service = service.to_string();
+        self
+    }
 
-        Box::new(ai)
+    /// The best of `Reply_count` replies is chosen by the selector
+    pub fn with_candidates(mut self, candidates: Arc<CandidateSelector>) -> Self {
+        self.candidates = Some(candidates);
+        self
     }
 }
 
-impl AIBuilderStreamed for ChatGPTAIBuilder {
-    fn build_streamed(&mut self) -> Box<dyn crate::dispatcher::AIinterfaceStreamed> {
+impl BackendBuilder for ChatGPTAIBuilder {
+    fn build_backend(&self, persona: &Persona) -> Box<dyn AIinterface> {
+        let mut config = self.config.clone();
+        if let Some(temperature) = persona.temperature {
+            config.temperature = temperature;
+        }
+        if let Some(top_p) = persona.top_p {
+            config.top_p = top_p;
+        }
+        if let Some(presence_penalty) = persona.presence_penalty {
+            config.presence_penalty = presence_penalty;
+        }
+        if let Some(frequency_penalty) = persona.frequency_penalty {
+            config.frequency_penalty = frequency_penalty;
+        }
+
         let ai = ChatGPT::new(
             self.openai_token.clone(),
-            self.config.clone(),
-            self.initial_prompt.clone(),
-        );
+            config,
+            persona.initial_prompt.clone(),
+        )
+        .with_context_budget(self.context_budget.clone())
+        .with_tools(self.tools.clone())
+        .with_service(Service::get(&self.service));
+        match &self.candidates {
+            Some(candidates) => Box::new(ai.with_candidates(candidates.clone())),
+            None => Box::new(ai),
+        }
+    }
 
-        Box::new(ai)
+    fn fact_extractor(&self) -> Arc<dyn FactExtractor> {
+        // one reply is enough for the facts
+        let mut config = self.config.clone();
+        config.reply_count = 1;
+        let ai = ChatGPT::new(self.openai_token.clone(), config, String::new())
+            .with_service(Service::get(&self.service));
+        Arc::new(ai.fact_extractor())
     }
 }
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use crate::{
+    config::{Config, ContextBudgetConfig, LocalModelConfig},
+    dispatcher::AIinterface,
+    fallback_ai::BackendBuilder,
+    local_model::{LocalApi, LocalModel, LocalModelClient, Sampling},
+    memory_store::FactExtractor,
+    persona::Persona,
+};
+
+/// Builds `LocalModel` for the llama.cpp and Ollama engines
+pub struct LocalModelBuilder {
+    client: LocalModelClient,
+    sampling: Sampling,
+    context_budget: ContextBudgetConfig,
+}
+
+impl LocalModelBuilder {
+    pub fn new(api: LocalApi, model_config: LocalModelConfig, config: &Config) -> Self {
+        Self {
+            client: LocalModelClient::new(api, model_config),
+            sampling: Sampling::with_engine(&config.ai_engine),
+            context_budget: config.ai_engine.context_budget.clone(),
+        }
+    }
+}
+
+impl BackendBuilder for LocalModelBuilder {
+    fn build_backend(&self, persona: &Persona) -> Box<dyn AIinterface> {
+        let client = self
+            .client
+            .clone()
+            .with_sampling(self.sampling.with_persona(persona));
+        Box::new(
+            LocalModel::new(client, persona.initial_prompt.clone())
+                .with_context_budget(self.context_budget.clone()),
+        )
+    }
+
+    fn fact_extractor(&self) -> Arc<dyn FactExtractor> {
+        Arc::new(LocalModel::new(self.client.clone(), String::new()).fact_extractor())
+    }
+}
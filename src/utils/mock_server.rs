@@ -0,0 +1,527 @@
+/// In-process HTTP server imitating the external services, so the tests don't need the network.
+/// Speaks the OpenAI `/v1/chat/completions` (also streamed and with tool calls), DeepLx JSON-RPC,
+/// TTS `/say` and whisper `/transcribe`. A path answers with its scripted responses in order,
+/// then with its default answer
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use reqwest::{StatusCode, Url};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+pub const CHAT_COMPLETIONS: &str = "/v1/chat/completions";
+pub const DEEPLX: &str = "/jsonrpc";
+pub const TTS: &str = "/say";
+pub const TRANSCRIBE: &str = "/transcribe";
+
+/// Request received by the server
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub method: String,
+    /// Path without the query
+    pub path: String,
+    pub query: String,
+    pub body: Bytes,
+}
+
+impl MockRequest {
+    /// `Null` if the body is not JSON
+    pub fn json(&self) -> Value {
+        serde_json::from_slice(&self.body).unwrap_or(Value::Null)
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).to_string()
+    }
+
+    pub fn param(&self, name: &str) -> Option<String> {
+        let url = Url::parse(&format!("http://localhost/?{}", self.query)).ok()?;
+        let value = url.query_pairs().find(|(key, _)| key == name)?.1;
+        Some(value.to_string())
+    }
+
+    /// Chat completion is asked to be streamed
+    pub fn is_stream(&self) -> bool {
+        self.json()["stream"].as_bool().unwrap_or(false)
+    }
+
+    /// Content of the last message of a chat completion request
+    pub fn last_message(&self) -> String {
+        self.json()["messages"]
+            .as_array()
+            .and_then(|messages| messages.last())
+            .and_then(|message| message["content"].as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    /// Text of a DeepLx request
+    pub fn translate_text(&self) -> String {
+        self.json()["params"]["texts"][0]["text"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MockResponse {
+    /// Chat completion, streamed word by word if the request asks for a stream
+    Completion(String),
+    /// Chat completion calling the tools, `(name, arguments)`. Streamed arguments come in parts
+    ToolCalls(Vec<(String, String)>),
+    /// DeepLx translation result
+    Translation(String),
+    /// DeepLx JSON-RPC error, e.g. `errors::DEEPL_TOO_MANY_REQUESTS`
+    TranslationError { code: i64, message: String },
+    /// TTS wav
+    Audio(Bytes),
+    /// Whisper transcription
+    Transcription { text: String, language: String },
+    /// Any answer
+    Raw {
+        status: u16,
+        content_type: String,
+        body: String,
+    },
+    /// Body with the `Content-Encoding` header, sent as is
+    Encoded { encoding: String, body: Bytes },
+    /// Fault: failed status with an OpenAI-like error body
+    Status(u16),
+    /// Fault: the response is sent after the delay
+    Delayed(Duration, Box<MockResponse>),
+    /// Fault: the connection is closed without an answer
+    Disconnect,
+    /// Fault: the first `n` words of the streamed completion are sent, then the connection breaks
+    BrokenStream(String, usize),
+}
+
+/// Answer of a path without the script
+fn default_response(request: &MockRequest) -> MockResponse {
+    match request.path.as_str() {
+        CHAT_COMPLETIONS => {
+            MockResponse::Completion(format!("You said: {}", request.last_message()))
+        }
+        DEEPLX => MockResponse::Translation(request.translate_text()),
+        TTS => MockResponse::Audio(silent_wav(100)),
+        TRANSCRIBE => MockResponse::Transcription {
+            text: "Hello".to_string(),
+            language: "en".to_string(),
+        },
+        _ => MockResponse::Status(404),
+    }
+}
+
+/// Mono 16 kHz wav of silence
+pub fn silent_wav(millis: u32) -> Bytes {
+    const SAMPLE_RATE: u32 = 16000;
+    let data_len = SAMPLE_RATE * millis / 1000 * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    wav.resize(44 + data_len as usize, 0);
+    wav.into()
+}
+
+#[derive(Default)]
+struct State {
+    scripts: HashMap<String, VecDeque<MockResponse>>,
+    requests: Vec<MockRequest>,
+}
+
+/// Listens on a free local port until dropped
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(handle(socket, state.clone()));
+                }
+            }
+        });
+
+        Self { addr, state, task }
+    }
+
+    pub fn url(&self, path: &str) -> Url {
+        Url::parse(&format!("http://{}{}", self.addr, path)).unwrap()
+    }
+
+    /// Answers to the next requests of the path, in order
+    pub fn script(&self, path: &str, responses: impl IntoIterator<Item = MockResponse>) {
+        self.state
+            .lock()
+            .unwrap()
+            .scripts
+            .entry(path.to_string())
+            .or_default()
+            .extend(responses);
+    }
+
+    /// All the requests received so far
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    pub fn requests_to(&self, path: &str) -> Vec<MockRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.path == path)
+            .collect()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle(mut socket: TcpStream, state: Arc<Mutex<State>>) {
+    let Some(request) = read_request(&mut socket).await else {
+        return;
+    };
+
+    let scripted = {
+        let mut state = state.lock().unwrap();
+        state.requests.push(request.clone());
+        state
+            .scripts
+            .get_mut(&request.path)
+            .and_then(VecDeque::pop_front)
+    };
+    let mut response = scripted.unwrap_or_else(|| default_response(&request));
+    while let MockResponse::Delayed(delay, inner) = response {
+        tokio::time::sleep(delay).await;
+        response = *inner;
+    }
+
+    // the client may be gone, e.g. after a timeout
+    let _ = respond(&mut socket, &request, response).await;
+}
+
+/// `None` if the connection is closed before the whole request is received
+async fn read_request(socket: &mut TcpStream) -> Option<MockRequest> {
+    let mut buf = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let header_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+
+    let head = String::from_utf8_lossy(&buf[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while buf.len() < header_end + content_length {
+        let n = socket.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    }
+
+    Some(MockRequest {
+        method,
+        path: path.to_string(),
+        query: query.to_string(),
+        body: Bytes::copy_from_slice(&buf[header_end..header_end + content_length]),
+    })
+}
+
+async fn write_response(
+    socket: &mut TcpStream,
+    status: u16,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let reason = StatusCode::from_u16(status)
+        .ok()
+        .and_then(|s| s.canonical_reason())
+        .unwrap_or_default();
+    let head = format!(
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    );
+    socket.write_all(head.as_bytes()).await?;
+    socket.write_all(body).await
+}
+
+async fn write_json(socket: &mut TcpStream, status: u16, body: Value) -> std::io::Result<()> {
+    write_response(
+        socket,
+        status,
+        "application/json",
+        body.to_string().as_bytes(),
+    )
+    .await
+}
+
+fn count_words(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+fn completion(request: &MockRequest, content: &str) -> Value {
+    let prompt_tokens = request.json()["messages"]
+        .as_array()
+        .map(|messages| {
+            messages
+                .iter()
+                .map(|m| count_words(m["content"].as_str().unwrap_or_default()))
+                .sum::<usize>()
+        })
+        .unwrap_or_default();
+    let completion_tokens = count_words(content);
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "created": 0,
+        "model": request.json()["model"],
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": content },
+            "finish_reason": "stop"
+        }],
+        "usage": {
+            "prompt_tokens": prompt_tokens,
+            "completion_tokens": completion_tokens,
+            "total_tokens": prompt_tokens + completion_tokens
+        }
+    })
+}
+
+fn completion_chunk(request: &MockRequest, delta: Value, finish_reason: Option<&str>) -> Value {
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion.chunk",
+        "created": 0,
+        "model": request.json()["model"],
+        "choices": [{ "index": 0, "delta": delta, "finish_reason": finish_reason }]
+    })
+}
+
+fn tool_calls(calls: &[(String, String)]) -> Vec<Value> {
+    calls
+        .iter()
+        .enumerate()
+        .map(|(i, (name, arguments))| {
+            json!({
+                "id": format!("call_{i}"),
+                "type": "function",
+                "function": { "name": name, "arguments": arguments }
+            })
+        })
+        .collect()
+}
+
+fn tool_calls_completion(request: &MockRequest, calls: &[(String, String)]) -> Value {
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "created": 0,
+        "model": request.json()["model"],
+        "choices": [{
+            "index": 0,
+            "message": { "role": "assistant", "content": null, "tool_calls": tool_calls(calls) },
+            "finish_reason": "tool_calls"
+        }]
+    })
+}
+
+async fn start_events(socket: &mut TcpStream) -> std::io::Result<()> {
+    socket
+        .write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n",
+        )
+        .await
+}
+
+async fn event(socket: &mut TcpStream, data: String) -> std::io::Result<()> {
+    let data = format!("data: {data}\n\n");
+    socket
+        .write_all(format!("{:x}\r\n{data}\r\n", data.len()).as_bytes())
+        .await
+}
+
+async fn finish_events(
+    socket: &mut TcpStream,
+    request: &MockRequest,
+    finish_reason: &str,
+) -> std::io::Result<()> {
+    let stop = completion_chunk(request, json!({}), Some(finish_reason));
+    event(socket, stop.to_string()).await?;
+    event(socket, "[DONE]".to_string()).await?;
+    socket.write_all(b"0\r\n\r\n").await
+}
+
+/// Tool calls as server-sent events, the arguments are split in two parts
+async fn stream_tool_calls(
+    socket: &mut TcpStream,
+    request: &MockRequest,
+    calls: &[(String, String)],
+) -> std::io::Result<()> {
+    start_events(socket).await?;
+    let role = completion_chunk(request, json!({ "role": "assistant" }), None);
+    event(socket, role.to_string()).await?;
+    for (i, mut call) in tool_calls(calls).into_iter().enumerate() {
+        let arguments = call["function"]["arguments"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string();
+        let middle = arguments
+            .char_indices()
+            .nth(arguments.chars().count() / 2)
+            .map_or(arguments.len(), |(i, _)| i);
+        let (head, tail) = arguments.split_at(middle);
+        call["index"] = json!(i);
+        call["function"]["arguments"] = json!(head);
+        let delta = completion_chunk(request, json!({ "tool_calls": [call] }), None);
+        event(socket, delta.to_string()).await?;
+        let rest = json!({ "tool_calls": [{ "index": i, "function": { "arguments": tail } }] });
+        event(socket, completion_chunk(request, rest, None).to_string()).await?;
+    }
+    finish_events(socket, request, "tool_calls").await
+}
+
+/// Server-sent events in the chunked encoding, the stream is not finished after `limit` words
+async fn stream_completion(
+    socket: &mut TcpStream,
+    request: &MockRequest,
+    content: &str,
+    limit: Option<usize>,
+) -> std::io::Result<()> {
+    start_events(socket).await?;
+    let role = completion_chunk(request, json!({ "role": "assistant" }), None);
+    event(socket, role.to_string()).await?;
+    for (i, word) in content.split_inclusive(' ').enumerate() {
+        if Some(i) == limit {
+            return Ok(()); // the connection breaks in the middle
+        }
+        let delta = completion_chunk(request, json!({ "content": word }), None);
+        event(socket, delta.to_string()).await?;
+    }
+    finish_events(socket, request, "stop").await
+}
+
+async fn respond(
+    socket: &mut TcpStream,
+    request: &MockRequest,
+    response: MockResponse,
+) -> std::io::Result<()> {
+    match response {
+        MockResponse::Completion(content) if request.is_stream() => {
+            stream_completion(socket, request, &content, None).await
+        }
+        MockResponse::Completion(content) => {
+            write_json(socket, 200, completion(request, &content)).await
+        }
+        MockResponse::ToolCalls(calls) if request.is_stream() => {
+            stream_tool_calls(socket, request, &calls).await
+        }
+        MockResponse::ToolCalls(calls) => {
+            write_json(socket, 200, tool_calls_completion(request, &calls)).await
+        }
+        MockResponse::BrokenStream(content, words) => {
+            stream_completion(socket, request, &content, Some(words)).await
+        }
+        MockResponse::Translation(text) => {
+            let body = json!({
+                "jsonrpc": "2.0",
+                "id": request.json()["id"],
+                "result": {
+                    "texts": [{ "alternatives": [], "text": text }],
+                    "lang": "EN",
+                    "lang_is_confident": true,
+                    "detectedLanguages": {}
+                }
+            });
+            write_json(socket, 200, body).await
+        }
+        MockResponse::TranslationError { code, message } => {
+            let body = json!({
+                "jsonrpc": "2.0",
+                "error": { "code": code, "message": message }
+            });
+            write_json(socket, 200, body).await
+        }
+        MockResponse::Audio(wav) => write_response(socket, 200, "audio/wav", &wav).await,
+        MockResponse::Transcription { text, language } => {
+            let body = json!({
+                "language": language,
+                "transcribed_segments": [{ "text": text }]
+            });
+            write_json(socket, 200, body).await
+        }
+        MockResponse::Raw {
+            status,
+            content_type,
+            body,
+        } => write_response(socket, status, &content_type, body.as_bytes()).await,
+        MockResponse::Encoded { encoding, body } => {
+            let head = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Encoding: {encoding}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            socket.write_all(head.as_bytes()).await?;
+            socket.write_all(&body).await
+        }
+        MockResponse::Status(status) => {
+            let error_type = match status {
+                429 => "rate_limit_exceeded",
+                500..=599 => "server_error",
+                _ => "invalid_request_error",
+            };
+            let body = json!({
+                "error": { "message": format!("mock error {status}"), "type": error_type }
+            });
+            write_json(socket, status, body).await
+        }
+        MockResponse::Disconnect => Ok(()),
+        MockResponse::Delayed(..) => unreachable!("delays are waited out before"),
+    }
+}
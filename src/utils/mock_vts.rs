@@ -0,0 +1,155 @@
+/// In-process stand-in of the VTube Studio plugin API, so the tests don't need VTube Studio.
+/// Gives `MOCK_TOKEN` to the token requests, authenticates only with it, answers the other
+/// requests with an empty `<Type>Response` and records all of them
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use reqwest::Url;
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{errors::VTS_USER_DENIED, vtube_studio::API_NAME};
+
+pub const MOCK_TOKEN: &str = "mock-token";
+
+/// Request received by the stand-in
+#[derive(Debug, Clone)]
+pub struct MockVTSRequest {
+    pub message_type: String,
+    pub data: Value,
+}
+
+#[derive(Default)]
+struct State {
+    requests: Vec<MockVTSRequest>,
+    /// The user presses "Deny" in the permission dialog
+    deny: bool,
+}
+
+/// Listens on a free local port until dropped
+pub struct MockVTubeStudio {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl MockVTubeStudio {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(Mutex::new(State::default()));
+
+        let task = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    tokio::spawn(handle(socket, state.clone()));
+                }
+            }
+        });
+
+        Self { addr, state, task }
+    }
+
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("ws://{}", self.addr)).unwrap()
+    }
+
+    /// Token requests are denied from now on
+    pub fn deny(&self) {
+        self.state.lock().unwrap().deny = true;
+    }
+
+    /// All the requests received so far
+    pub fn requests(&self) -> Vec<MockVTSRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+
+    /// Data of the requests of the type
+    pub fn requests_of(&self, message_type: &str) -> Vec<Value> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.message_type == message_type)
+            .map(|r| r.data)
+            .collect()
+    }
+}
+
+impl Drop for MockVTubeStudio {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+fn answer(state: &Mutex<State>, request: &MockVTSRequest) -> (String, Value) {
+    let deny = state.lock().unwrap().deny;
+    match request.message_type.as_str() {
+        "AuthenticationTokenRequest" if deny => (
+            "APIError".to_string(),
+            json!({ "errorID": VTS_USER_DENIED, "message": "User has denied API access" }),
+        ),
+        "AuthenticationTokenRequest" => (
+            "AuthenticationTokenResponse".to_string(),
+            json!({ "authenticationToken": MOCK_TOKEN }),
+        ),
+        "AuthenticationRequest" => {
+            let authenticated = request.data["authenticationToken"] == MOCK_TOKEN;
+            (
+                "AuthenticationResponse".to_string(),
+                json!({
+                    "authenticated": authenticated,
+                    "reason": if authenticated { "" } else { "Token invalid" },
+                }),
+            )
+        }
+        other => (
+            format!("{}Response", other.trim_end_matches("Request")),
+            json!({}),
+        ),
+    }
+}
+
+async fn handle(socket: TcpStream, state: Arc<Mutex<State>>) {
+    let Ok(mut socket) = tokio_tungstenite::accept_async(socket).await else {
+        return;
+    };
+
+    while let Some(Ok(message)) = socket.next().await {
+        let Message::Text(text) = message else {
+            continue;
+        };
+        let envelope: Value = serde_json::from_str(&text).unwrap_or(Value::Null);
+        let request = MockVTSRequest {
+            message_type: envelope["messageType"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string(),
+            data: envelope["data"].clone(),
+        };
+        state.lock().unwrap().requests.push(request.clone());
+
+        let (message_type, data) = answer(&state, &request);
+        let response = json!({
+            "apiName": API_NAME,
+            "apiVersion": envelope["apiVersion"],
+            "timestamp": 0,
+            "requestID": envelope["requestID"],
+            "messageType": message_type,
+            "data": data,
+        });
+        if socket
+            .send(Message::Text(response.to_string()))
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
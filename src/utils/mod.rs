@@ -1,7 +1,11 @@
 pub mod audio_dev;
 pub mod audio_halpers;
 pub mod audio_input;
+pub mod backend_chain_builder;
 pub mod chatgpt_builder;
-pub mod chatgpt_en_deeplx_builder;
+pub mod local_model_builder;
+pub mod mock_server;
+pub mod mock_vts;
 pub mod say;
-pub mod test_request;
\ No newline at end of file
+pub mod test_request;
+pub mod tts_pipeline;
\ No newline at end of file
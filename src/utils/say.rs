@@ -1,3 +1,6 @@
+use std::io::Cursor;
+
+use bytes::Bytes;
 use cpal::platform::Device;
 use rodio::{OutputStream, Sink, Decoder};
 
@@ -31,4 +34,41 @@ where
             error!("Audio output error");
         }
     }
-}
\ No newline at end of file
+}
+
+/// Play sound fragments one after another as they arrive, returns all played fragments
+pub fn say_queue<I, F>(audio_out: &Option<Device>, sound_data: I, f: F) -> Vec<Cursor<Bytes>>
+where
+    I: IntoIterator<Item = Cursor<Bytes>>,
+    F: FnOnce(),
+{
+    let mut played = vec![];
+
+    let output = audio_out
+        .as_ref()
+        .and_then(|ao| OutputStream::try_from_device(ao).ok());
+    let sink = output
+        .as_ref()
+        .and_then(|(_stream, stream_handle)| Sink::try_new(stream_handle).ok());
+    if audio_out.is_some() && sink.is_none() {
+        error!("Audio output error");
+    }
+
+    for fragment in sound_data {
+        if let Some(sink) = &sink {
+            // sink plays appended sources in order
+            match Decoder::new_wav(fragment.clone()) {
+                Ok(decoder) => sink.append(decoder),
+                Err(e) => error!("Decode wav error: {:?}", e),
+            }
+        }
+        played.push(fragment);
+    }
+
+    if let Some(sink) = sink {
+        sink.sleep_until_end();
+        f();
+    }
+
+    played
+}
@@ -5,6 +5,21 @@ pub struct TestRequest {
     pub channel: String,
 }
 
+impl TestRequest {
+    /// Request to the "Master" channel
+    pub fn new(request: impl Into<String>) -> Self {
+        Self {
+            request: request.into(),
+            channel: "Master".to_string(),
+        }
+    }
+
+    pub fn with_channel(mut self, channel: impl Into<String>) -> Self {
+        self.channel = channel.into();
+        self
+    }
+}
+
 impl AIRequest for TestRequest {
     fn request(&self) -> String {
         self.request.clone()
@@ -17,4 +32,4 @@ impl AIRequest for TestRequest {
     fn lang(&self) -> String {
         "auto".to_string()
     }
-}
\ No newline at end of file
+}
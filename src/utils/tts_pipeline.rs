@@ -0,0 +1,46 @@
+use std::{io::Cursor, sync::Arc};
+
+use bytes::Bytes;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+
+use tracing::error;
+
+use crate::tts_engine::TTSEngine;
+
+/// Sentences waiting for synthesis
+const SENTENCES_QUEUE_SIZE: usize = 16;
+
+/// Synthesized sentences waiting for playback, the next sentence is prepared while the previous one is playing
+const AUDIO_QUEUE_SIZE: usize = 2;
+
+/// Start a TTS stage: sentences sent to the returned `Sender` are synthesized one by one,
+/// the audio comes out of the returned `Receiver` in the same order.
+/// The stage stops when the `Sender` is dropped and all sentences are synthesized.
+pub fn spawn_tts_pipeline(tts: Arc<TTSEngine>) -> (Sender<String>, Receiver<Cursor<Bytes>>) {
+    let (sentences_tx, mut sentences_rx) = mpsc::channel::<String>(SENTENCES_QUEUE_SIZE);
+    let (audio_tx, audio_rx) = mpsc::channel(AUDIO_QUEUE_SIZE);
+
+    tokio::spawn(async move {
+        while let Some(sentence) = sentences_rx.recv().await {
+            if sentence.trim().is_empty() {
+                continue;
+            }
+
+            match tts.say(sentence).await {
+                Ok(sound_data) => {
+                    if sound_data.get_ref().is_empty() {
+                        continue; // TTS disabled
+                    }
+                    if audio_tx.send(sound_data).await.is_err() {
+                        break; // nobody listens
+                    }
+                }
+                Err(err) => {
+                    error!("TTS error: {:?}", err);
+                }
+            }
+        }
+    });
+
+    (sentences_tx, audio_rx)
+}
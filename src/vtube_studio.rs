@@ -0,0 +1,534 @@
+/// Avatar control through the VTube Studio plugin API: the expressions and hotkeys
+/// of the answer emotion and the mouth moving with the speech
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Cursor,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::{
+    net::TcpStream,
+    sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time::Instant,
+};
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    config::{EmotionConfig, VTubeStudioConfig},
+    dispatcher::AIResponseType,
+    errors::VTSError,
+    sanitizer,
+};
+
+pub const API_NAME: &str = "VTubeStudioPublicAPI";
+pub const API_VERSION: &str = "1.0";
+
+/// Requests are answered at once, except the token request waiting for the user (`TOKEN_TIMEOUT`)
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The user has this long to answer the permission dialog, then the connection is retried
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Pause between the connection attempts while VTube Studio is not available
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiRequest<'a> {
+    api_name: &'a str,
+    api_version: &'a str,
+    #[serde(rename = "requestID")]
+    request_id: String,
+    message_type: &'a str,
+    data: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiResponse {
+    #[serde(rename = "requestID", default)]
+    request_id: String,
+    message_type: String,
+    #[serde(default)]
+    data: Value,
+}
+
+/// Plugin API connection
+pub struct VTubeStudio {
+    socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    next_id: u64,
+}
+
+impl VTubeStudio {
+    pub async fn connect(url: &reqwest::Url) -> Result<Self, VTSError> {
+        let (socket, _) = tokio::time::timeout(
+            REQUEST_TIMEOUT,
+            tokio_tungstenite::connect_async(url.as_str()),
+        )
+        .await
+        .map_err(|_| VTSError::Connection("timeout".to_string()))?
+        .map_err(|e| VTSError::Connection(e.to_string()))?;
+
+        Ok(Self { socket, next_id: 0 })
+    }
+
+    /// Connect and authenticate the plugin. The token is read from `Token_path`;
+    /// if there is none or it is rejected, a new one is asked, VTube Studio shows
+    /// the permission dialog, and the token is saved for the next start
+    pub async fn connect_authenticated(config: &VTubeStudioConfig) -> Result<Self, VTSError> {
+        let mut vts = Self::connect(&config.url).await?;
+
+        if let Some(token) = load_token(config) {
+            if vts.authenticate(config, &token).await? {
+                return Ok(vts);
+            }
+            warn!("VTube Studio rejected the saved token, asking for a new one");
+        }
+
+        info!(
+            "Allow the plugin \"{}\" in VTube Studio",
+            config.plugin_name
+        );
+        let token = vts.request_token(config).await?;
+        save_token(config, &token);
+        if vts.authenticate(config, &token).await? {
+            Ok(vts)
+        } else {
+            Err(VTSError::Unauthorized(
+                "the new token is rejected".to_string(),
+            ))
+        }
+    }
+
+    async fn send(&mut self, message_type: &str, data: Value) -> Result<String, VTSError> {
+        self.next_id += 1;
+        let request = ApiRequest {
+            api_name: API_NAME,
+            api_version: API_VERSION,
+            request_id: self.next_id.to_string(),
+            message_type,
+            data,
+        };
+        let text =
+            serde_json::to_string(&request).map_err(|e| VTSError::BadResponse(e.to_string()))?;
+        self.socket
+            .send(Message::Text(text))
+            .await
+            .map_err(|e| VTSError::Connection(e.to_string()))?;
+        Ok(request.request_id)
+    }
+
+    /// Answer to the request, the events and the answers to the forgotten requests are skipped
+    async fn receive(&mut self, request_id: &str) -> Result<Value, VTSError> {
+        loop {
+            let message = match self.socket.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(e)) => return Err(VTSError::Connection(e.to_string())),
+                None => return Err(VTSError::Connection("closed".to_string())),
+            };
+            let text = match message {
+                Message::Text(text) => text,
+                Message::Close(_) => return Err(VTSError::Connection("closed".to_string())),
+                _ => continue,
+            };
+
+            let response: ApiResponse =
+                serde_json::from_str(&text).map_err(|e| VTSError::BadResponse(e.to_string()))?;
+            if response.request_id != request_id {
+                debug!("Skipping VTube Studio message {}", response.message_type);
+                continue;
+            }
+            if response.message_type == "APIError" {
+                return Err(VTSError::Api {
+                    id: response.data["errorID"].as_i64().unwrap_or_default(),
+                    message: response.data["message"]
+                        .as_str()
+                        .unwrap_or_default()
+                        .to_string(),
+                });
+            }
+            return Ok(response.data);
+        }
+    }
+
+    /// Send a request and wait for its answer
+    pub async fn request(&mut self, message_type: &str, data: Value) -> Result<Value, VTSError> {
+        let request_id = self.send(message_type, data).await?;
+        tokio::time::timeout(REQUEST_TIMEOUT, self.receive(&request_id))
+            .await
+            .map_err(|_| VTSError::Connection(format!("no answer to {message_type}")))?
+    }
+
+    /// Ask for a new token, waits until the user allows or denies the plugin, but not longer than a minute
+    pub async fn request_token(&mut self, config: &VTubeStudioConfig) -> Result<String, VTSError> {
+        let request_id = self
+            .send(
+                "AuthenticationTokenRequest",
+                json!({
+                    "pluginName": config.plugin_name,
+                    "pluginDeveloper": config.plugin_developer,
+                }),
+            )
+            .await?;
+        let data = tokio::time::timeout(TOKEN_TIMEOUT, self.receive(&request_id))
+            .await
+            .map_err(|_| VTSError::Connection("the plugin is not allowed in time".to_string()))??;
+        data["authenticationToken"]
+            .as_str()
+            .map(|token| token.to_string())
+            .ok_or_else(|| VTSError::BadResponse(data.to_string()))
+    }
+
+    /// `false` if the token is rejected
+    pub async fn authenticate(
+        &mut self,
+        config: &VTubeStudioConfig,
+        token: &str,
+    ) -> Result<bool, VTSError> {
+        let data = self
+            .request(
+                "AuthenticationRequest",
+                json!({
+                    "pluginName": config.plugin_name,
+                    "pluginDeveloper": config.plugin_developer,
+                    "authenticationToken": token,
+                }),
+            )
+            .await?;
+        if let Some(reason) = data["reason"].as_str().filter(|r| !r.is_empty()) {
+            debug!("VTube Studio authentication: {}", reason);
+        }
+        data["authenticated"]
+            .as_bool()
+            .ok_or_else(|| VTSError::BadResponse(data.to_string()))
+    }
+
+    /// `hotkey` - name or ID of the hotkey of the current model
+    pub async fn trigger_hotkey(&mut self, hotkey: &str) -> Result<(), VTSError> {
+        self.request("HotkeyTriggerRequest", json!({ "hotkeyID": hotkey }))
+            .await
+            .map(|_| ())
+    }
+
+    /// `expression` - file name of the expression, e.g. `happy.exp3.json`
+    pub async fn set_expression(&mut self, expression: &str, active: bool) -> Result<(), VTSError> {
+        self.request(
+            "ExpressionActivationRequest",
+            json!({ "expressionFile": expression, "active": active }),
+        )
+        .await
+        .map(|_| ())
+    }
+
+    /// Set the values of the input parameters, they are kept only while injected regularly
+    pub async fn inject_parameters(&mut self, values: &[(&str, f32)]) -> Result<(), VTSError> {
+        let values: Vec<Value> = values
+            .iter()
+            .map(|(id, value)| json!({ "id": id, "value": value, "weight": 1.0 }))
+            .collect();
+        self.request(
+            "InjectParameterDataRequest",
+            json!({ "faceFound": false, "mode": "set", "parameterValues": values }),
+        )
+        .await
+        .map(|_| ())
+    }
+}
+
+fn load_token(config: &VTubeStudioConfig) -> Option<String> {
+    let file = config.token_path.as_ref()?;
+    if !file.exists() {
+        return None;
+    }
+
+    match std::fs::read_to_string(file) {
+        Ok(token) => Some(token.trim().to_string()).filter(|token| !token.is_empty()),
+        Err(e) => {
+            error!("Failed to load VTube Studio token from {:?}: {}", file, e);
+            None
+        }
+    }
+}
+
+fn save_token(config: &VTubeStudioConfig, token: &str) {
+    let Some(file) = &config.token_path else {
+        return;
+    };
+
+    let res = match file.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => std::fs::create_dir_all(dir).map_err(|e| e.to_string()),
+        None => Ok(()),
+    }
+    .and_then(|_| std::fs::write(file, token).map_err(|e| e.to_string()));
+    if let Err(e) = res {
+        error!("Failed to save VTube Studio token to {:?}: {}", file, e);
+    }
+}
+
+/// Finds the configured emotion of an answer
+pub struct EmotionMapper {
+    emotions: Vec<(EmotionConfig, Option<Regex>)>,
+}
+
+impl EmotionMapper {
+    pub fn new(emotions: &[EmotionConfig]) -> Self {
+        let emotions = emotions
+            .iter()
+            .map(|emotion| {
+                let keywords: Vec<String> = emotion
+                    .keywords
+                    .iter()
+                    .filter(|k| !k.trim().is_empty())
+                    .map(|k| regex::escape(k.trim()))
+                    .collect();
+                let regex = (!keywords.is_empty())
+                    .then(|| Regex::new(&format!(r"(?i)\b({})\b", keywords.join("|"))).unwrap());
+                (emotion.clone(), regex)
+            })
+            .collect();
+        Self { emotions }
+    }
+
+    /// `[Name]` tags of the answer win over the keywords, the earlier configured emotion wins
+    /// among the keywords. The actions come from `Actions`, or from the raw answer if the
+    /// `Sanitize` layer is not used
+    pub fn emotion(&self, answer: &HashMap<AIResponseType, String>) -> Option<&EmotionConfig> {
+        let raw = answer
+            .get(&AIResponseType::RawAnswer)
+            .map_or("", String::as_str);
+        let actions: Vec<String> = if answer.contains_key(&AIResponseType::Actions) {
+            sanitizer::actions(answer)
+                .into_iter()
+                .map(|a| a.to_string())
+                .collect()
+        } else {
+            sanitizer::sanitize(raw).actions
+        };
+
+        let tagged = self.emotions.iter().find(|(emotion, _)| {
+            actions
+                .iter()
+                .any(|action| action.trim().eq_ignore_ascii_case(&emotion.name))
+        });
+        let said = || {
+            self.emotions.iter().find(|(_, keywords)| {
+                keywords.as_ref().is_some_and(|keywords| {
+                    keywords.is_match(raw) || actions.iter().any(|a| keywords.is_match(a))
+                })
+            })
+        };
+        tagged.or_else(said).map(|(emotion, _)| emotion)
+    }
+}
+
+/// Mouth openness for every 1/`fps` of the wav: RMS of the samples multiplied by `gain`, 0..1
+pub fn mouth_envelope(wav: &[u8], fps: u32, gain: f32) -> Result<Vec<f32>, hound::Error> {
+    let reader = hound::WavReader::new(Cursor::new(wav))?;
+    let spec = reader.spec();
+    let samples: Vec<f32> = match spec.sample_format {
+        hound::SampleFormat::Float => reader.into_samples::<f32>().collect::<Result<_, _>>()?,
+        hound::SampleFormat::Int => {
+            let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            reader
+                .into_samples::<i32>()
+                .map(|s| s.map(|s| s as f32 / scale))
+                .collect::<Result<_, _>>()?
+        }
+    };
+
+    let frame = (spec.sample_rate * spec.channels as u32 / fps.max(1)).max(1) as usize;
+    Ok(samples
+        .chunks(frame)
+        .map(|chunk| {
+            let rms = (chunk.iter().map(|s| s * s).sum::<f32>() / chunk.len() as f32).sqrt();
+            (rms * gain).min(1.0)
+        })
+        .collect())
+}
+
+enum AvatarEvent {
+    /// The emotion of the next sentence, `None` - back to neutral
+    Emotion(Option<EmotionConfig>),
+    /// Mouth frames of the wav queued for playback
+    Speech(Vec<f32>),
+}
+
+/// Handle of the background task talking to VTube Studio. The calls never block,
+/// while VTube Studio is not available the avatar is simply not moving
+#[derive(Clone)]
+pub struct Avatar {
+    events: UnboundedSender<AvatarEvent>,
+    mapper: Arc<EmotionMapper>,
+    config: Arc<VTubeStudioConfig>,
+}
+
+impl Avatar {
+    /// Must be called inside the tokio runtime
+    pub fn spawn(config: VTubeStudioConfig) -> Self {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(config.clone(), events_rx));
+        Self {
+            events,
+            mapper: Arc::new(EmotionMapper::new(&config.emotions)),
+            config: Arc::new(config),
+        }
+    }
+
+    /// Show the emotion of the answer or its sentence, if it has one
+    pub fn react(&self, answer: &HashMap<AIResponseType, String>) {
+        if let Some(emotion) = self.mapper.emotion(answer) {
+            let _ = self
+                .events
+                .send(AvatarEvent::Emotion(Some(emotion.clone())));
+        }
+    }
+
+    /// Move the mouth with the wav, it starts when the previous ones are finished
+    pub fn speak(&self, wav: &[u8]) {
+        match mouth_envelope(wav, self.config.mouth_fps, self.config.mouth_gain) {
+            Ok(frames) => {
+                let _ = self.events.send(AvatarEvent::Speech(frames));
+            }
+            Err(e) => error!("Failed to read wav for lip sync: {}", e),
+        }
+    }
+
+    /// The answer is spoken, the expression is deactivated
+    pub fn calm_down(&self) {
+        let _ = self.events.send(AvatarEvent::Emotion(None));
+    }
+}
+
+/// State of the background task
+struct Controller {
+    config: VTubeStudioConfig,
+    vts: Option<VTubeStudio>,
+    retry_at: Option<Instant>,
+    /// Expression activated by the plugin
+    expression: Option<String>,
+}
+
+impl Controller {
+    async fn connected(&mut self) -> bool {
+        if self.vts.is_none() && self.retry_at.map_or(true, |at| Instant::now() >= at) {
+            match VTubeStudio::connect_authenticated(&self.config).await {
+                Ok(vts) => {
+                    info!("Connected to VTube Studio at {}", self.config.url);
+                    self.vts = Some(vts);
+                    self.retry_at = None;
+                }
+                Err(e) => {
+                    warn!("{}, retrying in {:?}", e, RECONNECT_DELAY);
+                    self.retry_at = Some(Instant::now() + RECONNECT_DELAY);
+                }
+            }
+        }
+        self.vts.is_some()
+    }
+
+    fn failed(&mut self, e: VTSError) {
+        error!("{}", e);
+        if matches!(e, VTSError::Connection(_)) {
+            self.vts = None;
+        }
+    }
+
+    async fn show(&mut self, emotion: Option<EmotionConfig>) {
+        if !self.connected().await {
+            return;
+        }
+        let vts = self.vts.as_mut().unwrap();
+        if let Err(e) = show_emotion(vts, &mut self.expression, emotion).await {
+            self.failed(e);
+        }
+    }
+
+    async fn mouth(&mut self, value: f32) {
+        if !self.connected().await {
+            return;
+        }
+        let vts = self.vts.as_mut().unwrap();
+        let parameter = self.config.mouth_parameter.as_str();
+        if let Err(e) = vts.inject_parameters(&[(parameter, value)]).await {
+            self.failed(e);
+        }
+    }
+}
+
+/// An emotion without an expression keeps the current one, `None` deactivates it
+async fn show_emotion(
+    vts: &mut VTubeStudio,
+    active: &mut Option<String>,
+    emotion: Option<EmotionConfig>,
+) -> Result<(), VTSError> {
+    let expression = match &emotion {
+        Some(emotion) => emotion.expression.clone().or_else(|| active.clone()),
+        None => None,
+    };
+    if *active != expression {
+        if let Some(previous) = active.take() {
+            vts.set_expression(&previous, false).await?;
+        }
+        if let Some(expression) = &expression {
+            vts.set_expression(expression, true).await?;
+        }
+        *active = expression;
+    }
+    if let Some(hotkey) = emotion.as_ref().and_then(|e| e.hotkey.as_deref()) {
+        vts.trigger_hotkey(hotkey).await?;
+    }
+    Ok(())
+}
+
+async fn run(config: VTubeStudioConfig, mut events: UnboundedReceiver<AvatarEvent>) {
+    let frame = Duration::from_secs_f32(1.0 / config.mouth_fps.max(1) as f32);
+    let mut ticker = tokio::time::interval(frame);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let mut controller = Controller {
+        config,
+        vts: None,
+        retry_at: None,
+        expression: None,
+    };
+    let mut frames = VecDeque::new();
+    // time of the first queued frame, the late frames are dropped to stay in sync with the audio
+    let mut frames_start = Instant::now();
+    let mut mouth_open = false;
+
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(AvatarEvent::Emotion(emotion)) => controller.show(emotion).await,
+                Some(AvatarEvent::Speech(speech)) => {
+                    if frames.is_empty() {
+                        frames_start = Instant::now();
+                    }
+                    frames.extend(speech);
+                }
+                None => break,
+            },
+            _ = ticker.tick(), if !frames.is_empty() || mouth_open => {
+                let late = (frames_start.elapsed().as_secs_f32() / frame.as_secs_f32()) as usize;
+                let late = late.min(frames.len());
+                frames.drain(..late);
+                frames_start += frame * late as u32;
+                let value = match frames.pop_front() {
+                    Some(value) => {
+                        frames_start += frame;
+                        value
+                    }
+                    None => 0.0, // the mouth is closed after the speech
+                };
+                mouth_open = value > 0.0;
+                controller.mouth(value).await;
+            }
+        }
+    }
+}
@@ -1,48 +1,66 @@
-use reqwest::{Body, IntoUrl, Url};
+use bytes::Bytes;
+use reqwest::{IntoUrl, Url};
 use serde_json::Value;
 
+use crate::{
+    errors::STTError,
+    resilience::{self, check_status, Failure, Service},
+};
+
 #[derive(Clone)]
 pub struct OpenAIWhisperVoice2Txt {
     voice2txt_url: Url,
+    service: Service,
 }
 
 impl OpenAIWhisperVoice2Txt {
     pub fn new<URL: IntoUrl>(url: URL) -> Self {
         Self {
             voice2txt_url: url.into_url().unwrap(),
+            service: Service::get(resilience::WHISPER),
         }
     }
 
-    pub async fn recognize<V: Into<Body>>(
+    pub async fn recognize<V: Into<Bytes>>(
         &self,
         voice_data: V,
-    ) -> Result<(String, String), String> {
+    ) -> Result<(String, String), STTError> {
         let client = reqwest::Client::new();
-        let resp: Value = client
-            .post(self.voice2txt_url.clone())
-            .header("Content-Type", "audio/wav")
-            .body(voice_data)
-            .send()
-            .await
-            .map_err(|e| e.to_string())?
-            .json()
+        let voice_data = voice_data.into();
+        let resp: Value = self
+            .service
+            .call(|| async {
+                let res = client
+                    .post(self.voice2txt_url.clone())
+                    .header("Content-Type", "audio/wav")
+                    .body(voice_data.clone())
+                    .send()
+                    .await
+                    .map_err(Failure::from_reqwest)?;
+                check_status(res)
+                    .await?
+                    .json()
+                    .await
+                    .map_err(Failure::from_reqwest)
+            })
             .await
-            .map_err(|e| e.to_string())?;
+            .map_err(|e| STTError::Service(self.service.error(e)))?;
 
-        if let Value::Object(result) = resp {
-            let lang = if let Value::String(language) = &result["language"] {
+        if let Value::Object(result) = &resp {
+            let lang = if let Some(Value::String(language)) = result.get("language") {
                 language.clone()
             } else {
-                return Err("Incorrect server response: language".to_string());
+                return Err(STTError::BadResponse("language".to_string()));
             };
 
-            let string = if let Value::Array(transcribed_segments) = &result["transcribed_segments"]
+            let string = if let Some(Value::Array(transcribed_segments)) =
+                result.get("transcribed_segments")
             {
                 transcribed_segments
-                    .into_iter()
+                    .iter()
                     .map(|s| {
                         if let Value::Object(segment) = s {
-                            if let Value::String(text) = &segment["text"] {
+                            if let Some(Value::String(text)) = segment.get("text") {
                                 return text.clone();
                             }
                         }
@@ -53,11 +71,11 @@ impl OpenAIWhisperVoice2Txt {
                     .trim()
                     .to_string()
             } else {
-                return Err("Incorrect server response: transcribed_segments".to_string());
+                return Err(STTError::BadResponse("transcribed_segments".to_string()));
             };
 
             return Ok((string, lang));
         }
-        Err("Failed to recognize, incorrect result".to_string())
+        Err(STTError::BadResponse(resp.to_string()))
     }
 }
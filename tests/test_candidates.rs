@@ -0,0 +1,182 @@
+mod tests {
+    use ai_waifu::{
+        candidates::*,
+        config::{CandidateRuleConfig, Config},
+        config_validation::Severity,
+        dispatcher::AIResponseType,
+        utils::{
+            mock_server::{MockResponse, MockServer, CHAT_COMPLETIONS},
+            test_request::TestRequest,
+        },
+    };
+    use serde_json::json;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_length() {
+        let rule = LengthRule { min: 10, max: 20 };
+        assert_eq!(rule.score("Hello there!", &[]), 1.0);
+        assert_eq!(rule.score("Hello", &[]), 0.5);
+        assert_eq!(rule.score(&"a".repeat(40), &[]), 0.5);
+    }
+
+    #[test]
+    fn test_banned_words() {
+        let rule = BannedWordsRule::new(&strings(&["as an ai\\b"])).unwrap();
+        assert_eq!(rule.score("As an AI, I can't", &[]), 0.0);
+        assert_eq!(rule.score("As an aide I can", &[]), 1.0);
+        assert!(BannedWordsRule::new(&strings(&["("])).is_err());
+    }
+
+    #[test]
+    fn test_language() {
+        assert_eq!(Script::of_lang("ru"), Script::Cyrillic);
+        assert_eq!(Script::of_lang("en-US"), Script::Latin);
+        assert_eq!(Script::of_lang("ja_JP"), Script::Japanese);
+
+        let rule = LanguageRule::new("en");
+        assert_eq!(rule.score("Hello, 123!", &[]), 1.0);
+        assert_eq!(rule.score("abcd где", &[]), 4.0 / 7.0);
+        assert_eq!(rule.score("123", &[]), 1.0);
+        // kanji are japanese too
+        assert_eq!(LanguageRule::new("ja").score("私はピナです", &[]), 1.0);
+    }
+
+    #[test]
+    fn test_repetition() {
+        let rule = RepetitionRule { window: 1 };
+        let recent = strings(&["Cats and dogs", "I like dogs"]);
+        assert_eq!(rule.score("I like birds", &[]), 1.0);
+        assert_eq!(rule.score("I like dogs", &recent), 0.0);
+        // only the last answer is compared
+        assert_eq!(rule.score("Cats and dogs", &recent), 0.75);
+    }
+
+    #[test]
+    fn test_select() {
+        let selector = CandidateSelector::default()
+            .with_rule(Box::new(LengthRule { min: 0, max: 20 }), 1.0)
+            .with_rule(Box::new(RepetitionRule { window: 5 }), 2.0);
+        let recent = strings(&["I like dogs"]);
+
+        let candidates = strings(&["I like dogs", &"Long answer ".repeat(5), "Cats are cute"]);
+        assert_eq!(selector.select(&candidates, &recent), Some(2));
+        // the first of the equal ones wins
+        let candidates = strings(&["Hi", "Hello", "I like dogs"]);
+        assert_eq!(selector.select(&candidates, &recent), Some(0));
+        assert_eq!(selector.select(&[], &recent), None);
+    }
+
+    #[test]
+    fn test_rules_config() {
+        let rules: Vec<CandidateRuleConfig> = serde_json::from_value(json!([
+            { "type": "Length", "Max": 20 },
+            { "type": "BannedWords", "Words": ["sorry"], "Weight": 3.0 },
+            { "type": "Language", "Lang": "ru" }
+        ]))
+        .unwrap();
+        let selector = CandidateSelector::new(&rules).unwrap();
+        assert_eq!(selector.score("Привет!", &[]), 5.0);
+        assert_eq!(selector.score("Sorry", &[]), 1.0);
+
+        let rules: Vec<CandidateRuleConfig> =
+            serde_json::from_value(json!([{ "type": "BannedWords", "Words": ["("] }])).unwrap();
+        assert!(CandidateSelector::new(&rules).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_best_reply() {
+        let server = MockServer::start().await;
+        let choice = |index: usize, content: &str| {
+            json!({
+                "index": index,
+                "message": { "role": "assistant", "content": content },
+                "finish_reason": "stop"
+            })
+        };
+        let body = json!({
+            "id": "chatcmpl-mock",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "llama",
+            "choices": [
+                choice(0, "As an AI, I have no favourite color."),
+                choice(1, "Pink, of course!")
+            ],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 12, "total_tokens": 22 }
+        });
+        server.script(
+            CHAT_COMPLETIONS,
+            [MockResponse::Raw {
+                status: 200,
+                content_type: "application/json".to_string(),
+                body: body.to_string(),
+            }],
+        );
+
+        let mut ai = ai_waifu::create_streamed_ai(&{
+            let mut cfg = Config::default();
+            cfg.ai_engine.engine_type = ai_waifu::config::AIEngineType::LLaMa {
+                api_url: server.url(CHAT_COMPLETIONS),
+                model: None,
+            };
+            cfg.ai_engine.reply_count = Some(2);
+            cfg.ai_engine.candidate_rules = Some(vec![CandidateRuleConfig::BannedWords {
+                words: strings(&["as an ai\\b"]),
+                weight: 1.0,
+            }]);
+            cfg
+        });
+
+        let req = TestRequest::new("What is your favourite color?");
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        let res = ai.process_streamed(Box::new(req), tx).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Pink, of course!");
+
+        let requests = server.requests_to(CHAT_COMPLETIONS);
+        assert_eq!(requests.len(), 1);
+        // the winner can't be streamed
+        assert!(!requests[0].is_stream());
+        assert_eq!(requests[0].json()["n"], 2);
+        let history = ai.history().unwrap();
+        assert_eq!(history.last().unwrap().content, "Pink, of course!");
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.ai_engine.candidate_rules = Some(
+            serde_json::from_value(json!([
+                { "type": "Length", "Min": 50, "Max": 20 },
+                { "type": "BannedWords", "Words": ["ok", "("] },
+                { "type": "Repetition", "Window": 0 }
+            ]))
+            .unwrap(),
+        );
+
+        let report = config.validate_values();
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec![
+                "AIEngine.Candidate_rules[0].Min",
+                "AIEngine.Candidate_rules[1].Words[1]",
+                "AIEngine.Candidate_rules[2].Window"
+            ]
+        );
+        assert_eq!(
+            report.key_paths(Severity::Warning),
+            vec!["AIEngine.Candidate_rules"]
+        );
+
+        config.ai_engine.reply_count = Some(3);
+        let report = config.validate_values();
+        assert!(report.key_paths(Severity::Warning).is_empty());
+    }
+}
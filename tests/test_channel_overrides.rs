@@ -0,0 +1,259 @@
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, sync::Arc};
+
+    use ai_waifu::{
+        channel_overrides::{ChannelScope, ScopedConfigs},
+        config::{Config, OverrideConfig, PersonaConfig, TTSConfig},
+        config_validation::Severity,
+        dispatcher::*,
+        dummy_ai::DummyAI,
+        persona::{Personas, DEFAULT_PERSONA},
+    };
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+    use serde_json::json;
+
+    fn override_config(value: serde_json::Value) -> OverrideConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn config() -> Config {
+        let mut config = Config {
+            initial_prompt: "main prompt".to_string(),
+            busy_messages: vec!["busy".to_string()],
+            overrides: vec![
+                override_config(json!({ "Guild": "1", "AI_initial_prompt": "guild prompt" })),
+                override_config(json!({
+                    "Guild": "1",
+                    "Name": "anime.*",
+                    "Answer_lang": "ja",
+                    "DisplayRawResp": true
+                })),
+                override_config(json!({
+                    "Channel": "pina_stream",
+                    "AI_initial_prompt": "stream prompt",
+                    "TTS_Config": { "type": "SilerioTTSConfig" }
+                })),
+            ],
+            ..Default::default()
+        };
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.personas.insert(
+            "cat".to_string(),
+            PersonaConfig {
+                initial_prompt: "cat prompt".to_string(),
+                temperature: None,
+                top_p: None,
+                presence_penalty: None,
+                frequency_penalty: None,
+                tts_config: None,
+                answer_lang: None,
+                busy_messages: None,
+            },
+        );
+        config
+    }
+
+    fn anime_channel() -> ChannelScope {
+        ChannelScope::new("10")
+            .with_guild("1")
+            .with_name("anime-talk")
+    }
+
+    #[test]
+    fn test_matches() {
+        let o = override_config(json!({ "Guild": "1", "Name": "anime.*" }));
+        assert!(o.matches(&anime_channel()));
+        // all the selectors must match
+        assert!(!o.matches(&ChannelScope::new("10").with_guild("1").with_name("general")));
+        assert!(!o.matches(&ChannelScope::new("10").with_guild("2").with_name("anime")));
+        assert!(!o.matches(&ChannelScope::new("10").with_guild("1")));
+
+        // without selectors nothing matches
+        let o = override_config(json!({ "AI_initial_prompt": "prompt" }));
+        assert!(!o.matches(&anime_channel()));
+        assert!(!o.matches(&ChannelScope::default()));
+    }
+
+    #[test]
+    fn test_for_scope() {
+        let config = config();
+        assert_eq!(config.matching_overrides(&anime_channel()), vec![0, 1]);
+
+        let anime = config.for_scope(&anime_channel());
+        assert_eq!(anime.initial_prompt, "guild prompt");
+        assert_eq!(anime.deeplx_translate_config.dest_lang, "ja");
+        assert!(anime.display_raw_resp);
+
+        let general =
+            config.for_scope(&ChannelScope::new("11").with_guild("1").with_name("general"));
+        assert_eq!(general.initial_prompt, "guild prompt");
+        assert_eq!(general.deeplx_translate_config.dest_lang, "en");
+        assert!(!general.display_raw_resp);
+
+        let stream = config.for_scope(&ChannelScope::new("pina_stream"));
+        assert_eq!(stream.initial_prompt, "stream prompt");
+        assert!(matches!(
+            stream.tts_config,
+            TTSConfig::SilerioTTSConfig { .. }
+        ));
+
+        let other = config.for_scope(&ChannelScope::new("other"));
+        assert_eq!(other.initial_prompt, "main prompt");
+        assert!(matches!(other.tts_config, TTSConfig::Disabled));
+    }
+
+    #[test]
+    fn test_scoped_configs() {
+        let configs = ScopedConfigs::new(config());
+        let anime = configs.get(&anime_channel());
+        assert_eq!(anime.deeplx_translate_config.dest_lang, "ja");
+        // another channel with the same overrides gets the same config
+        let other = configs.get(&ChannelScope::new("12").with_guild("1").with_name("anime"));
+        assert!(Arc::ptr_eq(&anime, &other));
+        let general = configs.get(&ChannelScope::new("11").with_guild("1"));
+        assert_eq!(general.deeplx_translate_config.dest_lang, "en");
+
+        // an invalid name pattern matches nothing
+        let o = override_config(json!({ "Guild": "1", "Name": "(" }));
+        assert!(o.name.as_ref().unwrap().regex().is_err());
+        assert!(!o.matches(&anime_channel()));
+    }
+
+    #[test]
+    fn test_later_override_wins() {
+        let mut config = config();
+        config.overrides.push(override_config(
+            json!({ "Guild": "1", "AI_initial_prompt": "last prompt" }),
+        ));
+        assert_eq!(
+            config.for_scope(&anime_channel()).initial_prompt,
+            "last prompt"
+        );
+    }
+
+    #[test]
+    fn test_personas_in_scope() {
+        let personas = Personas::with_config(&config());
+
+        let default = personas.get(&anime_channel(), DEFAULT_PERSONA);
+        assert_eq!(default.initial_prompt, "guild prompt");
+        assert_eq!(default.answer_lang, "ja");
+        assert!(default.display_raw_resp);
+
+        // the named persona keeps its prompt, the rest comes from the channel settings
+        let cat = personas.get(&anime_channel(), "cat");
+        assert_eq!(cat.initial_prompt, "cat prompt");
+        assert_eq!(cat.answer_lang, "ja");
+        assert!(cat.display_raw_resp);
+
+        let cat = personas.get(&ChannelScope::new("other"), "cat");
+        assert_eq!(cat.answer_lang, "en");
+        assert!(!cat.display_raw_resp);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = config();
+        config.busy_messages = vec!["busy".to_string()];
+        config.stt_config.minimal_audio_fragment_length = 1.25;
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        assert!(!config.validate_values().has_errors());
+
+        config
+            .overrides
+            .push(override_config(json!({ "Answer_lang": "ja" })));
+        config
+            .overrides
+            .push(override_config(json!({ "Name": "(", "Answer_lang": "" })));
+        assert_eq!(
+            config.validate_values().key_paths(Severity::Error),
+            vec![
+                "Overrides[3]",
+                "Overrides[4].Name",
+                "Overrides[4].Answer_lang"
+            ]
+        );
+    }
+
+    /// Answers with the guild of the channel
+    struct ScopeAI(ChannelScope);
+
+    #[async_trait]
+    impl AIinterface for ScopeAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            let mut res = DummyAI.process(request).await?;
+            let guild = self.0.guild.clone().unwrap_or_default();
+            res.insert(AIResponseType::RawAnswer, guild);
+            Ok(res)
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    struct ScopeAIConstrictor;
+
+    impl AIBuilder for ScopeAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(ScopeAI(ChannelScope::default()))
+        }
+
+        fn build_for(
+            &mut self,
+            scope: &ChannelScope,
+            _persona: &str,
+        ) -> Option<Box<dyn AIinterface>> {
+            Some(Box::new(ScopeAI(scope.clone())))
+        }
+    }
+
+    struct GuildRequest;
+
+    impl AIRequest for GuildRequest {
+        fn request(&self) -> String {
+            "Hello!".to_string()
+        }
+
+        fn channel(&self) -> String {
+            "10".to_string()
+        }
+
+        fn lang(&self) -> String {
+            "auto".to_string()
+        }
+
+        fn scope(&self) -> ChannelScope {
+            anime_channel()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_builds_for_scope() {
+        let dispatcher = AIDispatcher::new(ScopeAIConstrictor, None);
+        let chunks = dispatcher
+            .try_process_request_streamed(Box::new(GuildRequest))
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+        match chunks.last() {
+            Some(Ok(AIResponseChunk::Done(res))) => {
+                assert_eq!(res[&AIResponseType::RawAnswer], "1")
+            }
+            other => panic!("Unexpected last chunk: {:?}", other),
+        }
+    }
+}
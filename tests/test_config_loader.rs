@@ -0,0 +1,333 @@
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use ai_waifu::{
+        config::{AIEngineType, Config, TTSConfig},
+        config_format::ConfigFormat,
+        config_loader::{
+            apply_env, load_layered, local_path, merge, redact_secrets, ConfigError, ConfigOrigin,
+            Layers, REDACTED,
+        },
+        config_validation::Severity,
+    };
+    use serde_json::json;
+
+    const BASE_CONFIG: &str = r#"{
+        "AIEngine": {
+            "Engine_Type": { "type": "ChatGPT", "OpenAI_Token": "base-token" },
+            "Temperature": 0.5
+        },
+        "AI_initial_prompt": "Act as japan pop-idol",
+        "Discord_Config": { "Discord_Token": "", "Discord_channel_whitelist": [] },
+        "DeepLx_Translate_Config": { "Answer_lang": "ru" },
+        "TTS_Config": { "type": "Disabled" },
+        "DisplayRawResp": false,
+        "Busy_messages": ["Busy"],
+        "STT_Config": {
+            "Minimal_audio_fragment_length": 1.25,
+            "Maximal_audio_fragment_length": 15.0
+        }
+    }"#;
+
+    fn vars(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn config_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(
+            local_path(Path::new("/etc/waifu/config.json")),
+            Path::new("/etc/waifu/config.local.json")
+        );
+        assert_eq!(local_path(Path::new("bot")), Path::new("bot.local"));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut base = json!({
+            "AIEngine": { "Temperature": 0.5, "Top_p": 0.9 },
+            "Busy_messages": ["a", "b"]
+        });
+        merge(
+            &mut base,
+            json!({
+                "AIEngine": { "Temperature": 0.7 },
+                "Busy_messages": ["c"],
+                "DisplayRawResp": true
+            }),
+        );
+
+        // objects are merged, arrays are replaced
+        assert_eq!(
+            base,
+            json!({
+                "AIEngine": { "Temperature": 0.7, "Top_p": 0.9 },
+                "Busy_messages": ["c"],
+                "DisplayRawResp": true
+            })
+        );
+    }
+
+    #[test]
+    fn test_env() {
+        let mut config = json!({
+            "AIEngine": {
+                "Engine_Type": { "type": "ChatGPT", "OpenAI_Token": "base-token" },
+                "Temperature": 0.5
+            }
+        });
+        let fields = apply_env(
+            &mut config,
+            vars(&[
+                ("WAIFU_AIENGINE__ENGINE_TYPE__OPENAI_TOKEN", "12345"),
+                ("WAIFU_aiengine__temperature", "0.9"),
+                ("WAIFU_BUSY_MESSAGES", r#"["Wait"]"#),
+                ("WAIFU_MODERATION__DEFAULT_ACTION", "Canned"),
+                ("WAIFU_PERSONAS__NEKO__AI_INITIAL_PROMPT", "Act as a cat"),
+                ("WAIFU_AIENGINE__OPENAI_TOKEN", "ignored"),
+                ("WAIFU_AIENGINE__TEMPERATURE__MAX", "ignored"),
+                ("WAIFU_MODERATON__DEFAULT_ACTION", "ignored"),
+                ("WAIFU___", "ignored"),
+                ("HOME", "/root"),
+            ]),
+        );
+
+        assert_eq!(
+            config,
+            json!({
+                "AIEngine": {
+                    // string fields are kept strings
+                    "Engine_Type": { "type": "ChatGPT", "OpenAI_Token": "12345" },
+                    "Temperature": 0.9
+                },
+                "Busy_messages": ["Wait"],
+                "Moderation": { "Default_action": "Canned" },
+                "Personas": { "NEKO": { "AI_initial_prompt": "Act as a cat" } }
+            })
+        );
+        assert!(fields.set.contains(&(
+            "WAIFU_MODERATION__DEFAULT_ACTION".to_string(),
+            vec!["Moderation".to_string(), "Default_action".to_string()]
+        )));
+
+        // nothing is set by the variables that don't name a field
+        let unknown = fields
+            .unknown
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            unknown,
+            [
+                "WAIFU_AIENGINE__OPENAI_TOKEN",
+                "WAIFU_AIENGINE__TEMPERATURE__MAX",
+                "WAIFU_MODERATON__DEFAULT_ACTION",
+                "WAIFU___",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redact() {
+        let config = json!({
+            "AIEngine": {
+                "Engine_Type": { "type": "ChatGPT", "OpenAI_Token": "secret" }
+            },
+            "Discord_Config": { "Discord_Token": "" },
+            "Personas": { "neko": { "AI_initial_prompt": "Act as a cat" } }
+        });
+
+        assert_eq!(
+            redact_secrets(config),
+            json!({
+                "AIEngine": {
+                    "Engine_Type": { "type": "ChatGPT", "OpenAI_Token": REDACTED }
+                },
+                // nothing to hide
+                "Discord_Config": { "Discord_Token": "" },
+                "Personas": { "neko": { "AI_initial_prompt": "Act as a cat" } }
+            })
+        );
+    }
+
+    #[test]
+    fn test_layered() {
+        let dir = config_dir("ai-waifu-config-layered");
+        let path = dir.join("config.json");
+        std::fs::write(&path, BASE_CONFIG).unwrap();
+
+        // no local file
+        let config = Config::from_value(load_layered(&path, vec![]).unwrap()).unwrap();
+        assert_eq!(config.ai_engine.temperature, Some(0.5));
+
+        std::fs::write(
+            dir.join("config.local.json"),
+            r#"{ "AIEngine": { "Temperature": 0.7 }, "DisplayRawResp": true }"#,
+        )
+        .unwrap();
+        let value = load_layered(
+            &path,
+            vars(&[("WAIFU_AIENGINE__ENGINE_TYPE__OPENAI_TOKEN", "env-token")]),
+        )
+        .unwrap();
+        let config = Config::from_value(value).unwrap();
+
+        assert_eq!(config.ai_engine.temperature, Some(0.7));
+        assert!(config.display_raw_resp);
+        match config.ai_engine.engine_type {
+            AIEngineType::ChatGPT { openai_token, .. } => {
+                assert_eq!(openai_token.expose(), "env-token")
+            }
+            _ => panic!("Unexpected engine type"),
+        }
+
+        let layers = Layers::load(
+            &path,
+            vars(&[("WAIFU_AIENGINE__OPENAI_TOKEN", "env-token")]),
+        )
+        .unwrap();
+        assert!(layers.to_config().is_ok());
+        let problems = layers.env_problems();
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].key_path, "WAIFU_AIENGINE__OPENAI_TOKEN");
+        assert_eq!(problems[0].severity, Severity::Warning);
+    }
+
+    #[test]
+    fn test_errors() {
+        let dir = config_dir("ai-waifu-config-errors");
+
+        let err = load_layered(&dir.join("missing.json"), vec![]).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read"));
+
+        let path = dir.join("broken.json");
+        std::fs::write(&path, "{ \"AIEngine\": ").unwrap();
+        let err = load_layered(&path, vec![]).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to parse"));
+
+        assert!(Config::from_value(json!({ "AIEngine": {} })).is_err());
+    }
+
+    #[test]
+    fn test_example_config() {
+        let config = Layers::load(Path::new("config.example.json"), vec![])
+            .unwrap()
+            .to_config()
+            .unwrap();
+
+        // the last of the alternatives is used
+        assert!(matches!(
+            config.ai_engine.engine_type,
+            AIEngineType::LLaMa { .. }
+        ));
+        assert!(matches!(
+            config.tts_config,
+            TTSConfig::JPVoicesTTSConfig { .. }
+        ));
+    }
+
+    #[test]
+    fn test_formats() {
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.TOML")),
+            ConfigFormat::Toml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.yml")),
+            ConfigFormat::Yaml
+        );
+        assert_eq!(
+            ConfigFormat::from_path(Path::new("config.jsonc")),
+            ConfigFormat::Json5
+        );
+
+        let expected = json!({ "AIEngine": { "Temperature": 0.5, "Tools": ["time"] } });
+        let json5 = r#"{
+            // comment
+            AIEngine: { "Temperature": 0.5, "Tools": ["time",], },
+        }"#;
+        let toml = "[AIEngine]\nTemperature = 0.5\nTools = [\"time\"]\n";
+        let yaml = "AIEngine:\n  Temperature: 0.5\n  Tools:\n    - time\n";
+
+        assert_eq!(ConfigFormat::Json5.parse(json5).unwrap(), expected);
+        assert_eq!(ConfigFormat::Toml.parse(toml).unwrap(), expected);
+        assert_eq!(ConfigFormat::Yaml.parse(yaml).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let json5 = "{\n  \"AIEngine\": {\n    \"Temperature\": 0.5\n    \"Top_p\": 1\n  }\n}";
+        let e = ConfigFormat::Json5.parse(json5).unwrap_err();
+        // missing comma after the value
+        assert_eq!((e.line, e.column), (3, 20));
+        assert_eq!(e.key_path, "AIEngine.Temperature");
+
+        let json5 = "{ \"Busy_messages\": [\"a\", \"b\" \"c\"] }";
+        let e = ConfigFormat::Json5.parse(json5).unwrap_err();
+        assert_eq!(e.key_path, "Busy_messages[1]");
+
+        let toml = "[AIEngine]\nTemperature = 0.5\nTop_p = \n";
+        let e = ConfigFormat::Toml.parse(toml).unwrap_err();
+        assert_eq!(e.line, 3);
+        assert_eq!(e.key_path, "AIEngine.Top_p");
+
+        let yaml = "AIEngine:\n  Engine_Type:\n    Url: [1, 2\n";
+        let e = ConfigFormat::Yaml.parse(yaml).unwrap_err();
+        assert_eq!(e.key_path, "AIEngine.Engine_Type.Url");
+
+        let dir = config_dir("ai-waifu-config-syntax");
+        let path = dir.join("config.json");
+        std::fs::write(&path, json5).unwrap();
+        assert_eq!(
+            load_layered(&path, vec![]).unwrap_err().to_string(),
+            format!(
+                "Failed to parse {}:1:26 at Busy_messages[1]: {}",
+                path.display(),
+                ConfigFormat::Json5.parse(json5).unwrap_err().message
+            )
+        );
+    }
+
+    #[test]
+    fn test_invalid_origin() {
+        let dir = config_dir("ai-waifu-config-origin");
+        let path = dir.join("config.json");
+        std::fs::write(&path, BASE_CONFIG).unwrap();
+        std::fs::write(
+            dir.join("config.local.json"),
+            "{\n  \"AIEngine\": {\n    \"Temperature\": \"hot\"\n  }\n}",
+        )
+        .unwrap();
+
+        match Layers::load(&path, vec![]).unwrap().to_config() {
+            Err(ConfigError::Invalid {
+                origin, key_path, ..
+            }) => {
+                assert_eq!(
+                    origin,
+                    ConfigOrigin::File(dir.join("config.local.json"), Some((3, 6)))
+                );
+                assert_eq!(key_path, "AIEngine.Temperature");
+            }
+            _ => panic!("Invalid temperature is not reported"),
+        }
+
+        let layers = Layers::load(&path, vars(&[("WAIFU_AIENGINE__TEMPERATURE", "[1]")])).unwrap();
+        match layers.to_config() {
+            Err(ConfigError::Invalid { origin, .. }) => assert_eq!(
+                origin,
+                ConfigOrigin::Env("WAIFU_AIENGINE__TEMPERATURE".to_string())
+            ),
+            _ => panic!("Invalid temperature is not reported"),
+        }
+    }
+}
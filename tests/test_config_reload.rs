@@ -0,0 +1,163 @@
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use ai_waifu::{
+        config::{Config, TTSConfig},
+        config_loader::Layers,
+        config_reload::{fixed_config, ConfigChanges, ConfigReloader, Live, ReloadError},
+    };
+    use serde_json::json;
+
+    const CONFIG: &str = r#"{
+        "AIEngine": {
+            "Engine_Type": { "type": "LLaMa", "Url": "http://localhost:8000/v1/chat/completions" },
+            "Temperature": 0.5
+        },
+        "AI_initial_prompt": "Act as japan pop-idol",
+        "Discord_Config": { "Discord_Token": "", "Discord_channel_whitelist": [".*Pina.*"] },
+        "DeepLx_Translate_Config": { "Answer_lang": "en" },
+        "TTS_Config": { "type": "Disabled" },
+        "DisplayRawResp": false,
+        "Busy_messages": ["Busy"],
+        "STT_Config": {
+            "Minimal_audio_fragment_length": 1.25,
+            "Maximal_audio_fragment_length": 15.0
+        }
+    }"#;
+
+    fn config_file(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.json");
+        std::fs::write(&path, CONFIG).unwrap();
+        path
+    }
+
+    fn reloader(path: &Path) -> ConfigReloader {
+        let layers = Layers::load(path, vec![]).unwrap();
+        let config = layers.to_config().unwrap();
+        ConfigReloader::new(path.to_owned(), layers.value, config)
+    }
+
+    #[test]
+    fn test_changes() {
+        let old = json!({
+            "AI_initial_prompt": "a",
+            "AIEngine": { "Temperature": 0.5, "Top_p": 0.9 },
+            "STT_Config": { "Minimal_audio_fragment_length": 1.0, "STT_Url": "http://a" },
+            "TTS_Config": { "type": "Disabled" }
+        });
+        let new = json!({
+            "AI_initial_prompt": "b",
+            "AIEngine": { "Temperature": 0.7, "Top_p": 0.9 },
+            "STT_Config": { "Minimal_audio_fragment_length": 2.0, "STT_Url": "http://b" },
+            "TTS_Config": { "type": "SilerioTTSConfig", "Voice_character": "kseniya" }
+        });
+
+        assert_eq!(
+            ConfigChanges::between(&old, &new),
+            ConfigChanges {
+                live: vec![
+                    "AI_initial_prompt".to_string(),
+                    "STT_Config.Minimal_audio_fragment_length".to_string(),
+                    "TTS_Config.Voice_character".to_string(),
+                    "TTS_Config.type".to_string(),
+                ],
+                restart: vec![
+                    "AIEngine.Temperature".to_string(),
+                    "STT_Config.STT_Url".to_string(),
+                ],
+            }
+        );
+        assert!(ConfigChanges::between(&old, &old).is_empty());
+    }
+
+    #[test]
+    fn test_reload() {
+        let path = config_file("ai-waifu-config-reload");
+        let mut reloader = reloader(&path);
+        let mut config = reloader.subscribe();
+
+        let changes = reloader.reload().unwrap();
+        assert!(changes.is_empty());
+        assert!(!config.has_changed().unwrap());
+
+        std::fs::write(
+            path.with_file_name("config.local.json"),
+            r#"{
+                "AI_initial_prompt": "Act as a cat",
+                "Busy_messages": ["Meow"],
+                "TTS_Config": { "type": "SilerioTTSConfig" },
+                "AIEngine": { "Temperature": 0.9 }
+            }"#,
+        )
+        .unwrap();
+        let changes = reloader.reload().unwrap();
+        assert_eq!(changes.restart, vec!["AIEngine.Temperature"]);
+        assert_eq!(changes.live.len(), 3);
+
+        assert!(config.has_changed().unwrap());
+        let current = config.borrow_and_update().clone();
+        assert_eq!(current.initial_prompt, "Act as a cat");
+        assert_eq!(current.busy_messages, vec!["Meow"]);
+        assert!(matches!(
+            current.tts_config,
+            TTSConfig::SilerioTTSConfig { .. }
+        ));
+        // needs restart
+        assert_eq!(current.ai_engine.temperature, Some(0.5));
+
+        // restart fields are reported once
+        let changes = reloader.reload().unwrap();
+        assert!(changes.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_reload() {
+        let path = config_file("ai-waifu-config-reload-invalid");
+        let mut reloader = reloader(&path);
+        let config = reloader.subscribe();
+
+        std::fs::write(
+            path.with_file_name("config.local.json"),
+            r#"{ "AI_initial_prompt": "Act as a cat", "Discord_Config": { "Discord_channel_whitelist": ["("] } }"#,
+        )
+        .unwrap();
+        assert!(matches!(reloader.reload(), Err(ReloadError::Invalid(_))));
+
+        std::fs::write(path.with_file_name("config.local.json"), "{ broken").unwrap();
+        assert!(matches!(reloader.reload(), Err(ReloadError::Load(_))));
+
+        assert!(!config.has_changed().unwrap());
+        assert_eq!(config.borrow().initial_prompt, "Act as japan pop-idol");
+    }
+
+    #[test]
+    fn test_live() {
+        let path = config_file("ai-waifu-config-reload-live");
+        let mut reloader = reloader(&path);
+        let whitelist = Live::new(reloader.subscribe(), |config| {
+            regex::RegexSet::new(&config.discord_config.channel_whitelist).unwrap()
+        });
+        assert!(whitelist.get().is_match("Pina chat"));
+        assert!(!whitelist.get().is_match("general"));
+
+        std::fs::write(
+            path.with_file_name("config.local.json"),
+            r#"{ "Discord_Config": { "Discord_channel_whitelist": ["general"] } }"#,
+        )
+        .unwrap();
+        reloader.reload().unwrap();
+        assert!(!whitelist.get().is_match("Pina chat"));
+        assert!(whitelist.get().is_match("general"));
+
+        // never changes
+        let config = Config {
+            busy_messages: vec!["Busy".to_string()],
+            ..Default::default()
+        };
+        let busy = Live::new(fixed_config(config), |config| config.busy_messages.clone());
+        assert_eq!(*busy.get(), vec!["Busy"]);
+    }
+}
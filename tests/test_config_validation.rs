@@ -0,0 +1,121 @@
+mod tests {
+    use std::collections::HashMap;
+
+    use ai_waifu::{
+        config::{AIEngineType, Config, MiddlewareConfig, PersonaConfig},
+        config_validation::{check_urls, Severity},
+        secret::Secret,
+    };
+    use reqwest::Url;
+
+    fn valid_config() -> Config {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.minimal_audio_fragment_length = 1.25;
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config
+    }
+
+    #[test]
+    fn test_valid() {
+        assert!(valid_config().validate_values().problems.is_empty());
+    }
+
+    #[test]
+    fn test_all_problems_reported() {
+        let mut config = valid_config();
+        config.ai_engine.engine_type = AIEngineType::ChatGPT {
+            openai_token: Secret::default(),
+            engine: Some("Gpt5".to_string()),
+        };
+        config.ai_engine.temperature = Some(3.0);
+        config.ai_engine.tools = vec!["time".to_string(), "weather".to_string()];
+        config.discord_config.channel_whitelist = vec![".*Pina.*".to_string(), "(".to_string()];
+        config.busy_messages = vec![];
+        config.stt_config.minimal_audio_fragment_length = 20.0;
+        config.middleware = Some(vec![MiddlewareConfig::Moderation]);
+        config.personas = HashMap::from([(
+            "neko".to_string(),
+            PersonaConfig {
+                initial_prompt: "Act as a cat".to_string(),
+                temperature: None,
+                top_p: Some(1.5),
+                presence_penalty: None,
+                frequency_penalty: None,
+                tts_config: None,
+                answer_lang: None,
+                busy_messages: None,
+            },
+        )]);
+
+        let report = config.validate_values();
+        assert!(report.has_errors());
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec![
+                "AIEngine.Engine_Type.OpenAI_Token",
+                "AIEngine.Engine_Type.GPT_Version",
+                "AIEngine.Temperature",
+                "AIEngine.Tools[1]",
+                "Discord_Config.Discord_channel_whitelist[1]",
+                "STT_Config.Minimal_audio_fragment_length",
+                "Personas.neko.Top_p",
+                "Middleware[0]",
+            ]
+        );
+        assert_eq!(report.key_paths(Severity::Warning), vec!["Busy_messages"]);
+
+        let text = report.to_string();
+        assert!(text.starts_with("Config has 8 error(s) and 1 warning(s)"));
+        assert!(text.contains(
+            "error: STT_Config.Minimal_audio_fragment_length: 20 is greater than Maximal_audio_fragment_length 15"
+        ));
+    }
+
+    #[test]
+    fn test_translate_needs_answer_lang() {
+        let mut config = valid_config();
+        config.deeplx_translate_config.dest_lang = "".to_string();
+        assert_eq!(
+            config.validate_values().key_paths(Severity::Error),
+            vec!["DeepLx_Translate_Config.Answer_lang"]
+        );
+
+        // not translated
+        config.middleware = Some(vec![MiddlewareConfig::NumbersToWords]);
+        assert!(!config.validate_values().has_errors());
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_url() {
+        // bind and drop to get a closed port
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let closed = Url::parse(&format!("http://127.0.0.1:{port}/say")).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = Url::parse(&format!(
+            "http://127.0.0.1:{}/transcribe",
+            listener.local_addr().unwrap().port()
+        ))
+        .unwrap();
+
+        let problems = check_urls(vec![
+            ("TTS_Config.TTS_Service_Url".to_string(), closed.clone()),
+            ("STT_Config.STT_Url".to_string(), open),
+            // checked once
+            (
+                "Personas.neko.TTS_Config.TTS_Service_Url".to_string(),
+                closed,
+            ),
+        ])
+        .await;
+
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].severity, Severity::Warning);
+        assert_eq!(problems[0].key_path, "TTS_Config.TTS_Service_Url");
+    }
+}
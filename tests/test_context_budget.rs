@@ -0,0 +1,44 @@
+mod tests {
+    use ai_waifu::{
+        config::ContextBudgetConfig,
+        context_budget::{estimate_tokens, ContextBudget},
+    };
+
+    fn budget(max_tokens: usize, keep_recent_turns: usize) -> ContextBudget {
+        ContextBudget::new(ContextBudgetConfig {
+            max_tokens,
+            keep_recent_turns,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_estimate_tokens() {
+        assert!(estimate_tokens("") > 0);
+        assert!(estimate_tokens("Hello, how are you?") < estimate_tokens(&"word ".repeat(100)));
+    }
+
+    #[test]
+    fn test_fits_into_budget() {
+        assert_eq!(budget(100, 2).messages_to_fold(10, &[10; 8]), 0);
+    }
+
+    #[test]
+    fn test_keep_recent_turns() {
+        // 4 turns, the last 2 are kept
+        assert_eq!(budget(50, 2).messages_to_fold(10, &[10; 8]), 4);
+    }
+
+    #[test]
+    fn test_fold_whole_turns() {
+        // 3 messages before the recent turn, the answer is folded with its question
+        assert_eq!(budget(30, 1).messages_to_fold(0, &[10; 5]), 4);
+    }
+
+    #[test]
+    fn test_recent_turns_too_big() {
+        // recent turns are folded too, except the last one
+        assert_eq!(budget(50, 3).messages_to_fold(10, &[20; 6]), 4);
+        assert_eq!(budget(10, 3).messages_to_fold(10, &[20; 6]), 4);
+    }
+}
@@ -0,0 +1,270 @@
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+    use ai_waifu::{dispatcher::*, dummy_ai::DummyAI, utils::test_request::TestRequest};
+    use async_trait::async_trait;
+    use futures_util::StreamExt;
+    use tokio::sync::Barrier;
+
+    struct DummyAIConstrictor;
+
+    impl AIBuilder for DummyAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(DummyAI)
+        }
+    }
+
+    /// Answers only when all the channels are processing requests at the same time
+    struct BarrierAI(Arc<Barrier>);
+
+    #[async_trait]
+    impl AIinterface for BarrierAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            self.0.wait().await;
+            DummyAI.process(request).await
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    struct BarrierAIConstrictor(Arc<Barrier>);
+
+    impl AIBuilder for BarrierAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(BarrierAI(self.0.clone()))
+        }
+    }
+
+    /// Answers with the persona name before the request
+    struct PersonaAI(String);
+
+    #[async_trait]
+    impl AIinterface for PersonaAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            let mut res = DummyAI.process(request).await?;
+            let answer = format!("{}: {}", self.0, res[&AIResponseType::RawAnswer]);
+            res.insert(AIResponseType::RawAnswer, answer);
+            Ok(res)
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    struct PersonaAIConstrictor;
+
+    impl AIBuilder for PersonaAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            self.build_persona("default").unwrap()
+        }
+
+        fn build_persona(&mut self, persona: &str) -> Option<Box<dyn AIinterface>> {
+            match persona {
+                "default" | "cat" => Some(Box::new(PersonaAI(persona.to_string()))),
+                _ => None,
+            }
+        }
+    }
+
+    /// Panics on the "panic" request
+    struct PanicAI;
+
+    #[async_trait]
+    impl AIinterface for PanicAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            if request.request() == "panic" {
+                panic!("AI failed");
+            }
+            DummyAI.process(request).await
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    struct PanicAIConstrictor;
+
+    impl AIBuilder for PanicAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(PanicAI)
+        }
+    }
+
+    async fn answer(stream: AIResponseStream) -> Result<String, AIError> {
+        let chunks = stream.collect::<Vec<_>>().await;
+        match chunks.last() {
+            Some(Ok(AIResponseChunk::Done(res))) => Ok(res[&AIResponseType::RawAnswer].clone()),
+            Some(Err(e)) => Err(e.clone()),
+            other => panic!("Unexpected last chunk: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streamed_dispatcher() {
+        let dispatcher = AIDispatcher::new(DummyAIConstrictor {}, None);
+
+        let req = TestRequest::new("Hello!");
+
+        let chunks = dispatcher
+            .try_process_request_streamed(Box::new(req))
+            .await
+            .unwrap()
+            .collect::<Vec<_>>()
+            .await;
+
+        assert!(matches!(
+            chunks.first(),
+            Some(Ok(AIResponseChunk::Sentence(_)))
+        ));
+        match chunks.last() {
+            Some(Ok(AIResponseChunk::Done(res))) => {
+                assert_eq!(res[&AIResponseType::RawAnswer], "Hello!")
+            }
+            other => panic!("Unexpected last chunk: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_channels_in_parallel() {
+        let dispatcher = AIDispatcher::new(BarrierAIConstrictor(Arc::new(Barrier::new(2))), None)
+            .with_concurrency_limit(2);
+
+        let first = dispatcher
+            .try_process_request_streamed(Box::new(TestRequest::new("Hello!")))
+            .await
+            .unwrap();
+        let second = dispatcher
+            .try_process_request_streamed(Box::new(TestRequest::new("Hi!").with_channel("Guest")))
+            .await
+            .unwrap();
+
+        let (first, second) = tokio::time::timeout(Duration::from_secs(5), async {
+            tokio::join!(answer(first), answer(second))
+        })
+        .await
+        .expect("channels are not processed in parallel");
+
+        assert_eq!(first.unwrap(), "Hello!");
+        assert_eq!(second.unwrap(), "Hi!");
+    }
+
+    #[tokio::test]
+    async fn test_channel_order() {
+        let dispatcher = AIDispatcher::new(DummyAIConstrictor {}, None);
+
+        let first = dispatcher
+            .try_process_request_streamed(Box::new(TestRequest::new("first")))
+            .await
+            .unwrap();
+        let second = dispatcher
+            .try_process_request_streamed(Box::new(TestRequest::new("second")))
+            .await
+            .unwrap();
+
+        // the second request waits for the first one
+        let second = tokio::spawn(answer(second));
+        assert_eq!(answer(first).await.unwrap(), "first");
+        assert_eq!(second.await.unwrap().unwrap(), "second");
+    }
+
+    #[tokio::test]
+    async fn test_panic_frees_channel() {
+        let dispatcher = AIDispatcher::new(PanicAIConstrictor, None);
+
+        let first = dispatcher
+            .try_process_request_streamed(Box::new(TestRequest::new("panic")))
+            .await
+            .unwrap();
+        let second = dispatcher
+            .try_process_request_streamed(Box::new(TestRequest::new("Hi")))
+            .await
+            .unwrap();
+
+        assert!(matches!(answer(first).await, Err(AIError::UnknownError)));
+        // the queued request and the later ones are still answered
+        let second = tokio::time::timeout(Duration::from_secs(5), answer(second))
+            .await
+            .expect("the channel is left busy");
+        assert_eq!(second.unwrap(), "Hi");
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Hello")))
+            .await;
+        assert_eq!(res.unwrap()[&AIResponseType::RawAnswer], "Hello");
+    }
+
+    #[tokio::test]
+    async fn test_set_persona() {
+        let dispatcher = AIDispatcher::new(PersonaAIConstrictor, None);
+
+        assert_eq!(dispatcher.persona("Master"), "default");
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Hi")))
+            .await;
+        assert_eq!(res.unwrap()[&AIResponseType::RawAnswer], "default: Hi");
+
+        dispatcher
+            .set_persona("Master".to_string(), "cat".to_string())
+            .await
+            .unwrap();
+        assert_eq!(dispatcher.persona("Master"), "cat");
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Hi")))
+            .await;
+        assert_eq!(res.unwrap()[&AIResponseType::RawAnswer], "cat: Hi");
+
+        // other channels keep their persona
+        assert_eq!(dispatcher.persona("Guest"), "default");
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Hi").with_channel("Guest")))
+            .await;
+        assert_eq!(res.unwrap()[&AIResponseType::RawAnswer], "default: Hi");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_persona() {
+        let dispatcher = AIDispatcher::new(PersonaAIConstrictor, None);
+
+        let res = dispatcher
+            .set_persona("Master".to_string(), "dog".to_string())
+            .await;
+        assert!(matches!(res, Err(AIError::UnknownPersona(p)) if p == "dog"));
+        assert_eq!(dispatcher.persona("Master"), "default");
+    }
+}
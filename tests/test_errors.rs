@@ -0,0 +1,175 @@
+mod tests {
+    use std::error::Error;
+
+    use ai_waifu::{
+        config::ServicePolicy,
+        dispatcher::AIError,
+        errors::{
+            ErrorInfo, ErrorKind, STTError, ServiceError, TTSError, TranslateError,
+            DEEPL_TOO_MANY_REQUESTS,
+        },
+        resilience::{Failure, Service},
+        silerio_tts::SilerioTTS,
+        utils::mock_server::{MockResponse, MockServer, TRANSCRIBE, TTS},
+        whisper_voice_recognize::OpenAIWhisperVoice2Txt,
+    };
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    fn status_error(status: StatusCode) -> ServiceError {
+        ServiceError::new("test", Failure::from_status(status, "failed"))
+    }
+
+    #[test]
+    fn test_service_kinds() {
+        assert_eq!(
+            status_error(StatusCode::TOO_MANY_REQUESTS).kind(),
+            ErrorKind::RateLimited
+        );
+        assert_eq!(
+            status_error(StatusCode::UNAUTHORIZED).kind(),
+            ErrorKind::Unauthorized
+        );
+        assert_eq!(
+            status_error(StatusCode::BAD_GATEWAY).kind(),
+            ErrorKind::Unavailable
+        );
+        assert_eq!(
+            status_error(StatusCode::BAD_REQUEST).kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            ServiceError::new("test", Failure::transient("timed out")).kind(),
+            ErrorKind::Unavailable
+        );
+        assert_eq!(
+            ServiceError::new("test", Failure::permanent("bad json")).kind(),
+            ErrorKind::Internal
+        );
+
+        let e = status_error(StatusCode::TOO_MANY_REQUESTS);
+        assert!(e.is_retryable());
+        assert_eq!(e.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!status_error(StatusCode::UNAUTHORIZED).is_retryable());
+    }
+
+    #[tokio::test]
+    async fn test_breaker_open_is_unavailable() {
+        let policy: ServicePolicy =
+            serde_json::from_value(json!({ "Max_retries": 0, "Breaker_threshold": 1 })).unwrap();
+        let service = Service::new("test", policy);
+        let _ = service
+            .call(|| async { Err::<(), _>(Failure::transient("503")) })
+            .await;
+
+        let failure = service.call(|| async { Ok(()) }).await.unwrap_err();
+        let e = service.error(failure);
+        assert_eq!(e.kind(), ErrorKind::Unavailable);
+        assert!(e.is_retryable());
+    }
+
+    #[test]
+    fn test_translate_kinds() {
+        assert_eq!(
+            TranslateError::InvalidLanguage.kind(),
+            ErrorKind::InvalidInput
+        );
+        let not_confident = TranslateError::NotConfident {
+            unsupported: 0.9,
+            text: "qwfp".to_string(),
+        };
+        assert_eq!(not_confident.kind(), ErrorKind::InvalidInput);
+        let too_many = TranslateError::Rpc {
+            code: DEEPL_TOO_MANY_REQUESTS,
+            message: "Too many requests".to_string(),
+        };
+        assert_eq!(too_many.kind(), ErrorKind::RateLimited);
+        assert_eq!(
+            TranslateError::BadResponse("Missing text results".to_string()).kind(),
+            ErrorKind::Internal
+        );
+    }
+
+    #[test]
+    fn test_ai_error_wraps() {
+        let e = AIError::from(TranslateError::InvalidLanguage);
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+        assert_eq!(e.to_string(), "Invalid target Lang");
+        assert!(e.source().is_some());
+
+        let e = AIError::from(TTSError::Service(status_error(
+            StatusCode::SERVICE_UNAVAILABLE,
+        )));
+        assert_eq!(e.kind(), ErrorKind::Unavailable);
+        assert_eq!(e.status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+
+        let e = AIError::from(STTError::BadResponse("language".to_string()));
+        assert_eq!(e.kind(), ErrorKind::Internal);
+
+        assert_eq!(AIError::Busy.kind(), ErrorKind::Busy);
+        assert_ne!(
+            AIError::Busy.user_message(),
+            AIError::UnknownError.user_message()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_source_chain() {
+        // nobody listens there
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        drop(listener);
+
+        let e = reqwest::get(url).await.unwrap_err();
+        let e = AIError::from(ServiceError::from_reqwest("llama", e));
+        assert_eq!(e.kind(), ErrorKind::Unavailable);
+
+        let mut source = e.source();
+        let mut found = false;
+        while let Some(e) = source {
+            found |= e.is::<reqwest::Error>();
+            source = e.source();
+        }
+        assert!(found, "reqwest error is not in the chain of {e}");
+    }
+
+    #[tokio::test]
+    async fn test_tts_status() {
+        let server = MockServer::start().await;
+        server.script(
+            TTS,
+            [MockResponse::Raw {
+                status: 401,
+                content_type: "text/plain".to_string(),
+                body: "bad token".to_string(),
+            }],
+        );
+        let tts = SilerioTTS::new(server.url(TTS), None);
+        match tts.say("Hi").await {
+            Err(TTSError::Service(e)) => {
+                assert_eq!(e.status(), Some(StatusCode::UNAUTHORIZED));
+                assert_eq!(e.kind(), ErrorKind::Unauthorized);
+                assert!(e.failure.message.contains("bad token"));
+            }
+            Ok(_) => panic!("TTS error expected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stt_bad_response() {
+        let server = MockServer::start().await;
+        server.script(
+            TRANSCRIBE,
+            [MockResponse::Raw {
+                status: 200,
+                content_type: "application/json".to_string(),
+                body: json!({ "language": "en" }).to_string(),
+            }],
+        );
+        let stt = OpenAIWhisperVoice2Txt::new(server.url(TRANSCRIBE));
+        assert_eq!(
+            stt.recognize(vec![0u8; 16]).await,
+            Err(STTError::BadResponse("transcribed_segments".to_string()))
+        );
+    }
+}
@@ -0,0 +1,296 @@
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use ai_waifu::{
+        chat_template::{ChatRole, ChatTurn},
+        config::{AIEngineType, Config, Platform, RouteConfig},
+        config_validation::Severity,
+        dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+        fallback_ai::{Backend, FallbackAI, Route},
+        utils::test_request::TestRequest,
+    };
+    use async_trait::async_trait;
+    use maplit::hashmap;
+    use serde_json::json;
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Mode {
+        Answer,
+        Fail,
+        Hang,
+        /// Streams a sentence and fails
+        Break,
+    }
+
+    /// Answers with its name before the request
+    struct FakeAI {
+        name: &'static str,
+        mode: Arc<Mutex<Mode>>,
+        history: Vec<ChatTurn>,
+    }
+
+    impl FakeAI {
+        fn new(name: &'static str, mode: Mode) -> (Self, Arc<Mutex<Mode>>) {
+            let mode = Arc::new(Mutex::new(mode));
+            let ai = Self {
+                name,
+                mode: mode.clone(),
+                history: vec![ChatTurn::new(ChatRole::System, "Act as a cat")],
+            };
+            (ai, mode)
+        }
+
+        fn answer(&mut self, request: String) -> HashMap<AIResponseType, String> {
+            let answer = format!("{}: {}", self.name, request);
+            self.history.push(ChatTurn::new(ChatRole::User, request));
+            self.history
+                .push(ChatTurn::new(ChatRole::Assistant, answer.clone()));
+            hashmap! { AIResponseType::RawAnswer => answer }
+        }
+    }
+
+    #[async_trait]
+    impl AIinterface for FakeAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            let mode = *self.mode.lock().unwrap();
+            match mode {
+                Mode::Answer => Ok(self.answer(request.request())),
+                Mode::Fail | Mode::Break => Err(AIError::NetworkError),
+                Mode::Hang => {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    Err(AIError::NetworkError)
+                }
+            }
+        }
+
+        async fn process_streamed(
+            &mut self,
+            request: Box<dyn AIRequest>,
+            chunks: tokio::sync::mpsc::Sender<AIResponseChunk>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            let mode = *self.mode.lock().unwrap();
+            if mode == Mode::Break {
+                let sentence = hashmap! { AIResponseType::RawAnswer => "Meow.".to_string() };
+                let _ = chunks.send(AIResponseChunk::Sentence(sentence)).await;
+                return Err(AIError::NetworkError);
+            }
+            let res = self.process(request).await?;
+            let _ = chunks.send(AIResponseChunk::Sentence(res.clone())).await;
+            Ok(res)
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            self.history.truncate(1);
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn history(&self) -> Option<Vec<ChatTurn>> {
+            Some(self.history.clone())
+        }
+
+        fn set_history(&mut self, history: Vec<ChatTurn>) {
+            self.history = history;
+        }
+    }
+
+    fn chain(modes: &[(&'static str, Mode)]) -> (FallbackAI, Vec<Arc<Mutex<Mode>>>) {
+        let (backends, modes) = modes
+            .iter()
+            .map(|(name, mode)| {
+                let (ai, mode) = FakeAI::new(name, *mode);
+                (Backend::new(*name, Box::new(ai)), mode)
+            })
+            .unzip();
+        (FallbackAI::new(backends), modes)
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let (mut ai, _) = chain(&[
+            ("main", Mode::Fail),
+            ("gpt35", Mode::Fail),
+            ("local", Mode::Answer),
+        ]);
+        let res = ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "local: Hi");
+        assert_eq!(ai.current(), "local");
+
+        let (mut ai, _) = chain(&[("main", Mode::Fail), ("local", Mode::Fail)]);
+        assert!(matches!(
+            ai.process(Box::new(TestRequest::new("Hi"))).await,
+            Err(AIError::NetworkError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (ai, _) = chain(&[("main", Mode::Hang), ("local", Mode::Answer)]);
+        let mut ai = ai.with_timeout(Duration::from_millis(50));
+        let res = ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "local: Hi");
+
+        let (ai, _) = chain(&[("main", Mode::Hang)]);
+        let mut ai = ai.with_timeout(Duration::from_millis(50));
+        match ai.process(Box::new(TestRequest::new("Hi"))).await {
+            Err(AIError::AnswerError(e)) => assert_eq!(e, "main timed out"),
+            _ => panic!("Timeout expected"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shared_history() {
+        let (mut ai, modes) = chain(&[("main", Mode::Answer), ("local", Mode::Answer)]);
+        ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+
+        *modes[0].lock().unwrap() = Mode::Fail;
+        let res = ai
+            .process(Box::new(TestRequest::new("Who are you?")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "local: Who are you?");
+
+        // and back to the main backend
+        *modes[0].lock().unwrap() = Mode::Answer;
+        ai.process(Box::new(TestRequest::new("Bye"))).await.unwrap();
+
+        let history = ai.history().unwrap();
+        let answers = history
+            .iter()
+            .filter(|t| t.role == ChatRole::Assistant)
+            .map(|t| t.content.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            answers,
+            vec!["main: Hi", "local: Who are you?", "main: Bye"]
+        );
+        assert_eq!(history[0].content, "Act as a cat");
+    }
+
+    #[tokio::test]
+    async fn test_streamed() {
+        let (ai, _) = chain(&[("main", Mode::Hang), ("local", Mode::Answer)]);
+        let mut ai = ai.with_timeout(Duration::from_millis(50));
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("Hi")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "local: Hi");
+        assert!(matches!(
+            rx.recv().await,
+            Some(AIResponseChunk::Sentence(_))
+        ));
+        assert!(rx.recv().await.is_none());
+
+        // the listeners already got a part of the answer, no other backend is asked
+        let (mut ai, _) = chain(&[("main", Mode::Break), ("local", Mode::Answer)]);
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        assert!(ai
+            .process_streamed(Box::new(TestRequest::new("Hi")), tx)
+            .await
+            .is_err());
+        assert!(matches!(
+            rx.recv().await,
+            Some(AIResponseChunk::Sentence(_))
+        ));
+        assert!(rx.recv().await.is_none());
+        assert_eq!(ai.current(), "main");
+    }
+
+    #[test]
+    fn test_routing() {
+        let (ai, _) = chain(&[
+            ("main", Mode::Answer),
+            ("gpt35", Mode::Answer),
+            ("local", Mode::Answer),
+        ]);
+        let ai = ai.with_routes(vec![
+            Route {
+                min_length: None,
+                max_length: Some(5),
+                backend: 2,
+            },
+            Route {
+                min_length: Some(100),
+                max_length: None,
+                backend: 0,
+            },
+        ]);
+        assert_eq!(ai.order("Hi"), vec![2, 0, 1]);
+        assert_eq!(ai.order("Who are you?"), vec![0, 1, 2]);
+        assert_eq!(ai.order(&"Meow ".repeat(20)), vec![0, 1, 2]);
+
+        let rule = serde_json::from_value::<RouteConfig>(json!({
+            "Platform": "Twitch",
+            "Persona": "Ksenia",
+            "Backend": "local"
+        }))
+        .unwrap();
+        assert!(rule.applies_to(Some(Platform::Twitch), "Ksenia"));
+        assert!(!rule.applies_to(Some(Platform::Discord), "Ksenia"));
+        assert!(!rule.applies_to(None, "Ksenia"));
+        assert!(!rule.applies_to(Some(Platform::Twitch), "default"));
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.ai_engine.fallback = serde_json::from_value(json!([
+            {
+                "Name": "main",
+                "Engine_Type": { "type": "LLaMa", "Url": "http://localhost:8000/v1/chat/completions" }
+            },
+            {
+                "Name": "local",
+                "Engine_Type": { "type": "Ollama", "Url": "http://localhost:11434/api/chat" }
+            }
+        ]))
+        .unwrap();
+        config.ai_engine.backend_timeout = Some(0.0);
+        config.ai_engine.routing = serde_json::from_value(json!([
+            { "Max_length": 40, "Backend": "local" },
+            { "Min_length": 40, "Max_length": 10, "Backend": "gpt35" },
+            { "Persona": "Ksenia", "Backend": "main" }
+        ]))
+        .unwrap();
+        assert!(matches!(
+            config.ai_engine.fallback[1].engine_type,
+            AIEngineType::Ollama(_)
+        ));
+
+        let report = config.validate_values();
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec![
+                "AIEngine.Fallback[0].Name",
+                "AIEngine.Fallback[1].Engine_Type.Model",
+                "AIEngine.Backend_timeout",
+                "AIEngine.Routing[1].Backend",
+                "AIEngine.Routing[1].Min_length",
+            ]
+        );
+        assert_eq!(
+            report.key_paths(Severity::Warning),
+            vec!["AIEngine.Routing[2].Persona"]
+        );
+    }
+}
@@ -0,0 +1,403 @@
+mod tests {
+    use ai_waifu::{
+        ai_memory_request::MemoryAIRequest,
+        chat_template::{cut_at_stop, ChatRole, ChatTurn},
+        config::{AIEngineType, ChatTemplate, Config, LocalModelConfig},
+        config_validation::Severity,
+        dispatcher::{AIError, AIResponseChunk, AIResponseType, AIinterface},
+        errors::ErrorInfo,
+        local_model::{LocalApi, LocalModel, LocalModelClient, Sampling, StreamPart, JSON_GRAMMAR},
+        resilience,
+        usage::TokenUsage,
+        utils::{
+            mock_server::{MockResponse, MockServer},
+            test_request::TestRequest,
+        },
+    };
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn history() -> Vec<ChatTurn> {
+        vec![
+            ChatTurn::new(ChatRole::System, "Act as a cat"),
+            ChatTurn::new(ChatRole::User, "Hi"),
+            ChatTurn::new(ChatRole::Assistant, "Meow"),
+            ChatTurn::new(ChatRole::User, "Who are you?"),
+        ]
+    }
+
+    fn local_config(value: serde_json::Value) -> LocalModelConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_templates() {
+        assert_eq!(
+            ChatTemplate::ChatML.render(&history()),
+            "<|im_start|>system\nAct as a cat<|im_end|>\n\
+             <|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\nMeow<|im_end|>\n\
+             <|im_start|>user\nWho are you?<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+        assert_eq!(
+            ChatTemplate::Llama2.render(&history()),
+            "<s>[INST] <<SYS>>\nAct as a cat\n<</SYS>>\n\nHi [/INST] Meow </s>\
+             <s>[INST] Who are you? [/INST]"
+        );
+        assert_eq!(
+            ChatTemplate::Alpaca.render(&history()),
+            "Act as a cat\n\n### Instruction:\nHi\n\n### Response:\nMeow\n\n\
+             ### Instruction:\nWho are you?\n\n### Response:\n"
+        );
+        assert_eq!(
+            ChatTemplate::Vicuna.render(&history()),
+            "Act as a cat\n\nUSER: Hi\nASSISTANT: Meow</s>\nUSER: Who are you?\nASSISTANT:"
+        );
+    }
+
+    #[test]
+    fn test_cut_at_stop() {
+        let stop = vec!["USER:".to_string(), "</s>".to_string()];
+        assert_eq!(cut_at_stop(" I'm a cat</s>\nUSER: Hi", &stop), "I'm a cat");
+        assert_eq!(cut_at_stop("No stop", &stop), "No stop");
+    }
+
+    #[test]
+    fn test_engine_config() {
+        let engine = serde_json::from_value::<AIEngineType>(json!({
+            "type": "Ollama",
+            "Url": "http://localhost:11434/api/chat",
+            "Model": "llama3",
+            "Json_mode": true
+        }))
+        .unwrap();
+        let (api, config) = engine.local_model().unwrap();
+        assert_eq!(api, LocalApi::Ollama);
+        assert_eq!(config.model.as_deref(), Some("llama3"));
+        assert_eq!(config.template, ChatTemplate::ChatML);
+        assert!(config.json_mode);
+
+        let engine = serde_json::from_value::<AIEngineType>(json!({
+            "type": "LlamaCpp",
+            "Url": "http://localhost:8080/completion",
+            "Prompt_template": "Vicuna"
+        }))
+        .unwrap();
+        let (api, config) = engine.local_model().unwrap();
+        assert_eq!(api, LocalApi::LlamaCpp);
+        assert_eq!(config.template, ChatTemplate::Vicuna);
+    }
+
+    #[test]
+    fn test_llama_cpp_body() {
+        let client = LocalModelClient::new(
+            LocalApi::LlamaCpp,
+            local_config(json!({
+                "Url": "http://localhost:8080/completion",
+                "Prompt_template": "Alpaca",
+                "Stop": ["\n\n\n"],
+                "Max_tokens": 128,
+                "Json_mode": true
+            })),
+        )
+        .with_sampling(Sampling {
+            temperature: Some(0.5),
+            ..Default::default()
+        });
+
+        let body = client.request_body(&history(), true);
+        assert_eq!(body["prompt"], ChatTemplate::Alpaca.render(&history()));
+        assert_eq!(
+            body["stop"],
+            json!(["### Instruction:", "### Response:", "\n\n\n"])
+        );
+        assert_eq!(body["n_predict"], 128);
+        assert_eq!(body["temperature"], 0.5);
+        assert!(body.get("top_p").is_none());
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["grammar"], JSON_GRAMMAR);
+
+        // own grammar wins
+        let client = LocalModelClient::new(
+            LocalApi::LlamaCpp,
+            local_config(json!({
+                "Url": "http://localhost:8080/completion",
+                "Grammar": "root ::= \"yes\" | \"no\"",
+                "Json_mode": true
+            })),
+        );
+        let body = client.request_body(&history(), false);
+        assert_eq!(body["grammar"], "root ::= \"yes\" | \"no\"");
+        assert!(body.get("n_predict").is_none());
+    }
+
+    #[test]
+    fn test_ollama_body() {
+        let client = LocalModelClient::new(
+            LocalApi::Ollama,
+            local_config(json!({
+                "Url": "http://localhost:11434/api/chat",
+                "Model": "llama3",
+                "Stop": ["User:"],
+                "Max_tokens": 64,
+                "Json_mode": true
+            })),
+        )
+        .with_sampling(Sampling {
+            top_p: Some(0.5),
+            ..Default::default()
+        });
+
+        let body = client.request_body(&history(), false);
+        assert_eq!(body["model"], "llama3");
+        assert_eq!(
+            body["messages"][0],
+            json!({ "role": "system", "content": "Act as a cat" })
+        );
+        assert_eq!(body["messages"][2]["role"], "assistant");
+        assert_eq!(
+            body["options"],
+            json!({ "top_p": 0.5, "num_predict": 64, "stop": ["User:"] })
+        );
+        assert_eq!(body["format"], "json");
+        assert!(body.get("prompt").is_none());
+        // Ollama applies the model template itself
+        assert_eq!(client.stop(), vec!["User:"]);
+    }
+
+    #[test]
+    fn test_parse_part() {
+        let url = json!({ "Url": "http://localhost/" });
+        let llama = LocalModelClient::new(LocalApi::LlamaCpp, local_config(url.clone()));
+        assert_eq!(
+            llama
+                .parse_part(r#"data: {"content": "Meow", "stop": false}"#)
+                .unwrap(),
+            Some(StreamPart {
+                content: "Meow".to_string(),
+                done: false,
+                usage: None
+            })
+        );
+        assert_eq!(llama.parse_part("").unwrap(), None);
+        assert_eq!(
+            llama
+                .parse_part(
+                    r#"data: {"content": "", "stop": true, "tokens_evaluated": 42, "tokens_predicted": 7}"#
+                )
+                .unwrap()
+                .unwrap()
+                .usage,
+            Some(TokenUsage::new(42, 7))
+        );
+        assert!(llama
+            .parse_part(r#"{"error": {"code": 500, "message": "context overflow"}}"#)
+            .is_err());
+
+        let ollama = LocalModelClient::new(LocalApi::Ollama, local_config(url));
+        assert_eq!(
+            ollama
+                .parse_part(r#"{"message": {"role": "assistant", "content": ""}, "done": true}"#)
+                .unwrap(),
+            Some(StreamPart {
+                content: "".to_string(),
+                done: true,
+                usage: None
+            })
+        );
+        assert_eq!(
+            ollama
+                .parse_part(r#"{"done": true, "prompt_eval_count": 26, "eval_count": 290}"#)
+                .unwrap()
+                .unwrap()
+                .usage,
+            Some(TokenUsage::new(26, 290))
+        );
+        assert!(ollama
+            .parse_part(r#"{"error": "model 'llama3' not found"}"#)
+            .is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.ai_engine.engine_type = AIEngineType::Ollama(local_config(json!({
+            "Url": "http://localhost:11434/api/chat",
+            "Grammar": "root ::= \"yes\"",
+            "Max_tokens": 0
+        })));
+
+        let report = config.validate_values();
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec![
+                "AIEngine.Engine_Type.Model",
+                "AIEngine.Engine_Type.Max_tokens"
+            ]
+        );
+        assert_eq!(
+            report.key_paths(Severity::Warning),
+            vec!["AIEngine.Engine_Type.Grammar"]
+        );
+    }
+
+    /// Answers one request with the body split into small chunks
+    async fn serve_once(body: &'static str) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api/chat", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 64 * 1024];
+            let _ = socket.read(&mut buf).await.unwrap();
+            socket
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: application/x-ndjson\r\nConnection: close\r\n\r\n")
+                .await
+                .unwrap();
+            for chunk in body.as_bytes().chunks(7) {
+                socket.write_all(chunk).await.unwrap();
+                socket.flush().await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_streamed_answer() {
+        let url = serve_once(concat!(
+            r#"{"message": {"role": "assistant", "content": "I'm a cat. "}, "done": false}"#,
+            "\n",
+            r#"{"message": {"role": "assistant", "content": "Meow!"}, "done": false}"#,
+            "\n",
+            r#"{"message": {"role": "assistant", "content": ""}, "done": true, "prompt_eval_count": 12, "eval_count": 6}"#,
+            "\n",
+        ))
+        .await;
+        let client = LocalModelClient::new(
+            LocalApi::Ollama,
+            local_config(json!({ "Url": url, "Model": "llama3" })),
+        );
+        let mut model = LocalModel::new(client, "Act as a cat");
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let res = model
+            .process_streamed(Box::new(TestRequest::new("Who are you?")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "I'm a cat. Meow!");
+
+        let mut sentences = vec![];
+        while let Some(chunk) = rx.recv().await {
+            if let AIResponseChunk::Sentence(sentence) = chunk {
+                sentences.push(sentence[&AIResponseType::RawAnswer].clone());
+            }
+        }
+        assert_eq!(sentences.len(), 2);
+        assert_eq!(model.last_usage(), Some(TokenUsage::new(12, 6)));
+
+        assert_eq!(
+            model.history(),
+            &[
+                ChatTurn::new(ChatRole::System, "Act as a cat"),
+                ChatTurn::new(ChatRole::User, "Who are you?"),
+                ChatTurn::new(ChatRole::Assistant, "I'm a cat. Meow!"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streamed_stop() {
+        let url = serve_once(concat!(
+            r#"data: {"content": "I'm a cat.", "stop": false}"#,
+            "\n\n",
+            r#"data: {"content": " Meow</", "stop": false}"#,
+            "\n\n",
+            r#"data: {"content": "s>\nUSER: Hi", "stop": false}"#,
+            "\n\n",
+        ))
+        .await;
+        let client = LocalModelClient::new(
+            LocalApi::LlamaCpp,
+            local_config(json!({ "Url": url, "Prompt_template": "Vicuna" })),
+        );
+        let mut model = LocalModel::new(client, "Act as a cat");
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let res = model
+            .process_streamed(Box::new(TestRequest::new("Who are you?")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "I'm a cat. Meow");
+
+        let mut deltas = String::new();
+        while let Some(chunk) = rx.recv().await {
+            if let AIResponseChunk::Delta(delta) = chunk {
+                deltas.push_str(&delta);
+            }
+        }
+        // the beginning of the stop sequence is not sent
+        assert_eq!(deltas, "I'm a cat. Meow");
+    }
+
+    #[tokio::test]
+    async fn test_service_error() {
+        let server = MockServer::start().await;
+        let client = LocalModelClient::new(
+            LocalApi::Ollama,
+            local_config(json!({ "Url": server.url("/api/chat"), "Model": "llama3" })),
+        );
+
+        let err = client.complete(&history()).await.unwrap_err();
+        match &err {
+            AIError::ServiceError(e) => assert_eq!(e.service, resilience::OLLAMA),
+            e => panic!("unexpected error {e:?}"),
+        }
+        assert_eq!(err.status().map(|s| s.as_u16()), Some(404));
+        assert_eq!(server.requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_request_context() {
+        let server = MockServer::start().await;
+        server.script(
+            "/api/chat",
+            [MockResponse::Raw {
+                status: 200,
+                content_type: "application/json".to_string(),
+                body:
+                    json!({ "message": { "role": "assistant", "content": "Meow" }, "done": true })
+                        .to_string(),
+            }],
+        );
+        let client = LocalModelClient::new(
+            LocalApi::Ollama,
+            local_config(json!({ "Url": server.url("/api/chat"), "Model": "llama3" })),
+        );
+        let mut model = LocalModel::new(client, "Act as a cat");
+
+        let request = MemoryAIRequest::new(
+            Box::new(TestRequest::new("Who am I?")),
+            vec!["Master likes fish".to_string()],
+        );
+        model.process(Box::new(request)).await.unwrap();
+
+        let messages = server.requests()[0].json()["messages"].clone();
+        assert_eq!(
+            messages[1],
+            json!({ "role": "system", "content": "What you remember about Master: Master likes fish" })
+        );
+        assert_eq!(server.requests()[0].last_message(), "Who am I?");
+        // the facts are sent with this request only
+        assert_eq!(
+            model.history(),
+            &[
+                ChatTurn::new(ChatRole::System, "Act as a cat"),
+                ChatTurn::new(ChatRole::User, "Who am I?"),
+                ChatTurn::new(ChatRole::Assistant, "Meow"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+mod tests {
+    use std::path::PathBuf;
+
+    use ai_waifu::{
+        config::MemoryConfig,
+        dispatcher::AIError,
+        memory_store::{lexical_similarity, EmbeddingBackend, MemoryStore},
+    };
+    use async_trait::async_trait;
+
+    fn memory_config(name: &str) -> MemoryConfig {
+        let memory_path = std::env::temp_dir().join(format!("ai-waifu-memory-{name}"));
+        let _ = std::fs::remove_dir_all(&memory_path);
+        MemoryConfig {
+            memory_path,
+            embeddings: None,
+            max_facts: 3,
+            recall_count: 2,
+        }
+    }
+
+    /// Every text containing "cat" is close to each other
+    struct CatEmbeddings;
+
+    #[async_trait]
+    impl EmbeddingBackend for CatEmbeddings {
+        async fn embed(&self, text: &str) -> Result<Vec<f32>, AIError> {
+            if text.contains("cat") || text.contains("kitten") {
+                Ok(vec![1.0, 0.1])
+            } else {
+                Ok(vec![0.1, 1.0])
+            }
+        }
+    }
+
+    struct OfflineEmbeddings;
+
+    #[async_trait]
+    impl EmbeddingBackend for OfflineEmbeddings {
+        async fn embed(&self, _text: &str) -> Result<Vec<f32>, AIError> {
+            Err(AIError::NetworkError)
+        }
+    }
+
+    #[test]
+    fn test_lexical_similarity() {
+        assert_eq!(
+            lexical_similarity("Alice likes cats", "alice LIKES cats!"),
+            1.0
+        );
+        assert_eq!(
+            lexical_similarity("Alice likes cats", "Bob is learning"),
+            0.0
+        );
+        assert!(lexical_similarity("Alice likes cats", "Does Alice like dogs?") > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_lexical_recall() {
+        let memory = MemoryStore::new(memory_config("lexical"));
+        memory.remember("Alice", "Alice likes cats").await;
+        memory.remember("Alice", "Alice lives in Berlin").await;
+        memory.remember("Bob", "Bob is learning Japanese").await;
+
+        assert_eq!(
+            memory.recall("Alice", "What do you know about cats?").await,
+            vec!["Alice likes cats".to_string()]
+        );
+        assert!(memory.recall("Alice", "Japanese lessons").await.is_empty());
+        assert_eq!(memory.recall("Bob", "japanese").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_duplicates_and_limit() {
+        let memory = MemoryStore::new(memory_config("limit"));
+        memory.remember("Alice", "Alice likes cats").await;
+        memory.remember("Alice", "alice likes cats.").await;
+        assert_eq!(memory.facts("Alice").len(), 1);
+
+        for fact in [
+            "Alice has a sister",
+            "Alice plays guitar",
+            "Alice drives a car",
+        ] {
+            memory.remember("Alice", fact).await;
+        }
+        let facts = memory.facts("Alice");
+        assert_eq!(facts.len(), 3);
+        assert_eq!(facts[0].text, "Alice has a sister");
+    }
+
+    #[tokio::test]
+    async fn test_persistence() {
+        let config = memory_config("persistence");
+        let memory_path: PathBuf = config.memory_path.clone();
+
+        MemoryStore::new(config.clone())
+            .remember("Bob", "Bob is learning Japanese")
+            .await;
+        assert!(memory_path.join("Bob.json").exists());
+
+        let memory = MemoryStore::new(config);
+        assert_eq!(memory.recall("Bob", "Japanese").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unsafe_user_name() {
+        let config = memory_config("traversal");
+        let memory_path: PathBuf = config.memory_path.clone();
+
+        let memory = MemoryStore::new(config.clone());
+        memory.remember("../x", "x likes cats").await;
+        memory.remember("/tmp/y", "y likes dogs").await;
+        assert!(!memory_path.parent().unwrap().join("x.json").exists());
+        assert!(memory_path.join("%2E%2E%2Fx.json").exists());
+        assert!(memory_path.join("%2Ftmp%2Fy.json").exists());
+
+        let memory = MemoryStore::new(config);
+        assert_eq!(memory.recall("../x", "cats").await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_embedding_recall() {
+        let memory =
+            MemoryStore::new(memory_config("embeddings")).with_embeddings(Box::new(CatEmbeddings));
+        memory.remember("Alice", "Alice likes cats").await;
+        memory.remember("Alice", "Alice lives in Berlin").await;
+
+        // no common words, but close embeddings
+        assert_eq!(
+            memory.recall("Alice", "Should I get a kitten?").await,
+            vec!["Alice likes cats".to_string()]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embedding_fallback() {
+        let memory =
+            MemoryStore::new(memory_config("offline")).with_embeddings(Box::new(OfflineEmbeddings));
+        memory.remember("Alice", "Alice likes cats").await;
+
+        assert!(memory.facts("Alice")[0].embedding.is_none());
+        assert_eq!(memory.recall("Alice", "cats").await.len(), 1);
+    }
+}
@@ -0,0 +1,126 @@
+mod tests {
+    use std::sync::Arc;
+
+    use ai_waifu::{
+        config::{Config, MiddlewareConfig},
+        dispatcher::{AIResponseChunk, AIResponseType, AIinterface},
+        dummy_ai::DummyAI,
+        middleware::{self, Middleware, NumbersToWords, PromptTemplate},
+        numbers_to_words_ai::NumbersToWordsAI,
+        persona::{Persona, DEFAULT_PERSONA},
+        prompt_template_ai::PromptTemplateAI,
+        utils::test_request::TestRequest,
+    };
+    use tokio::sync::mpsc;
+
+    fn persona() -> Persona {
+        Persona::resolve(&Config::default(), DEFAULT_PERSONA).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_numbers_to_words() {
+        let mut ai = NumbersToWordsAI::new(Box::new(DummyAI));
+
+        let res = ai
+            .process(Box::new(TestRequest::new("I have 42 apples")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "I have 42 apples");
+        assert_eq!(res[&AIResponseType::NoDigits], "I have forty-two apples");
+    }
+
+    #[tokio::test]
+    async fn test_numbers_to_words_streamed() {
+        let mut ai = NumbersToWordsAI::new(Box::new(DummyAI));
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("12 cats")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::NoDigits], "twelve cats");
+
+        match rx.recv().await {
+            Some(AIResponseChunk::Sentence(sentence)) => {
+                assert_eq!(sentence[&AIResponseType::NoDigits], "twelve cats")
+            }
+            other => panic!("Unexpected chunk: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prompt_template() {
+        let mut ai = PromptTemplateAI::new(
+            Box::new(DummyAI),
+            "{user} ({lang}) says: {request}".to_string(),
+        );
+
+        // placeholders in the request itself are kept as is
+        let res = ai
+            .process(Box::new(TestRequest::new("Hi {user}")))
+            .await
+            .unwrap();
+        assert_eq!(
+            res[&AIResponseType::RawAnswer],
+            "Master (auto) says: Hi {user}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chain_order() {
+        let chain: Vec<Arc<dyn Middleware>> = vec![
+            Arc::new(PromptTemplate {
+                template: "A({request})".to_string(),
+            }),
+            Arc::new(PromptTemplate {
+                template: "B({request})".to_string(),
+            }),
+            Arc::new(NumbersToWords),
+        ];
+        let mut ai = middleware::wrap_all(&chain, Box::new(DummyAI), &persona());
+
+        // the first layer is the outermost one
+        let res = ai.process(Box::new(TestRequest::new("12"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "B(A(12))");
+        assert_eq!(res[&AIResponseType::NoDigits], "B(A(twelve))");
+
+        let mut ai = middleware::wrap_all(&[], Box::new(DummyAI), &persona());
+        let res = ai.process(Box::new(TestRequest::new("12"))).await.unwrap();
+        assert_eq!(res.len(), 1);
+    }
+
+    #[test]
+    fn test_config() {
+        let chain: Vec<MiddlewareConfig> = serde_json::from_str(
+            r#"[
+                { "type": "Logging" },
+                { "type": "Translate", "Dest_lang": "ja" },
+                { "type": "PromptTemplate", "Template": "{request}!" }
+            ]"#,
+        )
+        .unwrap();
+        assert!(matches!(chain[0], MiddlewareConfig::Logging));
+        match &chain[1] {
+            MiddlewareConfig::Translate {
+                src_lang,
+                dest_lang,
+                drop_nonconfident_result,
+            } => {
+                assert_eq!(*src_lang, None);
+                assert_eq!(dest_lang.as_deref(), Some("ja"));
+                assert_eq!(*drop_nonconfident_result, 0.55);
+            }
+            other => panic!("Unexpected layer: {:?}", other),
+        }
+
+        let default_chain = MiddlewareConfig::default_chain(true);
+        assert!(matches!(default_chain[0], MiddlewareConfig::Sanitize));
+        assert!(matches!(default_chain[1], MiddlewareConfig::Moderation));
+        assert!(matches!(
+            default_chain[2],
+            MiddlewareConfig::Translate { .. }
+        ));
+        assert!(matches!(default_chain[3], MiddlewareConfig::NumbersToWords));
+        assert_eq!(MiddlewareConfig::default_chain(false).len(), 3);
+    }
+}
@@ -0,0 +1,249 @@
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+    };
+
+    use ai_waifu::{
+        config::{ModerationAction, ModerationConfig, Platform},
+        dispatcher::{AIError, AIRequest, AIResponseChunk, AIResponseType, AIinterface},
+        moderated_ai::ModeratedAI,
+        moderation::{ModerationBackend, Moderator},
+        utils::test_request::TestRequest,
+    };
+    use async_trait::async_trait;
+    use maplit::hashmap;
+    use tokio::sync::mpsc;
+
+    fn moderation_config() -> ModerationConfig {
+        ModerationConfig {
+            banned_words: vec![r"\bheck\b".to_string(), "darn(it)?".to_string()],
+            endpoint: None,
+            default_action: ModerationAction::Mask,
+            actions: hashmap! { Platform::Twitch => ModerationAction::Canned },
+            canned_lines: vec!["No comments.".to_string()],
+            max_regenerations: 2,
+        }
+    }
+
+    /// Answers with the next scripted line, remembers the forgotten turns
+    struct ScriptedAI {
+        answers: Vec<&'static str>,
+        forgotten: Arc<Mutex<usize>>,
+    }
+
+    #[async_trait]
+    impl AIinterface for ScriptedAI {
+        async fn process(
+            &mut self,
+            _request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            Ok(hashmap! {
+                AIResponseType::RawAnswer => self.answers.remove(0).to_string(),
+            })
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn forget_last_turn(&mut self) -> Result<(), AIError> {
+            *self.forgotten.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    /// Flags every text about violence
+    struct ViolenceBackend;
+
+    #[async_trait]
+    impl ModerationBackend for ViolenceBackend {
+        async fn check(&self, text: &str) -> Result<Vec<String>, AIError> {
+            if text.contains("fight") {
+                Ok(vec!["violence".to_string()])
+            } else {
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Counts the texts sent to the service
+    struct CountingBackend(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl ModerationBackend for CountingBackend {
+        async fn check(&self, text: &str) -> Result<Vec<String>, AIError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            ViolenceBackend.check(text).await
+        }
+    }
+
+    fn moderated(
+        answers: Vec<&'static str>,
+        action: ModerationAction,
+    ) -> (ModeratedAI, Arc<Mutex<usize>>) {
+        let forgotten = Arc::new(Mutex::new(0));
+        let moderator = Moderator::new(&moderation_config())
+            .unwrap()
+            .with_backend(Box::new(ViolenceBackend));
+        let ai = ScriptedAI {
+            answers,
+            forgotten: forgotten.clone(),
+        };
+        (
+            ModeratedAI::new(Box::new(ai), Arc::new(moderator), action),
+            forgotten,
+        )
+    }
+
+    #[test]
+    fn test_platform_action() {
+        let config = moderation_config();
+        assert_eq!(config.action(Platform::Twitch), ModerationAction::Canned);
+        assert_eq!(config.action(Platform::Discord), ModerationAction::Mask);
+    }
+
+    #[tokio::test]
+    async fn test_check_and_mask() {
+        let moderator = Moderator::new(&moderation_config()).unwrap();
+
+        let verdict = moderator.check("What the HECK, darnit!").await;
+        assert_eq!(verdict.banned_words, vec!["HECK", "darnit"]);
+        assert!(verdict.is_flagged() && verdict.is_maskable());
+        assert_eq!(
+            moderator.mask("What the HECK, darnit!"),
+            "What the ****, ******!"
+        );
+
+        // whole words only
+        assert!(!moderator.check("Checkmate").await.is_flagged());
+        assert!(Moderator::new(&ModerationConfig {
+            banned_words: vec!["(".to_string()],
+            ..moderation_config()
+        })
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mask() {
+        let (mut ai, forgotten) = moderated(vec!["Oh heck, hello!"], ModerationAction::Mask);
+
+        let res = ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Oh ****, hello!");
+        assert_eq!(*forgotten.lock().unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_mask_unmaskable() {
+        // masking can't hide what the service flagged
+        let (mut ai, forgotten) = moderated(vec!["Let's fight!"], ModerationAction::Mask);
+
+        let res = ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "No comments.");
+        assert_eq!(*forgotten.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_regenerate() {
+        let (mut ai, forgotten) = moderated(
+            vec!["Darn it!", "Let's fight!", "Hello!"],
+            ModerationAction::Regenerate,
+        );
+
+        let res = ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Hello!");
+        assert_eq!(*forgotten.lock().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_exhausted() {
+        let (mut ai, forgotten) = moderated(
+            vec!["Darn it!", "Heck!", "Heck no!"],
+            ModerationAction::Regenerate,
+        );
+
+        let res = ai.process(Box::new(TestRequest::new("Hi"))).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "No comments.");
+        assert_eq!(*forgotten.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_canned() {
+        let (mut ai, _) = moderated(vec!["Let's fight!"], ModerationAction::Canned);
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("Hi")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "No comments.");
+
+        let mut sentences = vec![];
+        while let Some(chunk) = rx.recv().await {
+            if let AIResponseChunk::Sentence(sentence) = chunk {
+                sentences.push(sentence[&AIResponseType::RawAnswer].clone());
+            }
+        }
+        assert_eq!(sentences, vec!["No comments."]);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_regenerate() {
+        let (mut ai, _) = moderated(vec!["Heck!", "Hello!"], ModerationAction::Regenerate);
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("Hi")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Hello!");
+
+        // the rejected answer is never sent
+        let mut sentences = vec![];
+        while let Some(chunk) = rx.recv().await {
+            if let AIResponseChunk::Sentence(sentence) = chunk {
+                sentences.push(sentence[&AIResponseType::RawAnswer].clone());
+            }
+        }
+        assert_eq!(sentences, vec!["Hello!"]);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_mask_checked_once() {
+        let checks = Arc::new(AtomicUsize::new(0));
+        let moderator = Moderator::new(&moderation_config())
+            .unwrap()
+            .with_backend(Box::new(CountingBackend(checks.clone())));
+        let ai = ScriptedAI {
+            answers: vec!["Oh heck, hello!"],
+            forgotten: Arc::new(Mutex::new(0)),
+        };
+        let mut ai = ModeratedAI::new(Box::new(ai), Arc::new(moderator), ModerationAction::Mask);
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("Hi")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Oh ****, hello!");
+        while let Some(chunk) = rx.recv().await {
+            if let AIResponseChunk::Sentence(sentence) = chunk {
+                assert_eq!(sentence[&AIResponseType::RawAnswer], "Oh ****, hello!");
+            }
+        }
+        // the sentence is the only checked text, the whole answer is not sent again
+        assert_eq!(checks.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,81 @@
+mod tests {
+    use ai_waifu::{
+        config::{Config, PersonaConfig, TTSConfig},
+        persona::{Persona, DEFAULT_PERSONA},
+    };
+
+    fn persona_config(prompt: &str) -> PersonaConfig {
+        PersonaConfig {
+            initial_prompt: prompt.to_string(),
+            temperature: Some(1.2),
+            top_p: None,
+            presence_penalty: None,
+            frequency_penalty: None,
+            tts_config: None,
+            answer_lang: Some("ru".to_string()),
+            busy_messages: None,
+        }
+    }
+
+    fn config() -> Config {
+        let mut config = Config {
+            initial_prompt: "main prompt".to_string(),
+            busy_messages: vec!["busy".to_string()],
+            ..Default::default()
+        };
+        config
+            .personas
+            .insert("cat".to_string(), persona_config("cat prompt"));
+        config
+            .personas
+            .insert("anna".to_string(), persona_config("anna prompt"));
+        config
+    }
+
+    #[test]
+    fn test_default_persona() {
+        let config = config();
+        let persona = Persona::resolve(&config, DEFAULT_PERSONA).unwrap();
+
+        assert_eq!(persona.initial_prompt, "main prompt");
+        assert_eq!(persona.temperature, None);
+        assert_eq!(
+            persona.answer_lang,
+            config.deeplx_translate_config.dest_lang
+        );
+        assert_eq!(persona.busy_message(), "busy");
+    }
+
+    #[test]
+    fn test_persona_overrides() {
+        let config = config();
+        let persona = Persona::resolve(&config, "cat").unwrap();
+
+        assert_eq!(persona.initial_prompt, "cat prompt");
+        assert_eq!(persona.temperature, Some(1.2));
+        assert_eq!(persona.answer_lang, "ru");
+        // missing settings are taken from the main config
+        assert_eq!(persona.busy_messages, vec!["busy".to_string()]);
+        assert!(matches!(persona.tts_config, TTSConfig::Disabled));
+    }
+
+    #[test]
+    fn test_default_overridden() {
+        let mut config = config();
+        config
+            .personas
+            .insert(DEFAULT_PERSONA.to_string(), persona_config("new default"));
+
+        let persona = Persona::resolve(&config, DEFAULT_PERSONA).unwrap();
+        assert_eq!(persona.initial_prompt, "new default");
+    }
+
+    #[test]
+    fn test_persona_names() {
+        let config = config();
+
+        assert_eq!(Persona::names(&config), vec!["default", "anna", "cat"]);
+        assert!(Persona::resolve(&config, "dog").is_none());
+        assert_eq!(Persona::all(&config).len(), 3);
+    }
+}
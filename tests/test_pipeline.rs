@@ -0,0 +1,152 @@
+mod tests {
+    use std::time::Duration;
+
+    use ai_waifu::{
+        config::{AIEngineType, Config, Platform, TTSConfig},
+        dispatcher::AIResponseType,
+        errors::{ErrorInfo, ErrorKind, TTSError},
+        resilience,
+        tts_engine::TTSEngine,
+        utils::{
+            mock_server::{
+                silent_wav, MockResponse, MockServer, CHAT_COMPLETIONS, DEEPLX, TRANSCRIBE, TTS,
+            },
+            test_request::TestRequest,
+        },
+        whisper_voice_recognize::OpenAIWhisperVoice2Txt,
+    };
+    use serde_json::json;
+
+    /// All the services of the bot are served by `server`
+    fn config(server: &MockServer) -> Config {
+        let mut config = Config::default();
+        config.ai_engine.engine_type = AIEngineType::LLaMa {
+            api_url: server.url(CHAT_COMPLETIONS),
+            model: None,
+        };
+        config.deeplx_translate_config.src_lang = "ru".to_string();
+        config.deeplx_translate_config.dest_lang = "ru".to_string();
+        config.deeplx_translate_config.url = server.url(DEEPLX);
+        config.stt_config.voice2txt_url = server.url(TRANSCRIBE);
+        config.tts_config = TTSConfig::SilerioTTSConfig {
+            tts_service_url: server.url(TTS),
+            voice_character: None,
+        };
+        config.resilience = serde_json::from_value(json!({
+            "Timeout": 0.5,
+            "Initial_backoff": 0.01,
+            "Max_backoff": 0.01,
+            "Breaker_threshold": 0
+        }))
+        .unwrap();
+        resilience::configure(&config.resilience);
+        config
+    }
+
+    #[tokio::test]
+    async fn test_voice_to_voice() {
+        let server = MockServer::start().await;
+        let config = config(&server);
+        server.script(
+            TRANSCRIBE,
+            [MockResponse::Transcription {
+                text: "Привет".to_string(),
+                language: "ru".to_string(),
+            }],
+        );
+        server.script(DEEPLX, [MockResponse::Translation("Hello".to_string())]);
+
+        let stt = OpenAIWhisperVoice2Txt::new(config.stt_config.voice2txt_url.clone());
+        let (text, lang) = stt.recognize(silent_wav(500)).await.unwrap();
+        assert_eq!((text.as_str(), lang.as_str()), ("Привет", "ru"));
+
+        let dispatcher = ai_waifu::create_ai_dispatcher(&config, Platform::Interactive);
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new(&text)))
+            .await
+            .unwrap();
+        // the mock translator echoes the answer
+        assert_eq!(res[&AIResponseType::Translated].trim(), "You said: Hello");
+
+        let completions = server.requests_to(CHAT_COMPLETIONS);
+        assert_eq!(completions.len(), 1);
+        assert_eq!(completions[0].last_message(), "Hello");
+
+        let tts = TTSEngine::with_config(&config.tts_config);
+        let wav = tts
+            .say(res[&AIResponseType::Translated].clone())
+            .await
+            .unwrap();
+        assert!(wav.get_ref().starts_with(b"RIFF"));
+        assert_eq!(
+            server.requests_to(TTS)[0].text(),
+            res[&AIResponseType::Translated]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_completion_retry() {
+        let server = MockServer::start().await;
+        let config = config(&server);
+        server.script(
+            CHAT_COMPLETIONS,
+            [
+                MockResponse::Status(503),
+                MockResponse::Completion("I'm back.".to_string()),
+            ],
+        );
+
+        let mut ai = ai_waifu::create_streamed_ai(&config);
+        let res = ai
+            .process(Box::new(TestRequest::new("Are you there?")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "I'm back.");
+
+        let completions = server.requests_to(CHAT_COMPLETIONS);
+        assert_eq!(completions.len(), 2);
+        assert!(!completions[1].is_stream());
+    }
+
+    #[tokio::test]
+    async fn test_translation_faults() {
+        let server = MockServer::start().await;
+        let config = config(&server);
+        server.script(
+            DEEPLX,
+            [
+                MockResponse::Disconnect,
+                MockResponse::Status(502),
+                MockResponse::Translation("Hello".to_string()),
+            ],
+        );
+
+        let dispatcher = ai_waifu::create_ai_dispatcher(&config, Platform::Discord);
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Привет")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer].trim(), "You said: Hello");
+        assert_eq!(server.requests_to(CHAT_COMPLETIONS).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_tts_timeout() {
+        let server = MockServer::start().await;
+        let config = config(&server);
+        let slow = || {
+            MockResponse::Delayed(
+                Duration::from_secs(5),
+                Box::new(MockResponse::Audio(silent_wav(100))),
+            )
+        };
+        server.script(TTS, [slow(), slow(), slow()]);
+
+        let tts = TTSEngine::with_config(&config.tts_config);
+        match tts.say("Hi").await {
+            Err(e @ TTSError::Service(_)) => assert_eq!(e.kind(), ErrorKind::Unavailable),
+            Ok(_) => panic!("TTS must time out"),
+        }
+        assert_eq!(server.requests_to(TTS).len(), 3);
+    }
+}
@@ -0,0 +1,103 @@
+mod tests {
+    use ai_waifu::{
+        config::{QueueOverflowPolicy, RequestQueueConfig},
+        dispatcher::{AIError, AIResponseChunk},
+        request_queue::{PendingRequest, RequestQueue},
+        utils::test_request::TestRequest,
+    };
+    use tokio::sync::mpsc::{self, Receiver};
+
+    fn queue(depth: usize, overflow_policy: QueueOverflowPolicy) -> RequestQueue {
+        RequestQueue::new(RequestQueueConfig {
+            depth,
+            overflow_policy,
+            ..Default::default()
+        })
+    }
+
+    fn pending(text: &str) -> (PendingRequest, Receiver<Result<AIResponseChunk, AIError>>) {
+        let (tx, rx) = mpsc::channel(8);
+        let req = TestRequest::new(text);
+        (PendingRequest::new(Box::new(req), tx), rx)
+    }
+
+    fn last_position(rx: &mut Receiver<Result<AIResponseChunk, AIError>>) -> Option<usize> {
+        let mut position = None;
+        while let Ok(chunk) = rx.try_recv() {
+            if let Ok(AIResponseChunk::Queued(p)) = chunk {
+                position = Some(p);
+            }
+        }
+        position
+    }
+
+    #[test]
+    fn test_queue_positions() {
+        let mut queue = queue(2, QueueOverflowPolicy::DropNewest);
+
+        let (first, _) = pending("first");
+        let (second, mut second_rx) = pending("second");
+        let (third, mut third_rx) = pending("third");
+
+        assert!(queue.push(first).unwrap().is_some());
+        assert!(queue.push(second).unwrap().is_none());
+        assert!(queue.push(third).unwrap().is_none());
+        assert_eq!(last_position(&mut second_rx), Some(1));
+        assert_eq!(last_position(&mut third_rx), Some(2));
+
+        let (request, _) = queue.pop_next().unwrap().into_parts();
+        assert_eq!(request.request(), "second");
+        assert_eq!(last_position(&mut third_rx), Some(1));
+
+        let (request, _) = queue.pop_next().unwrap().into_parts();
+        assert_eq!(request.request(), "third");
+        assert!(queue.pop_next().is_none());
+
+        // channel is idle again
+        let (fourth, _) = pending("fourth");
+        assert!(queue.push(fourth).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_queue_drop_newest() {
+        let mut queue = queue(1, QueueOverflowPolicy::DropNewest);
+
+        assert!(queue.push(pending("first").0).unwrap().is_some());
+        assert!(queue.push(pending("second").0).unwrap().is_none());
+        assert!(matches!(queue.push(pending("third").0), Err(AIError::Busy)));
+        assert_eq!(queue.len(), 1);
+    }
+
+    #[test]
+    fn test_queue_drop_oldest() {
+        let mut queue = queue(1, QueueOverflowPolicy::DropOldest);
+
+        let (second, mut second_rx) = pending("second");
+        assert!(queue.push(pending("first").0).unwrap().is_some());
+        assert!(queue.push(second).unwrap().is_none());
+        assert!(queue.push(pending("third").0).unwrap().is_none());
+
+        let mut dropped = false;
+        while let Ok(chunk) = second_rx.try_recv() {
+            dropped |= matches!(chunk, Err(AIError::Busy));
+        }
+        assert!(dropped);
+
+        let (request, _) = queue.pop_next().unwrap().into_parts();
+        assert_eq!(request.request(), "third");
+    }
+
+    #[test]
+    fn test_queue_merge() {
+        let mut queue = queue(1, QueueOverflowPolicy::Merge);
+
+        assert!(queue.push(pending("first").0).unwrap().is_some());
+        assert!(queue.push(pending("second").0).unwrap().is_none());
+        assert!(queue.push(pending("third").0).unwrap().is_none());
+        assert_eq!(queue.len(), 1);
+
+        let (request, listeners) = queue.pop_next().unwrap().into_parts();
+        assert_eq!(request.request(), "second\nthird");
+        assert_eq!(listeners.len(), 2);
+    }
+}
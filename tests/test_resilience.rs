@@ -0,0 +1,259 @@
+mod tests {
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        time::Duration,
+    };
+
+    use ai_waifu::{
+        config::{Config, ResilienceConfig, ServicePolicy},
+        config_validation::Severity,
+        dispatcher::AIError,
+        resilience::{self, BreakerState, Failure, Service},
+        silerio_tts::SilerioTTS,
+        utils::mock_server::{MockResponse, MockServer, TTS},
+    };
+    use bytes::Bytes;
+    use reqwest::StatusCode;
+    use serde_json::json;
+
+    fn policy(value: serde_json::Value) -> ServicePolicy {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Fails with `failure` the first `failures` attempts
+    async fn flaky(
+        service: &Service,
+        attempts: &AtomicU32,
+        failures: u32,
+        failure: Failure,
+    ) -> Result<u32, Failure> {
+        service
+            .call(|| async {
+                let attempt = attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                if attempt <= failures {
+                    Err(failure.clone())
+                } else {
+                    Ok(attempt)
+                }
+            })
+            .await
+    }
+
+    #[test]
+    fn test_failures() {
+        assert!(Failure::from_status(StatusCode::TOO_MANY_REQUESTS, "slow down").transient);
+        assert!(Failure::from_status(StatusCode::BAD_GATEWAY, "").transient);
+        assert!(!Failure::from_status(StatusCode::BAD_REQUEST, "").transient);
+        assert!(!Failure::from_status(StatusCode::UNAUTHORIZED, "").transient);
+
+        assert_eq!(
+            Failure::from_status(StatusCode::BAD_GATEWAY, "").status,
+            Some(StatusCode::BAD_GATEWAY)
+        );
+        assert_eq!(Failure::transient("reset by peer").status, None);
+
+        let service = Service::new("test", ServicePolicy::default());
+        match AIError::from(service.error(Failure::permanent("bad key"))) {
+            AIError::ServiceError(e) => assert_eq!(e.service, "test"),
+            e => panic!("Service error expected, got {e:?}"),
+        }
+    }
+
+    #[test]
+    fn test_backoff() {
+        let service = Service::new(
+            "test",
+            policy(json!({ "Initial_backoff": 1.0, "Max_backoff": 4.0 })),
+        );
+        for _ in 0..20 {
+            let first = service.backoff(0).as_secs_f32();
+            assert!((0.5..=1.0).contains(&first), "{first}");
+            let second = service.backoff(1).as_secs_f32();
+            assert!((1.0..=2.0).contains(&second), "{second}");
+            let capped = service.backoff(10).as_secs_f32();
+            assert!((2.0..=4.0).contains(&capped), "{capped}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let fast = json!({ "Max_retries": 2, "Initial_backoff": 0.001, "Max_backoff": 0.01 });
+
+        let service = Service::new("test", policy(fast));
+        let attempts = AtomicU32::new(0);
+        let res = flaky(&service, &attempts, 2, Failure::transient("503")).await;
+        assert_eq!(res, Ok(3));
+
+        let attempts = AtomicU32::new(0);
+        let res = flaky(&service, &attempts, 5, Failure::transient("503")).await;
+        assert_eq!(res, Err(Failure::transient("503")));
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+
+        // the server answered, asking again won't help
+        let attempts = AtomicU32::new(0);
+        let res = flaky(&service, &attempts, 5, Failure::permanent("400")).await;
+        assert_eq!(res, Err(Failure::permanent("400")));
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let service = Service::new("test", policy(json!({ "Timeout": 0.05, "Max_retries": 0 })));
+        let res = service
+            .call(|| async {
+                tokio::time::sleep(Duration::from_secs(10)).await;
+                Ok(())
+            })
+            .await;
+        assert_eq!(res, Err(Failure::transient("test timed out")));
+    }
+
+    #[tokio::test]
+    async fn test_circuit_breaker() {
+        let service = Service::new(
+            "test",
+            policy(json!({
+                "Max_retries": 0,
+                "Breaker_threshold": 2,
+                "Breaker_cooldown": 0.05
+            })),
+        );
+        let attempts = AtomicU32::new(0);
+        for _ in 0..2 {
+            assert!(flaky(&service, &attempts, 2, Failure::transient("503"))
+                .await
+                .is_err());
+        }
+        assert!(matches!(
+            service.breaker().state(),
+            BreakerState::Open { .. }
+        ));
+
+        // fails fast without calling the service
+        let res = flaky(&service, &attempts, 2, Failure::transient("503")).await;
+        assert!(res.unwrap_err().message.contains("circuit breaker is open"));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+        // the trial call after the cooldown closes it
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            flaky(&service, &attempts, 2, Failure::transient("503")).await,
+            Ok(3)
+        );
+        assert_eq!(
+            service.breaker().state(),
+            BreakerState::Closed { failures: 0 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_trial() {
+        let service = Service::new(
+            "test",
+            policy(json!({
+                "Max_retries": 0,
+                "Breaker_threshold": 1,
+                "Breaker_cooldown": 0.05
+            })),
+        );
+        let attempts = AtomicU32::new(0);
+        assert!(flaky(&service, &attempts, 1, Failure::transient("503"))
+            .await
+            .is_err());
+
+        // the trial call is cancelled by the caller, e.g. by Backend_timeout
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let trial = service.call(|| async {
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            Ok(0)
+        });
+        assert!(tokio::time::timeout(Duration::from_millis(20), trial)
+            .await
+            .is_err());
+        assert!(matches!(
+            service.breaker().state(),
+            BreakerState::Open { .. }
+        ));
+
+        // the next trial after the cooldown gets through
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(
+            flaky(&service, &attempts, 1, Failure::transient("503")).await,
+            Ok(2)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_breaker_shared() {
+        let policy = policy(json!({ "Max_retries": 0, "Breaker_threshold": 1 }));
+        resilience::configure(&ResilienceConfig {
+            default: ServicePolicy::default(),
+            services: [(resilience::LLAMA.to_string(), policy)].into(),
+        });
+
+        let attempts = AtomicU32::new(0);
+        let first = Service::get(resilience::LLAMA);
+        assert!(flaky(&first, &attempts, 1, Failure::transient("503"))
+            .await
+            .is_err());
+        // another client of the same service
+        let second = Service::get(resilience::LLAMA);
+        assert!(matches!(
+            second.breaker().state(),
+            BreakerState::Open { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tts_retry() {
+        let policy = policy(json!({ "Initial_backoff": 0.01, "Max_backoff": 0.01 }));
+        resilience::configure(&ResilienceConfig {
+            default: ServicePolicy::default(),
+            services: [(resilience::SILERO_TTS.to_string(), policy)].into(),
+        });
+
+        let server = MockServer::start().await;
+        server.script(
+            TTS,
+            [
+                MockResponse::Status(503),
+                MockResponse::Audio(Bytes::from_static(b"RIFF")),
+            ],
+        );
+        let tts = SilerioTTS::new(server.url(TTS), None);
+        let wav = tts.say("Hi").await.unwrap();
+        assert_eq!(wav.get_ref().as_ref(), b"RIFF");
+        assert_eq!(server.requests_to(TTS).len(), 2);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.resilience = serde_json::from_value(json!({
+            "Timeout": 0,
+            "Services": {
+                "openai": { "Max_retries": 5, "Max_backoff": 0.1 },
+                "deepl": {}
+            }
+        }))
+        .unwrap();
+        assert_eq!(config.resilience.default.max_retries, 2);
+        assert_eq!(config.resilience.services["openai"].max_retries, 5);
+
+        let report = config.validate_values();
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec![
+                "Resilience.Timeout",
+                "Resilience.Services.openai.Max_backoff"
+            ]
+        );
+        assert_eq!(
+            report.key_paths(Severity::Warning),
+            vec!["Resilience.Services.deepl"]
+        );
+    }
+}
@@ -0,0 +1,183 @@
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use ai_waifu::{
+        config::{Config, MiddlewareConfig},
+        config_validation::Severity,
+        dispatcher::*,
+        dummy_ai::DummyAI,
+        sanitized_ai::SanitizedAI,
+        sanitizer::*,
+        utils::test_request::TestRequest,
+    };
+    use async_trait::async_trait;
+    use maplit::hashmap;
+    use tokio::sync::mpsc;
+
+    /// Streams the parts of the request separated by `|` as sentences
+    struct SentencesAI;
+
+    #[async_trait]
+    impl AIinterface for SentencesAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            Ok(hashmap! {
+                AIResponseType::RawAnswer => request.request().replace('|', " "),
+            })
+        }
+
+        async fn process_streamed(
+            &mut self,
+            request: Box<dyn AIRequest>,
+            chunks: mpsc::Sender<AIResponseChunk>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            for sentence in request.request().split('|') {
+                let _ = chunks
+                    .send(AIResponseChunk::Sentence(hashmap! {
+                        AIResponseType::RawAnswer => sentence.to_string(),
+                    }))
+                    .await;
+            }
+            self.process(request).await
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_actions_and_emoji() {
+        let res = sanitize("*smiles* Hello **there**! Check https://example.com 😊 [happy]");
+        assert_eq!(res.spoken, "Hello there! Check");
+        assert_eq!(
+            res.display,
+            "*smiles* Hello **there**! Check https://example.com 😊"
+        );
+        assert_eq!(res.actions, vec!["smiles", "happy"]);
+    }
+
+    #[test]
+    fn test_markdown() {
+        let res = sanitize("# Title\n- first item\n- see [docs](https://docs.rs) for `code`");
+        assert_eq!(res.spoken, "Title first item see docs for code");
+        assert_eq!(
+            res.display,
+            "# Title\n- first item\n- see [docs](https://docs.rs) for `code`"
+        );
+        assert!(res.actions.is_empty());
+
+        // bullets are not actions
+        let res = sanitize("* one\n* two");
+        assert_eq!(res.spoken, "one two");
+        assert!(res.actions.is_empty());
+    }
+
+    #[test]
+    fn test_code_block() {
+        let res = sanitize("Try this:\n```rust\nlet x = [1];\n```\nIt works.");
+        assert_eq!(res.spoken, "Try this: It works.");
+        assert_eq!(
+            res.display,
+            "Try this:\n```rust\nlet x = [1];\n```\nIt works."
+        );
+        assert!(res.actions.is_empty());
+    }
+
+    #[test]
+    fn test_sentences() {
+        let mut sanitizer = Sanitizer::new();
+
+        let res = sanitizer.push("*She smiles.");
+        assert_eq!(res.spoken, "");
+        assert!(res.actions.is_empty());
+        let res = sanitizer.push("Then waves.* Hi!");
+        assert_eq!(res.spoken, "Hi!");
+        assert_eq!(res.actions, vec!["She smiles. Then waves."]);
+
+        let res = sanitizer.push("Code: ```let x = 1;");
+        assert_eq!(res.spoken, "Code:");
+        assert_eq!(res.display, "Code: ```let x = 1;");
+        let res = sanitizer.push("let y = 2;``` Done.");
+        assert_eq!(res.spoken, "Done.");
+        assert_eq!(res.display, "let y = 2;``` Done.");
+    }
+
+    #[test]
+    fn test_variant_fallbacks() {
+        let answer = hashmap! {
+            AIResponseType::RawAnswer => "I have 2 cats".to_string(),
+            AIResponseType::NoDigits => "I have two cats".to_string(),
+        };
+        assert_eq!(spoken_text(&answer), "I have two cats");
+        assert_eq!(display_text(&answer), "I have two cats");
+        assert!(actions(&answer).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sanitized_ai() {
+        let mut ai = SanitizedAI::new(Box::new(DummyAI));
+
+        let res = ai
+            .process(Box::new(TestRequest::new("*waves* Hi [happy]")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "*waves* Hi [happy]");
+        assert_eq!(spoken_text(&res), "Hi");
+        assert_eq!(display_text(&res), "*waves* Hi");
+        assert_eq!(actions(&res), vec!["waves", "happy"]);
+    }
+
+    #[tokio::test]
+    async fn test_sanitized_ai_streamed() {
+        let mut ai = SanitizedAI::new(Box::new(SentencesAI));
+
+        let (tx, mut rx) = mpsc::channel(16);
+        let res = ai
+            .process_streamed(
+                Box::new(TestRequest::new("*She smiles.|Then waves.* Hi!")),
+                tx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(spoken_text(&res), "Hi!");
+
+        let mut sentences = vec![];
+        while let Some(AIResponseChunk::Sentence(sentence)) = rx.recv().await {
+            sentences.push(sentence);
+        }
+        assert_eq!(spoken_text(&sentences[0]), "");
+        assert_eq!(display_text(&sentences[0]), "*She smiles.");
+        assert_eq!(spoken_text(&sentences[1]), "Hi!");
+        assert_eq!(actions(&sentences[1]), vec!["She smiles. Then waves."]);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.middleware = Some(vec![
+            MiddlewareConfig::NumbersToWords,
+            MiddlewareConfig::Sanitize,
+        ]);
+
+        let report = config.validate_values();
+        assert_eq!(report.key_paths(Severity::Warning), vec!["Middleware[1]"]);
+
+        config.middleware = None;
+        let report = config.validate_values();
+        assert!(report.key_paths(Severity::Warning).is_empty());
+    }
+}
@@ -0,0 +1,111 @@
+mod tests {
+    use ai_waifu::{
+        config::{AIEngineType, Config},
+        config_loader::{ConfigError, REDACTED},
+        secret::{Secret, SecretSource},
+    };
+    use serde_json::json;
+
+    fn config_with_token(token: serde_json::Value) -> Result<Config, ConfigError> {
+        Config::from_value(json!({
+            "AIEngine": {
+                "Engine_Type": { "type": "ChatGPT", "OpenAI_Token": token }
+            },
+            "AI_initial_prompt": "Act as japan pop-idol",
+            "Discord_Config": { "Discord_Token": token, "Discord_channel_whitelist": [] },
+            "DeepLx_Translate_Config": { "Answer_lang": "en" },
+            "TTS_Config": { "type": "Disabled" },
+            "DisplayRawResp": false,
+            "Busy_messages": ["Busy"],
+            "STT_Config": {
+                "Minimal_audio_fragment_length": 1.25,
+                "Maximal_audio_fragment_length": 15.0
+            },
+            "Moderation": { "Endpoint": { "Token": token } }
+        }))
+    }
+
+    fn openai_token(config: &Config) -> &str {
+        match &config.ai_engine.engine_type {
+            AIEngineType::ChatGPT { openai_token, .. } => openai_token.expose(),
+            _ => panic!("Unexpected engine type"),
+        }
+    }
+
+    #[test]
+    fn test_redacted() {
+        let secret = Secret::new("sk-123");
+        assert_eq!(secret.expose(), "sk-123");
+        assert_eq!(format!("{secret:?}"), format!("Secret({REDACTED})"));
+        assert_eq!(secret.to_string(), REDACTED);
+        assert!(!format!("{:?}", Some(secret)).contains("sk-123"));
+    }
+
+    #[test]
+    fn test_sources() {
+        assert_eq!(
+            SecretSource::from_value(json!("sk-123")),
+            Ok(SecretSource::Value("sk-123".to_string()))
+        );
+        assert_eq!(
+            SecretSource::from_value(json!({ "env": "OPENAI_API_KEY" })),
+            Ok(SecretSource::Env("OPENAI_API_KEY".to_string()))
+        );
+        assert_eq!(
+            SecretSource::from_value(json!({ "file": "/run/secrets/x" })),
+            Ok(SecretSource::File("/run/secrets/x".into()))
+        );
+        assert_eq!(
+            SecretSource::from_value(json!({ "command": "pass show openai" })),
+            Ok(SecretSource::Command("pass show openai".to_string()))
+        );
+
+        assert!(SecretSource::from_value(json!({ "vault": "x" })).is_err());
+        assert!(SecretSource::from_value(json!({ "env": "A", "file": "b" })).is_err());
+        assert!(SecretSource::from_value(json!({ "env": 1 })).is_err());
+        assert!(SecretSource::from_value(json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_plain_token() {
+        let config = config_with_token(json!("sk-plain")).unwrap();
+        assert_eq!(openai_token(&config), "sk-plain");
+        assert_eq!(config.discord_config.discord_token.expose(), "sk-plain");
+    }
+
+    #[test]
+    fn test_env_token() {
+        std::env::set_var("AI_WAIFU_TEST_SECRET_TOKEN", "sk-env");
+        let config = config_with_token(json!({ "env": "AI_WAIFU_TEST_SECRET_TOKEN" })).unwrap();
+        assert_eq!(openai_token(&config), "sk-env");
+        let endpoint = config.moderation.unwrap().endpoint.unwrap();
+        assert_eq!(endpoint.token.unwrap().expose(), "sk-env");
+
+        let e = config_with_token(json!({ "env": "AI_WAIFU_TEST_SECRET_MISSING" }))
+            .err()
+            .unwrap();
+        assert!(e.to_string().contains("AI_WAIFU_TEST_SECRET_MISSING"));
+    }
+
+    #[test]
+    fn test_file_token() {
+        let path = std::env::temp_dir().join("ai-waifu-test-secret");
+        std::fs::write(&path, "sk-file\n").unwrap();
+        let config = config_with_token(json!({ "file": path })).unwrap();
+        assert_eq!(openai_token(&config), "sk-file");
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(config_with_token(json!({ "file": path })).is_err());
+    }
+
+    #[test]
+    fn test_command_token() {
+        let config = config_with_token(json!({ "command": "echo sk-command" })).unwrap();
+        assert_eq!(openai_token(&config), "sk-command");
+
+        let e = SecretSource::Command("exit 3".to_string())
+            .resolve()
+            .unwrap_err();
+        assert!(e.contains("failed"));
+    }
+}
@@ -0,0 +1,40 @@
+mod tests {
+    use ai_waifu::sentence_splitter::SentenceSplitter;
+
+    fn split(fragments: &[&str]) -> Vec<String> {
+        let mut splitter = SentenceSplitter::new();
+        let mut sentences = vec![];
+        for fragment in fragments {
+            sentences.extend(splitter.push(fragment));
+        }
+        sentences.extend(splitter.finish());
+        sentences
+    }
+
+    #[test]
+    fn test_split_deltas() {
+        let sentences = split(&["Hel", "lo there! How", " are you", "?", " Fine"]);
+        assert_eq!(sentences, vec!["Hello there!", "How are you?", "Fine"]);
+    }
+
+    #[test]
+    fn test_split_numbers() {
+        let sentences = split(&["I have 3", ".5 apples. Visit example", ".com now."]);
+        assert_eq!(
+            sentences,
+            vec!["I have 3.5 apples.", "Visit example.com now."]
+        );
+    }
+
+    #[test]
+    fn test_split_quotes_and_newlines() {
+        let sentences = split(&["She said: \"Hi!\"\nThe end"]);
+        assert_eq!(sentences, vec!["She said: \"Hi!\"", "The end"]);
+    }
+
+    #[test]
+    fn test_split_japanese() {
+        let sentences = split(&["こんにちは。元気", "ですか？"]);
+        assert_eq!(sentences, vec!["こんにちは。", "元気ですか？"]);
+    }
+}
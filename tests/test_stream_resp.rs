@@ -1,24 +1,56 @@
 mod tests {
-    use ai_waifu::{config::Config, utils::test_request::TestRequest};
-    use reqwest::Url;
+    use ai_waifu::{
+        config::Config,
+        dispatcher::{AIResponseChunk, AIResponseType},
+        utils::{
+            mock_server::{MockResponse, MockServer, CHAT_COMPLETIONS},
+            test_request::TestRequest,
+        },
+    };
 
-    // не работает с LM Studio
-    // Работает с ghcr.io/abetlen/llama-cpp-python:latest и то через раз
     #[tokio::test]
     async fn test_stream_response() {
+        let server = MockServer::start().await;
+        server.script(
+            CHAT_COMPLETIONS,
+            [MockResponse::Completion(
+                "Hello there. How are you?".to_string(),
+            )],
+        );
+
         let mut chatgpt = ai_waifu::create_streamed_ai(&{
             let mut cfg = Config::default();
             cfg.ai_engine.engine_type = ai_waifu::config::AIEngineType::LLaMa {
-                api_url: Url::parse("http://localhost:8000/v1/chat/completions").unwrap(),
+                api_url: server.url(CHAT_COMPLETIONS),
                 model: Some("TheBloke/zephyr-7B-beta-GGUF".to_owned()),
             };
             cfg
         });
 
-        let req = TestRequest {
-            request: "Мама мыла раму.".to_string(),
-            channel: "Master".to_string(),
-        };
-        let _request = chatgpt.process_streamed(Box::new(req)).await;
+        let req = TestRequest::new("Мама мыла раму.");
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let printer = tokio::spawn(async move {
+            let mut sentences = vec![];
+            while let Some(chunk) = rx.recv().await {
+                if let AIResponseChunk::Sentence(sentence) = chunk {
+                    sentences.push(sentence[&AIResponseType::RawAnswer].clone());
+                }
+            }
+            sentences
+        });
+
+        let res = chatgpt.process_streamed(Box::new(req), tx).await.unwrap();
+        assert_eq!(
+            res[&AIResponseType::RawAnswer].trim(),
+            "Hello there. How are you?"
+        );
+        let sentences = printer.await.unwrap();
+        assert_eq!(sentences.len(), 2);
+        assert_eq!(sentences[0].trim(), "Hello there.");
+
+        let requests = server.requests_to(CHAT_COMPLETIONS);
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].is_stream());
+        assert_eq!(requests[0].last_message(), "Мама мыла раму.");
     }
 }
@@ -0,0 +1,319 @@
+mod tests {
+    use std::sync::Arc;
+
+    use ai_waifu::{
+        builtin_tools::{evaluate, parse_dice, roll_dice, DiceTool, RememberTool},
+        config::{AIEngineType, CandidateRuleConfig, Config, MemoryConfig, ResilienceConfig},
+        dispatcher::{AIError, AIResponseChunk, AIResponseType},
+        errors::ErrorInfo,
+        memory_store::MemoryStore,
+        resilience,
+        tools::{Tool, ToolRegistry},
+        utils::{
+            mock_server::{MockResponse, MockServer, CHAT_COMPLETIONS},
+            test_request::TestRequest,
+        },
+    };
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+
+    struct EchoTool;
+
+    #[async_trait]
+    impl Tool for EchoTool {
+        fn name(&self) -> &str {
+            "echo"
+        }
+
+        fn description(&self) -> &str {
+            "Repeat the text"
+        }
+
+        fn parameters(&self) -> Value {
+            json!({
+                "type": "object",
+                "properties": { "text": { "type": "string" } },
+                "required": ["text"]
+            })
+        }
+
+        async fn call(&self, user: &str, args: Value) -> Result<String, AIError> {
+            match args["text"].as_str() {
+                Some(text) => Ok(format!("{user}: {text}")),
+                None => Err(AIError::AnswerError("no text".to_string())),
+            }
+        }
+    }
+
+    #[test]
+    fn test_calculator() {
+        assert_eq!(evaluate("2 + 2 * 2").unwrap(), 6.0);
+        assert_eq!(evaluate("(2 + 2) * 2").unwrap(), 8.0);
+        assert_eq!(evaluate("-3 ^ 2").unwrap(), -9.0);
+        assert_eq!(evaluate("2 ^ -1").unwrap(), 0.5);
+        assert_eq!(evaluate("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(evaluate("10 % 4 / 2").unwrap(), 1.0);
+        assert_eq!(evaluate("sqrt(16) + abs(-1.5)").unwrap(), 5.5);
+        assert!((evaluate("sin(pi / 2)").unwrap() - 1.0).abs() < 1e-9);
+
+        assert!(evaluate("1 / 0").is_err());
+        assert!(evaluate("(1 + 2").is_err());
+        assert!(evaluate("1 + ").is_err());
+        assert!(evaluate("2 $ 3").is_err());
+        assert!(evaluate("foo(1)").is_err());
+    }
+
+    #[test]
+    fn test_calculator_nesting() {
+        let nested = format!("{}1{}", "(".repeat(50), ")".repeat(50));
+        assert_eq!(evaluate(&nested).unwrap(), 1.0);
+        assert_eq!(evaluate("--1").unwrap(), 1.0);
+
+        let deep = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(evaluate(&deep).is_err());
+        assert!(evaluate(&format!("{}1", "-".repeat(100_000))).is_err());
+        assert!(evaluate(&format!("{}1", "2^".repeat(100_000))).is_err());
+        assert!(evaluate(&format!("{}1", "abs ".repeat(100_000))).is_err());
+    }
+
+    #[test]
+    fn test_dice() {
+        assert_eq!(parse_dice("d20").unwrap(), (1, 20, 0));
+        assert_eq!(parse_dice("3d6").unwrap(), (3, 6, 0));
+        assert_eq!(parse_dice("2D10 + 4").unwrap(), (2, 10, 4));
+        assert_eq!(parse_dice("4d8-2").unwrap(), (4, 8, -2));
+        assert!(parse_dice("20").is_err());
+        assert!(parse_dice("0d6").is_err());
+        assert!(parse_dice("1d1").is_err());
+        assert!(parse_dice("1000d6").is_err());
+
+        for _ in 0..100 {
+            let (rolls, total) = roll_dice("3d6+1").unwrap();
+            assert_eq!(rolls.len(), 3);
+            assert!(rolls.iter().all(|r| (1..=6).contains(r)));
+            assert_eq!(total, rolls.iter().sum::<u32>() as i64 + 1);
+        }
+    }
+
+    #[test]
+    fn test_definitions() {
+        let registry = ToolRegistry::new()
+            .with_tool(Arc::new(EchoTool))
+            .with_tool(Arc::new(DiceTool));
+
+        let definitions = registry.definitions();
+        assert_eq!(definitions.len(), 2);
+        assert_eq!(definitions[0]["type"], "function");
+        assert_eq!(definitions[0]["function"]["name"], "echo");
+        assert_eq!(
+            definitions[0]["function"]["parameters"]["required"][0],
+            "text"
+        );
+        assert_eq!(definitions[1]["function"]["name"], "roll_dice");
+    }
+
+    #[test]
+    fn test_config() {
+        let names = ["time", "dice", "calculator", "remember"].map(|n| n.to_string());
+
+        // "remember" needs the memory store
+        let registry = ToolRegistry::with_config(&names, None);
+        assert_eq!(
+            registry.names(),
+            vec!["get_current_time", "roll_dice", "calculate"]
+        );
+
+        assert!(ToolRegistry::with_config(&[], None).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        let registry = ToolRegistry::new().with_tool(Arc::new(EchoTool));
+
+        assert_eq!(
+            registry.call("Master", "echo", r#"{"text": "hi"}"#).await,
+            "Master: hi"
+        );
+        assert_eq!(
+            registry.call("Master", "echo", "{}").await,
+            "Error: no text"
+        );
+        assert!(registry
+            .call("Master", "echo", "not json")
+            .await
+            .starts_with("Error: invalid arguments"));
+        assert_eq!(
+            registry.call("Master", "dice", "{}").await,
+            "Error: unknown tool dice"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_remember() {
+        let memory_path = std::env::temp_dir().join("ai-waifu-memory-tools");
+        let _ = std::fs::remove_dir_all(&memory_path);
+        let memory = Arc::new(MemoryStore::new(MemoryConfig {
+            memory_path,
+            embeddings: None,
+            max_facts: 10,
+            recall_count: 3,
+        }));
+
+        let registry = ToolRegistry::new().with_tool(Arc::new(RememberTool::new(memory.clone())));
+        let result = registry
+            .call(
+                "Master",
+                "remember",
+                r#"{"fact": "Master likes green tea"}"#,
+            )
+            .await;
+
+        assert_eq!(result, "Remembered");
+        assert_eq!(memory.facts("Master")[0].text, "Master likes green tea");
+        assert!(memory.facts("Guest").is_empty());
+    }
+
+    fn tools_config(server: &MockServer) -> Config {
+        let mut cfg = Config::default();
+        cfg.ai_engine.engine_type = AIEngineType::LLaMa {
+            api_url: server.url(CHAT_COMPLETIONS),
+            model: None,
+        };
+        cfg.ai_engine.tools = vec!["calculator".to_string()];
+        cfg
+    }
+
+    fn calculate(expression: &str) -> MockResponse {
+        MockResponse::ToolCalls(vec![(
+            "calculate".to_string(),
+            json!({ "expression": expression }).to_string(),
+        )])
+    }
+
+    #[tokio::test]
+    async fn test_streamed_tool_calls() {
+        let server = MockServer::start().await;
+        server.script(CHAT_COMPLETIONS, [calculate("2 + 2")]);
+        let mut ai = ai_waifu::create_streamed_ai(&tools_config(&server));
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        let printer = tokio::spawn(async move {
+            let mut deltas = vec![];
+            while let Some(chunk) = rx.recv().await {
+                if let AIResponseChunk::Delta(delta) = chunk {
+                    deltas.push(delta);
+                }
+            }
+            deltas
+        });
+
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("What is 2 + 2?")), tx)
+            .await
+            .unwrap();
+        // the default answer repeats the tool result
+        assert_eq!(res[&AIResponseType::RawAnswer], "You said: 4");
+        // the final answer is streamed by tokens
+        assert_eq!(printer.await.unwrap(), vec!["You ", "said: ", "4"]);
+
+        let requests = server.requests_to(CHAT_COMPLETIONS);
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|r| r.is_stream()));
+        let messages = requests[1].json()["messages"].clone();
+        let call = &messages[messages.as_array().unwrap().len() - 2];
+        assert_eq!(call["tool_calls"][0]["function"]["name"], "calculate");
+        assert_eq!(
+            call["tool_calls"][0]["function"]["arguments"],
+            json!({ "expression": "2 + 2" }).to_string()
+        );
+        assert_eq!(requests[1].last_message(), "4");
+
+        // only the request and the answer are kept
+        let history = ai.history().unwrap();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[2].content, "You said: 4");
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_with_candidates() {
+        let server = MockServer::start().await;
+        let body = json!({
+            "choices": [
+                { "index": 0, "message": { "role": "assistant", "content": "As an AI, I can't count." } },
+                { "index": 1, "message": { "role": "assistant", "content": "It's 4!" } }
+            ],
+            "usage": { "prompt_tokens": 10, "completion_tokens": 8, "total_tokens": 18 }
+        });
+        server.script(
+            CHAT_COMPLETIONS,
+            [
+                calculate("2 + 2"),
+                MockResponse::Raw {
+                    status: 200,
+                    content_type: "application/json".to_string(),
+                    body: body.to_string(),
+                },
+            ],
+        );
+        let mut ai = ai_waifu::create_streamed_ai(&{
+            let mut cfg = tools_config(&server);
+            cfg.ai_engine.reply_count = Some(2);
+            cfg.ai_engine.candidate_rules = Some(vec![CandidateRuleConfig::BannedWords {
+                words: vec!["as an ai\\b".to_string()],
+                weight: 1.0,
+            }]);
+            cfg
+        });
+
+        let (tx, mut rx) = tokio::sync::mpsc::channel(16);
+        tokio::spawn(async move { while rx.recv().await.is_some() {} });
+        let res = ai
+            .process_streamed(Box::new(TestRequest::new("What is 2 + 2?")), tx)
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "It's 4!");
+
+        let requests = server.requests_to(CHAT_COMPLETIONS);
+        assert_eq!(requests.len(), 2);
+        assert!(requests
+            .iter()
+            .all(|r| !r.is_stream() && r.json()["n"] == 2));
+        assert_eq!(requests[1].last_message(), "4");
+        assert_eq!(ai.last_usage().unwrap().completion_tokens, 8);
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_retry() {
+        resilience::configure(&ResilienceConfig {
+            default: serde_json::from_value(json!({
+                "Initial_backoff": 0.01,
+                "Max_backoff": 0.01,
+                "Breaker_threshold": 0
+            }))
+            .unwrap(),
+            services: Default::default(),
+        });
+        let server = MockServer::start().await;
+        server.script(
+            CHAT_COMPLETIONS,
+            [
+                MockResponse::Status(503),
+                calculate("2 + 2"),
+                MockResponse::Status(400),
+            ],
+        );
+        let mut ai = ai_waifu::create_streamed_ai(&tools_config(&server));
+
+        let err = ai
+            .process(Box::new(TestRequest::new("What is 2 + 2?")))
+            .await
+            .unwrap_err();
+        // the failure is of the configured backend
+        match &err {
+            AIError::ServiceError(e) => assert_eq!(e.service, resilience::LLAMA),
+            e => panic!("unexpected error {e:?}"),
+        }
+        assert_eq!(err.status().map(|s| s.as_u16()), Some(400));
+        assert_eq!(server.requests_to(CHAT_COMPLETIONS).len(), 3);
+    }
+}
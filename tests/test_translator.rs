@@ -1,28 +1,120 @@
 mod tests {
     use ai_waifu::{
-        deeplx_translate_owned::DeepLxTranslatorOwned, dispatcher::*, dummy_ai::DummyAI,
-        utils::test_request::TestRequest,
+        deeplx_translate_owned::DeepLxTranslatorOwned,
+        dispatcher::*,
+        dummy_ai::DummyAI,
+        errors::{ErrorInfo, ErrorKind, TranslateError, DEEPL_TOO_MANY_REQUESTS},
+        utils::{
+            mock_server::{MockResponse, MockServer, DEEPLX},
+            test_request::TestRequest,
+        },
     };
+    use bytes::Bytes;
+    use reqwest::Url;
 
-    struct DummuENAIConstrictor;
+    struct DummuENAIConstrictor {
+        url: Url,
+    }
 
     impl AIBuilder for DummuENAIConstrictor {
         fn build(&mut self) -> Box<dyn AIinterface> {
             let ai = Box::new(DummyAI);
-            let en_ai = DeepLxTranslatorOwned::new(ai, Some("ru".to_string()), None, None);
+            let en_ai = DeepLxTranslatorOwned::new(ai, Some("ru".to_string()), None, None)
+                .with_url(self.url.clone());
             Box::new(en_ai)
         }
     }
 
     #[tokio::test]
     async fn test_translate_ru() {
-        let mut dispatcher = AIDispatcher::new(DummuENAIConstrictor {}, None);
+        let server = MockServer::start().await;
+        server.script(
+            DEEPLX,
+            [
+                MockResponse::Translation("Mom washed the frame.".to_string()),
+                MockResponse::Translation("Мама мыла раму!".to_string()),
+            ],
+        );
+        let dispatcher = AIDispatcher::new(
+            DummuENAIConstrictor {
+                url: server.url(DEEPLX),
+            },
+            None,
+        );
+
+        let req = TestRequest::new("Мама мыла раму.");
+
+        let res = dispatcher.try_process_request(Box::new(req)).await.unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Mom washed the frame.");
+        assert_eq!(res[&AIResponseType::Translated], "Мама мыла раму!");
+
+        let requests = server.requests_to(DEEPLX);
+        assert_eq!(requests[0].translate_text(), "Мама мыла раму.");
+        let lang = &requests[0].json()["params"]["lang"];
+        assert_eq!(lang["source_lang_user_selected"], "ru");
+        assert_eq!(lang["target_lang"], "en");
+        assert_eq!(requests[1].translate_text(), "Mom washed the frame.");
+    }
+
+    #[tokio::test]
+    async fn test_translate_errors() {
+        let server = MockServer::start().await;
+        server.script(
+            DEEPLX,
+            [
+                MockResponse::TranslationError {
+                    code: DEEPL_TOO_MANY_REQUESTS,
+                    message: "Too many requests".to_string(),
+                },
+                MockResponse::TranslationError {
+                    code: -32600,
+                    message: "Invalid target_lang".to_string(),
+                },
+            ],
+        );
+        let mut translator =
+            DeepLxTranslatorOwned::new(Box::new(DummyAI), Some("ru".to_string()), None, None)
+                .with_url(server.url(DEEPLX));
+
+        let e = translator
+            .translate("Привет", Some("ru"), "en", None)
+            .await
+            .unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::RateLimited);
+
+        assert_eq!(
+            translator.translate("Привет", Some("ru"), "xx", None).await,
+            Err(TranslateError::InvalidLanguage)
+        );
+    }
 
-        let req = TestRequest {
-            request: "Мама мыла раму.".to_string(),
-            channel: "Master".to_string(),
-        };
+    #[tokio::test]
+    async fn test_bad_encoding() {
+        let server = MockServer::start().await;
+        server.script(
+            DEEPLX,
+            [
+                MockResponse::Encoded {
+                    encoding: "br".to_string(),
+                    body: Bytes::from_static(b"not brotli"),
+                },
+                MockResponse::Encoded {
+                    encoding: "gzip".to_string(),
+                    body: Bytes::from_static(b"{}"),
+                },
+            ],
+        );
+        let mut translator =
+            DeepLxTranslatorOwned::new(Box::new(DummyAI), Some("ru".to_string()), None, None)
+                .with_url(server.url(DEEPLX));
 
-        assert!(dispatcher.try_process_request(Box::new(req)).await.is_ok());
+        for _ in 0..2 {
+            assert!(matches!(
+                translator.translate("Привет", Some("ru"), "en", None).await,
+                Err(TranslateError::BadResponse(_))
+            ));
+        }
+        // the answer came, it is not asked again
+        assert_eq!(server.requests_to(DEEPLX).len(), 2);
     }
 }
@@ -1,12 +1,38 @@
 mod tests {
-    use ai_waifu::silerio_tts::SilerioTTS;
+    use ai_waifu::{
+        jp_tts::JpTTS,
+        silerio_tts::SilerioTTS,
+        utils::mock_server::{silent_wav, MockResponse, MockServer, TTS},
+    };
 
     #[tokio::test]
     async fn test_tts() {
-        let tts = SilerioTTS::new("http://localhost:8961/say", Some("kseniya".to_string()));
+        let server = MockServer::start().await;
+        let tts = SilerioTTS::new(server.url(TTS), Some("kseniya".to_string()));
 
-        let res = tts.say("Привет, мир!").await;
+        let res = tts.say("Привет, мир!").await.unwrap();
 
-        assert!(res.is_ok());
+        assert!(res.get_ref().starts_with(b"RIFF"));
+        let requests = server.requests_to(TTS);
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].param("voice_id").as_deref(), Some("kseniya"));
+        assert_eq!(requests[0].text(), "Привет, мир!");
+    }
+
+    #[tokio::test]
+    async fn test_jp_tts() {
+        let server = MockServer::start().await;
+        let wav = silent_wav(500);
+        server.script(TTS, [MockResponse::Audio(wav.clone())]);
+        let tts = JpTTS::new(server.url(TTS), Some(3), None);
+
+        let res = tts.say("こんにちは").await.unwrap();
+
+        assert_eq!(res.into_inner(), wav);
+        let requests = server.requests_to(TTS);
+        assert_eq!(requests[0].method, "GET");
+        assert_eq!(requests[0].param("voice_id").as_deref(), Some("3"));
+        assert_eq!(requests[0].param("text").as_deref(), Some("こんにちは"));
     }
 }
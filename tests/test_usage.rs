@@ -0,0 +1,435 @@
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use ai_waifu::{
+        config::{
+            Config, ModerationAction, ModerationConfig, QueueOverflowPolicy, RequestQueueConfig,
+            UsageConfig,
+        },
+        config_validation::Severity,
+        dispatcher::*,
+        dummy_ai::DummyAI,
+        moderated_ai::ModeratedAI,
+        moderation::Moderator,
+        usage::{Limit, TokenUsage, UsageTracker},
+        utils::test_request::TestRequest,
+    };
+    use async_trait::async_trait;
+    use chrono::NaiveDate;
+    use futures_util::StreamExt;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 5, day).unwrap()
+    }
+
+    fn usage_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ai-waifu-usage-{name}"));
+        let _ = std::fs::remove_dir_all(&path);
+        path
+    }
+
+    /// Echoes the request and reports 100 prompt and 20 answer tokens
+    struct CountingAI(Arc<AtomicUsize>);
+
+    #[async_trait]
+    impl AIinterface for CountingAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            DummyAI.process(request).await
+        }
+
+        fn last_usage(&self) -> Option<TokenUsage> {
+            Some(TokenUsage::new(100, 20))
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    struct CountingAIConstrictor(Arc<AtomicUsize>);
+
+    impl AIBuilder for CountingAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(CountingAI(self.0.clone()))
+        }
+    }
+
+    /// Echoes are regenerated while they are flagged
+    struct ModeratedAIConstrictor(Arc<AtomicUsize>);
+
+    impl AIBuilder for ModeratedAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            let moderator = Moderator::new(&ModerationConfig {
+                banned_words: vec![r"\bheck\b".to_string()],
+                endpoint: None,
+                default_action: ModerationAction::Regenerate,
+                actions: Default::default(),
+                canned_lines: vec!["No comments.".to_string()],
+                max_regenerations: 2,
+            })
+            .unwrap();
+            Box::new(ModeratedAI::new(
+                Box::new(CountingAI(self.0.clone())),
+                Arc::new(moderator),
+                ModerationAction::Regenerate,
+            ))
+        }
+    }
+
+    /// Answers slowly, so the next requests wait in the queue
+    struct SlowAI;
+
+    #[async_trait]
+    impl AIinterface for SlowAI {
+        async fn process(
+            &mut self,
+            request: Box<dyn AIRequest>,
+        ) -> Result<HashMap<AIResponseType, String>, AIError> {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            DummyAI.process(request).await
+        }
+
+        fn last_usage(&self) -> Option<TokenUsage> {
+            Some(TokenUsage::new(100, 20))
+        }
+
+        async fn reset(&mut self) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        async fn save_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+
+        fn load_context(&mut self, _file: PathBuf) -> Result<(), AIError> {
+            Ok(())
+        }
+    }
+
+    struct SlowAIConstrictor;
+
+    impl AIBuilder for SlowAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(SlowAI)
+        }
+    }
+
+    /// Request of a user in the shared channel
+    struct UserRequest {
+        user: String,
+    }
+
+    impl AIRequest for UserRequest {
+        fn request(&self) -> String {
+            format!("Hi from {}", self.user)
+        }
+
+        fn channel(&self) -> String {
+            "Master".to_string()
+        }
+
+        fn lang(&self) -> String {
+            "auto".to_string()
+        }
+
+        fn user(&self) -> String {
+            self.user.clone()
+        }
+    }
+
+    fn user_request(user: &str) -> Box<UserRequest> {
+        Box::new(UserRequest {
+            user: user.to_string(),
+        })
+    }
+
+    struct DummyAIConstrictor;
+
+    impl AIBuilder for DummyAIConstrictor {
+        fn build(&mut self) -> Box<dyn AIinterface> {
+            Box::new(DummyAI)
+        }
+    }
+
+    #[test]
+    fn test_estimate() {
+        let usage = TokenUsage::estimate(["Act as a cat", "Hello"], "Meow meow");
+        assert!(usage.prompt_tokens > usage.completion_tokens);
+        assert_eq!(usage.total(), usage.prompt_tokens + usage.completion_tokens);
+
+        let mut sum = TokenUsage::new(1, 2);
+        sum += TokenUsage::new(10, 20);
+        assert_eq!(sum, TokenUsage::new(11, 22));
+
+        assert_eq!(
+            TokenUsage::combine(Some(sum), Some(TokenUsage::new(1, 1))),
+            Some(TokenUsage::new(12, 23))
+        );
+        assert_eq!(TokenUsage::combine(None, Some(sum)), Some(sum));
+        assert_eq!(TokenUsage::combine(None, None), None);
+    }
+
+    #[test]
+    fn test_record_and_persist() {
+        let path = usage_path("persist");
+        let config = UsageConfig {
+            usage_path: Some(path.clone()),
+            ..Default::default()
+        };
+
+        let tracker = UsageTracker::new(config.clone());
+        tracker.record_on(date(2), "alice", "Master", TokenUsage::new(100, 10));
+        tracker.record_on(date(2), "bob", "Master", TokenUsage::new(50, 5));
+        tracker.record_on(date(3), "alice", "Master", TokenUsage::new(10, 1));
+        tracker.flush();
+        assert!(path.join("2024-05-02.json").exists());
+
+        // a restarted bot continues counting
+        let tracker = UsageTracker::new(config);
+        tracker.record_on(date(2), "alice", "Other", TokenUsage::new(1, 1));
+        let day = tracker.day(date(2));
+        assert_eq!(day.users["alice"].requests, 2);
+        assert_eq!(day.users["alice"].tokens, TokenUsage::new(101, 11));
+        assert_eq!(day.channels["Master"].requests, 2);
+        assert_eq!(day.channels["Other"].tokens.total(), 2);
+        assert_eq!(day.total(), 167);
+        assert_eq!(day.user_total("carol"), 0);
+
+        assert_eq!(tracker.month_total(date(1)), 0);
+        assert_eq!(tracker.month_total(date(2)), 167);
+        assert_eq!(tracker.month_total(date(31)), 178);
+    }
+
+    #[test]
+    fn test_budgets() {
+        let tracker = UsageTracker::new(UsageConfig {
+            daily_budget: Some(1000),
+            monthly_budget: Some(1500),
+            user_daily_budget: Some(300),
+            ..Default::default()
+        });
+
+        tracker.record_on(date(10), "alice", "Master", TokenUsage::new(250, 50));
+        assert_eq!(tracker.check_on(date(10), "alice"), Err(Limit::UserBudget));
+        assert_eq!(tracker.check_on(date(10), "bob"), Ok(()));
+        // the user budget is per day
+        assert_eq!(tracker.check_on(date(11), "alice"), Ok(()));
+
+        tracker.record_on(date(10), "bob", "Master", TokenUsage::new(700, 0));
+        assert_eq!(tracker.check_on(date(10), "carol"), Err(Limit::DailyBudget));
+        assert_eq!(tracker.check_on(date(11), "carol"), Ok(()));
+
+        tracker.record_on(date(11), "carol", "Master", TokenUsage::new(500, 0));
+        assert_eq!(
+            tracker.check_on(date(12), "dave"),
+            Err(Limit::MonthlyBudget)
+        );
+        assert_eq!(
+            tracker.check_on(NaiveDate::from_ymd_opt(2024, 6, 1).unwrap(), "dave"),
+            Ok(())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let tracker = UsageTracker::new(UsageConfig {
+            user_requests: Some(2),
+            user_rate_period: 0.2,
+            ..Default::default()
+        });
+
+        assert_eq!(tracker.check("alice"), Ok(()));
+        assert_eq!(tracker.check("alice"), Ok(()));
+        assert_eq!(tracker.check("alice"), Err(Limit::UserRate));
+        assert_eq!(tracker.check("bob"), Ok(()));
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        assert_eq!(tracker.check("alice"), Ok(()));
+        assert_ne!(
+            tracker.limit_message(Limit::UserRate),
+            tracker.limit_message(Limit::DailyBudget)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatcher_limits() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tracker = Arc::new(UsageTracker::new(UsageConfig {
+            user_daily_budget: Some(200),
+            budget_message: "No more tokens".to_string(),
+            ..Default::default()
+        }));
+        let dispatcher = AIDispatcher::new(CountingAIConstrictor(calls.clone()), None)
+            .with_usage(tracker.clone());
+
+        for _ in 0..2 {
+            let res = dispatcher
+                .try_process_request(Box::new(TestRequest::new("Hi")))
+                .await
+                .unwrap();
+            assert_eq!(res[&AIResponseType::RawAnswer], "Hi");
+        }
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Hi")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "No more tokens");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let today = tracker.day(chrono::Local::now().date_naive());
+        assert_eq!(today.users["Master"].requests, 2);
+        assert_eq!(today.users["Master"].tokens, TokenUsage::new(200, 40));
+    }
+
+    #[tokio::test]
+    async fn test_regenerated_answers_charged() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let tracker = Arc::new(UsageTracker::new(UsageConfig::default()));
+        let dispatcher = AIDispatcher::new(ModeratedAIConstrictor(calls.clone()), None)
+            .with_usage(tracker.clone());
+
+        let res = dispatcher
+            .try_process_request(Box::new(TestRequest::new("Oh heck")))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "No comments.");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // the rejected answers are paid for too
+        let today = tracker.day(chrono::Local::now().date_naive());
+        assert_eq!(today.channels["Master"].requests, 1);
+        assert_eq!(today.channels["Master"].tokens, TokenUsage::new(300, 60));
+    }
+
+    #[tokio::test]
+    async fn test_queue_overflow_not_counted() {
+        let tracker = Arc::new(UsageTracker::new(UsageConfig {
+            user_requests: Some(2),
+            user_rate_period: 60.0,
+            ..Default::default()
+        }));
+        let dispatcher = AIDispatcher::new(SlowAIConstrictor, None)
+            .with_usage(tracker.clone())
+            .with_queue_config(RequestQueueConfig {
+                depth: 0,
+                overflow_policy: QueueOverflowPolicy::DropNewest,
+                ..Default::default()
+            });
+
+        let answer = dispatcher
+            .try_process_request_streamed(user_request("alice"))
+            .await
+            .unwrap();
+        assert!(matches!(
+            dispatcher
+                .try_process_request_streamed(user_request("alice"))
+                .await,
+            Err(AIError::Busy)
+        ));
+        answer.collect::<Vec<_>>().await;
+
+        // the rejected request left room for one more
+        let res = dispatcher
+            .try_process_request(user_request("alice"))
+            .await
+            .unwrap();
+        assert_eq!(res[&AIResponseType::RawAnswer], "Hi from alice");
+    }
+
+    #[tokio::test]
+    async fn test_merged_requests_charged() {
+        let tracker = Arc::new(UsageTracker::new(UsageConfig::default()));
+        let dispatcher = AIDispatcher::new(SlowAIConstrictor, None)
+            .with_usage(tracker.clone())
+            .with_queue_config(RequestQueueConfig {
+                depth: 1,
+                overflow_policy: QueueOverflowPolicy::Merge,
+                ..Default::default()
+            });
+
+        let mut answers = Vec::new();
+        for user in ["alice", "bob", "carol"] {
+            answers.push(
+                dispatcher
+                    .try_process_request_streamed(user_request(user))
+                    .await
+                    .unwrap(),
+            );
+        }
+        for answer in answers {
+            answer.collect::<Vec<_>>().await;
+        }
+
+        // bob and carol are answered at once and share the tokens
+        let today = tracker.day(chrono::Local::now().date_naive());
+        assert_eq!(today.channels["Master"].requests, 2);
+        assert_eq!(today.channels["Master"].tokens, TokenUsage::new(200, 40));
+        assert_eq!(today.users["alice"].tokens, TokenUsage::new(100, 20));
+        for user in ["bob", "carol"] {
+            assert_eq!(today.users[user].requests, 1);
+            assert_eq!(today.users[user].tokens, TokenUsage::new(50, 10));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_estimated_usage() {
+        let tracker = Arc::new(UsageTracker::new(UsageConfig::default()));
+        let dispatcher = AIDispatcher::new(DummyAIConstrictor, None).with_usage(tracker.clone());
+
+        dispatcher
+            .try_process_request(Box::new(TestRequest::new("How are you?")))
+            .await
+            .unwrap();
+        let today = tracker.day(chrono::Local::now().date_naive());
+        assert_eq!(
+            today.channels["Master"].tokens,
+            TokenUsage::estimate(["How are you?"], "How are you?")
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.usage = serde_json::from_value(serde_json::json!({
+            "Daily_budget": 5000,
+            "Monthly_budget": 1000,
+            "User_daily_budget": 0,
+            "User_requests": 3,
+            "User_rate_period": 0
+        }))
+        .unwrap();
+
+        let report = config.validate_values();
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec!["Usage.User_daily_budget", "Usage.User_rate_period"]
+        );
+        assert_eq!(
+            report.key_paths(Severity::Warning),
+            vec!["Usage.Daily_budget"]
+        );
+    }
+}
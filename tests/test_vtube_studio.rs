@@ -0,0 +1,233 @@
+mod tests {
+    use std::{io::Cursor, path::PathBuf, time::Duration};
+
+    use ai_waifu::{
+        config::{Config, VTubeStudioConfig},
+        config_validation::Severity,
+        dispatcher::AIResponseType,
+        errors::{ErrorInfo, ErrorKind},
+        utils::{
+            mock_server::silent_wav,
+            mock_vts::{MockVTubeStudio, MOCK_TOKEN},
+        },
+        vtube_studio::*,
+    };
+    use maplit::hashmap;
+    use serde_json::json;
+
+    fn token_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ai-waifu-vts-{name}.txt"));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn vts_config(url: &str, token_path: Option<PathBuf>) -> VTubeStudioConfig {
+        serde_json::from_value(json!({
+            "Url": url,
+            "Token_path": token_path,
+            "Emotions": [
+                { "Name": "happy", "Keywords": ["smiles"], "Expression": "happy.exp3.json" },
+                { "Name": "angry", "Keywords": ["frowns", "so mad"], "Expression": "angry.exp3.json" },
+                { "Name": "wave", "Keywords": ["waves"], "Hotkey": "Wave" }
+            ]
+        }))
+        .unwrap()
+    }
+
+    /// Mono 16 kHz wav of a square wave
+    fn loud_wav(millis: u32, amplitude: f32) -> Vec<u8> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 16000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut wav = Cursor::new(vec![]);
+        let mut writer = hound::WavWriter::new(&mut wav, spec).unwrap();
+        for i in 0..16 * millis {
+            let sign = if i % 2 == 0 { 1.0 } else { -1.0 };
+            let sample = (sign * amplitude * i16::MAX as f32) as i16;
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+        wav.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_authentication() {
+        let mock = MockVTubeStudio::start().await;
+        let config = vts_config(mock.url().as_str(), Some(token_path("auth")));
+
+        VTubeStudio::connect_authenticated(&config).await.unwrap();
+        let saved = std::fs::read_to_string(config.token_path.as_ref().unwrap()).unwrap();
+        assert_eq!(saved, MOCK_TOKEN);
+
+        // the saved token is reused
+        VTubeStudio::connect_authenticated(&config).await.unwrap();
+        assert_eq!(mock.requests_of("AuthenticationTokenRequest").len(), 1);
+        let auth = mock.requests_of("AuthenticationRequest");
+        assert_eq!(auth.len(), 2);
+        assert_eq!(auth[1]["pluginName"], "AI Waifu");
+        assert_eq!(auth[1]["authenticationToken"], MOCK_TOKEN);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_token() {
+        let mock = MockVTubeStudio::start().await;
+        let path = token_path("rejected");
+        std::fs::write(&path, "stale-token\n").unwrap();
+        let config = vts_config(mock.url().as_str(), Some(path.clone()));
+
+        VTubeStudio::connect_authenticated(&config).await.unwrap();
+        assert_eq!(mock.requests_of("AuthenticationTokenRequest").len(), 1);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), MOCK_TOKEN);
+
+        // the user denies the plugin
+        let mock = MockVTubeStudio::start().await;
+        mock.deny();
+        let err = VTubeStudio::connect_authenticated(&vts_config(mock.url().as_str(), None))
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn test_requests() {
+        let mock = MockVTubeStudio::start().await;
+        let mut vts = VTubeStudio::connect_authenticated(&vts_config(mock.url().as_str(), None))
+            .await
+            .unwrap();
+
+        vts.trigger_hotkey("Wave").await.unwrap();
+        vts.set_expression("happy.exp3.json", true).await.unwrap();
+        vts.inject_parameters(&[("MouthOpen", 0.5)]).await.unwrap();
+
+        assert_eq!(
+            mock.requests_of("HotkeyTriggerRequest"),
+            vec![json!({ "hotkeyID": "Wave" })]
+        );
+        assert_eq!(
+            mock.requests_of("ExpressionActivationRequest"),
+            vec![json!({ "expressionFile": "happy.exp3.json", "active": true })]
+        );
+        let inject = &mock.requests_of("InjectParameterDataRequest")[0];
+        assert_eq!(inject["mode"], "set");
+        assert_eq!(inject["parameterValues"][0]["id"], "MouthOpen");
+        assert_eq!(inject["parameterValues"][0]["value"], 0.5);
+    }
+
+    #[test]
+    fn test_emotions() {
+        let config = vts_config("ws://localhost:8001", None);
+        let mapper = EmotionMapper::new(&config.emotions);
+        let emotion = |text: &str| {
+            let answer = hashmap! { AIResponseType::RawAnswer => text.to_string() };
+            mapper.emotion(&answer).map(|e| e.name.clone())
+        };
+
+        // the tag wins over the keywords
+        assert_eq!(emotion("*smiles* Fine. [angry]").as_deref(), Some("angry"));
+        assert_eq!(emotion("*smiles* Hello!").as_deref(), Some("happy"));
+        assert_eq!(emotion("I'm SO MAD at you").as_deref(), Some("angry"));
+        assert_eq!(emotion("[Wave] Bye").as_deref(), Some("wave"));
+        // whole words only
+        assert_eq!(emotion("Microwaves are loud"), None);
+        assert_eq!(emotion("Hello [sleepy]"), None);
+
+        // the actions of the Sanitize layer are used when present
+        let answer = hashmap! {
+            AIResponseType::RawAnswer => "Hi".to_string(),
+            AIResponseType::Actions => "waves".to_string(),
+        };
+        assert_eq!(mapper.emotion(&answer).unwrap().name, "wave");
+    }
+
+    #[test]
+    fn test_mouth_envelope() {
+        let frames = mouth_envelope(&silent_wav(100), 30, 4.0).unwrap();
+        assert_eq!(frames.len(), 4);
+        assert!(frames.iter().all(|&f| f == 0.0));
+
+        let frames = mouth_envelope(&loud_wav(1000, 0.2), 10, 1.0).unwrap();
+        assert_eq!(frames.len(), 10);
+        assert!(frames.iter().all(|&f| (f - 0.2).abs() < 0.01));
+
+        // clamped to fully open
+        let frames = mouth_envelope(&loud_wav(100, 0.5), 10, 4.0).unwrap();
+        assert!(frames.iter().all(|&f| f == 1.0));
+
+        assert!(mouth_envelope(b"not a wav", 30, 4.0).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_avatar() {
+        let mock = MockVTubeStudio::start().await;
+        let avatar = Avatar::spawn(vts_config(mock.url().as_str(), None));
+
+        avatar.react(&hashmap! { AIResponseType::RawAnswer => "*smiles* Hi!".to_string() });
+        avatar.react(&hashmap! { AIResponseType::RawAnswer => "*waves*".to_string() });
+        avatar.speak(&loud_wav(200, 0.5));
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        avatar.calm_down();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        // the expression is kept while the hotkey emotion is shown
+        assert_eq!(
+            mock.requests_of("ExpressionActivationRequest"),
+            vec![
+                json!({ "expressionFile": "happy.exp3.json", "active": true }),
+                json!({ "expressionFile": "happy.exp3.json", "active": false }),
+            ]
+        );
+        assert_eq!(
+            mock.requests_of("HotkeyTriggerRequest"),
+            vec![json!({ "hotkeyID": "Wave" })]
+        );
+
+        let mouth: Vec<f64> = mock
+            .requests_of("InjectParameterDataRequest")
+            .iter()
+            .map(|r| r["parameterValues"][0]["value"].as_f64().unwrap())
+            .collect();
+        assert!(mouth.len() > 2, "{:?}", mouth);
+        assert_eq!(mouth[0], 1.0);
+        // the mouth is closed after the speech
+        assert_eq!(*mouth.last().unwrap(), 0.0);
+    }
+
+    #[test]
+    fn test_validation() {
+        let mut config = Config::default();
+        config.deeplx_translate_config.dest_lang = "en".to_string();
+        config.busy_messages = vec!["Busy".to_string()];
+        config.stt_config.maximal_audio_fragment_length = 15.0;
+        config.vtube_studio = Some(vts_config("ws://localhost:8001", None));
+
+        let report = config.validate_values();
+        assert!(report.key_paths(Severity::Error).is_empty());
+        assert!(report.key_paths(Severity::Warning).is_empty());
+
+        let vts = config.vtube_studio.as_mut().unwrap();
+        vts.url = "http://localhost:8001".parse().unwrap();
+        vts.mouth_fps = 0;
+        vts.mouth_gain = 0.0;
+        vts.emotions[0].name = " ".to_string();
+        vts.emotions[2].hotkey = None;
+
+        let report = config.validate_values();
+        assert_eq!(
+            report.key_paths(Severity::Error),
+            vec![
+                "VTube_Studio.Url",
+                "VTube_Studio.Mouth_fps",
+                "VTube_Studio.Mouth_gain",
+                "VTube_Studio.Emotions[0].Name"
+            ]
+        );
+        assert_eq!(
+            report.key_paths(Severity::Warning),
+            vec!["VTube_Studio.Emotions[2]"]
+        );
+    }
+}